@@ -0,0 +1,64 @@
+//! Verifies arbitrary bytes as an IBC proof using [`cf_guest::proof::verify`].
+//!
+//! Input is `<path_len:u8> <path> <flags:u8> <value_len:u8> <value> <root:32>
+//! <proof>` where `path` is an IBC path in its string representation, lowest
+//! bit of `flags` indicates whether `value` is present and the next bit selects
+//! the hashing scheme.
+//!
+//! Verification must never panic.  Furthermore, if the proof is accepted, it
+//! must not be accepted for a different value.  Sequence paths are excluded
+//! from the latter check since proofs for them carry additional data
+//! depending on whether a value is verified or not.
+
+#![no_main]
+
+use core::str::FromStr;
+
+use cf_guest::proof::verify;
+use ibc_core_commitment_types::commitment::{
+    CommitmentPrefix, CommitmentProofBytes, CommitmentRoot,
+};
+use ibc_core_host::types::path::Path;
+use lib::hash::HashVersion;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let Some((&path_len, data)) = data.split_first() else { return };
+    let Some(path) = data.get(..usize::from(path_len)) else { return };
+    let data = &data[path.len()..];
+    let [flags, value_len, data @ ..] = data else { return };
+    let Some(value) = data.get(..usize::from(*value_len)) else { return };
+    let data = &data[value.len()..];
+    let Some(root) = data.get(..32) else { return };
+    let proof = &data[32..];
+
+    let Ok(path) = core::str::from_utf8(path) else { return };
+    let Ok(path) = Path::from_str(path) else { return };
+    let Ok(proof) = CommitmentProofBytes::try_from(proof.to_vec()) else {
+        return;
+    };
+    let root = CommitmentRoot::from(root.to_vec());
+    let prefix = CommitmentPrefix::default();
+    let value = (flags & 1 == 1).then_some(value);
+    let version = match flags & 2 {
+        0 => HashVersion::V0,
+        _ => HashVersion::V1,
+    };
+
+    let check = |value: Option<&[u8]>| {
+        verify(version, &prefix, &proof, &root, path.clone(), value).is_ok()
+    };
+
+    if !check(value) ||
+        matches!(path, Path::SeqSend(_) | Path::SeqRecv(_) | Path::SeqAck(_))
+    {
+        return;
+    }
+    if let Some(value) = value {
+        assert!(!check(None), "{path}: membership proof accepted as absent");
+        let mut other = value.to_vec();
+        other.push(0);
+        assert!(!check(Some(&other)), "{path}: proof accepted for {other:?}");
+    } else {
+        assert!(!check(Some(b"")), "{path}: absence proof accepted as present");
+    }
+});
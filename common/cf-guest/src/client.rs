@@ -1,4 +1,4 @@
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, HashVersion};
 
 use crate::proto;
 
@@ -26,6 +26,15 @@ pub struct ClientState<PK> {
     /// Whether client is frozen.
     pub is_frozen: bool,
 
+    /// Hashing scheme used by the guest blockchain.
+    ///
+    /// Block hashes, epoch commitments and hashes of values stored under
+    /// paths which include a client id (see [`crate::digest_with_client_id`])
+    /// depend on it.  Other values are hashed the same way regardless of the
+    /// version.  It must match the scheme the chain has been configured with
+    /// (see [`guestchain::Config::hash_version`]).
+    pub hash_version: HashVersion,
+
     _ph: core::marker::PhantomData<PK>,
 }
 
@@ -80,6 +89,10 @@ impl<PK: guestchain::PubKey> From<&ClientState<PK>> for proto::ClientState {
             trusting_period_ns: state.trusting_period_ns,
             epoch_commitment: state.epoch_commitment.to_vec(),
             is_frozen: state.is_frozen,
+            hash_version: match state.hash_version {
+                HashVersion::V0 => 0,
+                HashVersion::V1 => 1,
+            },
         }
     }
 }
@@ -99,12 +112,18 @@ impl<PK: guestchain::PubKey> TryFrom<&proto::ClientState> for ClientState<PK> {
         let epoch_commitment =
             CryptoHash::try_from(msg.epoch_commitment.as_slice())
                 .map_err(|_| proto::BadMessage)?;
+        let hash_version = match msg.hash_version {
+            0 => HashVersion::V0,
+            1 => HashVersion::V1,
+            _ => return Err(proto::BadMessage),
+        };
         Ok(Self {
             genesis_hash,
             latest_height: msg.latest_height.into(),
             trusting_period_ns: msg.trusting_period_ns,
             epoch_commitment,
             is_frozen: msg.is_frozen,
+            hash_version,
             _ph: core::marker::PhantomData,
         })
     }
@@ -119,6 +138,7 @@ super::any_convert! {
         trusting_period_ns: 30 * 24 * 3600 * 1_000_000_000,
         epoch_commitment: CryptoHash::test(11),
         is_frozen: false,
+        hash_version: HashVersion::V0,
         _ph: core::marker::PhantomData,
     },
     bad: proto::ClientState {
@@ -127,5 +147,10 @@ super::any_convert! {
         epoch_commitment: [0; 30].to_vec(),
         is_frozen: false,
         trusting_period_ns: 30 * 24 * 3600 * 1_000_000_000,
+        hash_version: 0,
+    },
+    bad: proto::ClientState {
+        hash_version: 2,
+        ..proto::ClientState::test()
     },
 }
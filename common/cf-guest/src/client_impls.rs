@@ -113,7 +113,8 @@ impl<PK: PubKey> ibc::ClientStateCommon for ClientState<PK> {
         value: Vec<u8>,
     ) -> Result {
         let value = Some(value.as_slice());
-        proof::verify(prefix, proof, root, path, value).map_err(Into::into)
+        proof::verify(self.hash_version, prefix, proof, root, path, value)
+            .map_err(Into::into)
     }
 
     /// Verifies membership proof.
@@ -126,7 +127,8 @@ impl<PK: PubKey> ibc::ClientStateCommon for ClientState<PK> {
         root: &ibc::CommitmentRoot,
         path: ibc::path::Path,
     ) -> Result {
-        proof::verify(prefix, proof, root, path, None).map_err(Into::into)
+        proof::verify(self.hash_version, prefix, proof, root, path, None)
+            .map_err(Into::into)
     }
 }
 
@@ -182,7 +184,8 @@ where
         header: Any,
     ) -> Result<Vec<ibc::Height>> {
         let header = crate::proto::Header::try_from(header)?;
-        let header = crate::Header::<PK>::try_from(header)?;
+        let header = crate::Header::<PK>::try_from(header)?
+            .with_hash_version(self.hash_version);
         let header_height =
             ibc::Height::new(0, header.block_header.block_height.into())?;
 
@@ -327,6 +330,7 @@ impl<PK: PubKey> ClientState<PK> {
         _client_id: &ibc::ClientId,
         header: Header<PK>,
     ) -> Result<()> {
+        let header = header.with_hash_version(self.hash_version);
         (|| {
             if header.epoch_commitment != self.epoch_commitment {
                 return Err("Unexpected epoch");
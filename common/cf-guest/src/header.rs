@@ -1,7 +1,7 @@
 use alloc::vec::Vec;
 
 use guestchain::{PubKey, Signature};
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, HashVersion};
 
 use crate::proto;
 
@@ -19,6 +19,23 @@ pub struct Header<PK: PubKey> {
     pub signatures: Vec<(u16, PK::Signature)>,
 }
 
+impl<PK: PubKey> Header<PK> {
+    /// Recalculates `block_hash` and `epoch_commitment` using given hashing
+    /// scheme.
+    ///
+    /// The Protocol Message doesn’t specify the scheme so when converting
+    /// from [`proto::Header`] the hashes are calculated with
+    /// [`HashVersion::V0`].  Light client configured with a different scheme
+    /// uses this method to get hashes it can compare against its state.
+    pub fn with_hash_version(self, version: HashVersion) -> Self {
+        Self {
+            block_hash: self.block_header.calc_hash(version),
+            epoch_commitment: self.epoch.calc_commitment(version),
+            ..self
+        }
+    }
+}
+
 impl<PK: PubKey> From<Header<PK>> for proto::Header {
     fn from(header: Header<PK>) -> Self { Self::from(&header) }
 }
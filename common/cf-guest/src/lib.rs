@@ -49,13 +49,20 @@ impl From<BadMessage> for ibc_core_client_context::types::error::ClientError {
 /// some other way.  We do this by mixing in the client id into the hash of
 /// the value stored at the path.
 ///
-/// Specifically, this calculates `digest(client_id || b'0' || serialised)`.
+/// Specifically, this calculates `digest(client_id || b'0' || serialised)` in
+/// [`lib::hash::domain::ClientId`] domain using given hashing scheme.  With
+/// [`lib::hash::HashVersion::V0`] this is a plain hash of the concatenation.
 #[inline]
 pub fn digest_with_client_id(
+    version: lib::hash::HashVersion,
     client_id: &ibc_core_host::types::identifiers::ClientId,
     value: &[u8],
 ) -> lib::hash::CryptoHash {
-    lib::hash::CryptoHash::digestv(&[client_id.as_bytes(), b"\0", value])
+    version.digestv::<lib::hash::domain::ClientId>(&[
+        client_id.as_bytes(),
+        b"\0",
+        value,
+    ])
 }
 
 
@@ -142,3 +149,28 @@ macro_rules! any_convert {
 }
 
 use any_convert;
+
+
+#[test]
+fn test_digest_with_client_id() {
+    use core::str::FromStr;
+
+    use lib::hash::{CryptoHash, HashVersion};
+
+    let client_id =
+        ibc_core_host::types::identifiers::ClientId::from_str("foo-bar-1")
+            .unwrap();
+
+    // V0 reproduces the legacy hash.
+    let want = CryptoHash::digest(b"foo-bar-1\0value");
+    assert_eq!(
+        want,
+        digest_with_client_id(HashVersion::V0, &client_id, b"value")
+    );
+
+    let want = CryptoHash::digest(b"\x12cf-guest/client-idfoo-bar-1\0value");
+    assert_eq!(
+        want,
+        digest_with_client_id(HashVersion::V1, &client_id, b"value")
+    );
+}
@@ -2,7 +2,7 @@ use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 use guestchain::BlockHeader;
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, HashVersion};
 
 mod ibc {
     pub use ibc_core_commitment_types::commitment::{
@@ -29,7 +29,8 @@ impl IbcProof {
 
 #[derive(Clone, Debug, PartialEq, Eq, derive_more::From)]
 pub enum GenerateError {
-    /// State root in block header and root of trie don’t match.
+    /// State root in block header doesn’t match root of the trie nor root of
+    /// any version retained by the trie.
     WrongState,
 
     /// Error reading data from the trie.
@@ -41,9 +42,11 @@ pub enum GenerateError {
 
 /// Generates a proof for given path.
 ///
-/// `block_header` is header whose hash will be the commitment root.  It’s
-/// state root must correspond to `trie`’s root.  `path` specifies IBC path
-/// of the value that needs proof.
+/// `block_header` is header whose hash (calculated with `hash_version` scheme)
+/// will be the commitment root.  It’s state root must correspond to `trie`’s
+/// root or, if the trie is in persistent mode, to root of one of its retained
+/// versions (see [`sealable_trie::Trie::checkpoint`]).  `path` specifies IBC
+/// path of the value that needs proof.
 ///
 /// # Proof format
 ///
@@ -71,17 +74,20 @@ pub enum GenerateError {
 /// (Note that Borsh uses little endian to encode integers so the sequence
 /// numbers cannot be simply borsh deserialised.)
 pub fn generate<A: sealable_trie::Allocator>(
+    hash_version: HashVersion,
     block_header: &BlockHeader,
     trie: &sealable_trie::Trie<A>,
     path: ibc::path::Path,
 ) -> Result<IbcProof, GenerateError> {
-    if trie.hash() != &block_header.state_root {
-        return Err(GenerateError::WrongState);
-    }
-    let root = block_header.calc_hash().to_vec().into();
+    let root = block_header.calc_hash(hash_version).to_vec().into();
 
     let trie_ids::PathInfo { key, seq_kind, .. } = path.try_into()?;
-    let (value, proof) = trie.prove(&key)?;
+    let (value, proof) = match trie.prove_at(&block_header.state_root, &key) {
+        Err(sealable_trie::Error::UnknownRoot) => {
+            return Err(GenerateError::WrongState)
+        }
+        res => res?,
+    };
     let mut proof = borsh::to_vec(&(&block_header, &proof)).unwrap();
 
     if let Some((value, seq_kind)) = value.as_ref().zip(seq_kind) {
@@ -133,6 +139,7 @@ impl From<borsh::maybestd::io::Error> for VerifyError {
 
 /// Verifies a proof for given entry or lack of entry.
 ///
+/// `hash_version` is the hashing scheme used by the guest blockchain.
 /// `prefix` must be empty, `proof` and `root` must follow format described in
 /// [`generate`] function.  `path` indicates IBC path the proof is for and
 /// `value` determines value or lack thereof expected at the path.
@@ -150,8 +157,10 @@ impl From<borsh::maybestd::io::Error> for VerifyError {
 ///    `google.protobuf.UInt64Value` protobuf and hash is calculated as
 ///    concatenation of the three sequence numbers as described in [`generate`].
 ///
-/// 3. Otherwise, the value is simply hashed.
+/// 3. Otherwise, the value is simply hashed.  This doesn’t depend on
+///    `hash_version`.
 pub fn verify(
+    hash_version: HashVersion,
     prefix: &ibc::CommitmentPrefix,
     proof: &ibc::CommitmentProofBytes,
     root: &ibc::CommitmentRoot,
@@ -175,7 +184,7 @@ pub fn verify(
     let (state_root, proof) = {
         let (header, proof): (BlockHeader, sealable_trie::proof::Proof) =
             borsh::BorshDeserialize::deserialize_reader(&mut proof_bytes)?;
-        if root != &header.calc_hash() {
+        if root != &header.calc_hash(hash_version) {
             return Err(VerifyError::VerificationFailed);
         }
         (header.state_root, proof)
@@ -210,7 +219,7 @@ pub fn verify(
         } else if let Some(id) = path.client_id.as_ref() {
             // If path includes client id, hash stored in the trie is calculated
             // with the id mixed in.
-            super::digest_with_client_id(id, value)
+            super::digest_with_client_id(hash_version, id, value)
         } else {
             // Otherwise, simply hash the value.
             CryptoHash::digest(value)
@@ -231,6 +240,12 @@ pub fn verify(
 
 #[test]
 fn test_proofs() {
+    check_proofs(HashVersion::V0);
+    check_proofs(HashVersion::V1);
+}
+
+#[cfg(test)]
+fn check_proofs(version: HashVersion) {
     use alloc::vec;
     use core::str::FromStr;
 
@@ -253,6 +268,7 @@ fn test_proofs() {
     }
 
     fn assert_path_proof(
+        version: HashVersion,
         path: ibc::path::Path,
         value: &[u8],
         stored_hash: &CryptoHash,
@@ -272,10 +288,18 @@ fn test_proofs() {
         };
 
         // First try non-membership proof.
-        let proof = generate(&trie.header, &trie.trie, path.clone()).unwrap();
+        let proof =
+            generate(version, &trie.header, &trie.trie, path.clone()).unwrap();
         assert!(proof.value.is_none());
-        verify(&proof.prefix(), &proof.proof, &proof.root, path.clone(), None)
-            .unwrap();
+        verify(
+            version,
+            &proof.prefix(),
+            &proof.proof,
+            &proof.root,
+            path.clone(),
+            None,
+        )
+        .unwrap();
 
         // Verify non-membership fails if value is inserted.
         let key = trie_ids::PathInfo::try_from(path.clone()).unwrap().key;
@@ -284,6 +308,7 @@ fn test_proofs() {
         assert_eq!(
             Err(VerifyError::VerificationFailed),
             verify(
+                version,
                 &proof.prefix(),
                 &proof.proof,
                 &trie.root(),
@@ -293,9 +318,11 @@ fn test_proofs() {
         );
 
         // Generate membership proof.
-        let proof = generate(&trie.header, &trie.trie, path.clone()).unwrap();
+        let proof =
+            generate(version, &trie.header, &trie.trie, path.clone()).unwrap();
         assert_eq!(Some(stored_hash), proof.value.as_ref());
         verify(
+            version,
             &proof.prefix(),
             &proof.proof,
             &proof.root,
@@ -304,10 +331,28 @@ fn test_proofs() {
         )
         .unwrap();
 
+        // Proof doesn’t verify with a different hashing scheme.
+        let other = match version {
+            HashVersion::V0 => HashVersion::V1,
+            HashVersion::V1 => HashVersion::V0,
+        };
+        assert_eq!(
+            Err(VerifyError::VerificationFailed),
+            verify(
+                other,
+                &proof.prefix(),
+                &proof.proof,
+                &proof.root,
+                path.clone(),
+                Some(value),
+            )
+        );
+
         // Check invalid membership proofs
         assert_eq!(
             Err(VerifyError::BadPrefix),
             verify(
+                version,
                 &vec![1u8, 2, 3].try_into().unwrap(),
                 &proof.proof,
                 &proof.root,
@@ -319,6 +364,7 @@ fn test_proofs() {
         assert_eq!(
             Err(VerifyError::BadRoot),
             verify(
+                version,
                 &proof.prefix(),
                 &proof.proof,
                 &vec![1u8, 2, 3].try_into().unwrap(),
@@ -332,6 +378,7 @@ fn test_proofs() {
                 "Unexpected length of input".into()
             )),
             verify(
+                version,
                 &proof.prefix(),
                 &vec![0u8, 1, 2, 3].try_into().unwrap(),
                 &proof.root,
@@ -345,6 +392,7 @@ fn test_proofs() {
         assert_eq!(
             Err(VerifyError::ProofDecodingFailure("Spurious bytes".into())),
             verify(
+                version,
                 &proof.prefix(),
                 &proof_bytes.try_into().unwrap(),
                 &proof.root,
@@ -356,6 +404,7 @@ fn test_proofs() {
         assert_eq!(
             Err(VerifyError::VerificationFailed),
             verify(
+                version,
                 &proof.prefix(),
                 &proof.proof,
                 &CryptoHash::test(11).to_vec().into(),
@@ -373,7 +422,7 @@ fn test_proofs() {
 
     let value = b"foo";
     let value_hash = CryptoHash::digest(value);
-    let cv_hash = super::digest_with_client_id(&client_id, value);
+    let cv_hash = super::digest_with_client_id(version, &client_id, value);
 
     let seq_value = prost::Message::encode_to_vec(&20u64);
     let seq_hash = |idx: usize| {
@@ -390,7 +439,7 @@ fn test_proofs() {
             check!($path, value, &cv_hash)
         };
         ($path:expr, $value:expr, $hash:expr) => {
-            assert_path_proof($path.into(), $value, $hash)
+            assert_path_proof(version, $path.into(), $value, $hash)
         };
     }
 
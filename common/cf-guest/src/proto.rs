@@ -148,6 +148,7 @@ impl_proto!(ClientState; test_client_state; Self {
     epoch_commitment: lib::hash::CryptoHash::test(11).to_vec(),
     is_frozen: false,
     trusting_period_ns: 30 * 24 * 3600 * 1_000_000_000,
+    hash_version: 0,
 });
 
 impl_proto!(ConsensusState; test_consensus_state; {
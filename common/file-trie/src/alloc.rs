@@ -0,0 +1,142 @@
+use std::collections::BTreeSet;
+
+use memory::Ptr;
+
+use crate::header::Header;
+
+const SZ: usize = sealable_trie::nodes::RawNode::SIZE;
+
+/// Allocator keeping an in-memory image of the trie file.
+///
+/// Pointers are byte offsets into the file (just like in `solana-trie`), with
+/// the first [`Header::ENCODED_SIZE`] bytes occupied by the header.  All reads
+/// are served from memory.  Modified blocks are remembered so that they can
+/// be written to the file when changes are committed.
+///
+/// This duplicates some of what [`memory::WriteLog`] does but the write log
+/// can’t be used here.  It holds a mutable borrow of the underlying allocator
+/// for its entire lifetime and is consumed on commit whereas the trie file
+/// needs to own its allocator and keep tracking changes across many commits.
+/// Furthermore, the write log only tracks nodes while commits need whole
+/// blocks (including the free list links) written to the file.
+#[derive(Debug)]
+pub struct Allocator {
+    /// Image of the file.  Always exactly `next_block` bytes long.
+    pub(crate) data: Vec<u8>,
+
+    /// Position of the next unallocated block.
+    ///
+    /// Blocks which were allocated and then freed don’t count as ‘unallocated’
+    /// in this context.  This is position of the next block to return if the
+    /// free list is empty.
+    pub(crate) next_block: u32,
+
+    /// Pointer to the first freed block; `None` if there were no freed blocks
+    /// yet.
+    pub(crate) first_free: Option<Ptr>,
+
+    /// Offsets of blocks modified since the last commit.
+    pub(crate) dirty: BTreeSet<u32>,
+}
+
+impl Allocator {
+    /// Creates the allocator from file contents and its decoded header.
+    ///
+    /// Returns `None` if the header is inconsistent with the data.
+    pub(crate) fn new(mut data: Vec<u8>, hdr: &Header) -> Option<Self> {
+        let len = usize::try_from(hdr.next_block).ok()?;
+        let blocks = len.checked_sub(Header::ENCODED_SIZE)?;
+        if blocks % SZ != 0 || data.len() < len || hdr.next_block > Ptr::MAX {
+            return None;
+        }
+        let first_free = Ptr::new(hdr.first_free).ok()?;
+        if first_free.map_or(false, |ptr| !Self::is_block(ptr, hdr.next_block))
+        {
+            return None;
+        }
+        data.truncate(len);
+        Some(Self {
+            data,
+            next_block: hdr.next_block,
+            first_free,
+            dirty: Default::default(),
+        })
+    }
+
+    /// Checks whether pointer points at a block within first `end` bytes.
+    fn is_block(ptr: Ptr, end: u32) -> bool {
+        let offset = ptr.get();
+        offset >= Header::ENCODED_SIZE as u32 &&
+            offset < end &&
+            (offset as usize - Header::ENCODED_SIZE) % SZ == 0
+    }
+
+    /// Returns range of the block at given pointer.
+    fn range(ptr: Ptr) -> core::ops::Range<usize> {
+        let idx = ptr.get() as usize;
+        idx..idx + SZ
+    }
+
+    /// Grabs a block from a free list.  Returns `None` if free list is empty.
+    fn alloc_from_freelist(&mut self) -> Option<Ptr> {
+        let ptr = self.first_free.take()?;
+        let idx = ptr.get() as usize;
+        let next = self.data[idx..idx + 4].try_into().unwrap();
+        self.first_free = Ptr::new(u32::from_le_bytes(next)).unwrap();
+        Some(ptr)
+    }
+
+    /// Grabs a next available block.  Returns `None` if the file reached its
+    /// maximum size or memory couldn’t be allocated.
+    fn alloc_next_block(&mut self) -> Option<Ptr> {
+        let ptr = Ptr::new(self.next_block).ok().flatten()?;
+        let end = self.next_block.checked_add(SZ as u32)?;
+        if end > Ptr::MAX {
+            return None;
+        }
+        self.data.try_reserve(SZ).ok()?;
+        self.data.resize(end as usize, 0);
+        self.next_block = end;
+        Some(ptr)
+    }
+
+    /// Returns blocks modified since the last commit.
+    pub(crate) fn dirty_blocks(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.dirty.iter().map(|&offset| {
+            let idx = offset as usize;
+            (offset, &self.data[idx..idx + SZ])
+        })
+    }
+}
+
+impl memory::Allocator for Allocator {
+    type Value = [u8; SZ];
+
+    fn alloc(
+        &mut self,
+        value: Self::Value,
+    ) -> Result<Ptr, memory::OutOfMemory> {
+        let ptr = self
+            .alloc_from_freelist()
+            .or_else(|| self.alloc_next_block())
+            .ok_or(memory::OutOfMemory)?;
+        self.set(ptr, value);
+        Ok(ptr)
+    }
+
+    fn get(&self, ptr: Ptr) -> &Self::Value {
+        self.data[Self::range(ptr)].try_into().unwrap()
+    }
+
+    fn get_mut(&mut self, ptr: Ptr) -> &mut Self::Value {
+        self.dirty.insert(ptr.get());
+        (&mut self.data[Self::range(ptr)]).try_into().unwrap()
+    }
+
+    fn free(&mut self, ptr: Ptr) {
+        let next =
+            self.first_free.map_or([0; 4], |ptr| ptr.get().to_le_bytes());
+        self.get_mut(ptr)[..4].copy_from_slice(&next);
+        self.first_free = Some(ptr);
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{self, Seek, Write};
+
+use lib::hash::CryptoHash;
+
+/// Magic number at the start of a journal.  This is a random 64-bit number.
+const MAGIC: [u8; 8] = [0x4f, 0xd0, 0x2b, 0x96, 0xc5, 0x17, 0x7e, 0x3a];
+
+/// Size of the checksum at the end of the journal.
+const CHECKSUM_SIZE: usize = core::mem::size_of::<CryptoHash>();
+
+/// Builder of a write-ahead journal.
+///
+/// The journal lists all writes which constitute a single commit.  It’s
+/// written and synced to disk before the trie file is modified.  If the
+/// process is interrupted while writing to the trie file, the journal is
+/// replayed next time the file is opened.  If it’s interrupted while writing
+/// the journal, the journal fails checksum verification and is discarded
+/// leaving the trie file in its previous state.
+// Encoding:
+//     magic:     [u8; 8]
+//     entries:   repeated
+//         offset: u32
+//         length: u32
+//         data:   [u8; length]
+//     checksum:  [u8; 32] — SHA-256 of all preceding bytes
+pub(crate) struct Journal(Vec<u8>);
+
+impl Journal {
+    pub(crate) fn new() -> Self { Self(MAGIC.to_vec()) }
+
+    /// Adds a write of `data` at given offset in the trie file.
+    pub(crate) fn push(&mut self, offset: u32, data: &[u8]) {
+        let len = u32::try_from(data.len()).unwrap();
+        self.0.extend_from_slice(&offset.to_le_bytes());
+        self.0.extend_from_slice(&len.to_le_bytes());
+        self.0.extend_from_slice(data);
+    }
+
+    /// Appends the checksum and returns encoded journal.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let checksum = CryptoHash::digest(&self.0);
+        self.0.extend_from_slice(checksum.as_slice());
+        self.0
+    }
+}
+
+/// Decodes a journal returning list of writes it describes.
+///
+/// Returns `None` if the journal is incomplete or corrupted.
+pub(crate) fn decode(bytes: &[u8]) -> Option<Vec<(u32, &[u8])>> {
+    let split = bytes.len().checked_sub(CHECKSUM_SIZE)?;
+    let (bytes, checksum) = bytes.split_at(split);
+    if CryptoHash::digest(bytes).as_slice() != checksum {
+        return None;
+    }
+    let mut bytes = bytes.strip_prefix(&MAGIC[..])?;
+    let mut entries = Vec::new();
+    while !bytes.is_empty() {
+        let (offset, rest) = stdx::split_at::<4, u8>(bytes)?;
+        let (len, rest) = stdx::split_at::<4, u8>(rest)?;
+        let len = usize::try_from(u32::from_le_bytes(*len)).ok()?;
+        if len > rest.len() {
+            return None;
+        }
+        let (data, rest) = rest.split_at(len);
+        entries.push((u32::from_le_bytes(*offset), data));
+        bytes = rest;
+    }
+    Some(entries)
+}
+
+/// Performs writes described by journal entries and syncs the file.
+pub(crate) fn apply<'a>(
+    file: &mut File,
+    entries: impl IntoIterator<Item = (u32, &'a [u8])>,
+) -> io::Result<()> {
+    for (offset, data) in entries {
+        file.seek(io::SeekFrom::Start(offset.into()))?;
+        file.write_all(data)?;
+    }
+    file.sync_all()
+}
+
+
+#[test]
+fn test_journal() {
+    let mut journal = Journal::new();
+    journal.push(0, b"foo");
+    journal.push(64, &[]);
+    journal.push(136, b"bar");
+    let bytes = journal.finish();
+    assert_eq!(8 + (8 + 3) + 8 + (8 + 3) + 32, bytes.len());
+
+    let want = [(0, &b"foo"[..]), (64, &[][..]), (136, &b"bar"[..])];
+    assert_eq!(Some(&want[..]), decode(&bytes).as_deref());
+
+    // Torn or corrupted journals are rejected.
+    for len in 0..bytes.len() {
+        assert_eq!(None, decode(&bytes[..len]), "len: {len}");
+    }
+    for idx in 0..bytes.len() {
+        let mut bytes = bytes.clone();
+        bytes[idx] ^= 1;
+        assert_eq!(None, decode(&bytes), "idx: {idx}");
+    }
+
+    assert_eq!(Some(Vec::new()), decode(&Journal::new().finish()));
+}
@@ -0,0 +1,307 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+mod alloc;
+mod header;
+mod journal;
+
+pub use sealable_trie::Trie;
+
+use crate::header::Header;
+
+/// Trie stored in a file.
+///
+/// All nodes are kept in memory with the file used as a persistent copy of
+/// the trie.  Much like with [`memory::WriteLog`], modifications aren’t
+/// written to disk until they’re committed with [`TrieFile::commit`].
+/// Dropping the object discards all uncommitted changes.
+///
+/// Commits are crash-safe.  Modified blocks are first written to a journal
+/// file (the trie file’s path with `.journal` suffix) and only then applied to
+/// the trie file.  If the process is interrupted in the middle of a commit,
+/// next time the file is opened it’s either rolled forward to the state after
+/// the commit or left in the state before it.
+///
+/// Pointers to nodes are byte offsets in the file thus the file is limited to
+/// [`memory::Ptr::MAX`] bytes.  The file must not be opened by multiple
+/// `TrieFile` objects at the same time.
+///
+/// Versions retained in persistent mode (see
+/// [`Trie::set_version_horizon`]) aren’t stored in the file.  Nodes referenced
+/// only by old versions are leaked when the file is reopened thus persistent
+/// mode should be disabled before the last commit.
+#[derive(Debug)]
+pub struct TrieFile {
+    trie: Trie<alloc::Allocator>,
+    file: File,
+    journal: PathBuf,
+}
+
+impl TrieFile {
+    /// Opens a trie stored in given file.
+    ///
+    /// If the file doesn’t exist or is empty, creates a new empty trie.  If
+    /// previous commit has been interrupted, it’s completed or rolled back
+    /// before the file is read.  Returns an error if the file isn’t a valid
+    /// trie file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut journal = path.as_os_str().to_owned();
+        journal.push(".journal");
+        let journal = PathBuf::from(journal);
+        recover(&mut file, &journal)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let hdr = if data.is_empty() {
+            data.resize(Header::ENCODED_SIZE, 0);
+            Header::empty()
+        } else {
+            data.get(..Header::ENCODED_SIZE)
+                .and_then(|hdr| Header::decode(hdr.try_into().unwrap()))
+                .ok_or_else(|| invalid_data("Invalid trie file header"))?
+        };
+        let alloc = alloc::Allocator::new(data, &hdr)
+            .ok_or_else(|| invalid_data("Corrupted trie file"))?;
+        let trie = Trie::from_parts(alloc, hdr.root_ptr, hdr.root_hash);
+        Ok(Self { trie, file, journal })
+    }
+
+    /// Writes all changes made since the last commit to the file.
+    ///
+    /// On failure, changes are kept in memory and the commit can be retried.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let entries = self.write_journal()?;
+        journal::apply(&mut self.file, journal::decode(&entries).unwrap())?;
+        remove_journal(&self.journal)?;
+        self.trie.allocator_mut().dirty.clear();
+        Ok(())
+    }
+
+    /// Writes journal describing changes since the last commit and syncs it to
+    /// disk.  Returns encoded journal.
+    ///
+    /// The directory containing the journal is synced as well so that the
+    /// journal’s directory entry is durable before the trie file is modified.
+    /// Otherwise, after a crash the journal could be missing while the trie
+    /// file is partially overwritten.
+    fn write_journal(&mut self) -> io::Result<Vec<u8>> {
+        let root_ptr = self.trie.root_ptr();
+        let root_hash = self.trie.hash().clone();
+        let alloc = self.trie.allocator_mut();
+        let hdr = Header {
+            root_ptr,
+            root_hash,
+            next_block: alloc.next_block,
+            first_free: alloc.first_free.map_or(0, |ptr| ptr.get()),
+        };
+
+        let mut journal = journal::Journal::new();
+        journal.push(0, &hdr.encode());
+        for (offset, block) in alloc.dirty_blocks() {
+            journal.push(offset, block);
+        }
+        let journal = journal.finish();
+
+        let mut file = File::create(&self.journal)?;
+        file.write_all(&journal)?;
+        file.sync_all()?;
+        sync_parent_dir(&self.journal)?;
+        Ok(journal)
+    }
+}
+
+/// Completes or rolls back an interrupted commit.
+///
+/// If a valid journal exists, applies it to the trie file.  Incomplete journal
+/// means that the trie file hasn’t been modified yet and the journal is simply
+/// discarded.
+fn recover(file: &mut File, journal: &Path) -> io::Result<()> {
+    let bytes = match std::fs::read(journal) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if let Some(entries) = journal::decode(&bytes) {
+        journal::apply(file, entries)?;
+    }
+    remove_journal(journal)?;
+    io::Seek::rewind(file)
+}
+
+/// Removes the journal file and syncs the directory containing it.
+///
+/// Syncing makes sure the removal is durable so that a stale journal isn’t
+/// applied again on top of a later commit’s changes after a crash.
+fn remove_journal(journal: &Path) -> io::Result<()> {
+    std::fs::remove_file(journal)?;
+    sync_parent_dir(journal)
+}
+
+/// Syncs directory containing given file so that changes to the directory
+/// entry (creation or removal of the file) are persisted.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Syncs directory containing given file.
+///
+/// Directories can’t be opened as files on non-Unix platforms thus this is
+/// a no-op.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> { Ok(()) }
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl core::ops::Deref for TrieFile {
+    type Target = Trie<alloc::Allocator>;
+    fn deref(&self) -> &Self::Target { &self.trie }
+}
+
+impl core::ops::DerefMut for TrieFile {
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut self.trie }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use lib::hash::CryptoHash;
+
+    use super::*;
+
+    /// Temporary trie file removed when dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let name = format!("file-trie-{}-{name}", std::process::id());
+            let path = Self(std::env::temp_dir().join(name));
+            path.remove();
+            path
+        }
+
+        fn open(&self) -> TrieFile { TrieFile::open(&self.0).unwrap() }
+
+        fn remove(&self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(self.journal());
+        }
+
+        fn journal(&self) -> PathBuf {
+            let mut journal = self.0.as_os_str().to_owned();
+            journal.push(".journal");
+            journal.into()
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) { self.remove() }
+    }
+
+    fn value(num: u8) -> CryptoHash { CryptoHash([num; 32]) }
+
+    #[track_caller]
+    fn check(trie: &TrieFile, want: &[(&[u8], Option<u8>)]) {
+        for (key, want) in want {
+            assert_eq!(Ok(want.map(value)), trie.get(key), "{key:?}");
+        }
+        let report = trie.verify_integrity();
+        assert!(report.is_healthy(), "Trie is corrupted: {report:?}");
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path = TempPath::new("persistence");
+        let mut trie = path.open();
+        assert!(trie.is_empty());
+        trie.set(b"foo", &value(1)).unwrap();
+        trie.set(b"bar", &value(2)).unwrap();
+        trie.commit().unwrap();
+        let hash = trie.hash().clone();
+        core::mem::drop(trie);
+
+        let mut trie = path.open();
+        assert_eq!(&hash, trie.hash());
+        check(&trie, &[(b"foo", Some(1)), (b"bar", Some(2))]);
+
+        // Freed blocks are reused after reopening.
+        trie.del(b"foo").unwrap();
+        trie.set(b"bar", &value(3)).unwrap();
+        trie.commit().unwrap();
+        let size = std::fs::metadata(&path.0).unwrap().len();
+        core::mem::drop(trie);
+
+        let mut trie = path.open();
+        check(&trie, &[(b"foo", None), (b"bar", Some(3))]);
+        trie.set(b"foo", &value(4)).unwrap();
+        trie.commit().unwrap();
+        assert_eq!(size, std::fs::metadata(&path.0).unwrap().len());
+        core::mem::drop(trie);
+        check(&path.open(), &[(b"foo", Some(4)), (b"bar", Some(3))]);
+    }
+
+    #[test]
+    fn test_uncommitted_changes_are_discarded() {
+        let path = TempPath::new("uncommitted");
+        let mut trie = path.open();
+        trie.set(b"foo", &value(1)).unwrap();
+        core::mem::drop(trie);
+        assert!(path.open().is_empty());
+
+        let mut trie = path.open();
+        trie.set(b"foo", &value(1)).unwrap();
+        trie.commit().unwrap();
+        trie.set(b"foo", &value(2)).unwrap();
+        trie.set(b"bar", &value(3)).unwrap();
+        core::mem::drop(trie);
+        check(&path.open(), &[(b"foo", Some(1)), (b"bar", None)]);
+    }
+
+    #[test]
+    fn test_interrupted_commit() {
+        let path = TempPath::new("interrupted");
+        let mut trie = path.open();
+        trie.set(b"foo", &value(1)).unwrap();
+        trie.commit().unwrap();
+
+        // Journal written but not applied.  Commit is completed on open.
+        trie.set(b"foo", &value(2)).unwrap();
+        trie.set(b"bar", &value(3)).unwrap();
+        let journal = trie.write_journal().unwrap();
+        core::mem::drop(trie);
+        check(&path.open(), &[(b"foo", Some(2)), (b"bar", Some(3))]);
+        assert!(!path.journal().exists());
+
+        // Torn journal.  Commit is rolled back on open.
+        let mut trie = path.open();
+        trie.set(b"foo", &value(4)).unwrap();
+        trie.write_journal().unwrap();
+        core::mem::drop(trie);
+        std::fs::write(path.journal(), &journal[..journal.len() - 1]).unwrap();
+        check(&path.open(), &[(b"foo", Some(2)), (b"bar", Some(3))]);
+        assert!(!path.journal().exists());
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        let path = TempPath::new("invalid");
+        std::fs::write(&path.0, b"foo").unwrap();
+        TrieFile::open(&path.0).unwrap_err();
+        std::fs::write(&path.0, [0; 64]).unwrap();
+        TrieFile::open(&path.0).unwrap_err();
+    }
+}
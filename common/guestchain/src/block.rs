@@ -1,14 +1,16 @@
 use core::num::NonZeroU64;
 
 use borsh::maybestd::io;
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, HashVersion};
 
 type Result<T, E = borsh::maybestd::io::Error> = core::result::Result<T, E>;
 
 /// A single block header of the guest blockchain.
 ///
 /// Guest blocks are uniquely identified by their hash which can be calculated
-/// using the [`BlockHeader::calc_hash`] method.
+/// using the [`BlockHeader::calc_hash`] method.  The hash depends on the
+/// hashing scheme the chain has been configured with (see
+/// [`crate::Config::hash_version`]).
 ///
 /// Each block belongs to an epoch (identifier by `epoch_id`) which describes
 /// set of validators which can sign the block.  A new epoch is introduced by
@@ -100,11 +102,35 @@ impl<PK: borsh::BorshSerialize> borsh::BorshSerialize for Block<PK> {
     }
 }
 
+/// Deserialises the block calculating next epoch commitment with
+/// [`HashVersion::V0`] scheme.
+///
+/// Use [`Block::deserialize_reader_with`] for chains which use a different
+/// hashing scheme.
 impl<PK> borsh::BorshDeserialize for Block<PK>
 where
     PK: borsh::BorshSerialize + borsh::BorshDeserialize,
 {
     fn deserialize_reader<R: io::Read>(reader: &mut R) -> io::Result<Self> {
+        Self::deserialize_reader_with(HashVersion::V0, reader)
+    }
+}
+
+impl<PK> Block<PK>
+where
+    PK: borsh::BorshSerialize + borsh::BorshDeserialize,
+{
+    /// Deserialises the block calculating next epoch commitment with given
+    /// hashing scheme.
+    ///
+    /// Next epoch commitment isn’t serialised.  Instead, it’s calculated from
+    /// the next epoch which is why the hashing scheme needs to be known.
+    pub fn deserialize_reader_with<R: io::Read>(
+        hash_version: HashVersion,
+        reader: &mut R,
+    ) -> io::Result<Self> {
+        use borsh::BorshDeserialize;
+
         let version = crate::common::VersionZero::deserialize_reader(reader)?;
         let prev_block_hash = CryptoHash::deserialize_reader(reader)?;
         let block_height = crate::BlockHeight::deserialize_reader(reader)?;
@@ -114,8 +140,9 @@ where
         let epoch_id = CryptoHash::deserialize_reader(reader)?;
         let next_epoch =
             Option::<crate::Epoch<PK>>::deserialize_reader(reader)?;
-        let next_epoch_commitment =
-            next_epoch.as_ref().map(crate::Epoch::calc_commitment);
+        let next_epoch_commitment = next_epoch
+            .as_ref()
+            .map(|epoch| epoch.calc_commitment(hash_version));
         Ok(Self {
             header: BlockHeader {
                 version,
@@ -141,6 +168,10 @@ where
 /// Inclusion of the genesis hash allows signatures made for different chains to
 /// be distinguished from each other so a validator can use the same key for
 /// multiple chains without risking being accused of misbehaviour.
+///
+/// Both hashes are calculated with the chain’s hashing scheme (see
+/// [`crate::Config::hash_version`]) so the fingerprint of a block differs
+/// between the schemes.
 #[derive(
     Clone,
     PartialEq,
@@ -191,9 +222,9 @@ impl BlockHeader {
             self.epoch_id == CryptoHash::DEFAULT
     }
 
-    /// Calculates hash of the block.
-    pub fn calc_hash(&self) -> CryptoHash {
-        let mut builder = CryptoHash::builder();
+    /// Calculates hash of the block using given hashing scheme.
+    pub fn calc_hash(&self, version: HashVersion) -> CryptoHash {
+        let mut builder = version.builder::<lib::hash::domain::BlockHash>();
         borsh::to_writer(&mut builder, self).unwrap();
         builder.build()
     }
@@ -202,9 +233,12 @@ impl BlockHeader {
     ///
     /// Returns a new block with `self` as the previous block.  Verifies that
     /// `host_height` and `timestamp_ns` are strictly increasing.  The new block
-    /// will have `block_height` incremented by one.
+    /// will have `block_height` incremented by one.  `version` specifies the
+    /// hashing scheme used to calculate previous block hash and next epoch
+    /// commitment.
     pub fn generate_next<PK: crate::PubKey>(
         &self,
+        version: HashVersion,
         host_height: crate::HostHeight,
         timestamp_ns: NonZeroU64,
         state_root: CryptoHash,
@@ -216,7 +250,7 @@ impl BlockHeader {
             return Err(GenerateError::BadHostTimestamp);
         }
 
-        let prev_block_hash = self.calc_hash();
+        let prev_block_hash = self.calc_hash(version);
         // If self defines a new epoch than the new block starts a new epoch
         // with epoch id equal to self’s block hash.  Otherwise, epoch doesn’t
         // change and the new block uses the same epoch id as self.
@@ -225,7 +259,7 @@ impl BlockHeader {
             true => prev_block_hash.clone(),
         };
         let next_epoch_commitment =
-            next_epoch.as_ref().map(crate::Epoch::calc_commitment);
+            next_epoch.as_ref().map(|epoch| epoch.calc_commitment(version));
         Ok(Block {
             header: Self {
                 version: crate::common::VersionZero,
@@ -246,8 +280,10 @@ impl<PK: crate::PubKey> Block<PK> {
     /// Constructs a new genesis block.
     ///
     /// A genesis block is identified by previous block hash and epoch id both
-    /// being all-zero hash.
+    /// being all-zero hash.  `version` specifies the hashing scheme used to
+    /// calculate next epoch commitment.
     pub fn generate_genesis(
+        version: HashVersion,
         block_height: crate::BlockHeight,
         host_height: crate::HostHeight,
         timestamp_ns: NonZeroU64,
@@ -260,7 +296,7 @@ impl<PK: crate::PubKey> Block<PK> {
                 host_height,
                 timestamp_ns,
                 state_root,
-                next_epoch.calc_commitment(),
+                next_epoch.calc_commitment(version),
             ),
             next_epoch: Some(next_epoch),
         })
@@ -272,9 +308,15 @@ impl Default for Fingerprint {
 }
 
 impl Fingerprint {
-    /// Calculates the fingerprint of the given block.
-    pub fn new(genesis_hash: &CryptoHash, header: &BlockHeader) -> Self {
-        Self::from_hash(genesis_hash, header.block_height, &header.calc_hash())
+    /// Calculates the fingerprint of the given block using given hashing
+    /// scheme.
+    pub fn new(
+        version: HashVersion,
+        genesis_hash: &CryptoHash,
+        header: &BlockHeader,
+    ) -> Self {
+        let hash = header.calc_hash(version);
+        Self::from_hash(genesis_hash, header.block_height, &hash)
     }
 
     /// Constructs the fingerprint of a block at given height and with given
@@ -343,6 +385,7 @@ fn test_block_generation() {
     let genesis_hash = CryptoHash::from_base64(genesis_hash).unwrap();
 
     let genesis = Block::generate_genesis(
+        HashVersion::V0,
         crate::BlockHeight::from(0),
         crate::HostHeight::from(42),
         NonZeroU64::new(24).unwrap(),
@@ -361,13 +404,14 @@ fn test_block_generation() {
     block.epoch_id = genesis_hash.clone();
     assert!(!block.is_genesis());
 
-    assert_eq!(genesis_hash, genesis.calc_hash());
-    assert_ne!(genesis_hash, block.calc_hash());
+    assert_eq!(genesis_hash, genesis.calc_hash(HashVersion::V0));
+    assert_ne!(genesis_hash, block.calc_hash(HashVersion::V0));
 
     // Try creating invalid next block.
     assert_eq!(
         Err(GenerateError::BadHostHeight),
         genesis.generate_next::<MockPubKey>(
+            HashVersion::V0,
             crate::HostHeight::from(42),
             NonZeroU64::new(100).unwrap(),
             CryptoHash::test(99),
@@ -377,6 +421,7 @@ fn test_block_generation() {
     assert_eq!(
         Err(GenerateError::BadHostTimestamp),
         genesis.generate_next::<MockPubKey>(
+            HashVersion::V0,
             crate::HostHeight::from(43),
             NonZeroU64::new(23).unwrap(),
             CryptoHash::test(99),
@@ -387,6 +432,7 @@ fn test_block_generation() {
     // Create next block and test its behaviour.
     let block = genesis
         .generate_next::<MockPubKey>(
+            HashVersion::V0,
             crate::HostHeight::from(50),
             NonZeroU64::new(50).unwrap(),
             CryptoHash::test(99),
@@ -399,12 +445,13 @@ fn test_block_generation() {
     assert_eq!(genesis_hash, block.epoch_id);
     let hash = "TCsK6A4O0a82t3z+gdev+Fn0RUWSyeCMp3Y14rrlwcg=";
     let hash = CryptoHash::from_base64(hash).unwrap();
-    assert_eq!(hash, block.calc_hash());
+    assert_eq!(hash, block.calc_hash(HashVersion::V0));
 
     // Create next block within and introduce a new epoch.
     let epoch = Some(crate::Epoch::test(&[(0, 20), (1, 10)]));
     let block = block
         .generate_next::<MockPubKey>(
+            HashVersion::V0,
             crate::HostHeight::from(60),
             NonZeroU64::new(60).unwrap(),
             CryptoHash::test(99),
@@ -415,11 +462,12 @@ fn test_block_generation() {
     assert_eq!(genesis_hash, block.epoch_id);
     let hash = "CD8+vN9X4orTzC9w3F3JNGEwdcigW0nS+Mh9Zcd70Ok=";
     let hash = CryptoHash::from_base64(hash).unwrap();
-    assert_eq!(hash, block.calc_hash());
+    assert_eq!(hash, block.calc_hash(HashVersion::V0));
 
     // Create next block which belongs to the new epoch.
     let block = block
         .generate_next::<MockPubKey>(
+            HashVersion::V0,
             crate::HostHeight::from(65),
             NonZeroU64::new(65).unwrap(),
             CryptoHash::test(99),
@@ -430,6 +478,75 @@ fn test_block_generation() {
     assert_eq!(hash, block.epoch_id);
 }
 
+#[test]
+fn test_hash_versions() {
+    use crate::validators::MockPubKey;
+
+    let epoch = crate::Epoch::test(&[(0, 10), (1, 10)]);
+    let genesis = |version| {
+        Block::generate_genesis(
+            version,
+            crate::BlockHeight::from(0),
+            crate::HostHeight::from(42),
+            NonZeroU64::new(24).unwrap(),
+            CryptoHash::test(66),
+            epoch.clone(),
+        )
+        .unwrap()
+    };
+
+    // V0 hashes serialised objects without domain separation.
+    let block = genesis(HashVersion::V0);
+    let serialised = borsh::to_vec(&block.header).unwrap();
+    let v0_hash = CryptoHash::digest(&serialised);
+    assert_eq!(v0_hash, block.calc_hash(HashVersion::V0));
+    assert_eq!(
+        Some(CryptoHash::digest(&borsh::to_vec(&epoch).unwrap())),
+        block.next_epoch_commitment
+    );
+
+    // V1 mixes in the domain tag.
+    let block = genesis(HashVersion::V1);
+    assert_eq!(
+        Some(epoch.calc_commitment(HashVersion::V1)),
+        block.next_epoch_commitment
+    );
+    let mut tagged = b"\x15guestchain/block-hash".to_vec();
+    tagged.extend_from_slice(&borsh::to_vec(&block.header).unwrap());
+    let v1_hash = CryptoHash::digest(&tagged);
+    assert_eq!(v1_hash, block.calc_hash(HashVersion::V1));
+    assert_ne!(v0_hash, v1_hash);
+
+    let next = block
+        .generate_next::<MockPubKey>(
+            HashVersion::V1,
+            crate::HostHeight::from(50),
+            NonZeroU64::new(50).unwrap(),
+            CryptoHash::test(99),
+            Some(epoch.clone()),
+        )
+        .unwrap();
+    assert_eq!(v1_hash, next.prev_block_hash);
+    assert_eq!(v1_hash, next.epoch_id);
+    assert_eq!(
+        Some(epoch.calc_commitment(HashVersion::V1)),
+        next.next_epoch_commitment
+    );
+
+    // Deserialisation recalculates commitment with the given scheme.
+    let serialised = borsh::to_vec(&next).unwrap();
+    let got = Block::<MockPubKey>::deserialize_reader_with(
+        HashVersion::V1,
+        &mut serialised.as_slice(),
+    );
+    assert_eq!(next, got.unwrap());
+
+    let genesis_hash = CryptoHash::test(1);
+    let fingerprint = Fingerprint::new(HashVersion::V1, &genesis_hash, &next);
+    let hash = next.calc_hash(HashVersion::V1);
+    assert_eq!((&genesis_hash, next.block_height, &hash), fingerprint.parse());
+}
+
 #[test]
 fn test_signatures() {
     use crate::validators::{MockPubKey, MockSignature, MockSigner};
@@ -483,6 +600,7 @@ fn test_borsh() {
     }
 
     let genesis = Block::generate_genesis(
+        HashVersion::V0,
         crate::BlockHeight::from(0),
         crate::HostHeight::from(42),
         NonZeroU64::new(24).unwrap(),
@@ -496,6 +614,7 @@ fn test_borsh() {
 
     let block = genesis
         .generate_next::<MockPubKey>(
+            HashVersion::V0,
             crate::HostHeight::from(50),
             NonZeroU64::new(50).unwrap(),
             CryptoHash::test(99),
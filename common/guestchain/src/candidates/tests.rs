@@ -70,6 +70,7 @@ impl From<Cfg> for crate::Config {
             min_quorum_stake: NonZeroU128::MIN,
             min_block_length: crate::height::HostDelta::from(1),
             min_epoch_length: crate::height::HostDelta::from(1),
+            hash_version: Default::default(),
         }
     }
 }
@@ -1,10 +1,14 @@
 use core::num::{NonZeroU128, NonZeroU16};
 
+use borsh::maybestd::io;
+use borsh::{BorshDeserialize, BorshSerialize};
+use lib::hash::HashVersion;
+
 /// Chain policies configuration.
 ///
-/// Those are not encoded within a blockchain and only matter when generating
-/// a new block.
-#[derive(Clone, Debug, borsh::BorshSerialize, borsh::BorshDeserialize)]
+/// Those are not encoded within a blockchain and, with the exception of
+/// `hash_version`, only matter when generating a new block.
+#[derive(Clone, Debug)]
 pub struct Config {
     /// Minimum number of validators allowed in an epoch.
     ///
@@ -71,4 +75,63 @@ pub struct Config {
     /// catch up verification by only having to verify blocks at end of each
     /// epoch.
     pub min_epoch_length: crate::height::HostDelta,
+
+    /// Hashing scheme used for block hashes, epoch commitments and
+    /// fingerprints.
+    ///
+    /// This is fixed at genesis since changing it would change hashes of all
+    /// the blocks.  Existing chains use [`lib::hash::HashVersion::V0`] which
+    /// is the default; new chains should use
+    /// [`lib::hash::HashVersion::LATEST`].  Light clients need to be
+    /// configured with the same version to verify blocks of the chain.
+    pub hash_version: HashVersion,
+}
+
+// Encoding: <0u16> <hash_version> <legacy>  if hash_version != V0
+//           <legacy>                        otherwise
+//
+// where <legacy> is borsh encoding of all the fields other than hash_version
+// in order of declaration.  Configurations using V0 keep the encoding used
+// before hashing scheme became configurable so that existing chains can still
+// be decoded.  Legacy encoding starts with non-zero min_validators thus the
+// zero marker makes the two unambiguous.
+impl BorshSerialize for Config {
+    fn serialize<W: io::Write>(&self, wr: &mut W) -> io::Result<()> {
+        if self.hash_version != HashVersion::V0 {
+            0u16.serialize(wr)?;
+            self.hash_version.serialize(wr)?;
+        }
+        self.min_validators.serialize(wr)?;
+        self.max_validators.serialize(wr)?;
+        self.min_validator_stake.serialize(wr)?;
+        self.min_total_stake.serialize(wr)?;
+        self.min_quorum_stake.serialize(wr)?;
+        self.min_block_length.serialize(wr)?;
+        self.min_epoch_length.serialize(wr)
+    }
+}
+
+impl BorshDeserialize for Config {
+    fn deserialize_reader<R: io::Read>(rd: &mut R) -> io::Result<Self> {
+        let mut hash_version = HashVersion::V0;
+        let mut min_validators = u16::deserialize_reader(rd)?;
+        if min_validators == 0 {
+            hash_version = HashVersion::deserialize_reader(rd)?;
+            if hash_version == HashVersion::V0 {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            min_validators = u16::deserialize_reader(rd)?;
+        }
+        Ok(Self {
+            min_validators: NonZeroU16::new(min_validators)
+                .ok_or(io::ErrorKind::InvalidData)?,
+            max_validators: <_>::deserialize_reader(rd)?,
+            min_validator_stake: <_>::deserialize_reader(rd)?,
+            min_total_stake: <_>::deserialize_reader(rd)?,
+            min_quorum_stake: <_>::deserialize_reader(rd)?,
+            min_block_length: <_>::deserialize_reader(rd)?,
+            min_epoch_length: <_>::deserialize_reader(rd)?,
+            hash_version,
+        })
+    }
 }
@@ -2,7 +2,7 @@ use alloc::vec::Vec;
 use core::num::NonZeroU128;
 
 use borsh::maybestd::io;
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, HashVersion};
 
 /// An epoch describing configuration applying to all blocks within an epoch.
 ///
@@ -80,12 +80,14 @@ impl<PK> Epoch<PK> {
         }
     }
 
-    /// Calculates commitment (i.e. hash) of the epoch.
-    pub fn calc_commitment(&self) -> CryptoHash
+    /// Calculates commitment (i.e. hash) of the epoch using given hashing
+    /// scheme.
+    pub fn calc_commitment(&self, version: HashVersion) -> CryptoHash
     where
         PK: borsh::BorshSerialize,
     {
-        let mut builder = CryptoHash::builder();
+        let mut builder =
+            version.builder::<lib::hash::domain::EpochCommitment>();
         borsh::to_writer(&mut builder, self).unwrap();
         builder.build()
     }
@@ -170,6 +172,18 @@ fn test_borsh_success() {
 
     let got = borsh::BorshDeserialize::try_from_slice(encoded.as_slice());
     assert_eq!(epoch, got.unwrap());
+
+    // V0 commitment is the untagged hash of the serialised epoch.
+    assert_eq!(
+        CryptoHash::digest(&encoded),
+        epoch.calc_commitment(HashVersion::V0)
+    );
+    let mut tagged = b"\x1bguestchain/epoch-commitment".to_vec();
+    tagged.extend_from_slice(&encoded);
+    assert_eq!(
+        CryptoHash::digest(&tagged),
+        epoch.calc_commitment(HashVersion::V1)
+    );
 }
 
 #[test]
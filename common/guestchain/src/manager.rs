@@ -4,13 +4,14 @@ use core::num::{NonZeroU128, NonZeroU64};
 #[cfg(feature = "std")]
 use std::collections::HashSet as Set;
 
-use lib::hash::CryptoHash;
+use borsh::maybestd::io;
+use lib::hash::{CryptoHash, HashVersion};
 
 use crate::candidates::Candidate;
 pub use crate::candidates::UpdateCandidateError;
 use crate::Validator;
 
-#[derive(Clone, Debug, borsh::BorshSerialize, borsh::BorshDeserialize)]
+#[derive(Clone, Debug, borsh::BorshSerialize)]
 pub struct ChainManager<PK> {
     /// Configuration specifying limits for block generation.
     config: crate::Config,
@@ -38,7 +39,7 @@ pub struct ChainManager<PK> {
 /// Pending block waiting for signatures.
 ///
 /// Once quorum of validators sign the block it’s promoted to the current block.
-#[derive(Clone, Debug, borsh::BorshSerialize, borsh::BorshDeserialize)]
+#[derive(Clone, Debug, borsh::BorshSerialize)]
 pub struct PendingBlock<PK> {
     /// The block that waits for signatures.
     next_block: crate::Block<PK>,
@@ -57,6 +58,56 @@ pub struct PendingBlock<PK> {
     signing_stake: u128,
 }
 
+/// Deserialises the manager.
+///
+/// Pending block doesn’t store its next epoch commitment (see
+/// [`crate::Block::deserialize_reader_with`]) so it’s calculated with hashing
+/// scheme from the deserialised configuration.
+impl<PK: crate::PubKey> borsh::BorshDeserialize for ChainManager<PK> {
+    fn deserialize_reader<R: io::Read>(reader: &mut R) -> io::Result<Self> {
+        let config = crate::Config::deserialize_reader(reader)?;
+        let genesis = <_>::deserialize_reader(reader)?;
+        let header = <_>::deserialize_reader(reader)?;
+        let next_epoch = <_>::deserialize_reader(reader)?;
+        let pending_block = match u8::deserialize_reader(reader)? {
+            0 => None,
+            1 => Some(PendingBlock::deserialize_reader_with(
+                config.hash_version,
+                reader,
+            )?),
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        };
+        let epoch_height = <_>::deserialize_reader(reader)?;
+        let candidates = <_>::deserialize_reader(reader)?;
+        Ok(Self {
+            config,
+            genesis,
+            header,
+            next_epoch,
+            pending_block,
+            epoch_height,
+            candidates,
+        })
+    }
+}
+
+impl<PK: crate::PubKey> PendingBlock<PK> {
+    /// Deserialises the pending block calculating its next epoch commitment
+    /// with given hashing scheme.
+    fn deserialize_reader_with<R: io::Read>(
+        version: HashVersion,
+        reader: &mut R,
+    ) -> io::Result<Self> {
+        use borsh::BorshDeserialize;
+        Ok(Self {
+            next_block: crate::Block::deserialize_reader_with(version, reader)?,
+            fingerprint: <_>::deserialize_reader(reader)?,
+            signers: <_>::deserialize_reader(reader)?,
+            signing_stake: <_>::deserialize_reader(reader)?,
+        })
+    }
+}
+
 /// Provided genesis block is invalid.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BadGenesis;
@@ -121,8 +172,8 @@ impl<PK: crate::PubKey> ChainManager<PK> {
             next_epoch.validators(),
         );
         Ok(Self {
+            genesis: header.calc_hash(config.hash_version),
             config,
-            genesis: header.calc_hash(),
             next_epoch,
             pending_block: None,
             epoch_height: header.host_height,
@@ -185,14 +236,16 @@ impl<PK: crate::PubKey> ChainManager<PK> {
         }
 
         let epoch_ends = self.header.next_epoch_commitment.is_some();
+        let version = self.config.hash_version;
         let next_block = self.header.generate_next(
+            version,
             host_height,
             host_timestamp,
             state_root,
             next_epoch,
         )?;
         let fingerprint =
-            crate::block::Fingerprint::new(&self.genesis, &next_block);
+            crate::block::Fingerprint::new(version, &self.genesis, &next_block);
         self.pending_block = Some(PendingBlock {
             fingerprint,
             next_block,
@@ -301,10 +354,19 @@ impl<PK: crate::PubKey> ChainManager<PK> {
     pub fn epoch_height(&self) -> crate::HostHeight { self.epoch_height }
 
     pub fn genesis(&self) -> &CryptoHash { &self.genesis }
+
+    /// Returns hashing scheme used by the chain.
+    pub fn hash_version(&self) -> HashVersion { self.config.hash_version }
 }
 
 #[test]
 fn test_generate() {
+    check_generate(HashVersion::V0);
+    check_generate(HashVersion::V1);
+}
+
+#[cfg(test)]
+fn check_generate(hash_version: HashVersion) {
     use crate::validators::MockPubKey;
 
     let epoch = crate::Epoch::test(&[(1, 2), (2, 2), (3, 2)]);
@@ -315,6 +377,7 @@ fn test_generate() {
     let eve = epoch.validators()[2].clone();
 
     let genesis = crate::Block::generate_genesis(
+        hash_version,
         1.into(),
         1.into(),
         NonZeroU64::MIN,
@@ -330,8 +393,11 @@ fn test_generate() {
         min_quorum_stake: core::num::NonZeroU128::MIN,
         min_block_length: 4.into(),
         min_epoch_length: 8.into(),
+        hash_version,
     };
+    let genesis_hash = genesis.calc_hash(hash_version);
     let mut mgr = ChainManager::new(config, genesis).unwrap();
+    assert_eq!(&genesis_hash, mgr.genesis());
 
     let one = NonZeroU64::new(1).unwrap();
     let two = NonZeroU64::new(2).unwrap();
@@ -364,9 +430,12 @@ fn test_generate() {
         mgr: &mut ChainManager<MockPubKey>,
         validator: &crate::validators::Validator<MockPubKey>,
     ) -> Result<AddSignatureEffect, AddSignatureError> {
-        let signature =
-            crate::block::Fingerprint::new(&mgr.genesis, mgr.head().1)
-                .sign(&validator.pubkey().make_signer());
+        let fingerprint = crate::block::Fingerprint::new(
+            mgr.hash_version(),
+            &mgr.genesis,
+            mgr.head().1,
+        );
+        let signature = fingerprint.sign(&validator.pubkey().make_signer());
         mgr.add_signature(validator.pubkey().clone(), &signature, &())
     }
 
@@ -389,8 +458,12 @@ fn test_generate() {
 
     // Signatures are verified
     let pubkey = MockPubKey(42);
-    let signature = crate::block::Fingerprint::new(&mgr.genesis, mgr.head().1)
-        .sign(&pubkey.make_signer());
+    let signature = crate::block::Fingerprint::new(
+        hash_version,
+        &mgr.genesis,
+        mgr.head().1,
+    )
+    .sign(&pubkey.make_signer());
     assert_eq!(
         Err(AddSignatureError::BadValidator),
         mgr.add_signature(pubkey, &signature, &())
@@ -420,6 +493,22 @@ fn test_generate() {
     );
     mgr.update_candidate(*eve.pubkey(), 1).unwrap();
     mgr.generate_next(15.into(), four, CryptoHash::test(2), false).unwrap();
+
+    // Deserialisation recalculates pending block’s next epoch commitment with
+    // the configured hashing scheme.
+    let commitment = mgr.head().1.next_epoch_commitment.clone();
+    let epoch =
+        mgr.pending_block.as_ref().unwrap().next_block.next_epoch.as_ref();
+    assert_eq!(
+        commitment,
+        epoch.map(|epoch| epoch.calc_commitment(hash_version))
+    );
+    let serialised = borsh::to_vec(&mgr).unwrap();
+    let mut mgr: ChainManager<MockPubKey> =
+        borsh::BorshDeserialize::try_from_slice(&serialised).unwrap();
+    assert_eq!(commitment, mgr.head().1.next_epoch_commitment);
+    assert_eq!(serialised, borsh::to_vec(&mgr).unwrap());
+
     assert_eq!(Ok(AddSignatureEffect::NoQuorumYet), sign_head(&mut mgr, &ali));
     assert_eq!(Ok(AddSignatureEffect::GotQuorum), sign_head(&mut mgr, &bob));
 
@@ -444,3 +533,50 @@ fn test_generate() {
     mgr.update_candidate(*eve.pubkey(), 0).unwrap();
     mgr.generate_next(40.into(), six, CryptoHash::test(2), false).unwrap();
 }
+
+#[test]
+fn test_decode_legacy() {
+    use crate::validators::MockPubKey;
+
+    // Manager serialised before hashing scheme became configurable.  It has
+    // a pending block which starts a new epoch.
+    let bytes = hex_literal::hex!(
+        "0100030001000000000000000000000000000000010000000000000000000000 \
+         0000000001000000000000000000000000000000040000000000000008000000 \
+         00000000d13290adcf66c05a7786476ff9d8f4660f9d003995670793f85f8a43 \
+         471b262600000000000000000000000000000000000000000000000000000000 \
+         0000000000010000000000000001000000000000000100000000000000000000 \
+         0100000001000000010000000100000001000000010000000100000001000000 \
+         000000000000000000000000000000000000000000000000000000000001b8bd \
+         23d28303af4e8c1e395c4d8abd9433e40fbb33b1ac47db0e72433a41dde60003 \
+         0000000001000000020000000000000000000000000000000002000000020000 \
+         0000000000000000000000000000030000000200000000000000000000000000 \
+         0000040000000000000000000000000000000100d13290adcf66c05a7786476f \
+         f9d8f4660f9d003995670793f85f8a43471b262602000000000000000a000000 \
+         0000000002000000000000000000000200000002000000020000000200000002 \
+         000000020000000200000002d13290adcf66c05a7786476ff9d8f4660f9d0039 \
+         95670793f85f8a43471b26260100030000000001000000020000000000000000 \
+         0000000000000000020000000200000000000000000000000000000000030000 \
+         0001000000000000000000000000000000030000000000000000000000000000 \
+         00d13290adcf66c05a7786476ff9d8f4660f9d003995670793f85f8a43471b26 \
+         260200000000000000e6dcbd2bb6e7e7a73cbb38743e928ef780734d675ebdd3 \
+         260213fab4a33aebb20000000000000000000000000000000000000000010000 \
+         0000000000030003000000010000000200000000000000000000000000000002 \
+         0000000200000000000000000000000000000003000000010000000000000000 \
+         000000000000000005000000000000000000000000000000"
+    );
+    let mgr: ChainManager<MockPubKey> =
+        borsh::BorshDeserialize::try_from_slice(&bytes).unwrap();
+    assert_eq!(HashVersion::V0, mgr.hash_version());
+    let head = mgr.head().1;
+    assert_eq!(
+        CryptoHash::from_base64("5ty9K7bn56c8uzh0PpKO94BzTWdevdMmAhP6tKM667I=")
+            .unwrap(),
+        head.calc_hash(HashVersion::V0)
+    );
+    assert_eq!(
+        CryptoHash::from_base64("WHws2sQyV05vRJZFMAxuuPwYw/MP6T4mJLfitZaBrWE="),
+        head.next_epoch_commitment
+    );
+    assert_eq!(&bytes[..], borsh::to_vec(&mgr).unwrap().as_slice());
+}
@@ -4,6 +4,12 @@ use base64::Engine;
 use borsh::maybestd::io;
 use bytemuck::TransparentWrapper;
 
+pub mod domain;
+mod encoding;
+
+pub use domain::{Domain, HashVersion};
+pub use encoding::{Base58, Base64, Hex, ParseError};
+
 /// A cryptographic hash.
 #[derive(
     Clone,
@@ -25,10 +31,6 @@ use bytemuck::TransparentWrapper;
 #[repr(transparent)]
 pub struct CryptoHash(pub [u8; CryptoHash::LENGTH]);
 
-// TODO(mina86): Make the code generic such that CryptoHash::digest take generic
-// argument for the hash to use.  This would then mean that Trie, Proof and
-// other objects which need to calculate hashes would need to take that argument
-// as well.
 impl CryptoHash {
     /// Length in bytes of the cryptographic hash.
     pub const LENGTH: usize = 32;
@@ -38,10 +40,16 @@ impl CryptoHash {
 
     /// Returns a builder which can be used to construct cryptographic hash by
     /// digesting bytes.
+    ///
+    /// The builder uses SHA-256.  To use a different hash function see
+    /// [`Hasher`].
     #[inline]
     pub fn builder() -> Builder { Builder::default() }
 
-    /// Returns hash of given bytes.
+    /// Returns SHA-256 hash of given bytes.
+    ///
+    /// This is equivalent to `Sha256::digest(bytes)`.  To use a different
+    /// hash function see [`Hasher`].
     #[inline]
     pub fn digest(bytes: &[u8]) -> Self {
         Self::digestv(core::slice::from_ref(&bytes))
@@ -105,13 +113,12 @@ impl CryptoHash {
 
 impl core::fmt::Display for CryptoHash {
     /// Encodes the hash as base64 and prints it as a string.
+    ///
+    /// Use [`CryptoHash::hex`] or [`CryptoHash::base58`] to format the hash in
+    /// other encodings.
+    #[inline]
     fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        const ENCODED_LENGTH: usize = (CryptoHash::LENGTH + 2) / 3 * 4;
-        let mut buf = [0u8; ENCODED_LENGTH];
-        let len =
-            BASE64_ENGINE.encode_slice(self.as_slice(), &mut buf[..]).unwrap();
-        // SAFETY: base64 fills the buffer with ASCII characters only.
-        fmtr.write_str(unsafe { core::str::from_utf8_unchecked(&buf[..len]) })
+        core::fmt::Display::fmt(&self.base64(), fmtr)
     }
 }
 
@@ -246,6 +253,55 @@ impl Builder {
     pub fn build(self) -> CryptoHash { CryptoHash(self.0.done()) }
 }
 
+/// A hash function used to calculate [`CryptoHash`].
+///
+/// Code which needs to be agnostic of the hash function (e.g. Merkle tries
+/// whose proofs are verified on different platforms) is generic over this
+/// trait.  [`Sha256`] is the default implementation; [`Keccak256`] is
+/// available with `keccak` crate feature.
+pub trait Hasher {
+    /// Returns hash of concatenation of given byte slices.
+    fn digestv(slices: &[&[u8]]) -> CryptoHash;
+
+    /// Returns hash of given bytes.
+    #[inline]
+    fn digest(bytes: &[u8]) -> CryptoHash {
+        Self::digestv(core::slice::from_ref(&bytes))
+    }
+}
+
+/// SHA-256 hash function.
+///
+/// On Solana, with `solana-program` feature enabled, uses the `sol_sha256`
+/// syscall.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sha256;
+
+impl Hasher for Sha256 {
+    #[inline]
+    fn digestv(slices: &[&[u8]]) -> CryptoHash { CryptoHash::digestv(slices) }
+}
+
+/// Keccak-256 hash function as used by Ethereum.
+///
+/// Note that this is different from SHA3-256 which uses different padding.
+#[cfg(feature = "keccak")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keccak256;
+
+#[cfg(feature = "keccak")]
+impl Hasher for Keccak256 {
+    fn digestv(slices: &[&[u8]]) -> CryptoHash {
+        use sha3::Digest;
+
+        let mut state = sha3::Keccak256::new();
+        for bytes in slices {
+            state.update(bytes);
+        }
+        CryptoHash(state.finalize().into())
+    }
+}
+
 #[cfg(feature = "borsh")]
 impl io::Write for Builder {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
@@ -294,3 +350,29 @@ fn test_new_hash() {
     };
     assert_eq!(want, got);
 }
+
+#[test]
+fn test_hasher() {
+    let abc = CryptoHash::digest(b"abc");
+    assert_eq!(abc, Sha256::digest(b"abc"));
+    assert_eq!(abc, Sha256::digestv(&[b"a", b"bc"]));
+}
+
+#[test]
+#[cfg(feature = "keccak")]
+fn test_keccak() {
+    let want = CryptoHash::from([
+        0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2,
+        0xdc, 0xc7, 0x03, 0xc0, 0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b,
+        0x7b, 0xfa, 0xd8, 0x04, 0x5d, 0x85, 0xa4, 0x70,
+    ]);
+    assert_eq!(want, Keccak256::digest(b""));
+
+    let want = CryptoHash::from([
+        0x4e, 0x03, 0x65, 0x7a, 0xea, 0x45, 0xa9, 0x4f, 0xc7, 0xd4, 0x7b, 0xa8,
+        0x26, 0xc8, 0xd6, 0x67, 0xc0, 0xd1, 0xe6, 0xe3, 0x3a, 0x64, 0xa0, 0x36,
+        0xec, 0x44, 0xf5, 0x8f, 0xa1, 0x2d, 0x6c, 0x45,
+    ]);
+    assert_eq!(want, Keccak256::digest(b"abc"));
+    assert_eq!(want, Keccak256::digestv(&[b"a", b"", b"bc"]));
+}
@@ -0,0 +1,181 @@
+//! Domain-separated hashing.
+//!
+//! Hashing different kinds of objects with distinct tags guarantees that hash
+//! of one kind of object can never be confused with hash of another even if
+//! their serialised representations happen to be the same.  For example,
+//! a block header whose borsh serialisation is a valid serialised epoch can
+//! no longer be passed off as an epoch commitment.
+//!
+//! Existing chains hash raw data and changing that would change all block
+//! hashes and commitments.  To allow migration, the scheme is selected at run
+//! time with [`HashVersion`]: [`HashVersion::V0`] reproduces the legacy
+//! untagged hashes while [`HashVersion::V1`] mixes in the domain tag.  New
+//! chains can opt into the latter.
+
+use alloc::vec::Vec;
+
+use super::{Builder, CryptoHash, Hasher, Sha256};
+
+/// A kind of hashed object used for domain separation.
+///
+/// Each domain is a separate type so that the domain a hash belongs to is
+/// checked at compile time.
+pub trait Domain {
+    /// Tag identifying the domain.
+    ///
+    /// Tags must be unique among all domains and at most 255 bytes long.
+    const TAG: &'static [u8];
+}
+
+macro_rules! domains {
+    ($($(#[$meta:meta])* $Name:ident = $tag:literal;)*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+            pub struct $Name;
+
+            impl Domain for $Name {
+                const TAG: &'static [u8] = $tag;
+            }
+        )*
+    };
+}
+
+domains! {
+    /// Hash of a guest blockchain block header.
+    BlockHash = b"guestchain/block-hash";
+    /// Commitment of a guest blockchain epoch.
+    EpochCommitment = b"guestchain/epoch-commitment";
+    /// Hash of a value stored in the state trie with IBC client id mixed in.
+    ClientId = b"cf-guest/client-id";
+}
+
+/// Version of the hashing scheme.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub enum HashVersion {
+    /// Legacy scheme which hashes raw data without domain separation.
+    #[default]
+    V0,
+    /// Domain-separated scheme.
+    ///
+    /// Hashed data is prefixed by length of the domain’s tag (as a single
+    /// byte) and the tag itself, i.e. the hash is `H(len(tag) || tag ||
+    /// data)`.
+    V1,
+}
+
+impl HashVersion {
+    /// The newest version of the hashing scheme.  New chains should use this
+    /// version.
+    pub const LATEST: Self = Self::V1;
+
+    /// Returns SHA-256 hash of given bytes in domain `D`.
+    #[inline]
+    pub fn digest<D: Domain>(self, bytes: &[u8]) -> CryptoHash {
+        self.digestv::<D>(core::slice::from_ref(&bytes))
+    }
+
+    /// Returns SHA-256 hash of concatenation of given byte slices in domain
+    /// `D`.
+    #[inline]
+    pub fn digestv<D: Domain>(self, slices: &[&[u8]]) -> CryptoHash {
+        self.digestv_with::<Sha256, D>(slices)
+    }
+
+    /// Returns hash of concatenation of given byte slices in domain `D` using
+    /// hash function `H`.
+    pub fn digestv_with<H: Hasher, D: Domain>(
+        self,
+        slices: &[&[u8]],
+    ) -> CryptoHash {
+        match self {
+            Self::V0 => H::digestv(slices),
+            Self::V1 => {
+                let len = [tag_len::<D>()];
+                let mut all = Vec::with_capacity(slices.len() + 2);
+                all.push(&len[..]);
+                all.push(D::TAG);
+                all.extend_from_slice(slices);
+                H::digestv(&all)
+            }
+        }
+    }
+
+    /// Returns a SHA-256 builder for hashing data in domain `D`.
+    ///
+    /// This is useful for hashing serialised objects, e.g. by passing the
+    /// builder to `borsh::to_writer`.
+    pub fn builder<D: Domain>(self) -> Builder {
+        let mut builder = Builder::default();
+        if self == Self::V1 {
+            builder.update(&[tag_len::<D>()]);
+            builder.update(D::TAG);
+        }
+        builder
+    }
+}
+
+/// Returns length of the domain’s tag.  Fails to compile if the tag is longer
+/// than 255 bytes.
+const fn tag_len<D: Domain>() -> u8 {
+    struct Len<D>(D);
+    impl<D: Domain> Len<D> {
+        const LEN: u8 = {
+            assert!(D::TAG.len() <= 255, "Domain tag too long");
+            D::TAG.len() as u8
+        };
+    }
+    Len::<D>::LEN
+}
+
+
+#[test]
+fn test_domain_separation() {
+    let data: &[&[u8]] = &[b"foo", b"bar"];
+
+    // V0 is plain untagged hash.
+    let want = CryptoHash::digest(b"foobar");
+    assert_eq!(want, HashVersion::V0.digest::<BlockHash>(b"foobar"));
+    assert_eq!(want, HashVersion::V0.digestv::<ClientId>(data));
+    let mut builder = HashVersion::V0.builder::<EpochCommitment>();
+    builder.update(b"foobar");
+    assert_eq!(want, builder.build());
+
+    // V1 mixes in the tag.
+    let want = CryptoHash::digest(b"\x15guestchain/block-hashfoobar");
+    assert_eq!(want, HashVersion::V1.digest::<BlockHash>(b"foobar"));
+    assert_eq!(want, HashVersion::V1.digestv::<BlockHash>(data));
+    let mut builder = HashVersion::V1.builder::<BlockHash>();
+    builder.update(b"foo");
+    builder.update(b"bar");
+    assert_eq!(want, builder.build());
+
+    // Different domains produce different hashes.
+    let hashes = [
+        HashVersion::V1.digest::<BlockHash>(b"foobar"),
+        HashVersion::V1.digest::<EpochCommitment>(b"foobar"),
+        HashVersion::V1.digest::<ClientId>(b"foobar"),
+        HashVersion::V0.digest::<ClientId>(b"foobar"),
+    ];
+    for (idx, hash) in hashes.iter().enumerate() {
+        assert!(!hashes[idx + 1..].contains(hash), "{idx}: {hash}");
+    }
+
+    assert_eq!(HashVersion::V0, HashVersion::default());
+    assert_eq!(HashVersion::V1, HashVersion::LATEST);
+}
+
+#[test]
+#[cfg(feature = "keccak")]
+fn test_domain_separation_keccak() {
+    use super::Keccak256;
+
+    let want = Keccak256::digest(b"\x15guestchain/block-hashfoobar");
+    let got =
+        HashVersion::V1.digestv_with::<Keccak256, BlockHash>(&[b"foobar"]);
+    assert_eq!(want, got);
+}
@@ -0,0 +1,427 @@
+//! Text representations of [`CryptoHash`].
+//!
+//! A hash can be formatted as base64 (which is what [`core::fmt::Display`]
+//! uses), hex (with `{:x}` and `{:X}` format specifiers) or base58 (which is
+//! what Solana uses for account addresses).  The [`Hex`], [`Base58`] and
+//! [`Base64`] wrappers select the encoding explicitly.
+//!
+//! Parsing with [`core::str::FromStr`] accepts any of the encodings.  The
+//! encoding is detected from the string: 64-character strings are hex,
+//! strings ending with a padding character are base64 and anything else is
+//! base58.  None of the encodings can be confused with another for 32-byte
+//! values.
+
+use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
+use base64::Engine;
+
+use super::CryptoHash;
+
+/// Formats the hash as lower-case hex.  Use `{:X}` format specifier on the
+/// wrapper or the hash to get upper-case hex.
+#[derive(Clone, Copy)]
+pub struct Hex<'a>(pub &'a CryptoHash);
+
+/// Formats the hash as base58 using the Bitcoin alphabet (same as Solana).
+#[derive(Clone, Copy)]
+pub struct Base58<'a>(pub &'a CryptoHash);
+
+/// Formats the hash as base64 with padding.
+#[derive(Clone, Copy)]
+pub struct Base64<'a>(pub &'a CryptoHash);
+
+/// Error returned when parsing a hash from a string fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, derive_more::Display)]
+#[display(fmt = "Invalid cryptographic hash")]
+pub struct ParseError;
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+impl CryptoHash {
+    /// Returns wrapper which formats the hash as hex.
+    #[inline]
+    pub fn hex(&self) -> Hex<'_> { Hex(self) }
+
+    /// Returns wrapper which formats the hash as base58.
+    #[inline]
+    pub fn base58(&self) -> Base58<'_> { Base58(self) }
+
+    /// Returns wrapper which formats the hash as base64.
+    #[inline]
+    pub fn base64(&self) -> Base64<'_> { Base64(self) }
+
+    /// Decodes a hex string representation of the hash.
+    ///
+    /// Both lower- and upper-case digits are accepted.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = <&[u8; 2 * Self::LENGTH]>::try_from(hex.as_bytes()).ok()?;
+        let mut hash = Self::DEFAULT;
+        for (out, chunk) in hash.0.iter_mut().zip(hex.chunks_exact(2)) {
+            *out = hex_digit(chunk[0])? << 4 | hex_digit(chunk[1])?;
+        }
+        Some(hash)
+    }
+
+    /// Decodes a base58 string representation of the hash.
+    pub fn from_base58(base58: &str) -> Option<Self> {
+        // Big-endian number with `len` least significant bytes used.
+        let mut hash = Self::DEFAULT;
+        let mut len = 0;
+        for ch in base58.bytes() {
+            let mut carry = u32::from(base58_digit(ch)?);
+            for byte in hash.0.iter_mut().rev().take(len) {
+                carry += u32::from(*byte) * 58;
+                *byte = carry as u8;
+                carry >>= 8;
+            }
+            while carry != 0 {
+                len += 1;
+                *hash
+                    .0
+                    .len()
+                    .checked_sub(len)
+                    .and_then(|i| hash.0.get_mut(i))? = carry as u8;
+                carry >>= 8;
+            }
+        }
+        // Each leading ‘1’ encodes a leading zero byte.
+        let zeros = base58.bytes().take_while(|ch| *ch == b'1').count();
+        (zeros + len == Self::LENGTH).then_some(hash)
+    }
+}
+
+impl core::str::FromStr for CryptoHash {
+    type Err = ParseError;
+
+    /// Parses a hash in hex, base58 or base64 representation.
+    fn from_str(text: &str) -> Result<Self, ParseError> {
+        if text.len() == 2 * Self::LENGTH {
+            Self::from_hex(text)
+        } else if text.ends_with('=') {
+            Self::from_base64(text)
+        } else {
+            Self::from_base58(text)
+        }
+        .ok_or(ParseError)
+    }
+}
+
+impl core::fmt::LowerHex for CryptoHash {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_hex(self, b"0123456789abcdef", fmtr)
+    }
+}
+
+impl core::fmt::UpperHex for CryptoHash {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_hex(self, b"0123456789ABCDEF", fmtr)
+    }
+}
+
+impl core::fmt::Display for Hex<'_> {
+    #[inline]
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::LowerHex::fmt(self.0, fmtr)
+    }
+}
+
+impl core::fmt::LowerHex for Hex<'_> {
+    #[inline]
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::LowerHex::fmt(self.0, fmtr)
+    }
+}
+
+impl core::fmt::UpperHex for Hex<'_> {
+    #[inline]
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::UpperHex::fmt(self.0, fmtr)
+    }
+}
+
+impl core::fmt::Display for Base58<'_> {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        const ALPHABET: &[u8; 58] =
+            b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+        // 32 bytes need at most 44 base58 digits.
+        const MAX_LENGTH: usize = 44;
+
+        // Little-endian base58 digits with `len` of them used.
+        let mut digits = [0u8; MAX_LENGTH];
+        let mut len = 0;
+        for byte in self.0.as_slice() {
+            let mut carry = u32::from(*byte);
+            for digit in digits[..len].iter_mut() {
+                carry += u32::from(*digit) << 8;
+                *digit = (carry % 58) as u8;
+                carry /= 58;
+            }
+            while carry != 0 {
+                digits[len] = (carry % 58) as u8;
+                len += 1;
+                carry /= 58;
+            }
+        }
+
+        // Each leading zero byte is encoded as ‘1’.
+        let zeros = self.0.as_slice().iter().take_while(|b| **b == 0).count();
+        let mut buf = [b'1'; MAX_LENGTH];
+        for (out, digit) in
+            buf[zeros..].iter_mut().zip(digits[..len].iter().rev())
+        {
+            *out = ALPHABET[usize::from(*digit)];
+        }
+        // SAFETY: buf is filled with ASCII characters only.
+        fmtr.write_str(unsafe {
+            core::str::from_utf8_unchecked(&buf[..zeros + len])
+        })
+    }
+}
+
+impl core::fmt::Display for Base64<'_> {
+    fn fmt(&self, fmtr: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        const ENCODED_LENGTH: usize = CryptoHash::LENGTH.div_ceil(3) * 4;
+        let mut buf = [0u8; ENCODED_LENGTH];
+        let len = BASE64_ENGINE
+            .encode_slice(self.0.as_slice(), &mut buf[..])
+            .unwrap();
+        // SAFETY: base64 fills the buffer with ASCII characters only.
+        fmtr.write_str(unsafe { core::str::from_utf8_unchecked(&buf[..len]) })
+    }
+}
+
+macro_rules! impl_debug {
+    ($($Wrapper:ident),*) => {
+        $(
+            impl core::fmt::Debug for $Wrapper<'_> {
+                #[inline]
+                fn fmt(
+                    &self,
+                    fmtr: &mut core::fmt::Formatter<'_>,
+                ) -> core::fmt::Result {
+                    core::fmt::Display::fmt(self, fmtr)
+                }
+            }
+        )*
+    };
+}
+
+impl_debug!(Hex, Base58, Base64);
+
+fn write_hex(
+    hash: &CryptoHash,
+    digits: &[u8; 16],
+    fmtr: &mut core::fmt::Formatter<'_>,
+) -> core::fmt::Result {
+    let mut buf = [0u8; 2 * CryptoHash::LENGTH];
+    for (out, byte) in buf.chunks_exact_mut(2).zip(hash.as_slice()) {
+        out[0] = digits[usize::from(byte >> 4)];
+        out[1] = digits[usize::from(byte & 15)];
+    }
+    // SAFETY: buf is filled with ASCII characters only.
+    fmtr.write_str(unsafe { core::str::from_utf8_unchecked(&buf[..]) })
+}
+
+fn hex_digit(ch: u8) -> Option<u8> {
+    match ch {
+        b'0'..=b'9' => Some(ch - b'0'),
+        b'a'..=b'f' => Some(ch - b'a' + 10),
+        b'A'..=b'F' => Some(ch - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn base58_digit(ch: u8) -> Option<u8> {
+    match ch {
+        b'1'..=b'9' => Some(ch - b'1'),
+        b'A'..=b'H' => Some(ch - b'A' + 9),
+        b'J'..=b'N' => Some(ch - b'J' + 17),
+        b'P'..=b'Z' => Some(ch - b'P' + 22),
+        b'a'..=b'k' => Some(ch - b'a' + 33),
+        b'm'..=b'z' => Some(ch - b'm' + 44),
+        _ => None,
+    }
+}
+
+
+/// Serialises the hash as base64 string in human-readable formats and as
+/// bytes otherwise.
+#[cfg(feature = "serde")]
+impl serde::Serialize for CryptoHash {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(&self.base64())
+        } else {
+            serializer.serialize_bytes(self.as_slice())
+        }
+    }
+}
+
+/// Deserialises the hash from a string in any of the supported encodings or
+/// from bytes.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CryptoHash {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        use serde::de;
+
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = CryptoHash;
+
+            fn expecting(
+                &self,
+                fmtr: &mut core::fmt::Formatter<'_>,
+            ) -> core::fmt::Result {
+                fmtr.write_str("a 32-byte hash in hex, base58 or base64")
+            }
+
+            fn visit_str<E: de::Error>(
+                self,
+                value: &str,
+            ) -> Result<Self::Value, E> {
+                value.parse().map_err(|_| {
+                    E::invalid_value(de::Unexpected::Str(value), &self)
+                })
+            }
+
+            fn visit_bytes<E: de::Error>(
+                self,
+                value: &[u8],
+            ) -> Result<Self::Value, E> {
+                CryptoHash::try_from(value)
+                    .map_err(|_| E::invalid_length(value.len(), &self))
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut hash = CryptoHash::DEFAULT;
+                for (idx, byte) in hash.0.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(idx, &self))?;
+                }
+                match seq.next_element::<u8>()? {
+                    None => Ok(hash),
+                    Some(_) => Err(de::Error::invalid_length(
+                        CryptoHash::LENGTH + 1,
+                        &self,
+                    )),
+                }
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(Visitor)
+        } else {
+            deserializer.deserialize_bytes(Visitor)
+        }
+    }
+}
+
+
+#[cfg(test)]
+const TEST_VECTORS: [(&str, &str, &str); 4] = [
+    (
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "11111111111111111111111111111111",
+        "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+    ),
+    (
+        "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+        "JEKNVnkbo3jma5nREBBJCDoXFVeKkD56V3xKrvRmWxFG",
+        "//////////////////////////////////////////8=",
+    ),
+    (
+        "00000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e",
+        "11CiMQsCUhqABwwLyCFeX2iPnBZX3s28dUUCBrirhs",
+        "AAABAgMEBQYHCAkKCwwNDg8QERITFBUWFxgZGhscHR4=",
+    ),
+    (
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        "GKot5hBsd81kMupNCXHaqbhv3huEbxAFMLnpcX2hniwn",
+        "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+    ),
+];
+
+#[test]
+fn test_encodings() {
+    use alloc::string::ToString;
+
+    for (hex, base58, base64) in TEST_VECTORS {
+        let hash = CryptoHash::from_hex(hex).unwrap();
+        assert_eq!(hex, hash.hex().to_string());
+        assert_eq!(hex, alloc::format!("{hash:x}"));
+        assert_eq!(hex.to_ascii_uppercase(), alloc::format!("{hash:X}"));
+        assert_eq!(base58, hash.base58().to_string());
+        assert_eq!(base64, hash.base64().to_string());
+        assert_eq!(base64, hash.to_string());
+
+        assert_eq!(Some(&hash), CryptoHash::from_base58(base58).as_ref());
+        assert_eq!(Some(&hash), CryptoHash::from_base64(base64).as_ref());
+        let upper = hex.to_ascii_uppercase();
+        assert_eq!(Some(&hash), CryptoHash::from_hex(&upper).as_ref());
+        for text in [hex, &upper, base58, base64] {
+            assert_eq!(Ok(&hash), text.parse::<CryptoHash>().as_ref());
+        }
+    }
+
+    // Solana’s token program address.
+    let hash: CryptoHash =
+        "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".parse().unwrap();
+    assert_eq!(
+        "06ddf6e1d765a193d9cbe146ceeb79ac1cb485ed5f5b37913a8cf5857eff00a9",
+        hash.hex().to_string()
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    for text in [
+        "",
+        // Bad hex digit or length.
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b85g",
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b8",
+        // Base58 with invalid characters, too short or too long.
+        "GKot5hBsd81kMupNCXHaqbhv3huEbxAFMLnpcX2hniw0",
+        "GKot5hBsd81kMupNCXHaqbhv3huEbxAFMLnpcX2hni",
+        "JEKNVnkbo3jma5nREBBJCDoXFVeKkD56V3xKrvRmWxFH",
+        "GKot5hBsd81kMupNCXHaqbhv3huEbxAFMLnpcX2hniwnn",
+        "1GKot5hBsd81kMupNCXHaqbhv3huEbxAFMLnpcX2hniwn",
+        "111111111111111111111111111111111",
+        "1111111111111111111111111111111",
+        // Base64 with bad length.
+        "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuA==",
+    ] {
+        assert_eq!(Err(ParseError), text.parse::<CryptoHash>(), "{text}");
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde() {
+    use serde::de::value::{BytesDeserializer, Error, StrDeserializer};
+    use serde::Deserialize;
+
+    for (hex, base58, base64) in TEST_VECTORS {
+        let want = CryptoHash::from_hex(hex).unwrap();
+        for text in [hex, base58, base64] {
+            let got =
+                CryptoHash::deserialize(StrDeserializer::<Error>::new(text));
+            assert_eq!(Ok(&want), got.as_ref());
+        }
+        let got =
+            CryptoHash::deserialize(BytesDeserializer::<Error>::new(&want.0));
+        assert_eq!(Ok(&want), got.as_ref());
+    }
+    CryptoHash::deserialize(StrDeserializer::<Error>::new("foo")).unwrap_err();
+    CryptoHash::deserialize(BytesDeserializer::<Error>::new(&[0; 31]))
+        .unwrap_err();
+}
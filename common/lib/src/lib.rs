@@ -1,7 +1,7 @@
 #![allow(clippy::unit_arg, clippy::comparison_chain)]
 #![no_std]
 extern crate alloc;
-#[cfg(any(feature = "test_utils", test))]
+#[cfg(any(feature = "std", feature = "test_utils", test))]
 extern crate std;
 
 pub mod hash;
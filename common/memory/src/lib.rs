@@ -1,9 +1,17 @@
+#![no_std]
 extern crate alloc;
+#[cfg(any(feature = "std", feature = "test_utils", test))]
+extern crate std;
 
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use core::fmt;
 use core::num::NonZeroU32;
 
+pub mod slab;
+
+pub use slab::Slab;
+
 /// A pointer value.  The value is 30-bit and always non-zero.
 #[derive(
     Copy,
@@ -26,6 +34,12 @@ pub struct OutOfMemory;
 #[derive(Copy, Clone, Debug, PartialEq, Eq, derive_more::Display)]
 pub struct AddressTooLarge(pub NonZeroU32);
 
+#[cfg(feature = "std")]
+impl std::error::Error for OutOfMemory {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AddressTooLarge {}
+
 impl Ptr {
     /// Largest value that can be stored in the pointer.
     // The two most significant bits are used internally in RawNode encoding
@@ -132,6 +146,44 @@ pub trait Allocator {
 
     /// Frees a block.
     fn free(&mut self, ptr: Ptr);
+
+    /// Returns list of all currently allocated blocks.
+    ///
+    /// Returns `None` if the allocator doesn’t keep track of allocated blocks
+    /// (which is what the default implementation does).  The list is used by
+    /// integrity checks to detect dangling pointers and leaked blocks thus
+    /// it doesn’t need to be cheap to compute.
+    fn allocated(&self) -> Option<Vec<Ptr>> { None }
+}
+
+/// Allows using an allocator through an exclusive reference.
+///
+/// This lets multiple data structures share a single allocator.  For example,
+/// a trie can be temporarily constructed on top of an allocator owned by
+/// another object.
+impl<A: Allocator + ?Sized> Allocator for &mut A {
+    type Value = A::Value;
+
+    #[inline]
+    fn alloc(&mut self, value: Self::Value) -> Result<Ptr, OutOfMemory> {
+        (**self).alloc(value)
+    }
+
+    #[inline]
+    fn get(&self, ptr: Ptr) -> &Self::Value { (**self).get(ptr) }
+
+    #[inline]
+    fn get_mut(&mut self, ptr: Ptr) -> &mut Self::Value {
+        (**self).get_mut(ptr)
+    }
+
+    #[inline]
+    fn set(&mut self, ptr: Ptr, value: Self::Value) { (**self).set(ptr, value) }
+
+    #[inline]
+    fn free(&mut self, ptr: Ptr) { (**self).free(ptr) }
+
+    fn allocated(&self) -> Option<Vec<Ptr>> { (**self).allocated() }
 }
 
 /// A write log which can be committed or rolled back.
@@ -141,13 +193,18 @@ pub trait Allocator {
 /// all allocated nodes are kept and during rollback all of those nodes are
 /// freed.
 ///
-/// **Note:** *All* reads are passed directly to the underlying allocator.  This
-/// means reading a node that has been written to will return the old result.
+/// Reads performed through the write log (either via [`WriteLog::get`] or the
+/// [`Allocator`] implementation) see pending writes, i.e. reading a node that
+/// has been written to returns the new value.  This makes it possible to run
+/// operations speculatively, e.g. by constructing a trie on top of the write
+/// log and discarding it if the result is not to be kept.
+///
+/// Changes can be grouped with nested savepoints (see [`WriteLog::savepoint`])
+/// which can be rolled back without discarding the entire write log.
 ///
-/// Note that the write log doesn’t offer isolation.  Most notably, writes to
-/// the allocator performed outside of the write log are visible when accessing
-/// the nodes via the write log.  (To indicate that, the API doesn’t offer `get`
-/// method and instead all reads need to go through the underlying allocator).
+/// Note that the write log doesn’t offer full isolation.  Most notably, writes
+/// to the allocator performed outside of the write log are visible when
+/// accessing nodes which weren’t modified via the write log.
 ///
 /// Secondly, allocations done via the write log are visible outside of the
 /// write log.  The assumption is that nothing outside of the client of the
@@ -157,33 +214,80 @@ pub struct WriteLog<'a, A: Allocator> {
     /// Allocator to pass requests to.
     alloc: &'a mut A,
 
-    /// List of changes in the transaction.
-    write_log: Vec<(Ptr, A::Value)>,
+    /// Pending changes in the transaction.
+    write_log: BTreeMap<Ptr, A::Value>,
 
     /// List pointers to nodes allocated during the transaction.
     allocated: Vec<Ptr>,
 
     /// List of nodes freed during the transaction.
     freed: Vec<Ptr>,
+
+    /// Operations to revert when rolling back to a savepoint.
+    ///
+    /// Entries are recorded only while there’s at least one savepoint.
+    undo_log: Vec<Undo<A::Value>>,
+
+    /// Lengths of `undo_log` at the time each savepoint was created together
+    /// with the savepoints’ generations.
+    savepoints: Vec<(usize, u64)>,
+
+    /// Generation to assign to the next savepoint.
+    ///
+    /// Used to detect invalidated savepoints whose depth has since been
+    /// reused by a newer savepoint.
+    next_generation: u64,
+}
+
+/// A savepoint in a [`WriteLog`] created by [`WriteLog::savepoint`].
+///
+/// Savepoint is invalidated when it or any of its enclosing savepoints is
+/// released or rolled back.  Using an invalidated savepoint panics.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use]
+pub struct Savepoint {
+    /// Number of active savepoints (including this one) when it was created.
+    depth: usize,
+    /// Generation number unique within the write log.
+    generation: u64,
+}
+
+/// An operation on a [`WriteLog`] which needs to be reverted when rolling back
+/// to a savepoint.
+enum Undo<V> {
+    /// Value at given pointer has been changed.  Holds the previous pending
+    /// value or `None` if there was none.
+    Write(Ptr, Option<V>),
+    /// A new node has been allocated from the underlying allocator and pushed
+    /// to `allocated` list.
+    Alloc,
+    /// A node has been pushed to `freed` list.
+    Free,
+    /// A node has been popped from `freed` list and reused.
+    Reuse(Ptr),
 }
 
 impl<'a, A: Allocator> WriteLog<'a, A> {
     pub fn new(alloc: &'a mut A) -> Self {
         Self {
             alloc,
-            write_log: Vec::new(),
+            write_log: BTreeMap::new(),
             allocated: Vec::new(),
             freed: Vec::new(),
+            undo_log: Vec::new(),
+            savepoints: Vec::new(),
+            next_generation: 0,
         }
     }
 
     /// Commit all changes to the allocator.
     ///
+    /// Changes made since any still active savepoints are committed as well.
     /// There’s no explicit rollback method.  To roll changes back, drop the
     /// object.
     pub fn commit(mut self) {
         self.allocated.clear();
-        for (ptr, value) in self.write_log.drain(..) {
+        for (ptr, value) in core::mem::take(&mut self.write_log) {
             self.alloc.set(ptr, value)
         }
         for ptr in self.freed.drain(..) {
@@ -194,28 +298,159 @@ impl<'a, A: Allocator> WriteLog<'a, A> {
     /// Returns underlying allocator.
     pub fn allocator(&self) -> &A { &*self.alloc }
 
+    /// Returns value stored at given pointer taking pending writes into
+    /// account.
+    ///
+    /// May panic or return garbage if `ptr` is invalid.
+    pub fn get(&self, ptr: Ptr) -> &A::Value {
+        self.write_log.get(&ptr).unwrap_or_else(|| self.alloc.get(ptr))
+    }
+
     pub fn alloc(&mut self, value: A::Value) -> Result<Ptr, OutOfMemory> {
         Ok(if let Some(ptr) = self.freed.pop() {
+            self.record(|| Undo::Reuse(ptr));
             self.set(ptr, value);
             ptr
         } else {
             let ptr = self.alloc.alloc(value)?;
             self.allocated.push(ptr);
+            self.record(|| Undo::Alloc);
             ptr
         })
     }
 
     pub fn set(&mut self, ptr: Ptr, value: A::Value) {
-        self.write_log.push((ptr, value))
+        let old = self.write_log.insert(ptr, value);
+        self.record(|| Undo::Write(ptr, old));
+    }
+
+    pub fn free(&mut self, ptr: Ptr) {
+        self.freed.push(ptr);
+        self.record(|| Undo::Free);
+    }
+
+    /// Creates a new savepoint.
+    ///
+    /// Changes made after this call can be reverted with
+    /// [`Self::rollback_to`] without affecting changes made before.
+    /// Savepoints can be nested; rolling back to or releasing a savepoint
+    /// affects all the savepoints created after it.
+    pub fn savepoint(&mut self) -> Savepoint {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        self.savepoints.push((self.undo_log.len(), generation));
+        Savepoint { depth: self.savepoints.len(), generation }
+    }
+
+    /// Reverts all changes made since given savepoint was created.
+    ///
+    /// The savepoint and all savepoints nested in it are invalidated.  Panics
+    /// if the savepoint has already been invalidated.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) {
+        let mark = self.pop_savepoint(savepoint);
+        for undo in self.undo_log.drain(mark..).rev() {
+            match undo {
+                Undo::Write(ptr, Some(value)) => {
+                    self.write_log.insert(ptr, value);
+                }
+                Undo::Write(ptr, None) => {
+                    self.write_log.remove(&ptr);
+                }
+                Undo::Alloc => {
+                    let ptr = self.allocated.pop().unwrap();
+                    self.write_log.remove(&ptr);
+                    self.alloc.free(ptr);
+                }
+                Undo::Free => {
+                    self.freed.pop();
+                }
+                Undo::Reuse(ptr) => self.freed.push(ptr),
+            }
+        }
+    }
+
+    /// Releases given savepoint keeping changes made since it was created.
+    ///
+    /// The changes become part of the enclosing savepoint (if any) and will be
+    /// reverted if that savepoint is rolled back.  The savepoint and all
+    /// savepoints nested in it are invalidated.  Panics if the savepoint has
+    /// already been invalidated.
+    pub fn release(&mut self, savepoint: Savepoint) {
+        self.pop_savepoint(savepoint);
+        if self.savepoints.is_empty() {
+            self.undo_log.clear();
+        }
     }
 
-    pub fn free(&mut self, ptr: Ptr) { self.freed.push(ptr); }
+    /// Removes given savepoint and all savepoints nested in it; returns
+    /// length of the undo log when the savepoint was created.
+    fn pop_savepoint(&mut self, savepoint: Savepoint) -> usize {
+        let Savepoint { depth, generation } = savepoint;
+        let mark = match self.savepoints.get(depth - 1) {
+            Some(&(mark, gen)) if gen == generation => mark,
+            _ => panic!("Tried to use invalidated savepoint"),
+        };
+        self.savepoints.truncate(depth - 1);
+        mark
+    }
+
+    /// Records an operation in the undo log if there are any savepoints.
+    fn record(&mut self, undo: impl FnOnce() -> Undo<A::Value>) {
+        if !self.savepoints.is_empty() {
+            self.undo_log.push(undo())
+        }
+    }
+}
+
+/// Allows running data structures (e.g. a trie) on top of a write log.
+///
+/// Reads see pending writes.  Obtaining an exclusive reference to a node which
+/// hasn’t been written to yet copies its value from the underlying allocator.
+impl<'a, A: Allocator> Allocator for WriteLog<'a, A>
+where
+    A::Value: Clone,
+{
+    type Value = A::Value;
+
+    #[inline]
+    fn alloc(&mut self, value: Self::Value) -> Result<Ptr, OutOfMemory> {
+        WriteLog::alloc(self, value)
+    }
+
+    #[inline]
+    fn get(&self, ptr: Ptr) -> &Self::Value { WriteLog::get(self, ptr) }
+
+    fn get_mut(&mut self, ptr: Ptr) -> &mut Self::Value {
+        if !self.write_log.contains_key(&ptr) {
+            let value = self.alloc.get(ptr).clone();
+            self.set(ptr, value);
+        } else if !self.savepoints.is_empty() {
+            let old = self.write_log.get(&ptr).cloned();
+            self.undo_log.push(Undo::Write(ptr, old));
+        }
+        self.write_log.get_mut(&ptr).unwrap()
+    }
+
+    #[inline]
+    fn set(&mut self, ptr: Ptr, value: Self::Value) {
+        WriteLog::set(self, ptr, value)
+    }
+
+    #[inline]
+    fn free(&mut self, ptr: Ptr) { WriteLog::free(self, ptr) }
+
+    fn allocated(&self) -> Option<Vec<Ptr>> {
+        let mut ptrs = self.alloc.allocated()?;
+        ptrs.retain(|ptr| !self.freed.contains(ptr));
+        Some(ptrs)
+    }
 }
 
 impl<'a, A: Allocator> core::ops::Drop for WriteLog<'a, A> {
     fn drop(&mut self) {
         self.write_log.clear();
         self.freed.clear();
+        self.undo_log.clear();
         for ptr in self.allocated.drain(..) {
             self.alloc.free(ptr)
         }
@@ -322,6 +557,11 @@ pub mod test_utils {
             }
             self.count -= 1;
         }
+
+        fn allocated(&self) -> Option<Vec<Ptr>> {
+            let ptrs = (0..self.pool.len()).map(Self::ptr_from_index);
+            Some(ptrs.filter(|ptr| !self.free_list.contains(ptr)).collect())
+        }
     }
 }
 
@@ -427,4 +667,103 @@ mod test_write_log {
         core::mem::drop(wlog);
         assert_nodes(10, &alloc, &ptrs, 0);
     }
+
+    #[test]
+    fn test_read_your_writes() {
+        let (mut alloc, ptrs) = make_allocator();
+        let mut wlog = WriteLog::new(&mut alloc);
+        wlog.set(ptrs[0], 10);
+        *Allocator::get_mut(&mut wlog, ptrs[1]) += 10;
+        let ptr = wlog.alloc(12).unwrap();
+        assert_eq!(
+            (10, 11, 12),
+            (*wlog.get(ptrs[0]), *wlog.get(ptrs[1]), *wlog.get(ptr))
+        );
+        assert_eq!(2, *wlog.get(ptrs[2]));
+        assert_nodes(11, wlog.allocator(), &ptrs, 0);
+        wlog.commit();
+        assert_eq!(
+            (10, 11, 12),
+            (*alloc.get(ptrs[0]), *alloc.get(ptrs[1]), *alloc.get(ptr))
+        );
+        assert_nodes(11, &alloc, &ptrs[2..], 2);
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let (mut alloc, ptrs) = make_allocator();
+        let mut wlog = WriteLog::new(&mut alloc);
+        wlog.set(ptrs[0], 10);
+        let outer = wlog.savepoint();
+        wlog.set(ptrs[0], 20);
+        wlog.set(ptrs[1], 21);
+        let new = wlog.alloc(22).unwrap();
+        wlog.free(ptrs[9]);
+        let inner = wlog.savepoint();
+        wlog.set(ptrs[0], 30);
+        *Allocator::get_mut(&mut wlog, new) += 10;
+        let reused = wlog.alloc(33).unwrap();
+        assert_eq!(ptrs[9], reused);
+        wlog.free(ptrs[8]);
+
+        wlog.rollback_to(inner);
+        assert_eq!(
+            (20, 21, 22),
+            (*wlog.get(ptrs[0]), *wlog.get(ptrs[1]), *wlog.get(new))
+        );
+        assert_eq!(
+            Some(10),
+            Allocator::allocated(&wlog).map(|ptrs| ptrs.len())
+        );
+
+        let inner = wlog.savepoint();
+        wlog.set(ptrs[2], 32);
+        wlog.release(inner);
+        assert_eq!(32, *wlog.get(ptrs[2]));
+
+        wlog.rollback_to(outer);
+        assert_eq!(
+            (10, 1, 2),
+            (*wlog.get(ptrs[0]), *wlog.get(ptrs[1]), *wlog.get(ptrs[2]))
+        );
+        assert_nodes(10, wlog.allocator(), &ptrs, 0);
+
+        let savepoint = wlog.savepoint();
+        wlog.free(ptrs[9]);
+        let new = wlog.alloc(40).unwrap();
+        let _nested = wlog.savepoint();
+        wlog.set(ptrs[1], 41);
+        wlog.release(savepoint);
+        wlog.commit();
+        assert_eq!(
+            (10, 41, 40),
+            (*alloc.get(ptrs[0]), *alloc.get(ptrs[1]), *alloc.get(new))
+        );
+        assert_nodes(10, &alloc, &ptrs[2..9], 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalidated_savepoint() {
+        let (mut alloc, _) = make_allocator();
+        let mut wlog = WriteLog::new(&mut alloc);
+        let outer = wlog.savepoint();
+        let inner = wlog.savepoint();
+        wlog.rollback_to(outer);
+        wlog.rollback_to(inner);
+    }
+
+    #[test]
+    #[should_panic(expected = "Tried to use invalidated savepoint")]
+    fn test_reused_savepoint_depth() {
+        let (mut alloc, ptrs) = make_allocator();
+        let mut wlog = WriteLog::new(&mut alloc);
+        let outer = wlog.savepoint();
+        let inner = wlog.savepoint();
+        wlog.release(outer);
+        let _outer = wlog.savepoint();
+        let _inner = wlog.savepoint();
+        wlog.set(ptrs[0], 10);
+        wlog.rollback_to(inner);
+    }
 }
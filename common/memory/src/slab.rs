@@ -0,0 +1,331 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::{Allocator, OutOfMemory, Ptr};
+
+/// A growable slab allocator keeping values in a contiguous vector.
+///
+/// Freed blocks are kept on an intrusive free list and reused by subsequent
+/// allocations.  When the free list is empty, the slab grows; growth is
+/// fallible and reported as [`OutOfMemory`] rather than aborting.  The slab
+/// never hands out more than [`Ptr::MAX`] blocks.
+///
+/// Freeing blocks leaves holes in the slab which can be measured with
+/// [`Slab::stats`] and removed with [`Slab::compact`].
+#[derive(Clone, Debug)]
+pub struct Slab<T> {
+    /// Storage for the blocks.  Block at index `n` has address `n + 1`.
+    slots: Vec<Slot<T>>,
+
+    /// Pointer to the first freed block; `None` if free list is empty.
+    first_free: Option<Ptr>,
+
+    /// Number of currently allocated blocks.
+    count: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Slot<T> {
+    /// An allocated block.
+    Used(T),
+    /// A freed block with pointer to the next freed block.
+    Free(Option<Ptr>),
+}
+
+/// Usage statistics of a [`Slab`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of allocated blocks.
+    pub allocated: usize,
+    /// Number of freed blocks waiting to be reused.
+    pub free: usize,
+    /// Number of blocks the slab can hold without reallocating.
+    pub capacity: usize,
+}
+
+impl Stats {
+    /// Returns fraction of blocks in the slab which are free.
+    ///
+    /// This is the fraction of memory used by the slab which would be
+    /// reclaimed by [`Slab::compact`].  Returns zero for an empty slab.
+    pub fn fragmentation(&self) -> f64 {
+        match self.allocated + self.free {
+            0 => 0.0,
+            total => self.free as f64 / total as f64,
+        }
+    }
+}
+
+/// Mapping from old to new block addresses produced by [`Slab::compact`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Remapping(BTreeMap<Ptr, Ptr>);
+
+impl Remapping {
+    /// Returns new address of a block which was at given address.
+    ///
+    /// Blocks which weren’t moved keep their address.
+    pub fn get(&self, ptr: Ptr) -> Ptr {
+        self.0.get(&ptr).copied().unwrap_or(ptr)
+    }
+
+    /// Returns new address of a block at given address if it has been moved.
+    pub fn moved(&self, ptr: Ptr) -> Option<Ptr> { self.0.get(&ptr).copied() }
+
+    /// Returns number of blocks which have been moved.
+    pub fn len(&self) -> usize { self.0.len() }
+
+    /// Returns whether no blocks have been moved.
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Returns iterator over `(old, new)` addresses of moved blocks.
+    pub fn iter(&self) -> impl Iterator<Item = (Ptr, Ptr)> + '_ {
+        self.0.iter().map(|(old, new)| (*old, *new))
+    }
+}
+
+impl<T> Slab<T> {
+    /// Creates a new empty slab.
+    pub const fn new() -> Self {
+        Self { slots: Vec::new(), first_free: None, count: 0 }
+    }
+
+    /// Creates a new empty slab with space for at least `capacity` blocks.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { slots: Vec::with_capacity(capacity), ..Self::new() }
+    }
+
+    /// Returns number of allocated blocks.
+    pub fn len(&self) -> usize { self.count }
+
+    /// Returns whether there are no allocated blocks.
+    pub fn is_empty(&self) -> bool { self.count == 0 }
+
+    /// Returns usage statistics of the slab.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            allocated: self.count,
+            free: self.slots.len() - self.count,
+            capacity: self.slots.capacity(),
+        }
+    }
+
+    /// Reserves space for at least `additional` more blocks.
+    ///
+    /// Blocks on the free list aren’t taken into account.
+    pub fn reserve(&mut self, additional: usize) -> Result<(), OutOfMemory> {
+        self.slots.try_reserve(additional).map_err(|_| OutOfMemory)
+    }
+
+    /// Moves allocated blocks to fill holes left by freed blocks and releases
+    /// unused memory.
+    ///
+    /// After compaction, allocated blocks occupy addresses `1..=len()` and the
+    /// free list is empty.  Returns mapping of blocks which have been moved.
+    /// Since the slab doesn’t know how values refer to each other, it’s up to
+    /// the caller to update any stored pointers (including ones inside of the
+    /// values) using the returned mapping.
+    pub fn compact(&mut self) -> Remapping {
+        let mut holes = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| matches!(slot, Slot::Free(_)))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>()
+            .into_iter();
+        let mut remapping = BTreeMap::new();
+        loop {
+            while let Some(Slot::Free(_)) = self.slots.last() {
+                self.slots.pop();
+            }
+            let Some(hole) = holes.next() else { break };
+            let Some(Slot::Used(value)) = self.slots.pop() else { break };
+            if hole >= self.slots.len() {
+                // All remaining holes lie past the end of the slab.
+                self.slots.push(Slot::Used(value));
+                break;
+            }
+            let old = ptr_from_index(self.slots.len());
+            self.slots[hole] = Slot::Used(value);
+            remapping.insert(old, ptr_from_index(hole));
+        }
+        self.first_free = None;
+        self.slots.shrink_to_fit();
+        debug_assert_eq!(self.count, self.slots.len());
+        Remapping(remapping)
+    }
+
+    /// Returns slot for given pointer.  Panics if it isn’t allocated.
+    #[track_caller]
+    fn slot_mut(&mut self, action: &str, ptr: Ptr) -> &mut T {
+        match self.slots.get_mut(index_from_ptr(ptr)) {
+            Some(Slot::Used(value)) => value,
+            Some(Slot::Free(_)) => {
+                panic!("Tried to {action} freed block at {ptr}")
+            }
+            None => panic!("Tried to {action} unallocated block at {ptr}"),
+        }
+    }
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<T> Allocator for Slab<T> {
+    type Value = T;
+
+    fn alloc(&mut self, value: T) -> Result<Ptr, OutOfMemory> {
+        let ptr = if let Some(ptr) = self.first_free {
+            let slot = &mut self.slots[index_from_ptr(ptr)];
+            let Slot::Free(next) = *slot else { unreachable!() };
+            self.first_free = next;
+            *slot = Slot::Used(value);
+            ptr
+        } else {
+            let len = self.slots.len();
+            let ptr = u32::try_from(len + 1)
+                .ok()
+                .and_then(|addr| Ptr::new(addr).ok().flatten())
+                .ok_or(OutOfMemory)?;
+            self.reserve(1)?;
+            self.slots.push(Slot::Used(value));
+            ptr
+        };
+        self.count += 1;
+        Ok(ptr)
+    }
+
+    #[track_caller]
+    fn get(&self, ptr: Ptr) -> &T {
+        match self.slots.get(index_from_ptr(ptr)) {
+            Some(Slot::Used(value)) => value,
+            Some(Slot::Free(_)) => panic!("Tried to read freed block at {ptr}"),
+            None => panic!("Tried to read unallocated block at {ptr}"),
+        }
+    }
+
+    #[track_caller]
+    fn get_mut(&mut self, ptr: Ptr) -> &mut T { self.slot_mut("access", ptr) }
+
+    #[track_caller]
+    fn set(&mut self, ptr: Ptr, value: T) {
+        *self.slot_mut("set", ptr) = value;
+    }
+
+    #[track_caller]
+    fn free(&mut self, ptr: Ptr) {
+        self.slot_mut("free", ptr);
+        self.slots[index_from_ptr(ptr)] = Slot::Free(self.first_free);
+        self.first_free = Some(ptr);
+        self.count -= 1;
+    }
+
+    fn allocated(&self) -> Option<Vec<Ptr>> {
+        let slots = self.slots.iter().enumerate();
+        let ptrs = slots.filter(|(_, slot)| matches!(slot, Slot::Used(_)));
+        Some(ptrs.map(|(idx, _)| ptr_from_index(idx)).collect())
+    }
+}
+
+/// Converts pointer into index in the slab.
+fn index_from_ptr(ptr: Ptr) -> usize {
+    // Ptr::MAX fits in usize on all supported platforms.
+    (ptr.get() - 1) as usize
+}
+
+/// Converts index in the slab into a pointer.
+///
+/// The slab never grows beyond [`Ptr::MAX`] blocks so the conversion always
+/// succeeds for indexes of existing slots.
+fn ptr_from_index(index: usize) -> Ptr {
+    Ptr::new_truncated((index + 1) as u32).unwrap()
+}
+
+#[test]
+fn test_alloc_free() {
+    let mut slab = Slab::new();
+    let ptrs = (0..10).map(|num| slab.alloc(num).unwrap()).collect::<Vec<_>>();
+    assert_eq!(Some(ptrs.clone()), slab.allocated());
+    assert_eq!(10, slab.len());
+
+    slab.free(ptrs[3]);
+    slab.free(ptrs[7]);
+    assert_eq!(8, slab.len());
+    let stats = slab.stats();
+    assert_eq!((8, 2), (stats.allocated, stats.free));
+    assert!(stats.capacity >= 10);
+    assert_eq!(0.2, stats.fragmentation());
+
+    // Freed blocks are reused, most recently freed first.
+    assert_eq!(Ok(ptrs[7]), slab.alloc(17));
+    assert_eq!(Ok(ptrs[3]), slab.alloc(13));
+    assert_eq!(0.0, slab.stats().fragmentation());
+    slab.set(ptrs[0], 10);
+    *slab.get_mut(ptrs[1]) += 10;
+    let want = [10, 11, 2, 13, 4, 5, 6, 17, 8, 9];
+    for (ptr, want) in ptrs.iter().zip(want) {
+        assert_eq!(want, *slab.get(*ptr));
+    }
+}
+
+#[test]
+#[should_panic]
+fn test_read_freed() {
+    let mut slab = Slab::new();
+    let ptr = slab.alloc(0).unwrap();
+    slab.free(ptr);
+    slab.get(ptr);
+}
+
+#[test]
+#[should_panic]
+fn test_double_free() {
+    let mut slab = Slab::new();
+    let ptr = slab.alloc(0).unwrap();
+    slab.free(ptr);
+    slab.free(ptr);
+}
+
+#[test]
+fn test_compact() {
+    let mut slab = Slab::with_capacity(100);
+    let ptrs = (0..20).map(|num| slab.alloc(num).unwrap()).collect::<Vec<_>>();
+    for idx in [0, 4, 5, 13, 17, 18, 19] {
+        slab.free(ptrs[idx]);
+    }
+    assert_eq!(7, slab.stats().free);
+
+    let remapping = slab.compact();
+    let stats = slab.stats();
+    assert_eq!((13, 0), (stats.allocated, stats.free));
+    // Trailing holes are dropped and blocks 16, 15 and 14 fill holes at 0,
+    // 4 and 5.  Hole at 13 ends up past the end of the slab.
+    let moved = remapping.iter().collect::<Vec<_>>();
+    assert_eq!(
+        [(ptrs[14], ptrs[5]), (ptrs[15], ptrs[4]), (ptrs[16], ptrs[0])],
+        moved[..]
+    );
+    assert_eq!(ptrs[0], remapping.get(ptrs[16]));
+    assert_eq!(ptrs[1], remapping.get(ptrs[1]));
+    assert_eq!(None, remapping.moved(ptrs[1]));
+    for num in [1, 2, 3, 6, 7, 8, 9, 10, 11, 12, 14, 15, 16] {
+        assert_eq!(num, *slab.get(remapping.get(ptrs[num])));
+    }
+    assert_eq!(Some(ptrs[..13].to_vec()), slab.allocated());
+
+    // Slab keeps working after compaction.
+    assert_eq!(Ok(ptrs[13]), slab.alloc(20));
+    assert!(slab.compact().is_empty());
+}
+
+#[test]
+fn test_compact_empty() {
+    let mut slab = Slab::<u32>::new();
+    assert!(slab.compact().is_empty());
+    let ptr = slab.alloc(0).unwrap();
+    slab.free(ptr);
+    assert!(slab.compact().is_empty());
+    assert_eq!(Stats::default(), slab.stats());
+    assert_eq!(Ok(ptr), slab.alloc(1));
+}
@@ -0,0 +1,59 @@
+//! Structure-aware checks of [`bits::Slice`] operations.
+//!
+//! Constructs a slice from bytes, offset and length given in the input and
+//! checks that splitting, concatenating, chunking and popping bits are
+//! consistent with one another.
+//!
+//! Input is `<offset:u8> <length:u32> <split:u32> <bytes>` with integers in
+//! little-endian.
+
+#![no_main]
+
+use lib::u3::U3;
+use sealable_trie::bits;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let [offset, l0, l1, l2, l3, s0, s1, s2, s3, bytes @ ..] = data else {
+        return;
+    };
+    let length = u32::from_le_bytes([*l0, *l1, *l2, *l3]);
+    let split = u32::from_le_bytes([*s0, *s1, *s2, *s3]);
+    let Some(slice) = bits::Slice::new(bytes, U3::wrap(*offset), length)
+    else {
+        let bits = u64::from(offset % 8) + u64::from(length);
+        assert!(bits > bytes.len() as u64 * 8, "{data:?}");
+        return;
+    };
+    assert_eq!(length, slice.len());
+    assert_eq!(bits::Owned::from(slice), slice);
+
+    // Splitting and concatenating gives the original slice back.
+    let at = (u64::from(split) % (u64::from(length) + 1)) as u32;
+    let (left, right) = slice.split_at(at).unwrap();
+    assert_eq!((at, slice.len() - at), (left.len(), right.len()));
+    assert_eq!(bits::Owned::concat(left, right).unwrap(), slice);
+    assert!(slice.starts_with(left));
+    let mut rest = slice;
+    assert!(rest.strip_prefix(left));
+    assert_eq!(right, rest);
+
+    // Chunks are valid Extension keys which together make the slice.
+    let mut chunks = slice.chunks();
+    if let Some(first) = chunks.next() {
+        let mut joined = bits::Owned::from(first.into_slice());
+        for chunk in chunks {
+            joined.extend(chunk.into_slice()).unwrap();
+        }
+        assert_eq!(joined, slice);
+    } else {
+        assert!(slice.is_empty());
+    }
+
+    // Popping bits from either end gives the same sequence.
+    let mut front = slice;
+    let mut back = slice;
+    let from_front = core::iter::from_fn(|| front.pop_front());
+    let mut from_back = core::iter::from_fn(|| back.pop_back()).collect::<Vec<_>>();
+    from_back.reverse();
+    assert!(from_front.eq(from_back));
+});
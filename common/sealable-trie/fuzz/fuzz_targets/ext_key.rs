@@ -0,0 +1,47 @@
+//! Structure-aware round-trip checks of [`bits::ExtKey`].
+//!
+//! Constructs an Extension key from bytes, offset and length given in the
+//! input and checks that an Extension node using it survives encoding and
+//! decoding.
+//!
+//! Input is `<offset:u8> <length:u16> <is_value:u8> <hash:32> <bytes>` with
+//! integers in little-endian.
+
+#![no_main]
+
+use lib::hash::CryptoHash;
+use lib::u3::U3;
+use sealable_trie::bits;
+use sealable_trie::nodes::{Node, RawNode, Reference, MAX_EXTENSION_KEY_SIZE};
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let [offset, l0, l1, is_value, rest @ ..] = data else { return };
+    let Some((hash, bytes)) = stdx::split_at::<32, _>(rest) else { return };
+    let length = u16::from_le_bytes([*l0, *l1]);
+    let offset = U3::wrap(*offset);
+
+    let slice = bits::Slice::new(bytes, offset, length.into());
+    let Some(key) = bits::ExtKey::new(bytes, offset, length) else {
+        // Key may be invalid only if it isn’t a valid slice, is empty or is
+        // too long.
+        let bits = usize::from(u8::from(offset)) + usize::from(length);
+        assert!(
+            slice.is_none() || length == 0 || bits > MAX_EXTENSION_KEY_SIZE * 8,
+            "{data:?}"
+        );
+        return;
+    };
+    assert_eq!(slice, Some(key.into_slice()));
+    assert_eq!(length, key.len());
+    assert_eq!(Ok(key), bits::ExtKey::try_from(key.into_slice()));
+
+    let hash = CryptoHash(*hash);
+    let child = match is_value & 1 == 1 {
+        true => Reference::value(false, &hash),
+        false => Reference::node(None, &hash),
+    };
+    let raw = RawNode::extension(key, child);
+    let node = Node::Extension { key, child };
+    assert_eq!(raw, node.encode());
+    assert_eq!(Ok(node), raw.decode());
+});
@@ -0,0 +1,33 @@
+//! Deserialises arbitrary bytes as a [`Proof`] and verifies it.
+//!
+//! Deserialisation must not panic and must accept canonical encodings only,
+//! i.e. serialising a decoded proof (in the encoding it was decoded from) must
+//! produce the bytes it was decoded from.  Verification of any proof against
+//! any key must not panic either.
+//!
+//! Bytes following the proof are used as the key the proof is verified for.
+
+#![no_main]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use lib::hash::CryptoHash;
+use sealable_trie::proof::Proof;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let mut key = data;
+    let Ok(proof) = Proof::deserialize(&mut key) else { return };
+    let encoded = &data[..data.len() - key.len()];
+
+    // Bit 14 of the little-endian header indicates compact encoding.
+    let mut bytes = Vec::new();
+    if encoded[1] & 0x40 == 0 {
+        proof.serialize(&mut bytes).unwrap();
+    } else {
+        proof.serialize_compact(&mut bytes).unwrap();
+    }
+    assert_eq!(encoded, bytes.as_slice(), "proof: {proof:?}");
+
+    let root = CryptoHash::digest(data);
+    proof.verify(&root, key, None);
+    proof.verify(&root, key, Some(&root));
+});
@@ -0,0 +1,49 @@
+//! Structure-aware round-trip checks of [`Proof`].
+//!
+//! Builds a trie from entries described by the input, generates proof for
+//! a key and checks that the proof verifies and survives serialisation in both
+//! Borsh and compact encodings.
+//!
+//! Input is a sequence of `<flags:u8> <len:u8> <key>` records.  All but the
+//! last record describe entries to insert with the most significant seven bits
+//! of `flags` choosing the value and the least significant bit requesting the
+//! entry to be sealed.  Key of the last record is the one to prove.
+
+#![no_main]
+
+use borsh::BorshDeserialize;
+use lib::hash::CryptoHash;
+use memory::test_utils::TestAllocator;
+use sealable_trie::proof::Proof;
+use sealable_trie::Trie;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let mut records = Vec::new();
+    let mut data = data;
+    while let [flags, len, rest @ ..] = data {
+        let (key, rest) = rest.split_at(usize::from(*len).min(rest.len()));
+        records.push((*flags, key));
+        data = rest;
+    }
+    let Some(((_, key), entries)) = records.split_last() else { return };
+
+    let mut trie = Trie::new(TestAllocator::new(10_000));
+    for (flags, key) in entries {
+        // Keys may conflict with one another or be sealed.  Errors are fine
+        // since the point is to get a trie of an arbitrary shape.
+        let _ = trie.set(key, &CryptoHash([flags >> 1; 32]));
+        if flags & 1 == 1 {
+            let _ = trie.seal(key);
+        }
+    }
+
+    let Ok((value, proof)) = trie.prove(key) else { return };
+    assert!(proof.verify(trie.hash(), key, value.as_ref()));
+
+    let bytes = borsh::to_vec(&proof).unwrap();
+    assert_eq!(proof, Proof::try_from_slice(&bytes).unwrap());
+
+    let mut compact = Vec::new();
+    proof.serialize_compact(&mut compact).unwrap();
+    assert_eq!(proof, Proof::try_from_slice(&compact).unwrap());
+});
@@ -0,0 +1,17 @@
+//! Decodes arbitrary bytes as a [`RawNode`].
+//!
+//! Decoding must not panic and must accept canonical representations only,
+//! i.e. encoding a decoded node must produce the bytes it was decoded from.
+
+#![no_main]
+
+use sealable_trie::nodes::RawNode;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let Ok(bytes) = <&[u8; RawNode::SIZE]>::try_from(data) else { return };
+    let raw = <&RawNode>::from(bytes);
+    if let Ok(node) = raw.decode() {
+        assert_eq!(*raw, node.encode(), "node: {node:?}");
+        node.hash();
+    }
+});
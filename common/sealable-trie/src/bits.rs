@@ -25,7 +25,7 @@ pub struct Slice<'a> {
     pub(crate) offset: U3,
 
     /// Length of the slice in bits.
-    length: u16,
+    length: u32,
 
     /// The bytes to read the bits from.
     ///
@@ -48,7 +48,7 @@ pub struct Owned {
     offset: U3,
 
     /// Length of the slice in bits.
-    length: u16,
+    length: u32,
 
     /// The underlying bytes to read the bits from.
     // Invariant: If `length` is zero than `bytes.is_empty()`; otherwise
@@ -69,7 +69,7 @@ impl<'a> Slice<'a> {
     /// Returns `None` if `bytes` doesn’t have enough underlying data for the
     /// length of the slice.
     #[inline]
-    pub fn new(bytes: &'a [u8], offset: U3, length: u16) -> Option<Self> {
+    pub fn new(bytes: &'a [u8], offset: U3, length: u32) -> Option<Self> {
         (bytes_len(offset, length) <= bytes.len()).then_some(Self {
             offset,
             length,
@@ -80,13 +80,13 @@ impl<'a> Slice<'a> {
 
     /// Constructs a new bit slice going through all bits in a bytes slice.
     ///
-    /// Returns `None` if the slice is too long.  The maximum length is 8191
-    /// bytes.
+    /// Returns `None` if the slice is too long.  The maximum length is
+    /// 2³²-1 bits, i.e. just under 512 MiB.
     #[inline]
     pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
         Some(Self {
             offset: U3::_0,
-            length: u16::try_from(bytes.len().checked_mul(8)?).ok()?,
+            length: u32::try_from(bytes.len().checked_mul(8)?).ok()?,
             ptr: bytes.as_ptr(),
             phantom: Default::default(),
         })
@@ -100,7 +100,7 @@ impl<'a> Slice<'a> {
     pub fn new_check_zeros(
         bytes: &'a [u8],
         offset: U3,
-        length: u16,
+        length: u32,
     ) -> Option<Self> {
         Self::new(bytes, offset, length).filter(|slice| {
             let (front, back) = Slice::masks(offset, length);
@@ -115,7 +115,7 @@ impl<'a> Slice<'a> {
 
     /// Returns length of the slice in bits.
     #[inline]
-    pub fn len(&self) -> u16 { self.length }
+    pub fn len(&self) -> u32 { self.length }
 
     /// Returns whether the slice is empty.
     #[inline]
@@ -166,7 +166,7 @@ impl<'a> Slice<'a> {
     #[inline]
     pub fn pop_back(&mut self) -> Option<bool> {
         self.length = self.length.checked_sub(1)?;
-        let total_bits = u32::from(self.offset) + u32::from(self.length);
+        let total_bits = u64::from(self.offset) + u64::from(self.length);
         // SAFETY: `ptr` is guaranteed to point at offset + original length
         // valid bits.  Furthermore, since original length was positive than
         // there’s at least one byte we can read.
@@ -197,7 +197,7 @@ impl<'a> Slice<'a> {
     /// assert_eq!(Slice::new(&[0x01], U3::_4, 4), Some(slice));
     /// ```
     #[inline]
-    pub fn pop_front_slice(&mut self, length: u16) -> Option<Self> {
+    pub fn pop_front_slice(&mut self, length: u32) -> Option<Self> {
         let (head, tail) = self.split_at(length)?;
         *self = tail;
         Some(head)
@@ -225,7 +225,7 @@ impl<'a> Slice<'a> {
     /// assert_eq!(Slice::new(&[0x80], U3::_0, 4), Some(slice));
     /// ```
     #[inline]
-    pub fn pop_back_slice(&mut self, length: u16) -> Option<Self> {
+    pub fn pop_back_slice(&mut self, length: u32) -> Option<Self> {
         let (head, tail) = self.split_at(self.length.checked_sub(length)?)?;
         *self = head;
         Some(tail)
@@ -245,14 +245,16 @@ impl<'a> Slice<'a> {
     /// This is like `[T]::split_at` except rather than panicking it returns
     /// `None` if the slice is too short.
     #[inline]
-    pub fn split_at(&self, length: u16) -> Option<(Self, Self)> {
+    pub fn split_at(&self, length: u32) -> Option<(Self, Self)> {
         let remaining = self.length.checked_sub(length)?;
         let left = Slice { length, ..*self };
         // SAFETY: By invariant, `ptr..ptr+(self.offset + self.length + 7) / 8`
         // is a valid range.  Since `length ≤ self.length` then `ptr +
         // (self.offset + length / 8) is valid as well`.
         let ptr = unsafe {
-            self.ptr.add((usize::from(self.offset) + usize::from(length)) / 8)
+            self.ptr.add(
+                ((u64::from(self.offset) + u64::from(length)) / 8) as usize,
+            )
         };
         let right = Slice {
             offset: self.offset.wrapping_add(length),
@@ -402,7 +404,7 @@ impl<'a> Slice<'a> {
                 return 0;
             }
             let length = self.length.min(other.length);
-            let length = u32::from(length) + u32::from(offset);
+            let length = length + u32::from(offset);
             let lhs = self.bytes().split_at(((length + 7) / 8) as usize).0;
             let rhs = other.bytes().split_at(((length + 7) / 8) as usize).0;
 
@@ -423,7 +425,7 @@ impl<'a> Slice<'a> {
             }
             .min(length);
 
-            total_bits_matched.saturating_sub(u32::from(offset)) as u16
+            total_bits_matched.saturating_sub(u32::from(offset))
         })(Slice::from(other));
         if length == 0 {
             return (None, Some(other));
@@ -452,7 +454,7 @@ impl<'a> Slice<'a> {
     /// slice returns: mask of bits in the first byte that are part of the
     /// slice and mask of bits in the last byte that are part of the slice.
     #[inline]
-    fn masks(offset: U3, length: u16) -> (u8, u8) {
+    fn masks(offset: U3, length: u32) -> (u8, u8) {
         let tail = -offset.wrapping_add(length);
         (0xFFu8 >> offset, 0xFFu8 << tail)
     }
@@ -460,12 +462,12 @@ impl<'a> Slice<'a> {
 
 /// Calculates underlying bytes length of a slice with given offset and length.
 #[inline]
-fn bytes_len(offset: U3, length: u16) -> usize {
+fn bytes_len(offset: U3, length: u32) -> usize {
     // We need to special-case zero length to make sure that in situation of
     // non-zero offset and zero length we return an empty slice.
     match length {
         0 => 0,
-        _ => ((u32::from(offset) + u32::from(length) + 7) / 8) as usize,
+        _ => (u64::from(offset) + u64::from(length)).div_ceil(8) as usize,
     }
 }
 
@@ -517,7 +519,7 @@ impl Owned {
 
     /// Returns length of the slice in bits.
     #[inline]
-    pub fn len(&self) -> u16 { self.length }
+    pub fn len(&self) -> u32 { self.length }
 
     /// Returns whether the slice is empty.
     #[inline]
@@ -676,7 +678,7 @@ impl Owned {
     /// assert_eq!(Slice::new(&[], U3::_2, 0).unwrap(), slice);
     /// ```
     #[inline]
-    pub fn truncate(&mut self, len: u16) {
+    pub fn truncate(&mut self, len: u32) {
         if len < self.length {
             self.length = len;
             self.bytes.truncate(bytes_len(self.offset, len));
@@ -807,8 +809,8 @@ impl fmt::Display for Slice<'_> {
             }
         }
 
-        let mut off = u32::from(self.offset);
-        let mut len = off + u32::from(self.length);
+        let mut off = u64::from(self.offset);
+        let mut len = off + u64::from(self.length);
         let mut buf = [AsciiChar::Null; 9];
         buf[0] = AsciiChar::b;
 
@@ -867,7 +869,7 @@ fn debug_fmt(
 #[test]
 fn test_new_check_zeros() {
     #[track_caller]
-    fn test(ok: bool, bytes: &[u8], offset: U3, length: u16) {
+    fn test(ok: bool, bytes: &[u8], offset: U3, length: u32) {
         assert_eq!(ok, Slice::new_check_zeros(bytes, offset, length).is_some());
         // Appending non-zero bytes makes it invalid.
         let mut bytes = [bytes, &[1][..]].concat();
@@ -903,7 +905,7 @@ fn test_common_prefix() {
 
 #[test]
 fn test_display() {
-    fn test(want: &str, bytes: &[u8], offset: U3, length: u16) {
+    fn test(want: &str, bytes: &[u8], offset: U3, length: u32) {
         let slice = Slice::new(bytes, offset, length).unwrap();
         assert_eq!(want, alloc::format!("{slice}"));
     }
@@ -957,7 +959,7 @@ fn test_pop() {
                 let slice = Slice::new(
                     &BYTES[..],
                     U3::try_from(start).unwrap(),
-                    (end - start) as u16,
+                    (end - start) as u32,
                 );
                 test(&WANT[start..end], slice.unwrap(), reverse, pop);
             }
@@ -7,24 +7,23 @@ use super::{Owned, Slice};
 
 /// Trying to concatenate slices which result in slice whose size is too large.
 ///
-/// Slice’s length must not overflow u16.
+/// Slice’s length must not overflow u32.
 ///
 /// ## Example
 ///
-/// The error can happen when trying to convert a bit slice which doesn’t cover
-/// full bytes into a vector of bytes.  This may happen even if the bit slice is
-/// empty if its offset is non-zero.
+/// The error can happen when trying to concatenate slices whose total length
+/// is 512 MiB or more.  (The example isn’t run since it needs a 256 MiB
+/// buffer).
 ///
-/// ```
-/// # use sealable_trie::bits::{Error, MisalignedSlice, Slice, Owned};
-/// # use lib::u3::U3;
+/// ```no_run
+/// # use sealable_trie::bits::{Error, Slice, Owned};
 ///
-/// let buf = [255; 4096];
+/// let buf = vec![0; 256 << 20];
 /// let slice = Slice::from_bytes(&buf[..]).unwrap();
 /// assert_eq!(Err(Error::SliceTooLong), Owned::concat(slice, slice));
 ///
-/// let (suffix, _) = slice.split_at(slice.len() - 1).unwrap();
-/// Owned::concat(slice, suffix).unwrap();
+/// let (prefix, _) = slice.split_at(4096).unwrap();
+/// Owned::concat(prefix, prefix).unwrap();
 /// ```
 #[derive(Debug, PartialEq, Eq)]
 pub struct SliceTooLong;
@@ -172,7 +171,8 @@ impl<'a, 'b> Concat<Slice<'b>> for Slice<'a> {
         )?;
         // Convert prefix to Owned but with enough spare capacity so that we can
         // append suffix without reallocation.
-        let capacity = (u32::from(prefix.offset) + u32::from(length) + 7) / 8;
+        let capacity =
+            (u64::from(prefix.offset) + u64::from(length)).div_ceil(8);
         let mut bytes = Vec::with_capacity(capacity as usize);
         bytes.extend_from_slice(prefix.bytes());
         let mut slice =
@@ -217,11 +217,11 @@ pub(super) fn extend_impl(
 
 
 /// Checks that concatenating two slices produces slice whose length doesn’t
-/// overflow `u16`.
+/// overflow `u32`.
 pub(super) fn check_length(
-    pre_len: u16,
-    suf_len: u16,
-) -> Result<u16, SliceTooLong> {
+    pre_len: u32,
+    suf_len: u32,
+) -> Result<u32, SliceTooLong> {
     pre_len.checked_add(suf_len).ok_or(SliceTooLong)
 }
 
@@ -231,7 +231,7 @@ pub(super) fn check_length(
 /// That is, that `pre_off + pre_len` is congruent to `suf_off`.
 fn check_alignment(
     pre_off: U3,
-    pre_len: u16,
+    pre_len: u32,
     suf_off: U3,
 ) -> Result<(), MisalignedSlice> {
     if U3::wrap(pre_len).wrapping_add(pre_off) != suf_off {
@@ -247,10 +247,10 @@ fn check_alignment(
 /// both checks fail, it’s unspecified which error is returned.
 fn check_alignment_and_length(
     pre_off: U3,
-    pre_len: u16,
+    pre_len: u32,
     suf_off: U3,
-    suf_len: u16,
-) -> Result<u16, Error> {
+    suf_len: u32,
+) -> Result<u32, Error> {
     check_alignment(pre_off, pre_len, suf_off)?;
     Ok(check_length(pre_len, suf_len)?)
 }
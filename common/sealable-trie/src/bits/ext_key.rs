@@ -37,13 +37,16 @@ impl<'a> ExtKey<'a> {
     /// Extension key are checked and `None` returned if they aren’t met.
     #[inline]
     pub fn new(bytes: &'a [u8], offset: U3, length: u16) -> Option<Self> {
-        Slice::new(bytes, offset, length)
+        Slice::new(bytes, offset, length.into())
             .and_then(|slice| Self::try_from(slice).ok())
     }
 
     /// Returns length of the slice in bits.
     #[inline]
-    pub fn len(&self) -> u16 { self.0.len() }
+    pub fn len(&self) -> u16 {
+        // Extension keys are at most 272 bits long so this never truncates.
+        self.0.len() as u16
+    }
 
     /// Converts the object into underlying [`Slice`].
     #[inline]
@@ -83,7 +86,7 @@ impl<'a> ExtKey<'a> {
         let (&[high, low], bytes) = stdx::split_at(src)?;
         let tag = u16::from_be_bytes([high ^ tag, low]);
         let (length, offset) = U3::divmod(tag);
-        Slice::new_check_zeros(bytes, offset, length)
+        Slice::new_check_zeros(bytes, offset, length.into())
             .and_then(|slice| Self::try_from(slice).ok())
     }
 
@@ -93,7 +96,7 @@ impl<'a> ExtKey<'a> {
     /// significant bits) four zero bits, 9-bit length and 3-bit offset.  The
     /// first byte is then further xored with the `tag` argument.
     fn encode_num(&self, tag: u8) -> [u8; 2] {
-        let num = (self.0.length << 3) | u16::from(self.0.offset);
+        let num = (self.len() << 3) | u16::from(self.0.offset);
         (num ^ (u16::from(tag) << 8)).to_be_bytes()
     }
 }
@@ -147,8 +150,8 @@ impl<'a> core::iter::Iterator for Chunks<'a> {
 
     #[inline]
     fn next(&mut self) -> Option<ExtKey<'a>> {
-        const MAX_LENGTH: u16 = (MAX_EXTENSION_KEY_SIZE * 8) as u16;
-        let length = (MAX_LENGTH - u16::from(self.0.offset)).min(self.0.length);
+        const MAX_LENGTH: u32 = (MAX_EXTENSION_KEY_SIZE * 8) as u32;
+        let length = (MAX_LENGTH - u32::from(self.0.offset)).min(self.0.length);
         if length == 0 {
             None
         } else {
@@ -186,7 +189,7 @@ impl<'a> core::iter::DoubleEndedIterator for Chunks<'a> {
         }
 
         let tail = self.0.offset.wrapping_add(self.0.length);
-        let length = (bytes.len() * 8 - usize::from(-tail)) as u16;
+        let length = (bytes.len() * 8 - usize::from(-tail)) as u32;
         self.0.length -= length;
 
         Some(ExtKey(Slice {
@@ -251,7 +254,7 @@ fn test_decode() {
         let got = ExtKey::decode(&bytes, 0).unwrap_or_else(|| {
             panic!("Expected to get a ExtKey from {bytes:x?}")
         });
-        assert_eq!((want_offset, want_length), (got.0.offset, got.0.length));
+        assert_eq!((want_offset, want_length), (got.0.offset, got.len()));
     }
 
     // Correct values, all bits zero.
@@ -277,7 +280,7 @@ fn test_decode() {
         });
         assert_eq!(
             (offset, length),
-            (got.0.offset, got.0.length),
+            (got.0.offset, got.len()),
             "Invalid offset and length decoding {good:x?}"
         );
 
@@ -287,7 +290,7 @@ fn test_decode() {
         });
         assert_eq!(
             (offset, length),
-            (got.0.offset, got.0.length),
+            (got.0.offset, got.len()),
             "Invalid offset and length decoding {good:x?}"
         );
     }
@@ -330,7 +333,7 @@ fn test_chunks() {
     let data = (0..=255).collect::<alloc::vec::Vec<u8>>();
     let data = data.as_slice();
 
-    let slice = |off: U3, len: u16| Slice::new(data, off, len).unwrap();
+    let slice = |off: U3, len: u16| Slice::new(data, off, len.into()).unwrap();
 
     // Single chunk
     for offset in U3::all() {
@@ -8,7 +8,7 @@ pub mod nodes;
 pub mod proof;
 pub mod trie;
 
-pub use trie::{Error, Trie};
+pub use trie::{Error, Trie, ValueTrie};
 
 pub trait Allocator:
     memory::Allocator<Value = [u8; nodes::RawNode::SIZE]>
@@ -1,5 +1,5 @@
 use bytemuck::TransparentWrapper;
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, Hasher, Sha256};
 use memory::Ptr;
 
 use crate::bits::ExtKey;
@@ -143,7 +143,16 @@ impl<'a, P, S> Node<'a, P, S> {
     /// Because of this, hash is not calculated over raw representation of the
     /// node (as held in [`RawNode`]) and instead a custom encoding which
     /// doesn’t include pointer values or sealed information is used.
-    pub fn hash(&self) -> CryptoHash {
+    ///
+    /// The hash is calculated using SHA-256.  See [`Self::hash_with`] for
+    /// using other hash functions.
+    #[inline]
+    pub fn hash(&self) -> CryptoHash { self.hash_with::<Sha256>() }
+
+    /// Returns a hash of the node calculated with given hash function.
+    ///
+    /// See [`Self::hash`] for description of properties of the hash.
+    pub fn hash_with<H: Hasher>(&self) -> CryptoHash {
         let mut buf = [0; 68];
 
         let parts = |rf: &_| match rf {
@@ -172,7 +181,7 @@ impl<'a, P, S> Node<'a, P, S> {
                 len + 32
             }
         };
-        CryptoHash::digest(&buf[..len])
+        H::digest(&buf[..len])
     }
 }
 
@@ -1,14 +1,26 @@
 use alloc::boxed::Box;
 use alloc::vec::Vec;
-use core::num::NonZeroU16;
+use core::num::{NonZeroU16, NonZeroU32};
 
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, Hasher, Sha256};
 
 use crate::bits::{self, ExtKey};
 use crate::nodes::{Node, Reference};
 
+#[cfg(feature = "borsh")]
+mod compact;
+mod empty;
+mod multi;
 #[cfg(feature = "borsh")]
 mod serialisation;
+mod witness;
+
+pub use empty::{EmptyProof, KeySet};
+pub use multi::MultiProof;
+pub(crate) use multi::{find_value, Part};
+#[cfg(feature = "borsh")]
+pub(crate) use serialisation::deserialize_ext_key;
+pub use witness::Witness;
 
 /// A proof of a membership or non-membership of a key.
 ///
@@ -46,11 +58,11 @@ pub(crate) enum Actual {
 
     /// Length of the lookup key remaining after reaching given Extension node
     /// whose key doesn’t match the lookup key.
-    Extension(u16, Box<[u8]>, OwnedRef),
+    Extension(u32, Box<[u8]>, OwnedRef),
 
     /// Length of the lookup key remaining after reaching a value reference with
     /// given value hash.
-    LookupKeyLeft(NonZeroU16, CryptoHash),
+    LookupKeyLeft(NonZeroU32, CryptoHash),
 }
 
 /// A reference to value or node.
@@ -76,17 +88,35 @@ impl Proof {
     /// Otherwise, verifies a membership-proof.  That is, that this object
     /// proves that given `root_hash` there’s given `value_hash` stored at
     /// specified `key`.
+    ///
+    /// Assumes the trie uses SHA-256 to hash nodes.  See [`Self::verify_with`]
+    /// for tries using other hash functions.
     pub fn verify(
         &self,
         root_hash: &CryptoHash,
         key: &[u8],
         value_hash: Option<&CryptoHash>,
+    ) -> bool {
+        self.verify_with::<Sha256>(root_hash, key, value_hash)
+    }
+
+    /// Verifies the proof for a trie which uses hash function `H` to hash
+    /// nodes.
+    ///
+    /// See [`Self::verify`] for description of the arguments.
+    pub fn verify_with<H: Hasher>(
+        &self,
+        root_hash: &CryptoHash,
+        key: &[u8],
+        value_hash: Option<&CryptoHash>,
     ) -> bool {
         match (self, value_hash) {
             (Self::Positive(proof), Some(hash)) => {
-                proof.verify(root_hash, key, hash)
+                proof.verify_with::<H>(root_hash, key, hash)
+            }
+            (Self::Negative(proof), None) => {
+                proof.verify_with::<H>(root_hash, key)
             }
-            (Self::Negative(proof), None) => proof.verify(root_hash, key),
             _ => false,
         }
     }
@@ -102,17 +132,30 @@ impl Proof {
 
 impl Membership {
     /// Verifies that this object proves membership of a given key.
+    ///
+    /// Assumes the trie uses SHA-256 to hash nodes.
     pub fn verify(
         &self,
         root_hash: &CryptoHash,
         key: &[u8],
         value_hash: &CryptoHash,
+    ) -> bool {
+        self.verify_with::<Sha256>(root_hash, key, value_hash)
+    }
+
+    /// Verifies that this object proves membership of a given key in a trie
+    /// which uses hash function `H` to hash nodes.
+    pub fn verify_with<H: Hasher>(
+        &self,
+        root_hash: &CryptoHash,
+        key: &[u8],
+        value_hash: &CryptoHash,
     ) -> bool {
         if *root_hash == crate::trie::EMPTY_TRIE_ROOT {
             false
         } else if let Some(key) = bits::Slice::from_bytes(key) {
             let want = OwnedRef::value(value_hash.clone());
-            verify_impl(root_hash, key, want, &self.0).is_some()
+            verify_impl::<H>(root_hash, key, want, &self.0).is_some()
         } else {
             false
         }
@@ -121,11 +164,23 @@ impl Membership {
 
 impl NonMembership {
     /// Verifies that this object proves non-membership of a given key.
+    ///
+    /// Assumes the trie uses SHA-256 to hash nodes.
     pub fn verify(&self, root_hash: &CryptoHash, key: &[u8]) -> bool {
+        self.verify_with::<Sha256>(root_hash, key)
+    }
+
+    /// Verifies that this object proves non-membership of a given key in
+    /// a trie which uses hash function `H` to hash nodes.
+    pub fn verify_with<H: Hasher>(
+        &self,
+        root_hash: &CryptoHash,
+        key: &[u8],
+    ) -> bool {
         if *root_hash == crate::trie::EMPTY_TRIE_ROOT {
             true
-        } else if let Some((key, want)) = self.get_reference(key) {
-            verify_impl(root_hash, key, want, &self.1).is_some()
+        } else if let Some((key, want)) = self.get_reference::<H>(key) {
+            verify_impl::<H>(root_hash, key, want, &self.1).is_some()
         } else {
             false
         }
@@ -136,7 +191,7 @@ impl NonMembership {
     /// For non-membership proofs, the proofs include the actual node that has
     /// been found while looking up the key.  This translates that information
     /// into a key and reference that the rest of the commitment needs to prove.
-    fn get_reference<'a>(
+    fn get_reference<'a, H: Hasher>(
         &self,
         key: &'a [u8],
     ) -> Option<(bits::Slice<'a>, OwnedRef)> {
@@ -150,7 +205,7 @@ impl NonMembership {
                 // We’re converting non-membership proof into proof that at key
                 // the given branch Node exists.
                 let node = Node::Branch { children: [lft.into(), rht.into()] };
-                Some((key, OwnedRef::to(node)))
+                Some((key, OwnedRef::to::<H, _, _>(node)))
             }
 
             Actual::Extension(left, key_buf, child) => {
@@ -176,7 +231,7 @@ impl NonMembership {
                         key: ext_key,
                         child: Reference::from(child),
                     };
-                    Some((key, OwnedRef::to(node)))
+                    Some((key, OwnedRef::to::<H, _, _>(node)))
                 }
             }
 
@@ -195,7 +250,7 @@ impl NonMembership {
     }
 }
 
-fn verify_impl(
+fn verify_impl<H: Hasher>(
     root_hash: &CryptoHash,
     mut key: bits::Slice,
     mut want: OwnedRef,
@@ -214,12 +269,12 @@ fn verify_impl(
             }
 
             Item::Extension(length) => Node::Extension {
-                key: ExtKey::try_from(key.pop_back_slice(length.get())?)
+                key: ExtKey::try_from(key.pop_back_slice(length.get().into())?)
                     .ok()?,
                 child: Reference::from(&want),
             },
         };
-        want = OwnedRef::to(node);
+        want = OwnedRef::to::<H, _, _>(node);
     }
 
     // If we’re here we’ve reached root hash according to the proof.  Check the
@@ -292,13 +347,11 @@ impl Builder {
     /// fields of the extension node.
     pub fn reached_extension<T: From<NonMembership>>(
         self,
-        left: u16,
+        left: u32,
         key: ExtKey,
         child: Reference,
     ) -> T {
-        let mut buf = [0; 36];
-        let len = key.encode_into(&mut buf, 0);
-        let ext_key = buf[..len].to_vec().into_boxed_slice();
+        let ext_key = encode_ext_key(key);
         self.negative(Actual::Extension(left, ext_key, child.into()))
     }
 
@@ -313,20 +366,33 @@ impl Builder {
     /// reference.
     pub fn lookup_key_left<T: From<NonMembership>>(
         self,
-        left: NonZeroU16,
+        left: NonZeroU32,
         value: CryptoHash,
     ) -> T {
         self.negative(Actual::LookupKeyLeft(left, value))
     }
 }
 
+/// Encodes Extension key into a buffer as stored in proofs.
+///
+/// The key is encoded as by [`ExtKey::encode_into`] with no tag and with
+/// unused trailing bytes stripped.  [`ExtKey::decode`] with zero tag can be
+/// used to get the key back.
+pub(crate) fn encode_ext_key(key: ExtKey) -> Box<[u8]> {
+    let mut buf = [0; 36];
+    let len = key.encode_into(&mut buf, 0);
+    buf[..len].to_vec().into_boxed_slice()
+}
+
 impl OwnedRef {
     /// Creates a reference pointing at node with given hash.
     fn node(hash: CryptoHash) -> Self { Self { is_value: false, hash } }
     /// Creates a reference pointing at value with given hash.
     fn value(hash: CryptoHash) -> Self { Self { is_value: true, hash } }
     /// Creates a reference pointing at given node.
-    fn to<P, S>(node: Node<P, S>) -> Self { Self::node(node.hash()) }
+    fn to<H: Hasher, P, S>(node: Node<P, S>) -> Self {
+        Self::node(node.hash_with::<H>())
+    }
 
     #[cfg(test)]
     #[allow(dead_code)]
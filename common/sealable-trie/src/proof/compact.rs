@@ -0,0 +1,430 @@
+use alloc::boxed::Box;
+use alloc::format;
+#[cfg(test)]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::num::NonZeroU16;
+
+use borsh::maybestd::io;
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+use super::serialisation::{
+    deserialize_owned_ref, invalid_data, NON_MEMBERSHIP_SHIFT,
+};
+use super::{Actual, Item, Membership, NonMembership, Proof};
+
+/// Bit of the proof header indicating compact encoding.
+///
+/// Borsh encoding limits number of entries in a proof to 8191 thus this bit is
+/// never set in its header.  This allows the encodings to be told apart.
+///
+/// Since there are no more spare bits in the header, the header is followed
+/// by a [`COMPACT_VERSION`] byte which allows changing the encoding in the
+/// future.
+pub(super) const COMPACT_FLAG: u16 = 1 << 14;
+
+/// Version of the compact encoding.
+///
+/// It’s written after the header and deserialisation rejects proofs with
+/// unknown version.
+const COMPACT_VERSION: u8 = 0;
+
+/// Bit of the compact proof header indicating presence of an [`Actual`].
+const HAS_ACTUAL_FLAG: u16 = 1 << 13;
+
+/// Mask for number of items in the compact proof header.
+const ITEMS_MASK: u16 = HAS_ACTUAL_FLAG - 1;
+
+// Encoding: <header:u16> <version:u8> <actual>? <flags> <entry>*
+//
+// `header` is `items.len() | has_actual << 13 | 1 << 14 | non_membership << 15`,
+// `version` is `COMPACT_VERSION` and `actual` uses the same encoding as in
+// Borsh proofs.
+//
+// `flags` is a bitmap (least significant bit first, zero-padded to full bytes)
+// with one bit for each item indicating whether it’s an Extension.  For Branch
+// items the bit is followed by another one indicating whether the sibling is
+// a value.
+//
+// Items follow in order with the following entries:
+//  - Branch — 32-byte hash of the sibling,
+//  - Extension starting a run of Extensions with equal key lengths —
+//    `varint((len - 1) << 1 | (run > 1))` followed by `varint(run - 2)` if
+//    `run > 1`; Extensions continuing a run have no entry.
+//
+// `varint` is an unsigned LEB128 integer.  Runs are always as long as possible
+// and integers use the shortest encoding which makes the encoding canonical.
+impl Proof {
+    /// Serialises the proof using compact encoding.
+    ///
+    /// Compared to the Borsh encoding, tag bits of all the items are packed
+    /// into a single bitmap, lengths of Extension keys use variable-length
+    /// integers and runs of Extensions with equal key lengths (which is what
+    /// proofs for long keys consist of) are coalesced.
+    ///
+    /// Borsh deserialisation of a [`Proof`] detects which encoding it’s reading
+    /// so the result can be decoded with [`BorshDeserialize`].
+    pub fn serialize_compact<W: io::Write>(
+        &self,
+        wr: &mut W,
+    ) -> io::Result<()> {
+        let (membership, actual, items) = match self {
+            Self::Positive(prf) => (true, None, prf.0.as_slice()),
+            Self::Negative(prf) => (false, prf.0.as_deref(), prf.1.as_slice()),
+        };
+
+        u16::try_from(items.len())
+            .ok()
+            .filter(|len| *len <= ITEMS_MASK)
+            .map(|len| {
+                len | (u16::from(actual.is_some()) * HAS_ACTUAL_FLAG) |
+                    COMPACT_FLAG |
+                    (u16::from(!membership) << NON_MEMBERSHIP_SHIFT)
+            })
+            .ok_or_else(|| {
+                invalid_data(format!("proof too long: {}", items.len()))
+            })?
+            .serialize(wr)?;
+        COMPACT_VERSION.serialize(wr)?;
+        if let Some(actual) = actual {
+            actual.serialize(wr)?;
+        }
+
+        let mut flags = BitWriter::default();
+        for item in items {
+            match item {
+                Item::Branch(child) => {
+                    flags.push(false);
+                    flags.push(child.is_value);
+                }
+                Item::Extension(_) => flags.push(true),
+            }
+        }
+        wr.write_all(&flags.0)?;
+
+        let mut items = items;
+        while let Some((item, rest)) = items.split_first() {
+            items = rest;
+            let len = match item {
+                Item::Branch(child) => {
+                    wr.write_all(child.hash.as_slice())?;
+                    continue;
+                }
+                Item::Extension(len) => len,
+            };
+            let run = items
+                .iter()
+                .take_while(
+                    |item| matches!(item, Item::Extension(l) if l == len),
+                )
+                .count();
+            items = &items[run..];
+            let head = (u32::from(len.get() - 1) << 1) | u32::from(run > 0);
+            serialize_varint(head, wr)?;
+            if run > 0 {
+                let run = u32::try_from(run - 1).unwrap();
+                serialize_varint(run, wr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deserialises a compact [`Proof`] whose header has already been read.
+///
+/// See [`Proof::serialize_compact`].
+pub(super) fn deserialize_cont(
+    header: u16,
+    rd: &mut impl io::Read,
+) -> io::Result<Proof> {
+    let version = u8::deserialize_reader(rd)?;
+    if version != COMPACT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported compact proof version: {version}"
+        )));
+    }
+    let is_membership = header & (1 << NON_MEMBERSHIP_SHIFT) == 0;
+    let len = usize::from(header & ITEMS_MASK);
+    let actual = if header & HAS_ACTUAL_FLAG == 0 {
+        None
+    } else if is_membership {
+        return Err(invalid_data("Actual in membership proof".into()));
+    } else {
+        Some(Box::new(Actual::deserialize_reader(rd)?))
+    };
+
+    // For each item, None if it’s an Extension or Some(is_value) if it’s
+    // a Branch.
+    let mut flags = BitReader::default();
+    let kinds = (0..len)
+        .map(|_| {
+            Ok(match flags.read(rd)? {
+                true => None,
+                false => Some(flags.read(rd)?),
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    flags.finish()?;
+
+    let mut items = Vec::with_capacity(len);
+    // Key length and number of remaining Extensions in the current run.
+    let mut run: Option<(NonZeroU16, usize)> = None;
+    // Key length of the previous item if it was an Extension.
+    let mut prev = None;
+    for kind in kinds {
+        let key_len = match (kind, run) {
+            (Some(_), Some(_)) => {
+                return Err(invalid_data("Extension run too long".into()));
+            }
+            (Some(is_value), None) => {
+                items.push(Item::Branch(deserialize_owned_ref(rd, is_value)?));
+                prev = None;
+                continue;
+            }
+            (None, Some((key_len, left))) => {
+                run = (left > 1).then_some((key_len, left - 1));
+                key_len
+            }
+            (None, None) => {
+                let head = deserialize_varint(rd)?;
+                let key_len = u16::try_from((head >> 1) + 1)
+                    .ok()
+                    .and_then(NonZeroU16::new)
+                    .ok_or_else(|| {
+                        invalid_data(format!("invalid Extension: {head}"))
+                    })?;
+                if prev == Some(key_len) {
+                    return Err(invalid_data("non-canonical Extension".into()));
+                }
+                if head & 1 == 1 {
+                    let left = deserialize_varint(rd)?;
+                    let left = usize::try_from(left)
+                        .ok()
+                        .filter(|left| *left < len)
+                        .ok_or_else(|| {
+                            invalid_data("Extension run too long".into())
+                        })?;
+                    run = Some((key_len, left + 1));
+                }
+                key_len
+            }
+        };
+        items.push(Item::Extension(key_len));
+        prev = Some(key_len);
+    }
+    if run.is_some() {
+        return Err(invalid_data("Extension run too long".into()));
+    }
+
+    Ok(if is_membership {
+        Proof::Positive(Membership(items))
+    } else {
+        Proof::Negative(NonMembership(actual, items))
+    })
+}
+
+/// Writer of the flags bitmap.
+#[derive(Default)]
+struct BitWriter(Vec<u8>, u8);
+
+impl BitWriter {
+    fn push(&mut self, bit: bool) {
+        if self.1 == 0 {
+            self.0.push(0);
+        }
+        if bit {
+            *self.0.last_mut().unwrap() |= 1 << self.1;
+        }
+        self.1 = (self.1 + 1) % 8;
+    }
+}
+
+/// Reader of the flags bitmap.
+///
+/// Holds the byte currently being read and number of bits left in it.
+#[derive(Default)]
+struct BitReader(u8, u8);
+
+impl BitReader {
+    fn read(&mut self, rd: &mut impl io::Read) -> io::Result<bool> {
+        if self.1 == 0 {
+            self.0 = u8::deserialize_reader(rd)?;
+            self.1 = 8;
+        }
+        let bit = self.0 & 1 == 1;
+        self.0 >>= 1;
+        self.1 -= 1;
+        Ok(bit)
+    }
+
+    /// Verifies that padding bits of the bitmap are zero.
+    fn finish(self) -> io::Result<()> {
+        match self.0 {
+            0 => Ok(()),
+            _ => Err(invalid_data("non-zero flags padding".into())),
+        }
+    }
+}
+
+/// Serialises an unsigned LEB128 integer.
+fn serialize_varint(mut value: u32, wr: &mut impl io::Write) -> io::Result<()> {
+    while value >= 0x80 {
+        wr.write_all(&[(value as u8) | 0x80])?;
+        value >>= 7;
+    }
+    wr.write_all(&[value as u8])
+}
+
+/// Deserialises an unsigned LEB128 integer rejecting non-canonical encodings.
+fn deserialize_varint(rd: &mut impl io::Read) -> io::Result<u32> {
+    let mut value = 0;
+    for shift in (0..32).step_by(7) {
+        let byte = u8::deserialize_reader(rd)?;
+        let bits = u32::from(byte & 0x7F);
+        if shift > 0 && byte == 0 {
+            return Err(invalid_data("non-canonical varint".into()));
+        } else if bits.leading_zeros() < shift {
+            return Err(invalid_data("varint overflow".into()));
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("varint overflow".into()))
+}
+
+
+#[cfg(test)]
+fn to_compact_vec(proof: &Proof) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    proof.serialize_compact(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn test_compact_proof() {
+    use alloc::vec;
+    use core::num::NonZeroU32;
+
+    use lib::hash::CryptoHash;
+
+    use super::OwnedRef;
+
+    #[track_caller]
+    fn test(want_proof: Proof, want_bytes: &[u8]) {
+        let got_bytes = to_compact_vec(&want_proof);
+        let got_proof =
+            Proof::try_from_slice(want_bytes).map_err(|err| err.to_string());
+        assert_eq!(
+            (Ok(&want_proof), want_bytes),
+            (got_proof.as_ref(), got_bytes.as_slice()),
+        );
+    }
+
+    let ext = |len| Item::Extension(NonZeroU16::new(len).unwrap());
+
+    test(Proof::Positive(Membership(vec![])), &[0, 0x40, 0]);
+    test(Proof::Negative(NonMembership(None, vec![])), &[0, 0xC0, 0]);
+    test(Proof::Positive(Membership(vec![ext(42)])), &[1, 0x40, 0, 1, 82]);
+    #[rustfmt::skip]
+    test(Proof::Positive(Membership(vec![
+        Item::Branch(OwnedRef::test(true, 1)),
+        ext(42), ext(42), ext(42), ext(7),
+        Item::Branch(OwnedRef::test(false, 2)),
+        ext(272), ext(272),
+    ])), &[
+        /* header: */ 8, 0x40,
+        /* version: */ 0,
+        /* flags: */ 0b0011_1110, 0b0000_0011,
+        /* branch: */ 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+                      0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+        /* extension ×3: */ 83, 1,
+        /* extension: */ 12,
+        /* branch: */ 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2,
+                      0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2,
+        /* extension ×2: */ 0x9F, 4, 0,
+    ]);
+    #[rustfmt::skip]
+    test(Proof::Negative(NonMembership(
+        Some(Box::new(Actual::LookupKeyLeft(
+            NonZeroU32::MIN,
+            CryptoHash::test(1),
+        ))),
+        vec![ext(1)],
+    )), &[
+        /* header: */ 1, 0xE0,
+        /* version: */ 0,
+        /* actual: */ 0x86, 1, 0,
+                      0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+                      0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+        /* flags: */ 1,
+        /* extension: */ 0,
+    ]);
+
+    // Invalid encodings are rejected.
+    for bytes in [
+        // Non-zero padding of flags.
+        &[1, 0x40, 0, 3, 82][..],
+        // Run longer than number of Extensions.
+        &[2, 0x40, 0, 3, 83, 1],
+        // Run interrupted by a Branch.
+        &[3, 0x40, 0, 0b1001, 83, 0],
+        // Two adjacent runs with the same key length.
+        &[2, 0x40, 0, 3, 82, 82],
+        // Non-canonical varint.
+        &[1, 0x40, 0, 1, 0xD2, 0],
+        // Extension key length too large.
+        &[1, 0x40, 0, 1, 0xFF, 0xFF, 0x0F],
+        // Actual in membership proof.
+        &[0, 0x60, 0, 0x86, 1, 0],
+        // Unknown version.
+        &[1, 0x40, 1, 1, 82],
+        // Missing version.
+        &[0, 0x40],
+    ] {
+        Proof::try_from_slice(bytes).unwrap_err();
+    }
+}
+
+#[test]
+fn test_compact_proof_trie() {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    let mut trie = crate::trie::Trie::test(10_000);
+    let mut keys = Vec::new();
+    for num in 0..100 {
+        let len = if num % 10 == 0 { 500 } else { rng.gen_range(1..20) };
+        // Start key with its length so that no key is a prefix of another.
+        let mut key = alloc::vec![0; len];
+        rng.fill(&mut key[..]);
+        key[0] = len as u8;
+        trie.set(&key, &lib::hash::CryptoHash::test(num)).unwrap();
+        keys.push(key);
+    }
+    keys.push(b"missing".to_vec());
+    keys.push(alloc::vec![0xF4; 600]);
+
+    // Compact encoding may be slightly longer for some proofs (e.g. ones
+    // consisting of a single long Extension) so only compare total sizes.
+    // For proofs in this test the overhead is at most the version byte and
+    // a byte of the flags bitmap.
+    let (mut borsh_total, mut compact_total) = (0, 0);
+    for key in keys {
+        let (_, proof) = trie.prove(&key).unwrap();
+        let borsh = borsh::to_vec(&proof).unwrap();
+        let compact = to_compact_vec(&proof);
+        if key.len() == 500 {
+            assert!(compact.len() + 20 < borsh.len(), "{key:?}");
+        }
+        assert!(compact.len() <= borsh.len() + 2, "{key:?}");
+        assert_eq!(proof, Proof::try_from_slice(&borsh).unwrap());
+        assert_eq!(proof, Proof::try_from_slice(&compact).unwrap());
+        borsh_total += borsh.len();
+        compact_total += compact.len();
+    }
+    assert!(compact_total < borsh_total);
+}
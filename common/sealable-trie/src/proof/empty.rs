@@ -0,0 +1,271 @@
+use core::cmp::Ordering;
+
+use lib::hash::{CryptoHash, Hasher, Sha256};
+
+use super::Part;
+use crate::bits::{self, ExtKey};
+
+/// A proof that there are no values in a set of keys.
+///
+/// The proof holds the portion of the trie which overlaps with the set of
+/// keys.  Subtries which are entirely outside of the set are included only as
+/// references.  Since every node in the trie has at least one value under it,
+/// a node which overlaps with the set must be included in the proof unless
+/// the set isn’t empty.
+///
+/// As with [`super::Proof`], the proof doesn’t include the set of keys.  It’s
+/// caller responsibility to pair proof with correct [`KeySet`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmptyProof(pub(super) Option<Part>);
+
+/// A set of keys whose emptiness can be proven with an [`EmptyProof`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeySet<'a> {
+    /// All keys starting with given prefix.
+    ///
+    /// The prefix is a bit slice so it doesn’t need to end at a byte boundary.
+    /// If it does, the key equal to the prefix also belongs to the set.
+    Prefix(bits::Slice<'a>),
+
+    /// All keys `key` such that `start ≤ key < end` using lexicographical
+    /// ordering.  If `start ≥ end` the set is empty.
+    Range(&'a [u8], &'a [u8]),
+}
+
+impl EmptyProof {
+    /// Creates a new proof with given root.
+    ///
+    /// `None` indicates that the trie is empty.
+    pub(crate) fn new(root: Option<Part>) -> Self { Self(root) }
+
+    /// Verifies that this object proves that given `root_hash` there are no
+    /// values at any of the keys in the `set`.
+    ///
+    /// Assumes the trie uses SHA-256 to hash nodes.  See [`Self::verify_with`]
+    /// for tries using other hash functions.
+    pub fn verify(&self, root_hash: &CryptoHash, set: KeySet<'_>) -> bool {
+        self.verify_with::<Sha256>(root_hash, set)
+    }
+
+    /// Verifies the proof for a trie which uses hash function `H` to hash
+    /// nodes.
+    pub fn verify_with<H: Hasher>(
+        &self,
+        root_hash: &CryptoHash,
+        set: KeySet<'_>,
+    ) -> bool {
+        if *root_hash == crate::trie::EMPTY_TRIE_ROOT {
+            return true;
+        }
+        let root = match self.0.as_ref() {
+            Some(root) => root,
+            None => return false,
+        };
+        match root.hash::<H>(&mut bits::Owned::default(), &[]) {
+            Some(rf) if !rf.is_value && rf.hash == *root_hash => (),
+            _ => return false,
+        }
+        check(root, &mut bits::Owned::default(), &set).unwrap_or(false)
+    }
+}
+
+/// Checks that no leaf of the `part` located at `path` belongs to the `set`.
+///
+/// Returns `None` if the proof is malformed.  When function returns, `path`
+/// has unspecified value.
+fn check(part: &Part, path: &mut bits::Owned, set: &KeySet) -> Option<bool> {
+    Some(match part {
+        Part::Ref(rf) if rf.is_value => !set.contains(path.as_slice()),
+        Part::Ref(_) => !set.overlaps(path.as_slice()),
+        // Value placeholders are used for values which are known to the
+        // verifier.  There are no such values in emptiness proofs.
+        Part::Value => false,
+        Part::Branch(children) => {
+            let len = path.len();
+            path.push_back(false).ok()?;
+            if !check(&children[0], path, set)? {
+                return Some(false);
+            }
+            path.truncate(len);
+            path.push_back(true).ok()?;
+            check(&children[1], path, set)?
+        }
+        Part::Extension(key, child) => {
+            path.extend(ExtKey::decode(key, 0)?.into_slice()).ok()?;
+            check(child, path, set)?
+        }
+    })
+}
+
+impl KeySet<'_> {
+    /// Returns whether a value at given `key` belongs to the set.
+    pub(crate) fn contains(&self, key: bits::Slice<'_>) -> bool {
+        match *self {
+            Self::Prefix(prefix) => {
+                key.len() >= prefix.len() &&
+                    compare(bits_of(key), bits_of(prefix)).is_none()
+            }
+            Self::Range(start, end) => match <&[u8]>::try_from(key) {
+                Ok(key) => start <= key && key < end,
+                // Values are always stored at byte-aligned keys.  Being
+                // conservative, treat any other key as part of the set.
+                Err(_) => true,
+            },
+        }
+    }
+
+    /// Returns whether a subtrie rooted at node at given `path` may contain
+    /// keys which belong to the set.
+    ///
+    /// Keys in such subtrie start with `path` and are longer than it.
+    pub(crate) fn overlaps(&self, path: bits::Slice<'_>) -> bool {
+        match *self {
+            Self::Prefix(prefix) => {
+                compare(bits_of(path), bits_of(prefix)).is_none()
+            }
+            Self::Range(start, end) => {
+                if start >= end {
+                    return false;
+                }
+                // All keys in the subtrie are greater than `path` so if `path`
+                // is `end` or follows it, there’s no overlap.
+                let before_end = match compare(bits_of(path), bytes_bits(end)) {
+                    Some(ord) => ord == Ordering::Less,
+                    None => u64::from(path.len()) < end.len() as u64 * 8,
+                };
+                // If `path` precedes `start` and isn’t its prefix, all keys in
+                // the subtrie precede `start` as well.
+                before_end &&
+                    compare(bits_of(path), bytes_bits(start)) !=
+                        Some(Ordering::Less)
+            }
+        }
+    }
+}
+
+/// Compares two bit strings lexicographically.
+///
+/// Returns `None` if one of the strings is a prefix of the other.  Otherwise
+/// returns ordering of the two strings as determined by the first differing
+/// bit.
+fn compare(
+    mut lhs: impl Iterator<Item = bool>,
+    mut rhs: impl Iterator<Item = bool>,
+) -> Option<Ordering> {
+    loop {
+        match (lhs.next()?, rhs.next()?) {
+            (a, b) if a == b => continue,
+            (a, b) => break Some(a.cmp(&b)),
+        }
+    }
+}
+
+/// Returns iterator over bits of a slice.
+fn bits_of(mut slice: bits::Slice<'_>) -> impl Iterator<Item = bool> + '_ {
+    core::iter::from_fn(move || slice.pop_front())
+}
+
+/// Returns iterator over bits of bytes starting from the most significant.
+fn bytes_bits(bytes: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |n| byte & (1 << n) != 0))
+}
+
+#[test]
+fn test_key_set() {
+    let slice = |bytes: &'static [u8], len: u32| {
+        bits::Slice::new(bytes, lib::u3::U3::_0, len).unwrap()
+    };
+    let bytes = |bytes: &'static [u8]| bits::Slice::from_bytes(bytes).unwrap();
+
+    let set = KeySet::Prefix(slice(b"fo", 12));
+    assert!(set.contains(bytes(b"fo")));
+    assert!(set.contains(bytes(b"foo")));
+    assert!(set.contains(bytes(b"fa")));
+    assert!(!set.contains(bytes(b"f")));
+    assert!(!set.contains(bytes(b"go")));
+    assert!(set.overlaps(slice(b"f", 4)));
+    assert!(set.overlaps(bytes(b"f")));
+    assert!(set.overlaps(bytes(b"foo")));
+    assert!(!set.overlaps(bytes(b"g")));
+    assert!(!set.overlaps(bytes(b"fp")));
+
+    let set = KeySet::Range(b"bar", b"foo");
+    assert!(set.contains(bytes(b"bar")));
+    assert!(set.contains(bytes(b"baz")));
+    assert!(set.contains(bytes(b"fo")));
+    assert!(!set.contains(bytes(b"ba")));
+    assert!(!set.contains(bytes(b"foo")));
+    assert!(!set.contains(bytes(b"fooo")));
+    assert!(set.overlaps(bytes(b"")));
+    assert!(set.overlaps(bytes(b"ba")));
+    assert!(set.overlaps(bytes(b"bar")));
+    assert!(set.overlaps(bytes(b"c")));
+    assert!(set.overlaps(bytes(b"fo")));
+    assert!(!set.overlaps(bytes(b"foo")));
+    assert!(!set.overlaps(bytes(b"a")));
+    assert!(!set.overlaps(bytes(b"baq")));
+    assert!(!set.overlaps(bytes(b"g")));
+
+    let set = KeySet::Range(b"foo", b"bar");
+    assert!(!set.contains(bytes(b"foo")));
+    assert!(!set.overlaps(bytes(b"")));
+}
+
+#[test]
+fn test_prove_empty() {
+    use lib::u3::U3;
+
+    let mut trie = crate::trie::Trie::test(1000);
+    for (idx, key) in ["foo", "bar", "baz", "qux", "quux"].iter().enumerate() {
+        trie.set(key.as_bytes(), &CryptoHash::test(idx)).unwrap();
+    }
+
+    let prefix = |bytes: &'static [u8]| {
+        KeySet::Prefix(bits::Slice::from_bytes(bytes).unwrap())
+    };
+    let short = bits::Slice::new(b"\x80", U3::_0, 3).unwrap();
+    for (set, want) in [
+        (prefix(b"fo"), false),
+        (prefix(b"foo"), false),
+        (prefix(b"qu"), false),
+        (prefix(b"fooo"), true),
+        (prefix(b"bay"), true),
+        (prefix(b"quuy"), true),
+        (prefix(b"x"), true),
+        (prefix(b""), false),
+        (KeySet::Prefix(short), true),
+        (KeySet::Prefix(short.split_at(1).unwrap().0), true),
+        (KeySet::Prefix(bits::Slice::new(b"b", U3::_0, 3).unwrap()), false),
+        (KeySet::Range(b"bar", b"bar"), true),
+        (KeySet::Range(b"bar", b"baz"), false),
+        (KeySet::Range(b"bara", b"baz"), true),
+        (KeySet::Range(b"bara", b"baza"), false),
+        (KeySet::Range(b"fooo", b"qux"), false),
+        (KeySet::Range(b"fooo", b"quux"), true),
+        (KeySet::Range(b"qux", b"bar"), true),
+        (KeySet::Range(b"r", b"z"), true),
+        (KeySet::Range(b"", b"bar"), true),
+    ] {
+        let proof = trie.prove_empty(set).unwrap();
+        assert_eq!(want, proof.is_some(), "set: {set:?}");
+        if let Some(proof) = proof {
+            assert!(proof.verify(trie.hash(), set), "set: {set:?}");
+            assert!(!proof.verify(&CryptoHash::test(42), set));
+        }
+    }
+
+    // Proof of one set doesn’t prove emptiness of an overlapping set.
+    let proof = trie.prove_empty(KeySet::Range(b"bara", b"baz")).unwrap();
+    let proof = proof.unwrap();
+    assert!(!proof.verify(trie.hash(), KeySet::Range(b"bar", b"baz")));
+    assert!(!proof.verify(trie.hash(), KeySet::Range(b"bara", b"bazz")));
+    assert!(!proof.verify(trie.hash(), prefix(b"f")));
+
+    // Empty trie.
+    let empty = crate::trie::Trie::test(10);
+    let proof = empty.prove_empty(prefix(b"")).unwrap().unwrap();
+    assert!(proof.verify(empty.hash(), prefix(b"")));
+    assert!(!proof.verify(trie.hash(), prefix(b"x")));
+}
@@ -0,0 +1,275 @@
+use alloc::boxed::Box;
+
+use lib::hash::{CryptoHash, Hasher, Sha256};
+
+use super::OwnedRef;
+use crate::bits::{self, ExtKey};
+use crate::nodes::Node;
+
+/// A proof of membership or non-membership of multiple keys.
+///
+/// Rather than holding a separate list of items for each key, the proof holds
+/// the portion of the trie traversed when looking up all the keys.  Nodes on
+/// paths shared by multiple keys are therefore included only once.
+///
+/// As with [`super::Proof`], the proof doesn’t include the keys or values.
+/// It’s caller responsibility to pair proof with correct keys and values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiProof(pub(super) Option<Part>);
+
+/// A portion of the trie included in a [`MultiProof`].
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Part {
+    /// Reference to a node or value whose contents aren’t part of the proof.
+    Ref(OwnedRef),
+
+    /// A value reference whose hash is provided by the verifier.
+    ///
+    /// Value hashes of keys whose membership is being proven are known to the
+    /// verifier thus there’s no need to include them in the proof.  The hash
+    /// is taken from entry for key corresponding to the path at which this
+    /// part is located.
+    Value,
+
+    /// A Branch node with given children.
+    Branch(Box<[Part; 2]>),
+
+    /// An Extension node with given key and child.  The key is encoded as
+    /// returned by [`super::encode_ext_key`].
+    Extension(Box<[u8]>, Box<Part>),
+}
+
+impl MultiProof {
+    /// Creates a new proof with given root.
+    ///
+    /// `None` indicates that the trie is empty.
+    pub(crate) fn new(root: Option<Part>) -> Self { Self(root) }
+
+    /// Returns root of the proof or `None` if the proof is for an empty trie.
+    pub(crate) fn root(&self) -> Option<&Part> { self.0.as_ref() }
+
+    /// Verifies that this object proves membership or non-membership of all
+    /// given keys.
+    ///
+    /// `entries` is a list of `(key, value_hash)` pairs.  If `value_hash` is
+    /// `None`, verifies that there’s no value at the `key`.  Otherwise,
+    /// verifies that there’s given `value_hash` stored at the `key`.
+    ///
+    /// Returns `true` only if proof is valid for all of the entries.  Since
+    /// value hashes aren’t included in the proof, `entries` must contain all
+    /// keys with a value that the proof has been generated for.  Keys without
+    /// a value may be omitted.
+    ///
+    /// Assumes the trie uses SHA-256 to hash nodes.  See [`Self::verify_with`]
+    /// for tries using other hash functions.
+    pub fn verify(
+        &self,
+        root_hash: &CryptoHash,
+        entries: &[(&[u8], Option<&CryptoHash>)],
+    ) -> bool {
+        self.verify_with::<Sha256>(root_hash, entries)
+    }
+
+    /// Verifies the proof for a trie which uses hash function `H` to hash
+    /// nodes.
+    ///
+    /// See [`Self::verify`] for description of the arguments.
+    pub fn verify_with<H: Hasher>(
+        &self,
+        root_hash: &CryptoHash,
+        entries: &[(&[u8], Option<&CryptoHash>)],
+    ) -> bool {
+        if *root_hash == crate::trie::EMPTY_TRIE_ROOT {
+            return entries.iter().all(|(_, value)| value.is_none());
+        }
+        let root = match self.0.as_ref() {
+            Some(root) => root,
+            None => return false,
+        };
+        match root.hash::<H>(&mut bits::Owned::default(), entries) {
+            Some(rf) if !rf.is_value && rf.hash == *root_hash => (),
+            _ => return false,
+        }
+        entries.iter().all(|(key, value)| root.lookup(key, *value, entries))
+    }
+}
+
+impl Part {
+    /// Calculates reference to this part of the proof.
+    ///
+    /// `path` is the key at which the part is located.  It’s used to find hash
+    /// of [`Part::Value`] values in `entries`.  When function returns, `path`
+    /// has unspecified value.
+    ///
+    /// Returns `None` if the proof is malformed.
+    pub(super) fn hash<H: Hasher>(
+        &self,
+        path: &mut bits::Owned,
+        entries: &[(&[u8], Option<&CryptoHash>)],
+    ) -> Option<OwnedRef> {
+        Some(match self {
+            Self::Ref(rf) => rf.clone(),
+            Self::Value => {
+                let key = <&[u8]>::try_from(path.as_slice()).ok()?;
+                OwnedRef::value(find_value(entries, key)?.clone())
+            }
+            Self::Branch(children) => {
+                let len = path.len();
+                path.push_back(false).ok()?;
+                let left = children[0].hash::<H>(path, entries)?;
+                path.truncate(len);
+                path.push_back(true).ok()?;
+                let right = children[1].hash::<H>(path, entries)?;
+                path.truncate(len);
+                let children = [(&left).into(), (&right).into()];
+                OwnedRef::to::<H, _, _>(Node::Branch { children })
+            }
+            Self::Extension(key, child) => {
+                let key = ExtKey::decode(key, 0)?;
+                let len = path.len();
+                path.extend(key.into_slice()).ok()?;
+                let child = child.hash::<H>(path, entries)?;
+                path.truncate(len);
+                let node = Node::Extension { key, child: (&child).into() };
+                OwnedRef::to::<H, _, _>(node)
+            }
+        })
+    }
+
+    /// Looks up key in the proof and checks whether the result matches
+    /// expected value hash.
+    ///
+    /// Returns `false` if the proof doesn’t include enough of the trie to
+    /// determine value at the key or the value doesn’t match.
+    fn lookup(
+        &self,
+        key: &[u8],
+        want: Option<&CryptoHash>,
+        entries: &[(&[u8], Option<&CryptoHash>)],
+    ) -> bool {
+        let full_key = key;
+        let mut key = match bits::Slice::from_bytes(key) {
+            Some(key) => key,
+            None => return false,
+        };
+        let mut part = self;
+        loop {
+            part = match part {
+                Self::Ref(rf) if rf.is_value => {
+                    return if key.is_empty() {
+                        want == Some(&rf.hash)
+                    } else {
+                        want.is_none()
+                    };
+                }
+                // Node reference at the end of the key means there’s no value
+                // at the key.  If the key hasn’t finished, the proof doesn’t
+                // include enough information to continue the lookup.
+                Self::Ref(_) => return key.is_empty() && want.is_none(),
+                Self::Value => {
+                    return if key.is_empty() {
+                        want.is_some() && want == find_value(entries, full_key)
+                    } else {
+                        want.is_none()
+                    };
+                }
+                Self::Branch(children) => match key.pop_front() {
+                    Some(bit) => &children[usize::from(bit)],
+                    None => return want.is_none(),
+                },
+                Self::Extension(ext_key, child) => {
+                    match ExtKey::decode(ext_key, 0) {
+                        Some(ext_key) if key.strip_prefix(ext_key.into()) => {
+                            child
+                        }
+                        Some(_) => return want.is_none(),
+                        None => return false,
+                    }
+                }
+            };
+        }
+    }
+}
+
+/// Returns value hash of the first entry with given key which has a value.
+pub(crate) fn find_value<'a>(
+    entries: &[(&[u8], Option<&'a CryptoHash>)],
+    key: &[u8],
+) -> Option<&'a CryptoHash> {
+    entries.iter().find(|(k, v)| *k == key && v.is_some()).and_then(|e| e.1)
+}
+
+#[test]
+fn test_prove_many() {
+    use alloc::vec::Vec;
+
+    let mut trie = crate::trie::Trie::test(1000);
+    let keys = ["foo", "bar", "baz", "qux", "quux"];
+    for (idx, key) in keys.into_iter().enumerate() {
+        trie.set(key.as_bytes(), &CryptoHash::test(idx)).unwrap();
+    }
+    let some_hash = CryptoHash::test(usize::MAX);
+
+    #[track_caller]
+    fn check(
+        root: &CryptoHash,
+        proof: &MultiProof,
+        entries: &[(&[u8], Option<&CryptoHash>)],
+        want: bool,
+    ) {
+        assert_eq!(
+            want,
+            proof.verify(root, entries),
+            "entries: {entries:?}; proof: {proof:?}"
+        );
+    }
+
+    let lookup = ["foo", "bar", "ba", "baz", "bay", "qu", "quuxx", "Foo"];
+    let (values, proof) =
+        trie.prove_many(lookup.iter().map(|key| key.as_bytes())).unwrap();
+    let want_values = [Some(0), Some(1), None, Some(2), None, None, None, None]
+        .map(|num| num.map(CryptoHash::test));
+    assert_eq!(&want_values[..], values.as_slice());
+
+    let entries = lookup
+        .iter()
+        .zip(values.iter())
+        .map(|(key, value)| (key.as_bytes(), value.as_ref()))
+        .collect::<Vec<_>>();
+    check(trie.hash(), &proof, &entries, true);
+    check(trie.hash(), &proof, &entries[..4], true);
+    check(trie.hash(), &proof, &entries[2..4], false);
+
+    // Wrong value or value for a key which doesn’t exist.
+    let mut bad = entries.clone();
+    bad[0].1 = Some(&some_hash);
+    check(trie.hash(), &proof, &bad, false);
+    let mut bad = entries.clone();
+    bad[1].1 = None;
+    check(trie.hash(), &proof, &bad, false);
+    let mut bad = entries.clone();
+    bad[2].1 = Some(&some_hash);
+    check(trie.hash(), &proof, &bad, false);
+
+    // Key not covered by the proof.
+    let qux = CryptoHash::test(3);
+    check(trie.hash(), &proof, &[(b"qux", Some(&qux))], false);
+    check(trie.hash(), &proof, &[(b"qux", None)], false);
+
+    // Wrong root.
+    check(&some_hash, &proof, &entries, false);
+
+    // Values are shared with single-key proofs.
+    for (key, value) in entries.iter() {
+        let (got, single) = trie.prove(key).unwrap();
+        assert_eq!(*value, got.as_ref());
+        assert!(single.verify(trie.hash(), key, *value));
+    }
+
+    // Empty trie.
+    let empty = crate::trie::Trie::test(10);
+    let (values, proof) = empty.prove_many([&b"foo"[..]]).unwrap();
+    assert_eq!(&[None], values.as_slice());
+    check(empty.hash(), &proof, &[(b"foo", None)], true);
+    check(empty.hash(), &proof, &[(b"foo", Some(&some_hash))], false);
+}
@@ -3,7 +3,7 @@ use alloc::string::String;
 #[cfg(test)]
 use alloc::string::ToString;
 use alloc::vec::Vec;
-use core::num::NonZeroU16;
+use core::num::{NonZeroU16, NonZeroU32};
 
 use borsh::maybestd::io;
 use borsh::{BorshDeserialize, BorshSerialize};
@@ -11,12 +11,18 @@ use lib::hash::CryptoHash;
 #[cfg(test)]
 use pretty_assertions::assert_eq;
 
-use super::{Actual, Item, OwnedRef, Proof};
+use super::{
+    Actual, EmptyProof, Item, MultiProof, OwnedRef, Part, Proof, Witness,
+};
 
-const NON_MEMBERSHIP_SHIFT: u32 = 15;
+pub(super) const NON_MEMBERSHIP_SHIFT: u32 = 15;
 
 // Encoding: <(items.len() + has_actual + (is_non_membership << 15)) as u16>
 //           <actual>? <item>*
+//
+// Deserialisation also accepts the compact encoding produced by
+// [`Proof::serialize_compact`].  The two are told apart by bit 14 of the
+// header which is never set in the encoding above.
 impl BorshSerialize for Proof {
     fn serialize<W: io::Write>(&self, wr: &mut W) -> io::Result<()> {
         let (membership, actual, items) = match self {
@@ -47,8 +53,14 @@ impl BorshSerialize for Proof {
 impl BorshDeserialize for Proof {
     fn deserialize_reader<R: io::Read>(rd: &mut R) -> io::Result<Self> {
         let tag = u16::deserialize_reader(rd)?;
+        if tag & super::compact::COMPACT_FLAG != 0 {
+            return super::compact::deserialize_cont(tag, rd);
+        }
         let is_membership = tag & (1 << NON_MEMBERSHIP_SHIFT) == 0;
         let len = usize::from(tag & !(1 << NON_MEMBERSHIP_SHIFT));
+        if len >= 8192 {
+            return Err(invalid_data(format!("proof too long: {len}")));
+        }
 
         // len == 0 means there’s no Actual or Items.  Return empty proof.
         // (Note: empty membership proof never verifies but is valid as far as
@@ -146,9 +158,15 @@ fn deserialize_item_cont(
 }
 
 // Encoding:
-//  - 0b1000_00vv <hash> <hash>            — Branch
-//  - 0b1000_010v <left> <key-buf> <hash>  — Extension
-//  - 0b1000_0110 <left> <hash>            — LookupKeyLeft
+//  - 0b1000_00vv <hash> <hash>                — Branch
+//  - 0b1000_010v <left:u16> <key-buf> <hash>  — Extension
+//  - 0b1000_100v <left:u32> <key-buf> <hash>  — Extension
+//  - 0b1000_0110 <left:u16> <hash>            — LookupKeyLeft
+//  - 0b1000_0111 <left:u32> <hash>            — LookupKeyLeft
+//
+// `left` is encoded as u32 only if it doesn’t fit in u16 which happens for
+// keys longer than 8191 bytes.  This keeps encoding of proofs for shorter keys
+// unchanged.
 impl BorshSerialize for Actual {
     fn serialize<W: io::Write>(&self, wr: &mut W) -> io::Result<()> {
         match self {
@@ -158,15 +176,22 @@ impl BorshSerialize for Actual {
                     .serialize(wr)
             }
             Self::Extension(left, key, child) => {
-                (0x84 | u8::from(child.is_value)).serialize(wr)?;
-                left.serialize(wr)?;
+                let is_value = u8::from(child.is_value);
+                match u16::try_from(*left) {
+                    Ok(left) => (0x84 | is_value, left).serialize(wr),
+                    Err(_) => (0x88 | is_value, *left).serialize(wr),
+                }?;
                 // Note: We’re not encoding length of the bytes slice since it
                 // can be recovered from the contents of the bytes slice.
                 wr.write_all(key)?;
                 child.hash.as_array().serialize(wr)
             }
             Self::LookupKeyLeft(left, hash) => {
-                (0x86u8, left, hash.as_array()).serialize(wr)
+                match u16::try_from(left.get()) {
+                    Ok(left) => (0x86u8, left).serialize(wr),
+                    Err(_) => (0x87u8, left.get()).serialize(wr),
+                }?;
+                hash.as_array().serialize(wr)
             }
         }
     }
@@ -197,38 +222,41 @@ fn deserialize_actual_cont(
             let right = deserialize_owned_ref(rd, first & 1 != 0)?;
             Ok(Actual::Branch(left, right))
         }
-        0x84 | 0x85 => {
-            use crate::nodes::MAX_EXTENSION_KEY_SIZE;
-
-            let left = u16::deserialize_reader(rd)?;
-
-            // Decode key.  We need to parse contents of the buffer to determine
-            // the length.
-            let mut buf = [0; { MAX_EXTENSION_KEY_SIZE + 2 }];
-            let (head, tail) = stdx::split_array_mut::<
-                2,
-                { MAX_EXTENSION_KEY_SIZE },
-                { MAX_EXTENSION_KEY_SIZE + 2 },
-            >(&mut buf);
-            *head = BorshDeserialize::deserialize_reader(rd)?;
-            let tag = u16::from_be_bytes(*head);
-            let len = ((tag % 8) + tag / 8 + 7) / 8;
-            let tail = tail.get_mut(..usize::from(len)).ok_or_else(|| {
-                invalid_data(format!("Actual::Extension key too long: {len}"))
-            })?;
-            rd.read_exact(tail)?;
-            let key = buf[..usize::from(len) + 2].to_vec().into_boxed_slice();
-
-            let child = deserialize_owned_ref(rd, first == 0x85)?;
+        0x84 | 0x85 | 0x88 | 0x89 => {
+            let left = deserialize_left(rd, first & 0x08 != 0)?;
+            let key = deserialize_ext_key(rd, "Actual::Extension")?;
+            let child = deserialize_owned_ref(rd, first & 1 != 0)?;
 
             Ok(Actual::Extension(left, key, child))
         }
-        0x86 => BorshDeserialize::deserialize_reader(rd)
-            .map(|(left, hash)| Actual::LookupKeyLeft(left, CryptoHash(hash))),
+        0x86 | 0x87 => {
+            let left = deserialize_left(rd, first == 0x87)?;
+            let left = NonZeroU32::new(left).ok_or_else(|| {
+                invalid_data("empty Actual::LookupKeyLeft".into())
+            })?;
+            let hash = CryptoHash(BorshDeserialize::deserialize_reader(rd)?);
+            Ok(Actual::LookupKeyLeft(left, hash))
+        }
         _ => Err(invalid_data(format!("invalid Actual tag: {first}"))),
     }
 }
 
+/// Deserialises length of the lookup key left in an [`Actual`].
+///
+/// If `wide` is true, the length is encoded as u32 and must not fit in u16.
+/// Otherwise it’s encoded as u16.  Rejecting wide encoding of short lengths
+/// keeps the encoding canonical.
+fn deserialize_left(rd: &mut impl io::Read, wide: bool) -> io::Result<u32> {
+    if !wide {
+        return u16::deserialize_reader(rd).map(u32::from);
+    }
+    let left = u32::deserialize_reader(rd)?;
+    if left <= u32::from(u16::MAX) {
+        return Err(invalid_data(format!("non-canonical Actual left: {left}")));
+    }
+    Ok(left)
+}
+
 /// Wrapper for deserialising an [`Item`] or an [`Actual`] from the reader.
 ///
 /// `Item` and `Actual` use encodings which are unambiguous when mixed together.
@@ -262,6 +290,175 @@ impl BorshDeserialize for ItemOrActual {
     }
 }
 
+/// Maximum nesting of parts in an encoded [`MultiProof`], [`EmptyProof`] or
+/// [`Witness`].
+///
+/// Each level of nesting corresponds to a node on a path from the root of the
+/// trie.  Parts are deserialised recursively so the limit prevents malicious
+/// input from exhausting the stack.  It’s way more than depth of any trie with
+/// a reasonable key distribution.  In particular, path to a key of
+/// [`crate::trie::MAX_KEY_SIZE`] bytes which consists of Extension nodes only
+/// is less than half as deep.
+const MAX_PART_DEPTH: usize = 1024;
+
+// Path to a key of MAX_KEY_SIZE bytes made of Extension nodes only must take
+// at most half of MAX_PART_DEPTH leaving the rest for Branch nodes.  The limit
+// is the largest power of two for which that holds.
+const _: () = {
+    const fn max_extensions(key_size: usize) -> usize {
+        key_size.div_ceil(crate::nodes::MAX_EXTENSION_KEY_SIZE) + 1
+    }
+    let max_key_size = crate::trie::MAX_KEY_SIZE;
+    assert!(2 * max_extensions(max_key_size) <= MAX_PART_DEPTH);
+    assert!(2 * max_extensions(2 * max_key_size) > MAX_PART_DEPTH);
+};
+
+// Encoding: 0x00 if trie is empty or 0x01 <part> otherwise.
+impl BorshSerialize for MultiProof {
+    fn serialize<W: io::Write>(&self, wr: &mut W) -> io::Result<()> {
+        serialize_root(self.0.as_ref(), wr)
+    }
+}
+
+impl BorshDeserialize for MultiProof {
+    fn deserialize_reader<R: io::Read>(rd: &mut R) -> io::Result<Self> {
+        deserialize_root(rd, "MultiProof").map(Self)
+    }
+}
+
+// Encoding: same as MultiProof.
+impl BorshSerialize for EmptyProof {
+    fn serialize<W: io::Write>(&self, wr: &mut W) -> io::Result<()> {
+        serialize_root(self.0.as_ref(), wr)
+    }
+}
+
+impl BorshDeserialize for EmptyProof {
+    fn deserialize_reader<R: io::Read>(rd: &mut R) -> io::Result<Self> {
+        deserialize_root(rd, "EmptyProof").map(Self)
+    }
+}
+
+// Encoding: same as MultiProof.
+impl BorshSerialize for Witness {
+    fn serialize<W: io::Write>(&self, wr: &mut W) -> io::Result<()> {
+        serialize_root(self.0.as_ref(), wr)
+    }
+}
+
+impl BorshDeserialize for Witness {
+    fn deserialize_reader<R: io::Read>(rd: &mut R) -> io::Result<Self> {
+        deserialize_root(rd, "Witness").map(Self)
+    }
+}
+
+/// Serialises root of a proof made of [`Part`]s.
+fn serialize_root(
+    root: Option<&Part>,
+    wr: &mut impl io::Write,
+) -> io::Result<()> {
+    match root {
+        None => 0u8.serialize(wr),
+        Some(root) => {
+            1u8.serialize(wr)?;
+            root.serialize(wr)
+        }
+    }
+}
+
+/// Deserialises root of a proof made of [`Part`]s.  `what` is used in the
+/// error message if the tag is invalid.
+fn deserialize_root(
+    rd: &mut impl io::Read,
+    what: &str,
+) -> io::Result<Option<Part>> {
+    match u8::deserialize_reader(rd)? {
+        0 => Ok(None),
+        1 => deserialize_part(rd, 0).map(Some),
+        tag => Err(invalid_data(format!("invalid {what} tag: {tag}"))),
+    }
+}
+
+// Encoding (parts are written in pre-order):
+//  - 0x00 <hash>             — Ref to a node
+//  - 0x10 <hash>             — Ref to a value
+//  - 0x20                    — Value
+//  - 0x30 <part> <part>      — Branch
+//  - 0x40 <key-buf> <part>   — Extension
+impl BorshSerialize for Part {
+    fn serialize<W: io::Write>(&self, wr: &mut W) -> io::Result<()> {
+        match self {
+            Self::Ref(rf) => {
+                (u8::from(rf.is_value) << 4, rf.hash.as_array()).serialize(wr)
+            }
+            Self::Value => 0x20u8.serialize(wr),
+            Self::Branch(children) => {
+                0x30u8.serialize(wr)?;
+                children[0].serialize(wr)?;
+                children[1].serialize(wr)
+            }
+            Self::Extension(key, child) => {
+                0x40u8.serialize(wr)?;
+                // Note: We’re not encoding length of the bytes slice since it
+                // can be recovered from the contents of the bytes slice.
+                wr.write_all(key)?;
+                child.serialize(wr)
+            }
+        }
+    }
+}
+
+/// Deserialises a [`Part`] located at given depth of the proof.
+fn deserialize_part(rd: &mut impl io::Read, depth: usize) -> io::Result<Part> {
+    if depth >= MAX_PART_DEPTH {
+        return Err(invalid_data(format!("Proof too deep: {depth}")));
+    }
+    match u8::deserialize_reader(rd)? {
+        tag @ (0x00 | 0x10) => {
+            deserialize_owned_ref(rd, tag != 0).map(Part::Ref)
+        }
+        0x20 => Ok(Part::Value),
+        0x30 => {
+            let left = deserialize_part(rd, depth + 1)?;
+            let right = deserialize_part(rd, depth + 1)?;
+            Ok(Part::Branch(alloc::boxed::Box::new([left, right])))
+        }
+        0x40 => {
+            let key = deserialize_ext_key(rd, "Part::Extension")?;
+            let child = deserialize_part(rd, depth + 1)?;
+            Ok(Part::Extension(key, alloc::boxed::Box::new(child)))
+        }
+        tag => Err(invalid_data(format!("invalid Part tag: {tag}"))),
+    }
+}
+
+/// Deserialises an Extension key as encoded by [`super::encode_ext_key`].
+///
+/// The length of the key isn’t encoded explicitly.  Instead, contents of the
+/// first two bytes are parsed to determine it.  `what` is used in the error
+/// message if the key is too long.
+pub(crate) fn deserialize_ext_key(
+    rd: &mut impl io::Read,
+    what: &str,
+) -> io::Result<alloc::boxed::Box<[u8]>> {
+    use crate::nodes::MAX_EXTENSION_KEY_SIZE;
+
+    let mut buf = [0; { MAX_EXTENSION_KEY_SIZE + 2 }];
+    let (head, tail) = stdx::split_array_mut::<
+        2,
+        { MAX_EXTENSION_KEY_SIZE },
+        { MAX_EXTENSION_KEY_SIZE + 2 },
+    >(&mut buf);
+    *head = BorshDeserialize::deserialize_reader(rd)?;
+    let tag = u16::from_be_bytes(*head);
+    let len = ((tag % 8) + tag / 8 + 7) / 8;
+    let tail = tail
+        .get_mut(..usize::from(len))
+        .ok_or_else(|| invalid_data(format!("{what} key too long: {len}")))?;
+    rd.read_exact(tail)?;
+    Ok(buf[..usize::from(len) + 2].to_vec().into_boxed_slice())
+}
+
 /// Deserialises an [`OwnedRef`] assuming whether it’s reference to a value or
 /// not based on `is_value` argument.
 ///
@@ -272,7 +469,7 @@ impl BorshDeserialize for ItemOrActual {
 ///
 /// This deserialises an `OwnedRef` with the `is_value` flag provided by the
 /// caller.
-fn deserialize_owned_ref(
+pub(super) fn deserialize_owned_ref(
     rd: &mut impl io::Read,
     is_value: bool,
 ) -> io::Result<OwnedRef> {
@@ -282,7 +479,7 @@ fn deserialize_owned_ref(
 }
 
 /// Returns an `io::Error` of kind `InvalidData` with specified message.
-fn invalid_data(msg: String) -> io::Error {
+pub(super) fn invalid_data(msg: String) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, msg)
 }
 
@@ -388,7 +585,7 @@ fn test_actual_borsh() {
     /* Extension */
 
     fn make_extension(
-        left: u16,
+        left: u32,
         bytes: &[u8],
         offset: U3,
         length: u16,
@@ -423,10 +620,19 @@ fn test_actual_borsh() {
                     0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
     ]);
 
+    #[rustfmt::skip]
+    test(make_extension(0x10000, &[1], U3::_7, 1, true), &[
+        /* tag: */ 0x89,
+        /* left: */ 0, 0, 1, 0,
+        /* key: */ 0, 15, 1,
+        /* hash: */ 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+                    0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+    ]);
+
     /* LookupKeyLeft */
 
     #[rustfmt::skip]
-    test(Actual::LookupKeyLeft(NonZeroU16::MIN, CryptoHash::test(1)), &[
+    test(Actual::LookupKeyLeft(NonZeroU32::MIN, CryptoHash::test(1)), &[
         /* tag: */ 0x86,
         /* left: */ 1, 0,
         /* hash: */ 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
@@ -434,12 +640,25 @@ fn test_actual_borsh() {
     ]);
 
     #[rustfmt::skip]
-    test(Actual::LookupKeyLeft(NonZeroU16::MAX, CryptoHash::test(1)), &[
+    test(Actual::LookupKeyLeft(NonZeroU32::new(0xFFFF).unwrap(), CryptoHash::test(1)), &[
         /* tag: */ 0x86,
         /* left: */ 0xFF, 0xFF,
         /* hash: */ 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
                     0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
     ]);
+
+    #[rustfmt::skip]
+    test(Actual::LookupKeyLeft(NonZeroU32::MAX, CryptoHash::test(1)), &[
+        /* tag: */ 0x87,
+        /* left: */ 0xFF, 0xFF, 0xFF, 0xFF,
+        /* hash: */ 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+                    0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+    ]);
+
+    // Short lengths must use the narrow encoding.
+    let mut bytes = alloc::vec![0x87, 0xFF, 0xFF, 0, 0];
+    bytes.extend_from_slice(CryptoHash::test(1).as_slice());
+    Actual::try_from_slice(&bytes).unwrap_err();
 }
 
 #[test]
@@ -458,7 +677,7 @@ fn test_proof_borsh() {
     }
 
     let item = Item::Extension(NonZeroU16::new(42).unwrap());
-    let actual = Actual::LookupKeyLeft(NonZeroU16::MIN, CryptoHash::test(1));
+    let actual = Actual::LookupKeyLeft(NonZeroU32::MIN, CryptoHash::test(1));
 
     test(Proof::Positive(super::Membership(vec![])), &[0, 0]);
     test(Proof::Positive(super::Membership(vec![item.clone()])), &[
@@ -497,4 +716,83 @@ fn test_proof_borsh() {
             /* item: */ 32, 42,
         ],
     );
+
+    // Proofs longer than what serialisation allows are rejected.
+    Proof::try_from_slice(&[0, 0x20]).unwrap_err();
+    Proof::try_from_slice(&[0, 0xA0]).unwrap_err();
+}
+
+#[test]
+fn test_multi_proof_borsh() {
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    #[track_caller]
+    fn test(want_proof: MultiProof, want_bytes: &[u8]) {
+        let got_bytes = borsh::to_vec(&want_proof).unwrap();
+        let got_proof = MultiProof::try_from_slice(want_bytes)
+            .map_err(|err| err.to_string());
+        assert_eq!(
+            (Ok(&want_proof), want_bytes),
+            (got_proof.as_ref(), got_bytes.as_slice()),
+        );
+    }
+
+    test(MultiProof(None), &[0]);
+    #[rustfmt::skip]
+    test(MultiProof(Some(Part::Ref(OwnedRef::test(false, 1)))), &[
+        /* tag: */ 1,
+        /* ref: */ 0x00, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+                   0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1,
+    ]);
+
+    let key = crate::bits::ExtKey::new(&[0xFF], lib::u3::U3::_0, 4).unwrap();
+    let branch = Part::Branch(Box::new([
+        Part::Value,
+        Part::Ref(OwnedRef::test(true, 2)),
+    ]));
+    let ext = Part::Extension(super::encode_ext_key(key), Box::new(branch));
+    #[rustfmt::skip]
+    test(MultiProof(Some(ext)), &[
+        /* tag: */ 1,
+        /* extension: */ 0x40, 0, 32, 0xF0,
+        /* branch: */ 0x30,
+        /* value: */ 0x20,
+        /* ref: */ 0x10, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2,
+                   0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2,
+    ]);
+
+    // Too deep proofs are rejected.
+    let mut bytes = vec![1];
+    bytes.resize(MAX_PART_DEPTH + 1, 0x30);
+    assert!(MultiProof::try_from_slice(&bytes).is_err());
+}
+
+#[test]
+fn test_empty_proof_borsh() {
+    let mut trie = crate::trie::Trie::test(100);
+    for key in ["foo", "bar", "baz"] {
+        trie.set(key.as_bytes(), &CryptoHash::test(1)).unwrap();
+    }
+    let set = super::KeySet::Range(b"bara", b"baz");
+    let proof = trie.prove_empty(set).unwrap().unwrap();
+    let bytes = borsh::to_vec(&proof).unwrap();
+    assert_eq!(proof, EmptyProof::try_from_slice(&bytes).unwrap());
+    assert_eq!(borsh::to_vec(&MultiProof(proof.0.clone())).unwrap(), bytes,);
+    assert!(EmptyProof::try_from_slice(&[2]).is_err());
+}
+
+#[test]
+fn test_witness_borsh() {
+    let mut trie = crate::trie::Trie::test(100);
+    let witness = trie.set_with_proof(b"foo", &CryptoHash::test(1)).unwrap();
+    assert_eq!(&[0], borsh::to_vec(&witness).unwrap().as_slice());
+    let old_root = trie.hash().clone();
+    let witness = trie.del_with_proof(b"foo").unwrap().1;
+    let bytes = borsh::to_vec(&witness).unwrap();
+    let witness = Witness::try_from_slice(&bytes).unwrap();
+    assert_eq!(
+        Some(trie.hash()),
+        witness.verify_del(&old_root, b"foo").as_ref()
+    );
 }
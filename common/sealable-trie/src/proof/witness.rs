@@ -0,0 +1,297 @@
+use alloc::vec::Vec;
+
+use lib::hash::{CryptoHash, Hasher, Sha256};
+use memory::Ptr;
+
+use super::Part;
+use crate::bits::ExtKey;
+use crate::nodes::{RawNode, Reference};
+use crate::trie::{Trie, EMPTY_TRIE_ROOT};
+
+/// A witness of a modification of the trie.
+///
+/// The witness holds the portion of the trie which is read or modified when
+/// setting, sealing or deleting a key.  It allows a stateless verifier which
+/// knows only the state root before the modification to apply the same change
+/// and calculate the state root after the modification.
+///
+/// Since trie hashes don’t commit to whether values or nodes are sealed,
+/// node references included in the witness as opaque references are treated
+/// as sealed.  In particular, when deleting a key whose sibling is an opaque
+/// reference, the sibling is not merged with the parent.  This matches the
+/// behaviour of the trie if the sibling is sealed.
+///
+/// As with [`super::Proof`], the witness doesn’t include the key or value.
+/// It’s caller responsibility to pair it with correct key and value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Witness(pub(super) Option<Part>);
+
+impl Witness {
+    /// Creates a new witness with given root.
+    ///
+    /// `None` indicates that the trie is empty.
+    pub(crate) fn new(root: Option<Part>) -> Self { Self(root) }
+
+    /// Verifies the witness against `old_root` and returns state root after
+    /// setting value at `key` to `value_hash`.
+    ///
+    /// Returns `None` if the witness is invalid or the modification fails
+    /// (e.g. because the key is a prefix of an existing key).  Setting and
+    /// immediately sealing a value results in the same state root thus this
+    /// method is also used to verify [`Trie::set_and_seal_with_proof`].
+    ///
+    /// Assumes the trie uses SHA-256 to hash nodes.  See
+    /// [`Self::verify_set_with`] for tries using other hash functions.
+    pub fn verify_set(
+        &self,
+        old_root: &CryptoHash,
+        key: &[u8],
+        value_hash: &CryptoHash,
+    ) -> Option<CryptoHash> {
+        self.verify_set_with::<Sha256>(old_root, key, value_hash)
+    }
+
+    /// Verifies the witness for a trie which uses hash function `H` to hash
+    /// nodes.  See [`Self::verify_set`].
+    pub fn verify_set_with<H: Hasher>(
+        &self,
+        old_root: &CryptoHash,
+        key: &[u8],
+        value_hash: &CryptoHash,
+    ) -> Option<CryptoHash> {
+        let mut trie = self.materialise::<H>(old_root)?;
+        trie.set(key, value_hash).ok()?;
+        Some(trie.hash().clone())
+    }
+
+    /// Verifies the witness against `old_root` and returns state root after
+    /// sealing value at `key`.
+    ///
+    /// Sealing doesn’t change the state root so on success this returns
+    /// `old_root`.  The witness verifies that there’s a value at the key.
+    ///
+    /// Assumes the trie uses SHA-256 to hash nodes.  See
+    /// [`Self::verify_seal_with`] for tries using other hash functions.
+    pub fn verify_seal(
+        &self,
+        old_root: &CryptoHash,
+        key: &[u8],
+    ) -> Option<CryptoHash> {
+        self.verify_seal_with::<Sha256>(old_root, key)
+    }
+
+    /// Verifies the witness for a trie which uses hash function `H` to hash
+    /// nodes.  See [`Self::verify_seal`].
+    pub fn verify_seal_with<H: Hasher>(
+        &self,
+        old_root: &CryptoHash,
+        key: &[u8],
+    ) -> Option<CryptoHash> {
+        let mut trie = self.materialise::<H>(old_root)?;
+        trie.seal(key).ok()?;
+        Some(trie.hash().clone())
+    }
+
+    /// Verifies the witness against `old_root` and returns state root after
+    /// deleting value at `key`.
+    ///
+    /// If there’s no value at the key, returns `old_root`.
+    ///
+    /// Assumes the trie uses SHA-256 to hash nodes.  See
+    /// [`Self::verify_del_with`] for tries using other hash functions.
+    pub fn verify_del(
+        &self,
+        old_root: &CryptoHash,
+        key: &[u8],
+    ) -> Option<CryptoHash> {
+        self.verify_del_with::<Sha256>(old_root, key)
+    }
+
+    /// Verifies the witness for a trie which uses hash function `H` to hash
+    /// nodes.  See [`Self::verify_del`].
+    pub fn verify_del_with<H: Hasher>(
+        &self,
+        old_root: &CryptoHash,
+        key: &[u8],
+    ) -> Option<CryptoHash> {
+        let mut trie = self.materialise::<H>(old_root)?;
+        trie.del(key).ok()?;
+        Some(trie.hash().clone())
+    }
+
+    /// Constructs a partial trie from the witness and verifies it has
+    /// expected root hash.
+    ///
+    /// Opaque node references in the witness become sealed nodes of the
+    /// trie.
+    fn materialise<H: Hasher>(
+        &self,
+        root_hash: &CryptoHash,
+    ) -> Option<Trie<Pool, H>> {
+        let mut pool = Pool(Vec::new());
+        let root = match self.0.as_ref() {
+            None if *root_hash == EMPTY_TRIE_ROOT => {
+                return Some(Trie::with_hasher(pool))
+            }
+            None => return None,
+            Some(root) => pool.build::<H>(root)?,
+        };
+        match root {
+            Built::Node(ptr, hash) if hash == *root_hash => {
+                Some(Trie::from_parts_with_hasher(pool, ptr, hash))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A simple allocator holding nodes of a trie constructed from a witness.
+///
+/// The trie is short-lived so freed nodes are never reused.
+struct Pool(Vec<[u8; RawNode::SIZE]>);
+
+/// A reference to a node or value built from a [`Part`].
+enum Built {
+    Node(Option<Ptr>, CryptoHash),
+    Value(CryptoHash),
+}
+
+impl Built {
+    fn to_ref(&self) -> Reference {
+        match self {
+            Self::Node(ptr, hash) => Reference::node(*ptr, hash),
+            Self::Value(hash) => Reference::value(false, hash),
+        }
+    }
+}
+
+impl Pool {
+    /// Allocates nodes of the `part` and returns reference to it.
+    ///
+    /// Returns `None` if the part is malformed or includes value placeholders
+    /// (which aren’t used in witnesses).
+    fn build<H: Hasher>(&mut self, part: &Part) -> Option<Built> {
+        let node = match part {
+            Part::Ref(rf) if rf.is_value => {
+                return Some(Built::Value(rf.hash.clone()))
+            }
+            Part::Ref(rf) => return Some(Built::Node(None, rf.hash.clone())),
+            Part::Value => return None,
+            Part::Branch(children) => {
+                let left = self.build::<H>(&children[0])?;
+                let right = self.build::<H>(&children[1])?;
+                RawNode::branch(left.to_ref(), right.to_ref())
+            }
+            Part::Extension(key, child) => {
+                let key = ExtKey::decode(key, 0)?;
+                let child = self.build::<H>(child)?;
+                RawNode::extension(key, child.to_ref())
+            }
+        };
+        let hash = node.decode().ok()?.hash_with::<H>();
+        let ptr = memory::Allocator::alloc(self, node.0).ok()?;
+        Some(Built::Node(Some(ptr), hash))
+    }
+}
+
+impl memory::Allocator for Pool {
+    type Value = [u8; RawNode::SIZE];
+
+    fn alloc(
+        &mut self,
+        value: Self::Value,
+    ) -> Result<Ptr, memory::OutOfMemory> {
+        let ptr = u32::try_from(self.0.len() + 1)
+            .ok()
+            .and_then(|ptr| Ptr::new(ptr).ok().flatten())
+            .ok_or(memory::OutOfMemory)?;
+        self.0.push(value);
+        Ok(ptr)
+    }
+
+    fn get(&self, ptr: Ptr) -> &Self::Value { &self.0[index(ptr)] }
+
+    fn get_mut(&mut self, ptr: Ptr) -> &mut Self::Value {
+        &mut self.0[index(ptr)]
+    }
+
+    fn free(&mut self, _ptr: Ptr) {}
+}
+
+/// Converts pointer into index in [`Pool`].
+fn index(ptr: Ptr) -> usize { usize::try_from(ptr.get() - 1).unwrap() }
+
+#[test]
+fn test_witness() {
+    let mut trie = Trie::test(1000);
+    let value = CryptoHash::test(100);
+    let bad_root = CryptoHash::test(101);
+
+    #[derive(Clone, Copy, Debug)]
+    enum Op {
+        Set(&'static str),
+        SetAndSeal(&'static str),
+        Seal(&'static str),
+        Del(&'static str),
+    }
+
+    for op in [
+        Op::Del("foo"),
+        Op::Set("foo"),
+        Op::Set("bar"),
+        Op::Set("baz"),
+        Op::Set("qux"),
+        Op::Set("quux"),
+        Op::Set("foo"),
+        Op::Set("fo\x7f"),
+        Op::Set("bay"),
+        Op::Set("qu\x00"),
+        Op::Set("0123456789012345678901234567890123456789"),
+        Op::Del("baz"),
+        Op::Del("baq"),
+        Op::Del("quux"),
+        Op::Seal("bar"),
+        Op::SetAndSeal("bat"),
+        Op::Del("bay"),
+        Op::Del("qux"),
+        Op::Del("foo"),
+        Op::Del("0123456789012345678901234567890123456789"),
+    ] {
+        let old_root = trie.hash().clone();
+        let (witness, got) = match op {
+            Op::Set(key) => {
+                let key = key.as_bytes();
+                let witness = trie.set_with_proof(key, &value).unwrap();
+                assert_eq!(None, witness.verify_set(&bad_root, key, &value));
+                let got = witness.verify_set(&old_root, key, &value);
+                (witness, got)
+            }
+            Op::SetAndSeal(key) => {
+                let key = key.as_bytes();
+                let witness =
+                    trie.set_and_seal_with_proof(key, &value).unwrap();
+                let got = witness.verify_set(&old_root, key, &value);
+                (witness, got)
+            }
+            Op::Seal(key) => {
+                let key = key.as_bytes();
+                let witness = trie.seal_with_proof(key).unwrap();
+                assert_eq!(None, witness.verify_seal(&bad_root, key));
+                let got = witness.verify_seal(&old_root, key);
+                (witness, got)
+            }
+            Op::Del(key) => {
+                let key = key.as_bytes();
+                let (_, witness) = trie.del_with_proof(key).unwrap();
+                assert_eq!(None, witness.verify_del(&bad_root, key));
+                let got = witness.verify_del(&old_root, key);
+                (witness, got)
+            }
+        };
+        assert_eq!(Some(trie.hash()), got.as_ref(), "{op:?}: {witness:?}");
+    }
+
+    // Witness doesn’t allow modifying other keys.
+    let witness = trie.set_with_proof(b"bay", &value).unwrap();
+    assert_eq!(None, witness.verify_set(&trie.hash().clone(), b"qux", &value));
+}
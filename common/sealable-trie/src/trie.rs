@@ -1,22 +1,60 @@
 use alloc::vec::Vec;
-use core::num::NonZeroU16;
+use core::marker::PhantomData;
+use core::num::NonZeroU32;
 
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, Hasher, Sha256};
 use memory::Ptr;
 
 use crate::nodes::{Node, NodeRef, RawNode, Reference};
 use crate::{bits, proof};
 
+mod batch;
+mod build;
 mod del;
+pub mod diff;
+mod integrity;
 mod iter;
+#[cfg(test)]
+mod model_tests;
+mod ordered;
+mod prove_empty;
+mod prove_many;
 mod seal;
 mod set;
+#[cfg(feature = "borsh")]
+mod snapshot;
+mod stats;
 #[cfg(test)]
 mod tests;
+mod unseal;
+mod values;
+mod versions;
+mod witness;
+
+pub use batch::BatchOp;
+pub use integrity::{IntegrityReport, Issue};
+pub use iter::{Entry, SubtrieIter};
+pub use ordered::FoundKey;
+pub use stats::{PrefixStats, Stats};
+pub use values::ValueTrie;
 
 /// Root trie hash if the trie is empty.
 pub const EMPTY_TRIE_ROOT: CryptoHash = CryptoHash::DEFAULT;
 
+/// Maximum length of a key which can be stored in the trie, in bytes.
+///
+/// The limit comes from proof encodings rather than from the trie itself.
+/// [`proof::MultiProof`], [`proof::EmptyProof`] and [`proof::Witness`] allow
+/// at most 1024 levels of nesting, one per node on a path from the root.
+/// Extension node holds at most 34 bytes of a key so a path to a 16 KiB key
+/// consists of at most 483 Extension nodes.  That’s less than half of the
+/// nesting limit which leaves more than 500 levels for Branch nodes where the
+/// key diverges from other keys.  16 KiB is the largest power of two for which
+/// this holds; 32 KiB keys could need as many as 965 Extension nodes.
+///
+/// Without a cap, proofs for some stored keys couldn’t be serialised.
+pub const MAX_KEY_SIZE: usize = 16 * 1024;
+
 /// A Merkle Patricia Trie with sealing/pruning feature.
 ///
 /// The trie is designed to work in situations where space is constrained.  To
@@ -52,9 +90,14 @@ pub const EMPTY_TRIE_ROOT: CryptoHash = CryptoHash::DEFAULT;
 ///    they aren’t stored.  In any case, this should be plenty since fully
 ///    balanced binary tree with that many nodes allows storing 500K keys.
 ///
-/// 4. Keys are limited to 8191 bytes (technically 2¹⁶-1 bits but there’s no
-///    interface for keys which hold partial bytes).  It would be possible to
-///    extend this limit but 8k bytes should be plenty for any reasonable usage.
+/// 4. Keys are limited to [`MAX_KEY_SIZE`] bytes.  Long keys are stored as
+///    chains of Extension nodes each holding at most 34 bytes of the key so
+///    node encoding doesn’t depend on the key length.  The limit bounds
+///    length of those chains to at most 483 nodes so that they take less
+///    than half of the 1024 levels of nesting allowed by proof encodings (see
+///    [`MAX_KEY_SIZE`] for details).  It also bounds stack usage of
+///    operations which recurse once per node on the path to the key.  Lookups
+///    (e.g. [`Self::get`] or [`Self::prove`]) accept keys of any length.
 ///
 ///    As an optimisation to take advantage of trie’s internal structure, it’s
 ///    best to keep keys up to 36-byte long.  Or at least, to keep common key
@@ -62,8 +105,12 @@ pub const EMPTY_TRIE_ROOT: CryptoHash = CryptoHash::DEFAULT;
 ///    a single value at a key whose length is withing 36 bytes has a single
 ///    node however if that key is longer than 36 bytes the trie needs at least
 ///    two nodes.
+///
+/// Nodes are hashed using hash function `H` which defaults to SHA-256.  Proofs
+/// generated by a trie using different hash function must be verified with
+/// matching `verify_with` method, e.g. [`proof::Proof::verify_with`].
 #[derive(Debug)]
-pub struct Trie<A> {
+pub struct Trie<A, H = Sha256> {
     /// Pointer to the root node. `None` if the trie is empty or the root node
     /// has been sealed.
     root_ptr: Option<Ptr>,
@@ -73,6 +120,14 @@ pub struct Trie<A> {
 
     /// Allocator used to access and allocate nodes.
     alloc: A,
+
+    /// State of persistent mode or `None` if old versions aren’t retained.
+    ///
+    /// See [`Self::set_version_horizon`].
+    versions: Option<versions::Versions>,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
 }
 
 /// Possible errors when reading or modifying the trie.
@@ -80,7 +135,7 @@ pub struct Trie<A> {
 pub enum Error {
     #[display(fmt = "Tried to access empty key")]
     EmptyKey,
-    #[display(fmt = "Key longer than 8191 bytes")]
+    #[display(fmt = "Key too long")]
     KeyTooLong,
     #[display(fmt = "Tried to access sealed node")]
     Sealed,
@@ -92,6 +147,12 @@ pub enum Error {
     OutOfMemory,
     #[display(fmt = "Error decoding node: {}", "_0")]
     BadRawNode(crate::nodes::DecodeError),
+    #[display(fmt = "Trie version with given root not found")]
+    UnknownRoot,
+    #[display(fmt = "Proof doesn’t match the trie")]
+    BadProof,
+    #[display(fmt = "Keys aren’t sorted or contain duplicates")]
+    UnsortedKeys,
 }
 
 impl From<memory::OutOfMemory> for Error {
@@ -107,6 +168,16 @@ impl From<crate::nodes::DecodeError> for Error {
 type Result<T, E = Error> = ::core::result::Result<T, E>;
 type Value = [u8; crate::nodes::RawNode::SIZE];
 
+/// Converts key of a value being inserted into the trie into a bit slice.
+///
+/// Returns [`Error::KeyTooLong`] if the key is longer than [`MAX_KEY_SIZE`].
+fn stored_key(key: &[u8]) -> Result<bits::Slice<'_>> {
+    if key.len() > MAX_KEY_SIZE {
+        return Err(Error::KeyTooLong);
+    }
+    bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)
+}
+
 macro_rules! proof {
     ($proof:ident push $item:expr) => {
         $proof.as_mut().map(|proof| proof.push($item));
@@ -121,8 +192,29 @@ macro_rules! proof {
 
 impl<A: memory::Allocator<Value = Value>> Trie<A> {
     /// Creates a new empty trie using given allocator.
-    pub fn new(alloc: A) -> Self {
-        Self { root_ptr: None, root_hash: EMPTY_TRIE_ROOT, alloc }
+    ///
+    /// The trie uses SHA-256 to hash nodes.  See [`Self::with_hasher`] for
+    /// using a different hash function.
+    pub fn new(alloc: A) -> Self { Self::with_hasher(alloc) }
+
+    /// Creates a new trie from individual parts.
+    ///
+    /// It’s up to the caller to guarantee that the `root_ptr` and `root_hash`
+    /// values are correct and correspond to nodes stored within the pool
+    /// allocator `alloc`.  The trie uses SHA-256 to hash nodes.
+    pub fn from_parts(
+        alloc: A,
+        root_ptr: Option<Ptr>,
+        root_hash: CryptoHash,
+    ) -> Self {
+        Self::from_parts_with_hasher(alloc, root_ptr, root_hash)
+    }
+}
+
+impl<A: memory::Allocator<Value = Value>, H: Hasher> Trie<A, H> {
+    /// Creates a new empty trie using given allocator and hash function `H`.
+    pub fn with_hasher(alloc: A) -> Self {
+        Self::from_parts_with_hasher(alloc, None, EMPTY_TRIE_ROOT)
     }
 
     /// Returns hash of the root node.
@@ -131,23 +223,38 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
     /// Returns whether the trie is empty.
     pub fn is_empty(&self) -> bool { self.root_hash == EMPTY_TRIE_ROOT }
 
+    /// Returns pointer to the root node; `None` if the trie is empty.
+    pub fn root_ptr(&self) -> Option<Ptr> { self.root_ptr }
+
+    /// Returns exclusive reference to the underlying allocator.
+    ///
+    /// Modifying nodes used by the trie through the allocator corrupts the
+    /// trie.  This is meant for allocators which need to perform maintenance
+    /// (such as flushing data to persistent storage) without taking the trie
+    /// apart.
+    pub fn allocator_mut(&mut self) -> &mut A { &mut self.alloc }
+
     /// Deconstructs the object into the individual parts — allocator, root
     /// pointer and root hash.
+    ///
+    /// If the trie is in persistent mode, retained versions are forgotten
+    /// without freeing nodes which belong to them only.  To avoid leaking
+    /// memory, disable persistent mode first.
     pub fn into_parts(self) -> (A, Option<Ptr>, CryptoHash) {
         (self.alloc, self.root_ptr, self.root_hash)
     }
 
-    /// Creates a new trie from individual parts.
+    /// Creates a new trie using hash function `H` from individual parts.
     ///
     /// It’s up to the caller to guarantee that the `root_ptr` and `root_hash`
     /// values are correct and correspond to nodes stored within the pool
-    /// allocator `alloc`.
-    pub fn from_parts(
+    /// allocator `alloc` and hashed with `H`.
+    pub fn from_parts_with_hasher(
         alloc: A,
         root_ptr: Option<Ptr>,
         root_hash: CryptoHash,
     ) -> Self {
-        Self { root_ptr, root_hash, alloc }
+        Self { root_ptr, root_hash, alloc, versions: None, hasher: PhantomData }
     }
 
     /// Retrieves value at given key.
@@ -155,7 +262,8 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
     /// Returns `None` if there’s no value at given key.  Returns an error if
     /// the value (or its ancestor) has been sealed.
     pub fn get(&self, key: &[u8]) -> Result<Option<CryptoHash>> {
-        let (value, _) = self.get_impl(key, true)?;
+        let (value, _) =
+            self.get_impl(self.root_ptr, &self.root_hash, key, true)?;
         Ok(value)
     }
 
@@ -167,28 +275,117 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
         &self,
         key: &[u8],
     ) -> Result<(Option<CryptoHash>, proof::Proof)> {
-        let (value, proof) = self.get_impl(key, true)?;
+        let (value, proof) =
+            self.get_impl(self.root_ptr, &self.root_hash, key, true)?;
         Ok((value, proof.unwrap()))
     }
 
+    /// Retrieves value at given key in version of the trie with given root
+    /// hash.
+    ///
+    /// `root_hash` must be the current root hash or root hash of one of the
+    /// versions retained in persistent mode (see [`Self::checkpoint`]).
+    /// Otherwise returns [`Error::UnknownRoot`].
+    pub fn get_at(
+        &self,
+        root_hash: &CryptoHash,
+        key: &[u8],
+    ) -> Result<Option<CryptoHash>> {
+        let root_ptr = self.find_root(root_hash)?;
+        let (value, _) = self.get_impl(root_ptr, root_hash, key, false)?;
+        Ok(value)
+    }
+
+    /// Retrieves value at given key in version of the trie with given root
+    /// hash and provides proof of the result.
+    ///
+    /// See [`Self::get_at`] and [`Self::prove`].
+    pub fn prove_at(
+        &self,
+        root_hash: &CryptoHash,
+        key: &[u8],
+    ) -> Result<(Option<CryptoHash>, proof::Proof)> {
+        let root_ptr = self.find_root(root_hash)?;
+        let (value, proof) = self.get_impl(root_ptr, root_hash, key, true)?;
+        Ok((value, proof.unwrap()))
+    }
+
+    /// Retrieves values at given keys and provides a single proof of all the
+    /// results.
+    ///
+    /// Returns list of values in the same order as `keys`.  Parts of the trie
+    /// common to multiple keys are included in the proof only once which makes
+    /// it smaller than a list of separate proofs for each key.
+    ///
+    /// Returns an error if any of the values (or their ancestors) have been
+    /// sealed.
+    pub fn prove_many<'k>(
+        &self,
+        keys: impl IntoIterator<Item = &'k [u8]>,
+    ) -> Result<(Vec<Option<CryptoHash>>, proof::MultiProof)> {
+        let keys = keys
+            .into_iter()
+            .map(|key| bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong))
+            .enumerate()
+            .map(|(idx, key)| key.map(|key| (idx, key)))
+            .collect::<Result<Vec<_>>>()?;
+        if self.root_hash == EMPTY_TRIE_ROOT {
+            let values = alloc::vec![None; keys.len()];
+            return Ok((values, proof::MultiProof::new(None)));
+        }
+        prove_many::Context::<_, H>::new(&self.alloc, keys.len())
+            .prove(NodeRef::new(self.root_ptr, &self.root_hash), keys)
+    }
+
+    /// Checks whether there are no values at any of the keys in the `set` and
+    /// if so provides proof of that.
+    ///
+    /// Returns `None` if there’s a value in the set.  Returns an error if
+    /// a sealed node which overlaps with the set needs to be traversed to
+    /// determine the result.
+    pub fn prove_empty(
+        &self,
+        set: proof::KeySet<'_>,
+    ) -> Result<Option<proof::EmptyProof>> {
+        if self.root_hash == EMPTY_TRIE_ROOT {
+            return Ok(Some(proof::EmptyProof::new(None)));
+        }
+        prove_empty::Context::<_, H>::new(&self.alloc, set)
+            .prove(NodeRef::new(self.root_ptr, &self.root_hash))
+    }
+
     fn get_impl(
         &self,
+        root_ptr: Option<Ptr>,
+        root_hash: &CryptoHash,
+        key: &[u8],
+        include_proof: bool,
+    ) -> Result<(Option<CryptoHash>, Option<proof::Proof>)> {
+        self.get_impl_with::<H>(root_ptr, root_hash, key, include_proof)
+    }
+
+    /// Looks up a key in a trie with given root whose nodes are hashed with
+    /// `G`.  The root may be of a different trie stored in the same allocator.
+    fn get_impl_with<G: Hasher>(
+        &self,
+        root_ptr: Option<Ptr>,
+        root_hash: &CryptoHash,
         key: &[u8],
         include_proof: bool,
     ) -> Result<(Option<CryptoHash>, Option<proof::Proof>)> {
         let mut key = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
-        if self.root_hash == EMPTY_TRIE_ROOT {
+        if *root_hash == EMPTY_TRIE_ROOT {
             let proof = include_proof.then(proof::Proof::empty_trie);
             return Ok((None, proof));
         }
 
         let mut proof = include_proof.then(proof::Proof::builder);
-        let mut node_ptr = self.root_ptr;
-        let mut node_hash = self.root_hash.clone();
+        let mut node_ptr = root_ptr;
+        let mut node_hash = root_hash.clone();
         loop {
             let node = self.alloc.get(node_ptr.ok_or(Error::Sealed)?);
             let node = <&RawNode>::from(node).decode()?;
-            debug_assert_eq!(node_hash, node.hash());
+            debug_assert_eq!(node_hash, node.hash_with::<G>());
 
             let child = match node {
                 Node::Branch { children } => {
@@ -220,7 +417,7 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
                 Reference::Value(value) => {
                     return if value.is_sealed {
                         Err(Error::Sealed)
-                    } else if let Some(len) = NonZeroU16::new(key.len()) {
+                    } else if let Some(len) = NonZeroU32::new(key.len()) {
                         let proof = proof!(proof rev.lookup_key_left(len, value.hash.clone()));
                         Ok((None, proof))
                     } else {
@@ -233,10 +430,7 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
     }
 
     /// Returns all keys and values in a given subtrie.
-    pub fn get_subtrie<'a>(
-        &'a self,
-        key: &'a [u8],
-    ) -> Result<Vec<iter::Entry>> {
+    pub fn get_subtrie<'a>(&'a self, key: &'a [u8]) -> Result<Vec<Entry>> {
         if self.is_empty() {
             Ok(Vec::new())
         } else {
@@ -244,6 +438,28 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
         }
     }
 
+    /// Returns a lazy iterator over keys and values in a given subtrie.
+    ///
+    /// Unlike [`Self::get_subtrie`], nodes are read as the iterator advances
+    /// so memory usage doesn’t depend on the size of the subtrie.  Entries are
+    /// returned in lexicographical order of their sub keys.
+    ///
+    /// If `after` is given, only entries whose `sub_key` follows it are
+    /// returned.  This allows resuming iteration by passing `sub_key` of the
+    /// last entry returned by previous iterator.
+    pub fn iter_subtrie<'a>(
+        &'a self,
+        key: &[u8],
+        after: Option<bits::Slice<'_>>,
+    ) -> Result<SubtrieIter<'a, A>> {
+        if self.is_empty() {
+            bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
+            Ok(SubtrieIter::empty(&self.alloc))
+        } else {
+            SubtrieIter::new(&self.alloc, self.root_ptr, key, after)
+        }
+    }
+
     /// Inserts a new value hash at given key.
     ///
     /// Sets value hash at given key to given to the provided one.  If the value
@@ -251,20 +467,39 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
     /// [`Error::Sealed`] error.
     ///
     /// If `proof` is specified, stores proof nodes into the provided vector.
-    // TODO(mina86): Add set_with_proof
     pub fn set(&mut self, key: &[u8], value_hash: &CryptoHash) -> Result<()> {
-        let key = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
+        let key = stored_key(key)?;
         self.set_impl(key, value_hash)
     }
 
+    /// Inserts a new value hash at given key and returns witness of the
+    /// change.
+    ///
+    /// Behaves like [`Self::set`] but additionally returns a witness which
+    /// lets stateless verifier calculate the new state root from the old one.
+    /// See [`proof::Witness::verify_set`].
+    pub fn set_with_proof(
+        &mut self,
+        key: &[u8],
+        value_hash: &CryptoHash,
+    ) -> Result<proof::Witness> {
+        let key = stored_key(key)?;
+        let witness = self.witness(key, false)?;
+        self.set_impl(key, value_hash)?;
+        Ok(witness)
+    }
+
     fn set_impl(
         &mut self,
         key: bits::Slice<'_>,
         value_hash: &CryptoHash,
     ) -> Result<()> {
-        let (ptr, hash) = set::Context::new(&mut self.alloc, key, value_hash)
-            .set(self.root_ptr, &self.root_hash)?;
-        self.root_ptr = Some(ptr);
+        let wlog =
+            versions::NodeLog::new(&mut self.alloc, self.versions.as_mut());
+        let (ptr, hash) =
+            set::Context::<_, H>::new(wlog, key, value_hash, false)
+                .set(self.root_ptr, &self.root_hash)?;
+        self.root_ptr = ptr;
         self.root_hash = hash;
         Ok(())
     }
@@ -279,7 +514,6 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
     /// at the key.  For example, if trie contains key `foobar` only, neither
     /// `foo` nor `qux` keys can be sealed.  In those cases, function returns
     /// an error.
-    // TODO(mina86): Add seal_with_proof.
     pub fn seal(&mut self, key: &[u8]) -> Result<()> {
         if self.root_hash == EMPTY_TRIE_ROOT {
             return Err(Error::NotFound);
@@ -288,37 +522,139 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
         self.seal_impl(key)
     }
 
+    /// Seals value at given key and returns witness of the change.
+    ///
+    /// Behaves like [`Self::seal`] but additionally returns a witness which
+    /// proves existence of the sealed value.  Since sealing doesn’t change the
+    /// state root, the witness is mostly useful to demonstrate that a sealing
+    /// operation was valid.  See [`proof::Witness::verify_seal`].
+    pub fn seal_with_proof(&mut self, key: &[u8]) -> Result<proof::Witness> {
+        if self.root_hash == EMPTY_TRIE_ROOT {
+            return Err(Error::NotFound);
+        }
+        let key = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
+        let witness = self.witness(key, false)?;
+        self.seal_impl(key)?;
+        Ok(witness)
+    }
+
     fn seal_impl(&mut self, key: bits::Slice<'_>) -> Result<()> {
-        let removed = seal::Context::new(&mut self.alloc, key)
+        let wlog =
+            versions::NodeLog::new(&mut self.alloc, self.versions.as_mut());
+        let moved = seal::Context::<_, H>::new(wlog, key)
             .seal(NodeRef::new(self.root_ptr, &self.root_hash))?;
-        if removed {
-            self.root_ptr = None;
+        if let Some(ptr) = moved {
+            self.root_ptr = ptr;
+        }
+        Ok(())
+    }
+
+    /// Restores sealed values and nodes from a proof.
+    ///
+    /// `proof` must have been generated with [`Self::prove_many`] from a copy
+    /// of the trie which still holds the sealed data (e.g. an archival node)
+    /// and `entries` must be the corresponding `(key, value_hash)` pairs, see
+    /// [`proof::MultiProof::verify`].  The proof includes the original nodes
+    /// of the sealed subtries; they are checked against hashes of the sealed
+    /// references and reinstated.  Values at all keys present in `entries`
+    /// become unsealed while parts of the trie included in the proof only as
+    /// opaque references stay sealed.
+    ///
+    /// Returns [`Error::BadProof`] if the proof or the entries don’t match
+    /// the trie.  In that case the trie is left unmodified.  Since sealing
+    /// doesn’t change hashes, root hash of the trie stays the same.
+    pub fn unseal(
+        &mut self,
+        proof: &proof::MultiProof,
+        entries: &[(&[u8], Option<&CryptoHash>)],
+    ) -> Result<()> {
+        if !proof.verify_with::<H>(&self.root_hash, entries) {
+            return Err(Error::BadProof);
+        }
+        let root = match proof.root() {
+            Some(root) if self.root_hash != EMPTY_TRIE_ROOT => root,
+            _ => return Ok(()),
+        };
+        let wlog =
+            versions::NodeLog::new(&mut self.alloc, self.versions.as_mut());
+        let moved = unseal::Context::<_, H>::new(wlog, entries)
+            .unseal(NodeRef::new(self.root_ptr, &self.root_hash), root)?;
+        if let Some(ptr) = moved {
+            self.root_ptr = ptr;
         }
         Ok(())
     }
 
     /// Inserts a new value hash at given key and immediately seals it.
     ///
-    /// This is equivalent to calling [`Self::set`] followed by [`Self::seal`].
-    /// (In current implementation this is exactly equivalent to doing those two
-    /// calls but in the future the call is intended to be optimised to be more
-    /// efficient).
-    // TODO(mina86): Implement optimised version.
-    // TODO(mina86): Add set_and_seal_with_proof
+    /// This is equivalent to calling [`Self::set`] followed by [`Self::seal`]
+    /// but is done in a single traversal of the trie.  Furthermore, nodes which
+    /// would be freed by sealing the value are never allocated.
     pub fn set_and_seal(
         &mut self,
         key: &[u8],
         value_hash: &CryptoHash,
     ) -> Result<()> {
-        let key = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
-        self.set_impl(key, value_hash)?;
-        self.seal_impl(key)
+        let key = stored_key(key)?;
+        self.set_and_seal_impl(key, value_hash)
+    }
+
+    fn set_and_seal_impl(
+        &mut self,
+        key: bits::Slice<'_>,
+        value_hash: &CryptoHash,
+    ) -> Result<()> {
+        let wlog =
+            versions::NodeLog::new(&mut self.alloc, self.versions.as_mut());
+        let (ptr, hash) =
+            set::Context::<_, H>::new(wlog, key, value_hash, true)
+                .set(self.root_ptr, &self.root_hash)?;
+        self.root_ptr = ptr;
+        self.root_hash = hash;
+        Ok(())
+    }
+
+    /// Inserts a new value hash at given key, immediately seals it and returns
+    /// witness of the change.
+    ///
+    /// Since sealing doesn’t change the state root, the witness is the same as
+    /// one returned by [`Self::set_with_proof`] and is verified with
+    /// [`proof::Witness::verify_set`].
+    pub fn set_and_seal_with_proof(
+        &mut self,
+        key: &[u8],
+        value_hash: &CryptoHash,
+    ) -> Result<proof::Witness> {
+        let key = stored_key(key)?;
+        let witness = self.witness(key, false)?;
+        self.set_and_seal_impl(key, value_hash)?;
+        Ok(witness)
     }
 
     /// Deletes value at given key.  Returns `false` if key was not found.
     pub fn del(&mut self, key: &[u8]) -> Result<bool> {
         let key = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
-        let res = del::Context::new(&mut self.alloc, key)
+        self.del_impl(key)
+    }
+
+    /// Deletes value at given key and returns witness of the change.
+    ///
+    /// Behaves like [`Self::del`] but additionally returns a witness which
+    /// lets stateless verifier calculate the new state root from the old one.
+    /// See [`proof::Witness::verify_del`].
+    pub fn del_with_proof(
+        &mut self,
+        key: &[u8],
+    ) -> Result<(bool, proof::Witness)> {
+        let key = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
+        let witness = self.witness(key, true)?;
+        Ok((self.del_impl(key)?, witness))
+    }
+
+    fn del_impl(&mut self, key: bits::Slice<'_>) -> Result<bool> {
+        let wlog =
+            versions::NodeLog::new(&mut self.alloc, self.versions.as_mut());
+        let res = del::Context::<_, H>::new(wlog, key)
             .del(self.root_ptr, &self.root_hash);
         match res {
             Ok(res) => {
@@ -332,6 +668,119 @@ impl<A: memory::Allocator<Value = Value>> Trie<A> {
         }
     }
 
+    /// Applies a batch of operations atomically.
+    ///
+    /// Operations are applied in order with the same semantics as the
+    /// corresponding [`Self::set`], [`Self::set_and_seal`], [`Self::seal`]
+    /// and [`Self::del`] methods (in particular, deleting a missing key is not
+    /// an error).  However, hash of each affected node is calculated only
+    /// once, after all operations are applied, rather than after each
+    /// operation.
+    ///
+    /// If any operation fails, the error is returned and the trie is left
+    /// unmodified.  Otherwise, the resulting trie is the same as if the
+    /// operations were applied one by one.
+    pub fn apply_batch<'a>(
+        &mut self,
+        ops: impl IntoIterator<Item = (&'a [u8], BatchOp<'a>)>,
+    ) -> Result<()> {
+        let wlog =
+            versions::NodeLog::new(&mut self.alloc, self.versions.as_mut());
+        let (ptr, hash) = batch::Context::<_, H>::new(wlog).apply(
+            self.root_ptr,
+            &self.root_hash,
+            ops,
+        )?;
+        self.root_ptr = ptr;
+        self.root_hash = hash;
+        Ok(())
+    }
+
+    /// Enables or disables persistent mode and sets its garbage-collection
+    /// horizon.
+    ///
+    /// By default, the trie modifies nodes in place and frees nodes which are
+    /// no longer used.  As a result, only the current version of the trie can
+    /// be queried.  In persistent mode, nodes which belong to versions
+    /// recorded with [`Self::checkpoint`] are never modified.  Instead, they
+    /// are copied on write and kept alive until their version falls beyond
+    /// the horizon.  Old versions can be queried with [`Self::get_at`] and
+    /// [`Self::prove_at`].
+    ///
+    /// `horizon` is the number of most recent versions to retain.  `None`
+    /// disables persistent mode.  Shrinking the horizon or disabling
+    /// persistent mode frees nodes which belong to dropped versions only.
+    pub fn set_version_horizon(&mut self, horizon: Option<usize>) {
+        match (self.versions.as_mut(), horizon) {
+            (Some(versions), horizon) => {
+                versions.set_horizon(&mut self.alloc, horizon.unwrap_or(0));
+                if horizon.is_none() {
+                    self.versions = None;
+                }
+            }
+            (None, Some(horizon)) => {
+                self.versions = Some(versions::Versions::new(horizon))
+            }
+            (None, None) => (),
+        }
+    }
+
+    /// Returns the garbage-collection horizon or `None` if the trie isn’t in
+    /// persistent mode.
+    pub fn version_horizon(&self) -> Option<usize> {
+        self.versions.as_ref().map(|versions| versions.horizon())
+    }
+
+    /// Records current state of the trie as a version which is retained in
+    /// persistent mode.
+    ///
+    /// If number of retained versions exceeds the horizon, the oldest version
+    /// is dropped and nodes which belong only to it are freed.  Does nothing
+    /// if the trie isn’t in persistent mode.
+    pub fn checkpoint(&mut self) {
+        if let Some(versions) = self.versions.as_mut() {
+            versions.checkpoint(
+                &mut self.alloc,
+                self.root_ptr,
+                &self.root_hash,
+            );
+        }
+    }
+
+    /// Returns root hashes of versions retained in persistent mode, oldest
+    /// first.
+    pub fn versions(&self) -> impl Iterator<Item = &CryptoHash> {
+        self.versions.iter().flat_map(|versions| versions.roots())
+    }
+
+    /// Returns pointer to root node of version of the trie with given root
+    /// hash.
+    fn find_root(&self, root_hash: &CryptoHash) -> Result<Option<Ptr>> {
+        if *root_hash == self.root_hash {
+            return Ok(self.root_ptr);
+        }
+        self.versions
+            .as_ref()
+            .and_then(|versions| versions.find(root_hash))
+            .ok_or(Error::UnknownRoot)
+    }
+
+    /// Generates witness of a modification of value at given key.
+    ///
+    /// If `expand_siblings` is true, includes siblings of nodes on the path to
+    /// the key which is necessary to replicate deletion.
+    fn witness(
+        &self,
+        key: bits::Slice<'_>,
+        expand_siblings: bool,
+    ) -> Result<proof::Witness> {
+        if self.root_hash == EMPTY_TRIE_ROOT {
+            return Ok(proof::Witness::new(None));
+        }
+        witness::Context::<_, H>::new(&self.alloc, key, expand_siblings)
+            .witness(NodeRef::new(self.root_ptr, &self.root_hash))
+    }
+
     /// Prints the trie.  Used for testing and debugging only.
     #[cfg(test)]
     pub(crate) fn print(&self) {
@@ -0,0 +1,610 @@
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+
+use lib::hash::{CryptoHash, Hasher};
+use memory::Ptr;
+
+use super::seal::is_sealed_branch;
+use super::versions::NodeLog;
+use super::{Error, Result};
+use crate::bits;
+use crate::nodes::{Node, RawNode, Reference};
+
+/// An operation applied to the trie as part of a batch.
+///
+/// See [`super::Trie::apply_batch`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchOp<'a> {
+    /// Sets value hash at the key; see [`super::Trie::set`].
+    Set(&'a CryptoHash),
+    /// Sets value hash at the key and seals it; see
+    /// [`super::Trie::set_and_seal`].
+    SetAndSeal(&'a CryptoHash),
+    /// Seals value at the key; see [`super::Trie::seal`].
+    Seal,
+    /// Deletes value at the key if it exists; see [`super::Trie::del`].
+    Del,
+}
+
+/// Context for [`super::Trie::apply_batch`] operation.
+///
+/// Operations are applied to an in-memory overlay of the part of the trie
+/// they touch.  Stored nodes are loaded into the overlay as operations
+/// traverse them and no hashes are calculated until all operations are
+/// applied.  Only then the overlay is written back, bottom-up, hashing each
+/// modified node exactly once.
+pub(super) struct Context<'a, A: memory::Allocator<Value = super::Value>, H> {
+    /// Write log used to allocate, modify and free nodes.
+    wlog: NodeLog<'a, A>,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
+}
+
+/// A subtrie in the overlay.
+enum Tree {
+    /// Reference to a value or a node which hasn’t been loaded.
+    Ref(OwnedRef),
+
+    /// A Branch node.  The pointer is address the node has been loaded from
+    /// or `None` if it’s a new node.
+    Branch(Option<Ptr>, Box<[Tree; 2]>),
+
+    /// An Extension node.  The pointer is address the node has been loaded
+    /// from or `None` if it’s a new node.
+    ///
+    /// The key may be longer than fits in a single Extension node in which
+    /// case a chain of nodes is written.
+    Extension(Option<Ptr>, bits::Owned, Box<Tree>),
+}
+
+impl<'a, A: memory::Allocator<Value = super::Value>, H: Hasher>
+    Context<'a, A, H>
+{
+    pub(super) fn new(wlog: NodeLog<'a, A>) -> Self {
+        Self { wlog, hasher: PhantomData }
+    }
+
+    /// Applies all operations to the trie with given root and commits the
+    /// changes.
+    ///
+    /// Returns pointer to the new root node and its hash.  If any operation
+    /// fails, nothing is written.
+    pub(super) fn apply<'b>(
+        mut self,
+        root_ptr: Option<Ptr>,
+        root_hash: &CryptoHash,
+        ops: impl IntoIterator<Item = (&'b [u8], BatchOp<'b>)>,
+    ) -> Result<(Option<Ptr>, CryptoHash)> {
+        let mut root = (*root_hash != super::EMPTY_TRIE_ROOT)
+            .then(|| Tree::Ref(OwnedRef::Node(root_ptr, root_hash.clone())));
+        for (key, op) in ops {
+            let key = match op {
+                BatchOp::Set(_) | BatchOp::SetAndSeal(_) => {
+                    super::stored_key(key)?
+                }
+                _ => bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?,
+            };
+            root = match (op, root) {
+                (BatchOp::Set(hash), root) => Some(self.set(
+                    root,
+                    key,
+                    OwnedRef::Value(false, hash.clone()),
+                )?),
+                (BatchOp::SetAndSeal(hash), root) => Some(self.set(
+                    root,
+                    key,
+                    OwnedRef::Value(true, hash.clone()),
+                )?),
+                (BatchOp::Seal, Some(root)) => Some(self.seal(root, key)?),
+                (BatchOp::Seal, None) => return Err(Error::NotFound),
+                (BatchOp::Del, Some(root)) => {
+                    if self.find(&root, key)? {
+                        self.del(root, key)?
+                    } else {
+                        Some(root)
+                    }
+                }
+                (BatchOp::Del, None) => None,
+            };
+        }
+
+        let res = match root {
+            None => (None, super::EMPTY_TRIE_ROOT),
+            Some(root) => match self.store(root)? {
+                OwnedRef::Node(ptr, hash) => (ptr, hash),
+                OwnedRef::Value(..) => unreachable!(),
+            },
+        };
+        self.wlog.commit();
+        Ok(res)
+    }
+
+    /// Loads node the tree refers to into the overlay.
+    ///
+    /// If `tree` is a reference to a stored node, returns the node with
+    /// references to its children.  Otherwise returns `tree` unchanged.
+    fn load(&self, tree: Tree) -> Result<Tree> {
+        let (ptr, hash) = match tree {
+            Tree::Ref(OwnedRef::Node(ptr, hash)) => (ptr, hash),
+            tree => return Ok(tree),
+        };
+        let ptr = ptr.ok_or(Error::Sealed)?;
+        let node = RawNode(*self.wlog.allocator().get(ptr));
+        let node = node.decode()?;
+        debug_assert_eq!(hash, node.hash_with::<H>());
+        Ok(match node {
+            Node::Branch { children } => {
+                let children = children.map(|child| Tree::Ref(child.into()));
+                Tree::Branch(Some(ptr), Box::new(children))
+            }
+            Node::Extension { key, child } => Tree::Extension(
+                Some(ptr),
+                bits::Slice::from(key).into(),
+                Box::new(Tree::Ref(child.into())),
+            ),
+        })
+    }
+
+    /// Inserts value into the subtrie; see [`super::Trie::set`].
+    fn set(
+        &mut self,
+        tree: Option<Tree>,
+        key: bits::Slice,
+        value: OwnedRef,
+    ) -> Result<Tree> {
+        match tree {
+            Some(tree) => self.set_impl(tree, key, value),
+            None if key.is_empty() => Err(Error::EmptyKey),
+            None => Ok(new_value(key, value)),
+        }
+    }
+
+    fn set_impl(
+        &mut self,
+        tree: Tree,
+        mut key: bits::Slice,
+        value: OwnedRef,
+    ) -> Result<Tree> {
+        match self.load(tree)? {
+            Tree::Ref(OwnedRef::Value(is_sealed, _)) => {
+                if !key.is_empty() {
+                    // Existing key is a prefix of our key.
+                    Err(Error::BadKeyPrefix)
+                } else if is_sealed {
+                    Err(Error::Sealed)
+                } else {
+                    Ok(Tree::Ref(value))
+                }
+            }
+            Tree::Ref(OwnedRef::Node(..)) => unreachable!(),
+            Tree::Branch(ptr, children) => {
+                let bit = key.pop_front().ok_or(Error::BadKeyPrefix)?;
+                let [left, right] = *children;
+                let children = if bit {
+                    [left, self.set_impl(right, key, value)?]
+                } else {
+                    [self.set_impl(left, key, value)?, right]
+                };
+                Ok(Tree::Branch(ptr, Box::new(children)))
+            }
+            Tree::Extension(ptr, ext_key, child) => {
+                if key.is_empty() {
+                    return Err(Error::BadKeyPrefix);
+                }
+                let ext = ext_key.as_slice();
+                let len = common_prefix_len(key, ext);
+                let (_, mut key) = key.split_at(len).unwrap();
+                if len == ext.len() {
+                    let child = self.set_impl(*child, key, value)?;
+                    return Ok(Tree::Extension(ptr, ext_key, Box::new(child)));
+                }
+
+                // Split the Extension with a Branch; see
+                // set::Context::handle_extension for an illustration.  Just
+                // like there, only the node holding the bit where keys
+                // diverge is split with the nodes after it kept as they are.
+                // Any of the resulting Extensions may end up empty in which
+                // case it’s omitted.
+                let our = key.pop_front().ok_or(Error::BadKeyPrefix)?;
+                let (prefix, suffix) = ext.split_at(len).unwrap();
+                let (mut suffix, rest) =
+                    suffix.split_at(node_end(ext, len) - len).unwrap();
+                let theirs = suffix.pop_front().unwrap();
+                debug_assert_ne!(our, theirs);
+                let mut theirs = *child;
+                for part in [rest, suffix] {
+                    if !part.is_empty() {
+                        theirs = Tree::Extension(
+                            None,
+                            part.into(),
+                            Box::new(theirs),
+                        );
+                    }
+                }
+                let ours = new_value(key, value);
+                let children =
+                    if our { [theirs, ours] } else { [ours, theirs] };
+                let branch = Tree::Branch(ptr, Box::new(children));
+                Ok(if prefix.is_empty() {
+                    branch
+                } else {
+                    Tree::Extension(None, prefix.into(), Box::new(branch))
+                })
+            }
+        }
+    }
+
+    /// Seals value in the subtrie; see [`super::Trie::seal`].
+    fn seal(&mut self, tree: Tree, mut key: bits::Slice) -> Result<Tree> {
+        match self.load(tree)? {
+            Tree::Ref(OwnedRef::Value(is_sealed, hash)) => {
+                if is_sealed {
+                    Err(Error::Sealed)
+                } else if !key.is_empty() {
+                    Err(Error::NotFound)
+                } else {
+                    Ok(Tree::Ref(OwnedRef::Value(true, hash)))
+                }
+            }
+            Tree::Ref(OwnedRef::Node(..)) => unreachable!(),
+            Tree::Branch(ptr, children) => {
+                let bit = key.pop_front().ok_or(Error::NotFound)?;
+                let [left, right] = *children;
+                let children = if bit {
+                    [left, self.seal(right, key)?]
+                } else {
+                    [self.seal(left, key)?, right]
+                };
+                Ok(Tree::Branch(ptr, Box::new(children)))
+            }
+            Tree::Extension(ptr, ext_key, child) => {
+                if !key.strip_prefix(ext_key.as_slice()) {
+                    return Err(Error::NotFound);
+                }
+                let child = self.seal(*child, key)?;
+                Ok(Tree::Extension(ptr, ext_key, Box::new(child)))
+            }
+        }
+    }
+
+    /// Checks whether value at given key exists in the subtrie.
+    ///
+    /// Returns an error if the value or its ancestor is sealed.
+    fn find(&self, tree: &Tree, mut key: bits::Slice) -> Result<bool> {
+        match tree {
+            Tree::Ref(OwnedRef::Value(is_sealed, _)) => {
+                if *is_sealed {
+                    Err(Error::Sealed)
+                } else {
+                    Ok(key.is_empty())
+                }
+            }
+            Tree::Ref(rf @ OwnedRef::Node(..)) => {
+                self.find(&self.load(Tree::Ref(rf.clone()))?, key)
+            }
+            Tree::Branch(_, children) => match key.pop_front() {
+                Some(bit) => self.find(&children[usize::from(bit)], key),
+                None => Ok(false),
+            },
+            Tree::Extension(_, ext_key, child) => {
+                if key.strip_prefix(ext_key.as_slice()) {
+                    self.find(child, key)
+                } else {
+                    Ok(false)
+                }
+            }
+        }
+    }
+
+    /// Deletes value from the subtrie; see [`super::Trie::del`].
+    ///
+    /// The value must exist (see [`Self::find`]).  Returns `None` if the
+    /// entire subtrie has been deleted.
+    fn del(
+        &mut self,
+        tree: Tree,
+        mut key: bits::Slice,
+    ) -> Result<Option<Tree>> {
+        match self.load(tree)? {
+            Tree::Ref(OwnedRef::Value(..)) => Ok(None),
+            Tree::Ref(OwnedRef::Node(..)) => unreachable!(),
+            Tree::Branch(ptr, children) => {
+                let offset = key.offset;
+                let bit = key.pop_front().unwrap();
+                let [left, right] = *children;
+                let (child, other) =
+                    if bit { (right, left) } else { (left, right) };
+                if let Some(child) = self.del(child, key)? {
+                    let children =
+                        if bit { [other, child] } else { [child, other] };
+                    return Ok(Some(Tree::Branch(ptr, Box::new(children))));
+                }
+
+                // The Branch is left with a single child.  Replace it with an
+                // Extension leading to the other child merging it with the
+                // child if it’s an Extension.  Like in del::Context, only the
+                // first node of the child is merged.
+                let mut ext_key = bits::Owned::bit(!bit, offset);
+                let other = match other {
+                    Tree::Ref(OwnedRef::Node(Some(ptr), _))
+                        if self.is_extension(ptr)? =>
+                    {
+                        self.load(other)?
+                    }
+                    other => other,
+                };
+                let child = match other {
+                    Tree::Extension(ptr, key, child) => {
+                        self.free(ptr);
+                        let key = key.as_slice();
+                        let (head, rest) =
+                            key.split_at(node_end(key, 0)).unwrap();
+                        ext_key.extend(head).map_err(|_| Error::KeyTooLong)?;
+                        if rest.is_empty() {
+                            child
+                        } else {
+                            Box::new(Tree::Extension(None, rest.into(), child))
+                        }
+                    }
+                    other => Box::new(other),
+                };
+                Ok(Some(Tree::Extension(ptr, ext_key, child)))
+            }
+            Tree::Extension(ptr, mut ext_key, child) => {
+                let stripped = key.strip_prefix(ext_key.as_slice());
+                debug_assert!(stripped);
+                match self.del(*child, key)? {
+                    None => {
+                        self.free(ptr);
+                        Ok(None)
+                    }
+                    Some(Tree::Extension(child_ptr, suffix, child)) => {
+                        self.free(child_ptr);
+                        ext_key
+                            .extend(suffix.as_slice())
+                            .map_err(|_| Error::KeyTooLong)?;
+                        Ok(Some(Tree::Extension(ptr, ext_key, child)))
+                    }
+                    Some(child) => {
+                        Ok(Some(Tree::Extension(ptr, ext_key, Box::new(child))))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns whether stored node at given address is an Extension.
+    fn is_extension(&self, ptr: Ptr) -> Result<bool> {
+        let node = <&RawNode>::from(self.wlog.allocator().get(ptr));
+        Ok(matches!(node.decode()?, Node::Extension { .. }))
+    }
+
+    /// Writes the subtrie into the allocator and returns reference to it.
+    fn store(&mut self, tree: Tree) -> Result<OwnedRef> {
+        match tree {
+            Tree::Ref(rf) => Ok(rf),
+            Tree::Branch(ptr, children) => {
+                let [left, right] = *children;
+                let left = self.store(left)?;
+                let right = self.store(right)?;
+                let children = [left.to_ref(), right.to_ref()];
+                let node = RawNode::branch(children[0], children[1]);
+                if is_sealed_branch(&children) {
+                    self.free(ptr);
+                    Ok(OwnedRef::Node(None, node.decode()?.hash_with::<H>()))
+                } else {
+                    self.put(ptr, node)
+                }
+            }
+            Tree::Extension(mut ptr, key, child) => {
+                let mut child = self.store(*child)?;
+                for chunk in key.as_slice().chunks().rev() {
+                    let node = RawNode::extension(chunk, child.to_ref());
+                    child = self.put(ptr.take(), node)?;
+                }
+                Ok(child)
+            }
+        }
+    }
+
+    /// Stores node at given address or allocates a new one if the address is
+    /// `None`.  Returns reference to the node.
+    fn put(&mut self, ptr: Option<Ptr>, node: RawNode) -> Result<OwnedRef> {
+        let hash = node.decode()?.hash_with::<H>();
+        let ptr = match ptr {
+            Some(ptr) => self.wlog.set(ptr, *node)?,
+            None => self.wlog.alloc(*node)?,
+        };
+        Ok(OwnedRef::Node(Some(ptr), hash))
+    }
+
+    /// Frees node loaded from given address if any.
+    fn free(&mut self, ptr: Option<Ptr>) {
+        if let Some(ptr) = ptr {
+            self.wlog.free(ptr);
+        }
+    }
+}
+
+/// Returns a new subtrie holding a single value at given key.
+fn new_value(key: bits::Slice, value: OwnedRef) -> Tree {
+    let value = Tree::Ref(value);
+    if key.is_empty() {
+        value
+    } else {
+        Tree::Extension(None, key.into(), Box::new(value))
+    }
+}
+
+/// Returns length of the part of Extension key up to the end of the node
+/// holding bit at given index.
+///
+/// Key of an Extension in the overlay may be longer than fits in a single
+/// node in which case it’s stored as a chain of nodes; see
+/// [`Context::store`].
+fn node_end(key: bits::Slice, index: u32) -> u32 {
+    let mut end = 0;
+    for chunk in key.chunks() {
+        end += u32::from(chunk.len());
+        if end > index {
+            break;
+        }
+    }
+    end
+}
+
+/// Returns length of the common prefix of two bit slices.
+fn common_prefix_len(mut key: bits::Slice, ext: bits::Slice) -> u32 {
+    let mut len = 0;
+    for chunk in ext.chunks() {
+        let (prefix, rest) = key.forward_common_prefix(chunk);
+        len += prefix.map_or(0, |prefix| bits::Slice::from(prefix).len());
+        if rest.is_some() {
+            break;
+        }
+    }
+    len
+}
+
+#[derive(Clone)]
+enum OwnedRef {
+    Node(Option<Ptr>, CryptoHash),
+    Value(bool, CryptoHash),
+}
+
+impl OwnedRef {
+    fn to_ref(&self) -> Reference {
+        match self {
+            Self::Node(ptr, hash) => Reference::node(*ptr, hash),
+            Self::Value(is_sealed, hash) => Reference::value(*is_sealed, hash),
+        }
+    }
+}
+
+impl From<Reference<'_>> for OwnedRef {
+    fn from(rf: Reference<'_>) -> Self {
+        match rf {
+            Reference::Node(node) => Self::Node(node.ptr, node.hash.clone()),
+            Reference::Value(value) => {
+                Self::Value(value.is_sealed, value.hash.clone())
+            }
+        }
+    }
+}
+
+#[test]
+fn test_apply_batch() {
+    let mut rng = rand::thread_rng();
+    let hashes = (0..100).map(CryptoHash::test).collect::<alloc::vec::Vec<_>>();
+    let short = (0..200u8).map(|num| alloc::vec![num % 4, num / 4 * 5]);
+    // Long keys share runs of bits which don’t fit in a single Extension node.
+    let long = (0..200u8).map(|num| {
+        let mut key = alloc::vec![0; 81];
+        key[[12, 40, 62, 80][usize::from(num % 4)]] = num / 4 + 1;
+        key[33] = num % 3;
+        key
+    });
+    for (keys, divisor) in [(short.collect(), 2000), (long.collect(), 5000)] {
+        test_apply_batch_impl(&mut rng, &hashes, keys, divisor);
+    }
+}
+
+#[cfg(test)]
+fn test_apply_batch_impl(
+    rng: &mut impl rand::Rng,
+    hashes: &[CryptoHash],
+    keys: alloc::vec::Vec<alloc::vec::Vec<u8>>,
+    divisor: usize,
+) {
+    type TestTrie =
+        super::Trie<memory::test_utils::TestAllocator<super::Value>>;
+
+    fn apply_one(trie: &mut TestTrie, key: &[u8], op: BatchOp) -> Result<()> {
+        match op {
+            BatchOp::Set(hash) => trie.set(key, hash),
+            BatchOp::SetAndSeal(hash) => trie.set_and_seal(key, hash),
+            BatchOp::Seal => trie.seal(key),
+            BatchOp::Del => trie.del(key).map(|_| ()),
+        }
+    }
+
+    #[track_caller]
+    fn assert_same(want: &TestTrie, got: &TestTrie) {
+        assert_eq!(want.hash(), got.hash());
+        assert_eq!(want.alloc.count(), got.alloc.count());
+        let report = got.verify_integrity();
+        assert!(report.is_healthy(), "{report:?}");
+    }
+
+    for _ in 0..lib::test_utils::get_iteration_count(divisor) {
+        let mut want = TestTrie::test(1000);
+        let mut got = TestTrie::test(1000);
+        for _ in 0..10 {
+            // Apply operations one by one dropping ones which fail.  What’s
+            // left must succeed when applied as a batch.
+            let mut ops = alloc::vec::Vec::new();
+            for _ in 0..rng.gen_range(1..30) {
+                let key = &keys[rng.gen_range(0..keys.len())][..];
+                let hash = &hashes[rng.gen_range(0..hashes.len())];
+                let op = match rng.gen_range(0..10) {
+                    0 => BatchOp::SetAndSeal(hash),
+                    1 => BatchOp::Seal,
+                    2..=4 => BatchOp::Del,
+                    _ => BatchOp::Set(hash),
+                };
+                if apply_one(&mut want, key, op).is_ok() {
+                    ops.push((key, op));
+                }
+            }
+            got.apply_batch(ops.iter().copied()).unwrap();
+            assert_same(&want, &got);
+        }
+
+        // A failing operation rolls back the entire batch.
+        let hash = &hashes[0];
+        let ops = keys
+            .iter()
+            .map(|key| (&key[..], BatchOp::Set(hash)))
+            .chain([(&b""[..], BatchOp::Set(hash))]);
+        assert!(got.apply_batch(ops).is_err());
+        assert_same(&want, &got);
+    }
+}
+
+#[test]
+fn test_apply_batch_persistent() {
+    let mut trie = super::Trie::test(1000);
+    trie.set_version_horizon(Some(2));
+    let one = CryptoHash::test(1);
+    let two = CryptoHash::test(2);
+    trie.apply_batch([
+        (&b"foo"[..], BatchOp::Set(&one)),
+        (b"bar", BatchOp::Set(&one)),
+        (b"baz", BatchOp::SetAndSeal(&one)),
+    ])
+    .unwrap();
+    trie.checkpoint();
+    let old = trie.hash().clone();
+
+    trie.apply_batch([
+        (&b"foo"[..], BatchOp::Set(&two)),
+        (b"bar", BatchOp::Del),
+        (b"qux", BatchOp::Set(&two)),
+        (b"qux", BatchOp::Seal),
+    ])
+    .unwrap();
+    assert_eq!(Ok(Some(two.clone())), trie.get(b"foo"));
+    assert_eq!(Ok(None), trie.get(b"bar"));
+    assert_eq!(Err(Error::Sealed), trie.get(b"qux"));
+    assert_eq!(Ok(Some(one.clone())), trie.get_at(&old, b"foo"));
+    assert_eq!(Ok(Some(one)), trie.get_at(&old, b"bar"));
+    assert_eq!(Ok(None), trie.get_at(&old, b"qux"));
+    assert!(trie.verify_integrity().is_healthy());
+
+    assert_eq!(
+        Err(Error::Sealed),
+        trie.apply_batch([(&b"foo"[..], BatchOp::Del), (b"baz", BatchOp::Del)])
+    );
+    assert_eq!(Ok(Some(two)), trie.get(b"foo"));
+}
@@ -0,0 +1,301 @@
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use lib::hash::{CryptoHash, Hasher};
+use memory::Ptr;
+
+use super::seal::is_sealed_branch;
+use super::{Error, Result, Trie, Value};
+use crate::bits;
+use crate::nodes::{RawNode, Reference};
+
+/// An entry to insert into the trie: key, value hash and whether to seal the
+/// value.
+type Entry<'a> = (&'a [u8], &'a CryptoHash, bool);
+
+/// Context for [`Trie::from_sorted_entries`] operation.
+struct Context<'a, A: memory::Allocator<Value = Value>, H> {
+    /// Write log used to allocate nodes.
+    wlog: memory::WriteLog<'a, A>,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
+}
+
+impl<A: memory::Allocator<Value = Value>, H: Hasher> Trie<A, H> {
+    /// Constructs a trie from entries sorted by key.
+    ///
+    /// Each entry is a `(key, value_hash, seal)` tuple.  If `seal` is true,
+    /// the value is inserted as sealed (as with [`Self::set_and_seal`]).
+    ///
+    /// Rather than inserting keys one at a time, the trie is built bottom-up
+    /// with each node allocated and hashed exactly once.  Branch nodes whose
+    /// both children are sealed aren’t stored.
+    ///
+    /// The result is the same as a trie constructed by inserting the entries
+    /// one by one in ascending key order (and then sealing the ones which are
+    /// to be sealed).  Note that when keys share runs of bits longer than fit
+    /// in a single Extension node, the way [`Self::set`] splits those runs
+    /// into nodes depends on the order of insertion.  Inserting the same
+    /// entries in a different order may thus result in a different root hash.
+    ///
+    /// Keys must be sorted in strictly ascending order.  Returns
+    /// [`Error::UnsortedKeys`] if they aren’t, [`Error::BadKeyPrefix`] if one
+    /// key is a prefix of another and [`Error::EmptyKey`] or
+    /// [`Error::KeyTooLong`] if a key is invalid.  On failure, all nodes
+    /// allocated while building are freed.
+    pub fn from_sorted_entries<'a>(
+        mut alloc: A,
+        entries: impl IntoIterator<Item = Entry<'a>>,
+    ) -> Result<Self> {
+        let mut sorted = Vec::<Entry<'a>>::new();
+        for entry in entries {
+            if entry.0.is_empty() {
+                return Err(Error::EmptyKey);
+            }
+            super::stored_key(entry.0)?;
+            if let Some(last) = sorted.last() {
+                if last.0 >= entry.0 {
+                    return Err(Error::UnsortedKeys);
+                } else if entry.0.starts_with(last.0) {
+                    // Since keys are sorted, if a key is a prefix of any
+                    // other key, it is a prefix of the key directly after it.
+                    return Err(Error::BadKeyPrefix);
+                }
+            }
+            sorted.push(entry);
+        }
+        if sorted.is_empty() {
+            return Ok(Self::with_hasher(alloc));
+        }
+
+        let (root_ptr, root_hash) = {
+            let mut ctx = Context::<A, H> {
+                wlog: memory::WriteLog::new(&mut alloc),
+                hasher: PhantomData,
+            };
+            let root = ctx.build(&sorted, 0, 0)?;
+            ctx.wlog.commit();
+            match root {
+                OwnedRef::Node(ptr, hash) => (ptr, hash),
+                // Keys are non-empty so there is at least one node.
+                OwnedRef::Value(..) => unreachable!(),
+            }
+        };
+        Ok(Self::from_parts_with_hasher(alloc, root_ptr, root_hash))
+    }
+}
+
+impl<'a, A: memory::Allocator<Value = Value>, H: Hasher> Context<'a, A, H> {
+    /// Builds subtrie holding given entries.
+    ///
+    /// All keys in `entries` share first `offset` bits which have already been
+    /// consumed by ancestors of the subtrie.  `origin` is the index of the bit
+    /// right after the point where the first key diverges from keys preceding
+    /// it (or zero if there are no preceding keys); see [`Self::extend`].
+    /// Returns reference to the root of the subtrie.
+    fn build(
+        &mut self,
+        entries: &[Entry],
+        offset: u32,
+        origin: u32,
+    ) -> Result<OwnedRef> {
+        let (first, last) = (entries[0], entries[entries.len() - 1]);
+        let key = bits::Slice::from_bytes(first.0).unwrap();
+        if entries.len() == 1 {
+            let child = OwnedRef::Value(first.2, first.1.clone());
+            return self.extend(key, origin, offset..key.len(), child);
+        }
+
+        // Keys are sorted thus the common prefix of the first and last key is
+        // shared by all keys.  The first key has zero bit right after it while
+        // the last one has one bit.  The first key of the right subtrie
+        // diverges from the key preceding it at that bit.
+        let split = common_prefix_len(first.0, last.0);
+        let mid = entries.partition_point(|entry| !bit_at(entry.0, split));
+        let left = self.build(&entries[..mid], split + 1, origin)?;
+        let right = self.build(&entries[mid..], split + 1, split + 1)?;
+
+        let children = [left.to_ref(), right.to_ref()];
+        let node = RawNode::branch(children[0], children[1]);
+        let branch = if is_sealed_branch(&children) {
+            OwnedRef::Node(None, node.decode()?.hash_with::<H>())
+        } else {
+            let (ptr, hash) = self.alloc_node(node)?;
+            OwnedRef::Node(Some(ptr), hash)
+        };
+        self.extend(key, origin, offset..split, branch)
+    }
+
+    /// Prepends Extension nodes holding given bits of the key to the
+    /// reference.
+    ///
+    /// When [`Trie::set`] inserts a key, the part of the key past the point
+    /// where it diverges from existing keys is split into Extension nodes
+    /// starting at `origin` bit.  Later insertions only ever split those nodes
+    /// at points where other keys diverge.  To produce the same trie, bits in
+    /// `range` are split into nodes at the same positions.  If the range is
+    /// empty, returns `child` unchanged.
+    fn extend(
+        &mut self,
+        key: bits::Slice,
+        origin: u32,
+        range: core::ops::Range<u32>,
+        mut child: OwnedRef,
+    ) -> Result<OwnedRef> {
+        let (head, _) = key.split_at(range.end).unwrap();
+        let (_, tail) = head.split_at(origin).unwrap();
+        let mut end = range.end;
+        for chunk in tail.chunks().rev() {
+            if end <= range.start {
+                break;
+            }
+            let start = end - u32::from(chunk.len());
+            let lo = start.max(range.start);
+            let (_, part) = head.split_at(lo).unwrap();
+            let (part, _) = part.split_at(end - lo).unwrap();
+            let part = bits::ExtKey::try_from(part).unwrap();
+            let node = RawNode::extension(part, child.to_ref());
+            let (ptr, hash) = self.alloc_node(node)?;
+            child = OwnedRef::Node(Some(ptr), hash);
+            end = start;
+        }
+        Ok(child)
+    }
+
+    /// Allocates a new node and sets it to given value.
+    fn alloc_node(&mut self, node: RawNode) -> Result<(Ptr, CryptoHash)> {
+        let hash = node.decode()?.hash_with::<H>();
+        let ptr = self.wlog.alloc(*node)?;
+        Ok((ptr, hash))
+    }
+}
+
+/// Returns length in bits of the common prefix of two keys neither of which
+/// is a prefix of the other.
+fn common_prefix_len(lhs: &[u8], rhs: &[u8]) -> u32 {
+    let idx = lhs.iter().zip(rhs).position(|(a, b)| a != b).unwrap();
+    let bits = idx * 8 + (lhs[idx] ^ rhs[idx]).leading_zeros() as usize;
+    // Keys have been verified to fit in a bits::Slice.
+    u32::try_from(bits).unwrap()
+}
+
+/// Returns bit at given index of the key.
+fn bit_at(key: &[u8], index: u32) -> bool {
+    let index = index as usize;
+    key[index / 8] & (0x80 >> (index % 8)) != 0
+}
+
+enum OwnedRef {
+    Node(Option<Ptr>, CryptoHash),
+    Value(bool, CryptoHash),
+}
+
+impl OwnedRef {
+    fn to_ref(&self) -> Reference {
+        match self {
+            Self::Node(ptr, hash) => Reference::node(*ptr, hash),
+            Self::Value(is_sealed, hash) => Reference::value(*is_sealed, hash),
+        }
+    }
+}
+
+#[test]
+fn test_from_sorted_entries() {
+    use rand::Rng;
+
+    type TestTrie = Trie<memory::test_utils::TestAllocator<Value>>;
+
+    let alloc = || memory::test_utils::TestAllocator::new(10_000);
+    let hash = CryptoHash::test(1);
+    let build = |entries: &[Entry]| {
+        TestTrie::from_sorted_entries(alloc(), entries.iter().copied())
+    };
+
+    assert!(build(&[]).unwrap().is_empty());
+    assert_eq!(Err(Error::EmptyKey), build(&[(b"", &hash, false)]).map(|_| ()));
+    let unsorted = [(&b"b"[..], &hash, false), (b"a", &hash, false)];
+    assert_eq!(Err(Error::UnsortedKeys), build(&unsorted).map(|_| ()));
+    let dup = [(&b"a"[..], &hash, false), (b"a", &hash, false)];
+    assert_eq!(Err(Error::UnsortedKeys), build(&dup).map(|_| ()));
+    let prefix = [(&b"a"[..], &hash, false), (b"ab", &hash, false)];
+    assert_eq!(Err(Error::BadKeyPrefix), build(&prefix).map(|_| ()));
+
+    // Builds the trie by inserting entries in ascending order and then
+    // sealing the ones which need to be sealed.
+    let set_one_by_one = |entries: &[Entry]| {
+        let mut trie = TestTrie::test(10_000);
+        for (key, hash, _) in entries {
+            trie.set(key, hash).unwrap();
+        }
+        for (key, _, seal) in entries {
+            if *seal {
+                trie.seal(key).unwrap();
+            }
+        }
+        trie
+    };
+
+    let mut rng = rand::thread_rng();
+    for (long, divisor) in [(false, 100), (true, 1000)] {
+        for _ in 0..lib::test_utils::get_iteration_count(divisor) {
+            let mut keys = alloc::collections::BTreeMap::new();
+            for num in 0..rng.gen_range(1..200) {
+                let key = if long {
+                    // Long keys differing in few bytes so that they share runs
+                    // longer than fit in a single Extension node.
+                    let mut key = [0; 81];
+                    key[rng.gen_range(0..81)] = rng.gen();
+                    key[rng.gen_range(0..81)] = rng.gen();
+                    key.to_vec()
+                } else {
+                    // Keys starting with their length are prefix-free.
+                    // Mostly use short keys so that the trie is dense.
+                    let mut key = [0; 34];
+                    let len = if rng.gen_ratio(1, 4) {
+                        34
+                    } else {
+                        rng.gen_range(2..5)
+                    };
+                    key[0] = len as u8;
+                    rng.fill(&mut key[1..len]);
+                    key[..len].to_vec()
+                };
+                keys.insert(key, (num, rng.gen_ratio(1, 3)));
+            }
+            let hashes = keys
+                .values()
+                .map(|(num, _)| CryptoHash::test(*num))
+                .collect::<Vec<_>>();
+            let entries = keys
+                .iter()
+                .zip(hashes.iter())
+                .map(|((key, (_, seal)), hash)| (&key[..], hash, *seal))
+                .collect::<Vec<_>>();
+
+            let want = set_one_by_one(&entries);
+            let got = build(&entries).unwrap();
+            assert_eq!(want.hash(), got.hash());
+            assert_eq!(want.alloc.count(), got.alloc.count());
+            assert!(got.verify_integrity().is_healthy());
+            for (key, hash, seal) in entries {
+                let want = if seal {
+                    Err(Error::Sealed)
+                } else {
+                    Ok(Some(hash.clone()))
+                };
+                assert_eq!(want, got.get(key));
+            }
+        }
+    }
+
+    // Keys sharing runs of bits which span several Extension nodes.
+    let mut keys = [[0; 81]; 3];
+    keys[1][12] = 1;
+    keys[2][62] = 1;
+    keys.sort();
+    let entries =
+        keys.iter().map(|key| (&key[..], &hash, false)).collect::<Vec<_>>();
+    let want = set_one_by_one(&entries);
+    assert_eq!(want.hash(), build(&entries).unwrap().hash());
+}
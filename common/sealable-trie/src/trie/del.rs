@@ -1,26 +1,33 @@
-use lib::hash::CryptoHash;
+use core::marker::PhantomData;
+
+use lib::hash::{CryptoHash, Hasher};
 use memory::Ptr;
 
+use super::versions::NodeLog;
 use super::{Error, Result};
 use crate::bits;
 use crate::nodes::{Node, NodeRef, RawNode, Reference, ValueRef};
 
 /// Context for [`super::Trie::del`] operation.
-pub(super) struct Context<'a, A: memory::Allocator<Value = super::Value>> {
+pub(super) struct Context<'a, A: memory::Allocator<Value = super::Value>, H> {
     /// Part of the key yet to be traversed.
     ///
     /// It starts as the key user provided and as trie is traversed bits are
     /// removed from its front.
     key: bits::Slice<'a>,
 
-    /// Allocator used to allocate new nodes.
-    wlog: memory::WriteLog<'a, A>,
+    /// Write log used to allocate, modify and free nodes.
+    wlog: NodeLog<'a, A>,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
 }
 
-impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
-    pub(super) fn new(alloc: &'a mut A, key: bits::Slice<'a>) -> Self {
-        let wlog = memory::WriteLog::new(alloc);
-        Self { key, wlog }
+impl<'a, A: memory::Allocator<Value = super::Value>, H: Hasher>
+    Context<'a, A, H>
+{
+    pub(super) fn new(wlog: NodeLog<'a, A>, key: bits::Slice<'a>) -> Self {
+        Self { key, wlog, hasher: PhantomData }
     }
 
     /// Inserts value hash into the trie.
@@ -62,7 +69,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         let ptr = nref.ptr.ok_or(Error::Sealed)?;
         let node = RawNode(*self.wlog.allocator().get(ptr));
         let node = node.decode()?;
-        debug_assert_eq!(*nref.hash, node.hash());
+        debug_assert_eq!(*nref.hash, node.hash_with::<H>());
 
         match node {
             Node::Branch { children } => self.handle_branch(ptr, children),
@@ -149,7 +156,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         if let Reference::Node(NodeRef { ptr: Some(ptr), hash }) = child {
             let node = RawNode(*self.wlog.allocator().get(ptr));
             let node = node.decode()?;
-            debug_assert_eq!(*hash, node.hash());
+            debug_assert_eq!(*hash, node.hash_with::<H>());
 
             if let Node::Extension { key, child } = node {
                 // Drop the child Extension and merge keys.
@@ -163,9 +170,11 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
 
     /// Sets value of a node cell at given address and returns an [`OwnedRef`]
     /// pointing at the node.
+    ///
+    /// In persistent mode, the node may be stored at a new address.
     fn set_node(&mut self, ptr: Ptr, node: RawNode) -> Result<OwnedRef> {
-        let hash = node.decode()?.hash();
-        self.wlog.set(ptr, *node);
+        let hash = node.decode()?.hash_with::<H>();
+        let ptr = self.wlog.set(ptr, *node)?;
         Ok(OwnedRef::Node(Some(ptr), hash))
     }
 
@@ -187,7 +196,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         for chunk in key.as_slice().chunks().rev() {
             let node = RawNode::extension(chunk, child.to_ref());
             let ptr = self.wlog.alloc(node.0)?;
-            child = OwnedRef::Node(Some(ptr), node.decode()?.hash());
+            child = OwnedRef::Node(Some(ptr), node.decode()?.hash_with::<H>());
         }
 
         Ok(Some(child))
@@ -0,0 +1,458 @@
+//! Comparing contents of two tries.
+//!
+//! The comparison walks both tries at the same time and skips subtries whose
+//! hashes are equal.  Thanks to that, cost of computing a difference is
+//! proportional to the number of changes rather than size of the tries.
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use lib::hash::{CryptoHash, Hasher};
+use memory::Ptr;
+
+use super::iter::SubtrieIter;
+use super::{Error, Result, Trie, Value, EMPTY_TRIE_ROOT};
+use crate::bits;
+use crate::nodes::{Node, RawNode, Reference};
+
+/// A single difference between two tries.
+///
+/// Keys are bit slices since sealed nodes may be located at keys which don’t
+/// consist of whole bytes.  Keys of values always consist of whole bytes and
+/// can be converted into byte vectors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// Value was added to the trie.
+    Added { key: bits::Owned, hash: CryptoHash },
+
+    /// Value hash has changed.
+    Changed { key: bits::Owned, old: CryptoHash, new: CryptoHash },
+
+    /// Value was deleted from the trie.
+    Deleted { key: bits::Owned, hash: CryptoHash },
+
+    /// Value or a subtrie has been sealed.
+    ///
+    /// `hash` is the hash of the value if it’s a value that has been sealed.
+    /// It’s `None` if a whole subtrie at `key` has been sealed.  In the latter
+    /// case, it’s not possible to determine what values within the subtrie
+    /// have been added, changed or deleted and no other changes are reported
+    /// for keys under `key`.
+    ///
+    /// Sealing a value which has been added or changed is reported as
+    /// [`Change::Added`] or [`Change::Changed`] followed by [`Change::Sealed`]
+    /// for the same key.
+    Sealed { key: bits::Owned, hash: Option<CryptoHash> },
+}
+
+/// Returns differences between `old` and `new` trie.
+///
+/// Changes are returned in lexicographical order of their keys.
+///
+/// Since trie hashes don’t commit to whether nodes are sealed, subtries with
+/// equal hashes are considered identical.  As a consequence, sealing done
+/// within such subtries is not reported.  Sealing is detected only where the
+/// tries differ otherwise or where a sealed node in `new` trie corresponds to
+/// an unsealed node in `old` trie.
+///
+/// Returns [`Error::Sealed`] if a sealed value or subtrie of the `old` trie
+/// differs from the `new` trie which can only happen if the `new` trie isn’t derived
+/// from the `old` trie.
+pub fn diff<A, B, H>(old: &Trie<A, H>, new: &Trie<B, H>) -> Result<Vec<Change>>
+where
+    A: memory::Allocator<Value = Value>,
+    B: memory::Allocator<Value = Value>,
+    H: Hasher,
+{
+    let old_root = root(old.root_ptr, &old.root_hash);
+    let new_root = root(new.root_ptr, &new.root_hash);
+    Context::<_, _, H>::new(&old.alloc, &new.alloc).run(old_root, new_root)
+}
+
+/// Returns differences between an older root and current state of the trie.
+///
+/// `old_root_ptr` and `old_root_hash` describe an older version of the trie
+/// whose nodes are still stored in trie’s allocator.  It’s caller’s
+/// responsibility to guarantee that those nodes haven’t been freed or
+/// modified.  See [`diff`] for details.
+pub fn diff_with_root<A: memory::Allocator<Value = Value>, H: Hasher>(
+    trie: &Trie<A, H>,
+    old_root_ptr: Option<Ptr>,
+    old_root_hash: &CryptoHash,
+) -> Result<Vec<Change>> {
+    let old_root = root(old_root_ptr, old_root_hash);
+    let new_root = root(trie.root_ptr, &trie.root_hash);
+    Context::<_, _, H>::new(&trie.alloc, &trie.alloc).run(old_root, new_root)
+}
+
+/// A reference within one of the compared tries.
+#[derive(Clone, Copy)]
+struct Side<'a> {
+    /// Part of an Extension key which still needs to be traversed before
+    /// reaching `rf`.
+    pending: bits::Slice<'a>,
+
+    /// Reference to the node or value.
+    rf: Reference<'a>,
+}
+
+/// Returns [`Side`] for root of a trie or `None` if the trie is empty.
+fn root(ptr: Option<Ptr>, hash: &CryptoHash) -> Option<Side<'_>> {
+    (*hash != EMPTY_TRIE_ROOT).then(|| Side {
+        pending: bits::Slice::default(),
+        rf: Reference::node(ptr, hash),
+    })
+}
+
+/// Context for the diff operation.
+struct Context<'a, A, B, H> {
+    /// Allocator of the old trie.
+    old: &'a A,
+
+    /// Allocator of the new trie.
+    new: &'a B,
+
+    /// Path from the root of the tries to the currently compared references.
+    path: bits::Owned,
+
+    /// Changes found so far.
+    changes: Vec<Change>,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
+}
+
+impl<'a, A, B, H> Context<'a, A, B, H>
+where
+    A: memory::Allocator<Value = Value>,
+    B: memory::Allocator<Value = Value>,
+    H: Hasher,
+{
+    fn new(old: &'a A, new: &'a B) -> Self {
+        Self {
+            old,
+            new,
+            path: bits::Owned::default(),
+            changes: Vec::new(),
+            hasher: PhantomData,
+        }
+    }
+
+    fn run(
+        mut self,
+        old: Option<Side<'a>>,
+        new: Option<Side<'a>>,
+    ) -> Result<Vec<Change>> {
+        self.diff(old, new)?;
+        Ok(self.changes)
+    }
+
+    /// Compares references located at current path.
+    fn diff(
+        &mut self,
+        old: Option<Side<'a>>,
+        new: Option<Side<'a>>,
+    ) -> Result<()> {
+        let (mut old, mut new) = match (old, new) {
+            (None, None) => return Ok(()),
+            (Some(old), None) => return self.deleted(old),
+            (None, Some(new)) => return self.added(new),
+            (Some(old), Some(new)) => (old, new),
+        };
+
+        // Skip common part of pending Extension keys.
+        let len = self.path.len();
+        loop {
+            let (mut old_key, mut new_key) = (old.pending, new.pending);
+            match (old_key.pop_front(), new_key.pop_front()) {
+                (Some(old_bit), Some(new_bit)) if old_bit == new_bit => {
+                    self.path
+                        .push_back(old_bit)
+                        .map_err(|_| Error::KeyTooLong)?;
+                    old.pending = old_key;
+                    new.pending = new_key;
+                }
+                _ => break,
+            }
+        }
+        let result = self.diff_refs(old, new);
+        self.path.truncate(len);
+        result
+    }
+
+    /// Compares references whose pending keys have no common prefix.
+    fn diff_refs(&mut self, old: Side<'a>, new: Side<'a>) -> Result<()> {
+        if old.pending.is_empty() && new.pending.is_empty() {
+            match (old.rf, new.rf) {
+                (Reference::Value(old), Reference::Value(new)) => {
+                    if old.hash != new.hash && old.is_sealed {
+                        return Err(Error::Sealed);
+                    } else if old.hash != new.hash {
+                        self.changes.push(Change::Changed {
+                            key: self.path.clone(),
+                            old: old.hash.clone(),
+                            new: new.hash.clone(),
+                        });
+                    }
+                    if new.is_sealed && !old.is_sealed {
+                        self.changes.push(Change::Sealed {
+                            key: self.path.clone(),
+                            hash: Some(new.hash.clone()),
+                        });
+                    }
+                    return Ok(());
+                }
+                (Reference::Node(old), Reference::Node(new))
+                    if old.hash == new.hash =>
+                {
+                    if new.ptr.is_none() && old.ptr.is_some() {
+                        self.push_sealed_node();
+                    }
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+
+        match new.rf {
+            // New subtrie has been sealed so there’s no way to tell what has
+            // changed in it.
+            Reference::Node(node)
+                if node.ptr.is_none() && new.pending.is_empty() =>
+            {
+                self.push_sealed_node();
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        // If either reference is a value at current path, its keys in the
+        // other trie all have the path as a proper prefix.
+        let is_value = |side: &Side| {
+            side.pending.is_empty() && matches!(side.rf, Reference::Value(_))
+        };
+        if is_value(&old) || is_value(&new) {
+            self.deleted(old)?;
+            return self.added(new);
+        }
+
+        let old = split::<_, H>(self.old, old)?;
+        let new = split::<_, H>(self.new, new)?;
+        let len = self.path.len();
+        for (bit, (old, new)) in old.into_iter().zip(new).enumerate() {
+            self.path.truncate(len);
+            self.path.push_back(bit != 0).map_err(|_| Error::KeyTooLong)?;
+            self.diff(old, new)?;
+        }
+        Ok(())
+    }
+
+    /// Reports all values under given reference in the old trie as deleted.
+    fn deleted(&mut self, old: Side<'a>) -> Result<()> {
+        for entry in entries(self.old, &self.path, old)? {
+            let entry = entry?;
+            let hash = match entry.hash {
+                Some(hash) if !entry.is_sealed => hash,
+                _ => return Err(Error::Sealed),
+            };
+            self.changes.push(Change::Deleted { key: entry.sub_key, hash });
+        }
+        Ok(())
+    }
+
+    /// Reports all values under given reference in the new trie as added.
+    fn added(&mut self, new: Side<'a>) -> Result<()> {
+        for entry in entries(self.new, &self.path, new)? {
+            let entry = entry?;
+            let key = entry.sub_key;
+            let hash = match entry.hash {
+                Some(hash) => hash,
+                None => {
+                    self.changes.push(Change::Sealed { key, hash: None });
+                    continue;
+                }
+            };
+            let change = Change::Added { key: key.clone(), hash: hash.clone() };
+            self.changes.push(change);
+            if entry.is_sealed {
+                self.changes.push(Change::Sealed { key, hash: Some(hash) });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports sealed subtrie at current path.
+    fn push_sealed_node(&mut self) {
+        let key = self.path.clone();
+        self.changes.push(Change::Sealed { key, hash: None });
+    }
+}
+
+/// Returns iterator over all entries under given reference.
+fn entries<'a, A: memory::Allocator<Value = Value>>(
+    alloc: &'a A,
+    path: &bits::Owned,
+    side: Side<'a>,
+) -> Result<SubtrieIter<'a, A>> {
+    let mut prefix = path.clone();
+    if !side.pending.is_empty() {
+        prefix.extend(side.pending).map_err(|_| Error::KeyTooLong)?;
+    }
+    Ok(SubtrieIter::at(alloc, prefix, side.rf.into()))
+}
+
+/// Splits reference into its children.
+///
+/// If the reference has a pending key, returns the reference with the first
+/// bit of the key removed as the child at that bit.  Otherwise, the reference
+/// must point at an unsealed node.  If it’s a Branch, returns its children;
+/// if it’s an Extension, splits its child with the Extension key pending.
+fn split<'a, A: memory::Allocator<Value = Value>, H: Hasher>(
+    alloc: &'a A,
+    mut side: Side<'a>,
+) -> Result<[Option<Side<'a>>; 2]> {
+    loop {
+        if let Some(bit) = side.pending.pop_front() {
+            let mut children = [None, None];
+            children[usize::from(bit)] = Some(side);
+            return Ok(children);
+        }
+        let nref = match side.rf {
+            Reference::Node(nref) => nref,
+            Reference::Value(_) => unreachable!(),
+        };
+        let ptr = nref.ptr.ok_or(Error::Sealed)?;
+        let node = <&RawNode>::from(alloc.get(ptr)).decode()?;
+        debug_assert_eq!(*nref.hash, node.hash_with::<H>());
+        match node {
+            Node::Branch { children } => {
+                let pending = bits::Slice::default();
+                return Ok(children.map(|rf| Some(Side { pending, rf })));
+            }
+            Node::Extension { key, child } => {
+                side = Side { pending: key.into(), rf: child };
+            }
+        }
+    }
+}
+
+#[test]
+fn test_diff() {
+    let key = |key: &str| {
+        bits::Owned::from(bits::Slice::from_bytes(key.as_bytes()).unwrap())
+    };
+    let value = CryptoHash::test;
+
+    let make = || {
+        let mut trie = Trie::test(1000);
+        for (idx, key) in
+            ["foo", "bar", "baz", "qux", "quux"].iter().enumerate()
+        {
+            trie.set(key.as_bytes(), &value(idx)).unwrap();
+        }
+        trie
+    };
+    let old = make();
+    let mut new = make();
+    assert_eq!(Ok(Vec::new()), diff(&old, &new));
+
+    new.set(b"foo", &value(10)).unwrap();
+    new.del(b"bar").unwrap();
+    new.set(b"bat", &value(11)).unwrap();
+    new.seal(b"foo").unwrap();
+    new.set_and_seal(b"zzz", &value(12)).unwrap();
+    assert_eq!(
+        Ok(alloc::vec![
+            Change::Deleted { key: key("bar"), hash: value(1) },
+            Change::Added { key: key("bat"), hash: value(11) },
+            Change::Changed { key: key("foo"), old: value(0), new: value(10) },
+            Change::Sealed { key: key("foo"), hash: Some(value(10)) },
+            Change::Added { key: key("zzz"), hash: value(12) },
+            Change::Sealed { key: key("zzz"), hash: Some(value(12)) },
+        ]),
+        diff(&old, &new)
+    );
+
+    // Reverse diff fails since sealed values can’t be deleted.
+    assert_eq!(Err(Error::Sealed), diff(&new, &old));
+
+    // Sealing within otherwise unchanged subtrie isn’t detected.
+    let mut new = make();
+    new.seal(b"qux").unwrap();
+    assert_eq!(Ok(Vec::new()), diff(&old, &new));
+
+    // Diff against empty trie.
+    let empty = Trie::test(10);
+    let got = diff(&empty, &old).unwrap();
+    assert_eq!(5, got.len());
+    assert!(got.iter().all(|change| matches!(change, Change::Added { .. })));
+    let got = diff(&old, &empty).unwrap();
+    assert!(got.iter().all(|change| matches!(change, Change::Deleted { .. })));
+
+    // Diff against an older root in the same allocator.
+    let (alloc, ptr, hash) = old.into_parts();
+    let trie = Trie::from_parts(alloc, ptr, hash.clone());
+    assert_eq!(Ok(Vec::new()), diff_with_root(&trie, ptr, &hash));
+}
+
+#[test]
+fn stress_test_diff() {
+    use alloc::collections::BTreeMap;
+
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..lib::test_utils::get_iteration_count(1000) {
+        let mut maps = [BTreeMap::new(), BTreeMap::new()];
+        for _ in 0..200 {
+            let key = rng.gen::<[u8; 2]>().to_vec();
+            let value = CryptoHash::test(rng.gen_range(0..3));
+            match rng.gen_range(0..4) {
+                0 => maps[0].insert(key, value),
+                1 => maps[1].insert(key, value),
+                _ => {
+                    maps[0].insert(key.clone(), value.clone());
+                    maps[1].insert(key, value)
+                }
+            };
+        }
+        let [old, new] = maps.each_ref().map(|map| {
+            let mut trie = Trie::test(1000);
+            for (key, value) in map {
+                trie.set(key, value).unwrap();
+            }
+            trie
+        });
+
+        let mut want = Vec::new();
+        for (key, hash) in maps[0].iter() {
+            let bits = || bits::Slice::from_bytes(key).unwrap().into();
+            match maps[1].get(key) {
+                None => want
+                    .push(Change::Deleted { key: bits(), hash: hash.clone() }),
+                Some(new) if new != hash => want.push(Change::Changed {
+                    key: bits(),
+                    old: hash.clone(),
+                    new: new.clone(),
+                }),
+                Some(_) => (),
+            }
+        }
+        for (key, hash) in maps[1].iter() {
+            if !maps[0].contains_key(key) {
+                let key = bits::Slice::from_bytes(key).unwrap().into();
+                want.push(Change::Added { key, hash: hash.clone() });
+            }
+        }
+        let key = |change: &Change| match change {
+            Change::Added { key, .. } |
+            Change::Changed { key, .. } |
+            Change::Deleted { key, .. } |
+            Change::Sealed { key, .. } => {
+                Vec::<u8>::try_from(key.clone()).unwrap()
+            }
+        };
+        want.sort_by_key(key);
+        assert_eq!(want, diff(&old, &new).unwrap());
+    }
+}
@@ -0,0 +1,374 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use lib::hash::{CryptoHash, Hasher};
+use memory::Ptr;
+
+use super::{Trie, Value};
+use crate::nodes::{DecodeError, Node, RawNode, Reference};
+
+/// Result of a trie integrity check.
+///
+/// See [`Trie::verify_integrity`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Number of distinct nodes reachable from the root of the trie and roots
+    /// of versions retained in persistent mode.
+    pub nodes: usize,
+
+    /// Whether the allocator reported list of allocated blocks.
+    ///
+    /// If it didn’t, dangling pointers and leaked blocks cannot be detected.
+    pub checked_allocations: bool,
+
+    /// Problems found in the trie.  Empty if the trie is healthy.
+    pub issues: Vec<Issue>,
+}
+
+/// A problem found by [`Trie::verify_integrity`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Issue {
+    /// Node stored at given pointer couldn’t be decoded.
+    BadNode { ptr: Ptr, error: DecodeError },
+
+    /// Hash of the node stored at given pointer doesn’t match hash in the
+    /// reference pointing at it.
+    HashMismatch { ptr: Ptr, expected: CryptoHash, actual: CryptoHash },
+
+    /// Node stored at given pointer is its own descendant.
+    Cycle { ptr: Ptr },
+
+    /// Node stored at given pointer is referenced more than once within
+    /// a single version of the trie.
+    ///
+    /// Nodes in a trie form a tree thus this indicates a corruption which
+    /// would lead to double free when the node is removed.  Note that in
+    /// persistent mode nodes are legitimately shared between versions.
+    SharedNode { ptr: Ptr },
+
+    /// Pointer refers to a block which isn’t allocated.
+    DanglingPtr { ptr: Ptr },
+
+    /// Block is allocated but isn’t reachable from any root.
+    Leaked { ptr: Ptr },
+}
+
+impl IntegrityReport {
+    /// Returns whether no issues have been found.
+    pub fn is_healthy(&self) -> bool { self.issues.is_empty() }
+}
+
+/// A step of the depth-first traversal of the trie.
+enum Step<'a> {
+    /// Visit node at given pointer expecting it to have given hash.
+    Enter(Ptr, &'a CryptoHash),
+    /// All descendants of the node have been visited.
+    Exit(Ptr, CryptoHash),
+}
+
+/// Context for [`Trie::verify_integrity`] operation.
+pub(super) struct Context<'a, A, H> {
+    trie: &'a Trie<A, H>,
+
+    /// Set of allocated blocks or `None` if the allocator doesn’t report it.
+    allocated: Option<BTreeSet<Ptr>>,
+
+    /// Nodes which have been fully verified together with their hashes.
+    ///
+    /// In persistent mode, versions share nodes.  Subtries verified when
+    /// walking one version aren’t walked again.
+    verified: BTreeMap<Ptr, CryptoHash>,
+
+    /// All nodes reachable from any of the roots.
+    reachable: BTreeSet<Ptr>,
+
+    issues: Vec<Issue>,
+}
+
+impl<A: memory::Allocator<Value = Value>, H: Hasher> Trie<A, H> {
+    /// Walks all nodes reachable from the root of the trie and verifies the
+    /// trie is well-formed.
+    ///
+    /// Hash of each node is recomputed and compared against the reference
+    /// pointing at the node.  Nodes referenced more than once or being their
+    /// own descendants are reported.  If the allocator reports list of
+    /// allocated blocks (see [`memory::Allocator::allocated`]), pointers to
+    /// unallocated blocks and allocated blocks which aren’t reachable from
+    /// any root are reported as well.  Otherwise, reading a dangling pointer
+    /// may panic or yield garbage.
+    ///
+    /// In persistent mode, roots of all retained versions are checked as
+    /// well.
+    ///
+    /// Sealed subtries aren’t stored in the trie thus they cannot be checked.
+    pub fn verify_integrity(&self) -> IntegrityReport {
+        Context::new(self).finish()
+    }
+}
+
+impl<'a, A: memory::Allocator<Value = Value>, H: Hasher> Context<'a, A, H> {
+    /// Creates a new context and walks the trie and all its retained
+    /// versions.
+    pub(super) fn new(trie: &'a Trie<A, H>) -> Self {
+        let allocated = trie.alloc.allocated();
+        let mut ctx = Context {
+            trie,
+            allocated: allocated.map(|ptrs| ptrs.into_iter().collect()),
+            verified: BTreeMap::new(),
+            reachable: BTreeSet::new(),
+            issues: Vec::new(),
+        };
+        ctx.walk(trie.root_ptr, &trie.root_hash);
+        for (ptr, hash) in trie.versions.iter().flat_map(|v| v.root_refs()) {
+            ctx.walk(ptr, hash);
+        }
+        ctx
+    }
+
+    /// Reports blocks which haven’t been reached and returns the result.
+    pub(super) fn finish(mut self) -> IntegrityReport {
+        if let Some(allocated) = self.allocated.as_ref() {
+            let leaked = allocated.difference(&self.reachable);
+            self.issues.extend(leaked.map(|&ptr| Issue::Leaked { ptr }));
+        }
+        IntegrityReport {
+            nodes: self.reachable.len(),
+            checked_allocations: self.allocated.is_some(),
+            issues: self.issues,
+        }
+    }
+
+    /// Returns whether no issues have been found so far.
+    pub(super) fn is_healthy(&self) -> bool { self.issues.is_empty() }
+
+    /// Marks block at given pointer, which isn’t a trie node, as reachable.
+    ///
+    /// Returns whether the block can be read, i.e. whether it’s allocated and
+    /// hasn’t been reached before.  Otherwise, reports the problem.
+    pub(super) fn mark(&mut self, ptr: Ptr) -> bool {
+        if self.allocated.as_ref().is_some_and(|set| !set.contains(&ptr)) {
+            self.issues.push(Issue::DanglingPtr { ptr });
+            false
+        } else if !self.reachable.insert(ptr) {
+            self.issues.push(Issue::SharedNode { ptr });
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Walks a single version of the trie with given root.
+    pub(super) fn walk(
+        &mut self,
+        root_ptr: Option<Ptr>,
+        root_hash: &'a CryptoHash,
+    ) {
+        self.walk_with::<H>(root_ptr, root_hash)
+    }
+
+    /// Walks a trie with given root whose nodes are hashed with `G`.
+    ///
+    /// The root may be of a different trie stored in the same allocator.
+    pub(super) fn walk_with<G: Hasher>(
+        &mut self,
+        root_ptr: Option<Ptr>,
+        root_hash: &'a CryptoHash,
+    ) {
+        let root_ptr = match root_ptr {
+            Some(ptr) => ptr,
+            None => return,
+        };
+        // Nodes visited in this version and nodes on the path from the root
+        // to the node currently being visited.
+        let mut seen = BTreeSet::new();
+        let mut path = BTreeSet::new();
+        let mut stack = alloc::vec![Step::Enter(root_ptr, root_hash)];
+        while let Some(step) = stack.pop() {
+            let (ptr, expected) = match step {
+                Step::Enter(ptr, hash) => (ptr, hash),
+                Step::Exit(ptr, hash) => {
+                    path.remove(&ptr);
+                    self.verified.insert(ptr, hash);
+                    continue;
+                }
+            };
+
+            if self.allocated.as_ref().is_some_and(|set| !set.contains(&ptr)) {
+                self.issues.push(Issue::DanglingPtr { ptr });
+                continue;
+            } else if path.contains(&ptr) {
+                self.issues.push(Issue::Cycle { ptr });
+                continue;
+            } else if !seen.insert(ptr) {
+                self.issues.push(Issue::SharedNode { ptr });
+                continue;
+            }
+            self.reachable.insert(ptr);
+
+            if let Some(actual) = self.verified.get(&ptr) {
+                self.check_hash(ptr, expected, actual.clone());
+                continue;
+            }
+
+            let trie = self.trie;
+            let raw = <&'a RawNode>::from(trie.alloc.get(ptr));
+            let node = match raw.decode() {
+                Ok(node) => node,
+                Err(error) => {
+                    self.issues.push(Issue::BadNode { ptr, error });
+                    continue;
+                }
+            };
+            let actual = node.hash_with::<G>();
+            self.check_hash(ptr, expected, actual.clone());
+
+            path.insert(ptr);
+            stack.push(Step::Exit(ptr, actual));
+            let mut push = |child: Reference<'a>| {
+                if let Reference::Node(node) = child {
+                    if let Some(ptr) = node.ptr {
+                        stack.push(Step::Enter(ptr, node.hash));
+                    }
+                }
+            };
+            match node {
+                Node::Branch { children } => {
+                    push(children[1]);
+                    push(children[0]);
+                }
+                Node::Extension { child, .. } => push(child),
+            }
+        }
+    }
+
+    /// Reports hash mismatch if `actual` hash of node at `ptr` differs from
+    /// the `expected` one.
+    fn check_hash(
+        &mut self,
+        ptr: Ptr,
+        expected: &CryptoHash,
+        actual: CryptoHash,
+    ) {
+        if *expected != actual {
+            let expected = expected.clone();
+            self.issues.push(Issue::HashMismatch { ptr, expected, actual });
+        }
+    }
+}
+
+#[test]
+fn test_verify_integrity() {
+    use lib::hash::Sha256;
+    use memory::Allocator;
+
+    type TestTrie = Trie<memory::test_utils::TestAllocator<Value>>;
+
+    let mut trie = Trie::test(1000);
+    let want =
+        IntegrityReport { checked_allocations: true, ..Default::default() };
+    assert_eq!(want, trie.verify_integrity());
+
+    for num in 0..50u8 {
+        trie.set(&[num * 5, b'k'], &CryptoHash::test(num.into())).unwrap();
+    }
+    trie.seal(&[0, b'k']).unwrap();
+    let report = trie.verify_integrity();
+    assert!(report.is_healthy(), "{report:?}");
+    assert_eq!(trie.alloc.count(), report.nodes);
+
+    // Old versions share nodes with the current one.
+    trie.set_version_horizon(Some(3));
+    trie.checkpoint();
+    for num in 0..10u8 {
+        trie.set(&[num * 5 + 5, b'k'], &CryptoHash::test(100)).unwrap();
+        trie.checkpoint();
+    }
+    let report = trie.verify_integrity();
+    assert!(report.is_healthy(), "{report:?}");
+    assert_eq!(trie.alloc.count(), report.nodes);
+    trie.set_version_horizon(None);
+
+    let root = trie.root_ptr.unwrap();
+    let raw = <&RawNode>::from(trie.alloc.get(root));
+    let (left, right) = match raw.decode().unwrap() {
+        Node::Branch { children: [Reference::Node(l), Reference::Node(r)] } => {
+            (l.ptr.unwrap(), r.ptr.unwrap())
+        }
+        _ => panic!(),
+    };
+    let hash_of = |ptr| {
+        <&RawNode>::from(trie.alloc.get(ptr))
+            .decode()
+            .unwrap()
+            .hash_with::<Sha256>()
+    };
+    let root_hash = trie.root_hash.clone();
+    let left_hash = hash_of(left);
+    let right_hash = hash_of(right);
+
+    let check = |trie: &mut TestTrie, node: RawNode, want: &[Issue]| {
+        let old = core::mem::replace(trie.alloc.get_mut(root), node.0);
+        let report = trie.verify_integrity();
+        *trie.alloc.get_mut(root) = old;
+        for issue in want {
+            assert!(report.issues.contains(issue), "{issue:?} {report:?}");
+        }
+        assert!(trie.verify_integrity().is_healthy());
+    };
+
+    // Modified child hash.
+    let bad_hash = CryptoHash::test(1000);
+    let node = RawNode::branch(
+        Reference::node(Some(left), &bad_hash),
+        Reference::node(Some(right), &right_hash),
+    );
+    check(&mut trie, node, &[
+        Issue::HashMismatch {
+            ptr: root,
+            expected: root_hash.clone(),
+            actual: node.decode().unwrap().hash_with::<Sha256>(),
+        },
+        Issue::HashMismatch {
+            ptr: left,
+            expected: bad_hash.clone(),
+            actual: left_hash.clone(),
+        },
+    ]);
+
+    // Undecodable node.
+    let error = RawNode([0xff; RawNode::SIZE]).decode().unwrap_err();
+    check(&mut trie, RawNode([0xff; RawNode::SIZE]), &[Issue::BadNode {
+        ptr: root,
+        error,
+    }]);
+
+    // Cycle and a node referenced twice which also leaks the other child.
+    let node = RawNode::branch(
+        Reference::node(Some(root), &root_hash),
+        Reference::node(Some(right), &right_hash),
+    );
+    check(&mut trie, node, &[Issue::Cycle { ptr: root }, Issue::Leaked {
+        ptr: left,
+    }]);
+    let node = RawNode::branch(
+        Reference::node(Some(right), &right_hash),
+        Reference::node(Some(right), &right_hash),
+    );
+    check(&mut trie, node, &[
+        Issue::SharedNode { ptr: right },
+        Issue::Leaked { ptr: left },
+    ]);
+
+    // Dangling pointer and a leaked block.
+    let ptr = trie.alloc.alloc([0; RawNode::SIZE]).unwrap();
+    let report = trie.verify_integrity();
+    assert_eq!(alloc::vec![Issue::Leaked { ptr }], report.issues);
+    trie.alloc.free(ptr);
+    let node = RawNode::branch(
+        Reference::node(Some(ptr), &left_hash),
+        Reference::node(Some(right), &right_hash),
+    );
+    check(&mut trie, node, &[Issue::DanglingPtr { ptr }, Issue::Leaked {
+        ptr: left,
+    }]);
+}
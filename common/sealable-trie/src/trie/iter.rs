@@ -31,61 +31,262 @@ pub struct Entry {
     pub hash: Option<CryptoHash>,
 }
 
+/// A lazy iterator over entries of a subtrie.
+///
+/// Nodes are read from the allocator on demand as the iterator advances.
+/// Memory used by the iterator is proportional to depth of the trie rather
+/// than number of entries in the subtrie.
+///
+/// Entries are returned in lexicographical order of their `sub_key`s.  To
+/// resume iteration, create a new iterator with `after` argument set to
+/// `sub_key` of the last returned entry (see [`super::Trie::iter_subtrie`]).
+pub struct SubtrieIter<'a, A> {
+    /// Allocator used to fetch the trie nodes.
+    alloc: &'a A,
+
+    /// Key prefix of the `current` reference or, if there’s none, of the node
+    /// visited last.
+    prefix: bits::Owned,
+
+    /// Reference to visit next.
+    current: Option<Ref<'a>>,
+
+    /// Right children of Branch nodes which are yet to be visited together
+    /// with length of the prefix of the Branch node.
+    stack: Vec<(u32, Ref<'a>)>,
+}
+
+/// A reference to a node or a value.
+#[derive(Clone, Copy)]
+pub(super) enum Ref<'a> {
+    /// Reference to a node; `None` if the node is sealed.
+    Node(Option<Ptr>),
+    /// Reference to a value; the flag specifies whether it’s sealed.
+    Value(bool, &'a CryptoHash),
+}
+
+impl<'a> From<Reference<'a>> for Ref<'a> {
+    fn from(rf: Reference<'a>) -> Self {
+        match rf {
+            Reference::Node(node) => Self::Node(node.ptr),
+            Reference::Value(value) => Self::Value(value.is_sealed, value.hash),
+        }
+    }
+}
+
 /// Returns all entries of a sub-trie of at given `key`.
 pub(super) fn get_entries<A: memory::Allocator<Value = super::Value>>(
     alloc: &A,
     root_ptr: Option<Ptr>,
     key: &[u8],
 ) -> Result<Vec<Entry>> {
-    let key = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
-    match get_subtrie_root(alloc, root_ptr, key) {
-        GetSubtrieRootResult::Root(prefix, node_ptr) => {
-            Context { alloc, prefix, entries: Vec::new() }
-                .get(node_ptr)
-                .map_err(Error::from)
+    SubtrieIter::new(alloc, root_ptr, key, None)?.collect()
+}
+
+impl<'a, A: memory::Allocator<Value = super::Value>> SubtrieIter<'a, A> {
+    /// Creates an iterator over subtrie at given `key` which starts after
+    /// given `after` sub key.
+    ///
+    /// `root_ptr` is the root of the trie.  If `after` is `None`, iterator
+    /// goes over all entries in the subtrie.  Otherwise, only entries whose
+    /// `sub_key` follows `after` are returned.
+    pub(super) fn new(
+        alloc: &'a A,
+        root_ptr: Option<Ptr>,
+        key: &[u8],
+        after: Option<bits::Slice<'_>>,
+    ) -> Result<Self> {
+        let key = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
+        let mut this = Self::empty(alloc);
+        let (prefix, root) = match get_subtrie_root(alloc, root_ptr, key)? {
+            Some(root) => root,
+            None => return Ok(this),
+        };
+        this.prefix = prefix;
+        let mut after = match after {
+            None => {
+                this.current = Some(root);
+                return Ok(this);
+            }
+            Some(after) => after,
+        };
+        // Subtrie root may be located below the requested key.  Compare its
+        // sub key with `after` the same way as if it was an Extension key.
+        match seek_extension(this.prefix.as_slice(), &mut after) {
+            Seek::Descend => this.seek(root, after)?,
+            Seek::All => this.current = Some(root),
+            Seek::Nothing => (),
         }
-        GetSubtrieRootResult::Empty => Ok(Vec::new()),
-        GetSubtrieRootResult::Single(entry) => Ok(alloc::vec![entry]),
-        GetSubtrieRootResult::Err(err) => Err(err.into()),
+        Ok(this)
     }
-}
 
-/// Result returned from [`get_subtrie_root`] method.
-enum GetSubtrieRootResult {
-    /// Found a subtrie root node.
+    /// Creates an iterator over all entries under given reference.
     ///
-    /// The first element is a subkey from the requested subtrie key to the
-    /// identified node.  For example, if trying to get subtrie ‘foo’ and the
-    /// method found a node at ‘foobar’ the first element of this enum variant
-    /// will be ‘bar’.
-    Root(bits::Owned, Option<Ptr>),
+    /// `prefix` is the key of the reference and is included in `sub_key`s of
+    /// the returned entries.
+    pub(super) fn at(alloc: &'a A, prefix: bits::Owned, rf: Ref<'a>) -> Self {
+        Self { alloc, prefix, current: Some(rf), stack: Vec::new() }
+    }
 
-    /// No subtrie at given key.
-    Empty,
+    /// Creates an iterator which returns no entries.
+    pub(super) fn empty(alloc: &'a A) -> Self {
+        Self {
+            alloc,
+            prefix: bits::Owned::default(),
+            current: None,
+            stack: Vec::new(),
+        }
+    }
 
-    /// There’s exactly one entry to return at given subtrie.
-    Single(Entry),
+    /// Sets up the iterator state such that it will return entries located
+    /// under `rf` whose sub key follows `after`.
+    ///
+    /// `rf` is a reference located at current prefix and `after` is the
+    /// remaining part of the sub key after the current prefix.
+    fn seek(
+        &mut self,
+        mut rf: Ref<'a>,
+        mut after: bits::Slice<'_>,
+    ) -> Result<(), DecodeError> {
+        loop {
+            let ptr = match rf {
+                // Node is at `after` and all its descendants follow it.
+                Ref::Node(Some(_)) if after.is_empty() => {
+                    self.current = Some(rf);
+                    return Ok(());
+                }
+                Ref::Node(Some(ptr)) => ptr,
+                // Value or sealed node at `after` or its prefix.  Either way,
+                // the entry doesn’t follow `after`.
+                _ => return Ok(()),
+            };
+            match <&RawNode>::from(self.alloc.get(ptr)).decode()? {
+                Node::Branch { children } => {
+                    let bit = after.pop_front().unwrap();
+                    if !bit {
+                        self.stack
+                            .push((self.prefix.len(), children[1].into()));
+                    }
+                    self.prefix.push_back(bit).unwrap();
+                    rf = children[usize::from(bit)].into();
+                }
+                Node::Extension { key, child } => {
+                    let seek = seek_extension(key.into(), &mut after);
+                    if let Seek::Nothing = seek {
+                        return Ok(());
+                    }
+                    self.prefix.extend(key.into_slice()).unwrap();
+                    if let Seek::All = seek {
+                        self.current = Some(child.into());
+                        return Ok(());
+                    }
+                    rf = child.into();
+                }
+            }
+        }
+    }
 
-    /// Internal error when decoding nodes.
-    Err(DecodeError),
+    /// Returns entry at current prefix.
+    fn entry(&self, is_sealed: bool, hash: Option<&CryptoHash>) -> Entry {
+        Entry { is_sealed, sub_key: self.prefix.clone(), hash: hash.cloned() }
+    }
+}
+
+impl<'a, A: memory::Allocator<Value = super::Value>> Iterator
+    for SubtrieIter<'a, A>
+{
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rf = match self.current.take() {
+                Some(rf) => rf,
+                None => {
+                    let (len, rf) = self.stack.pop()?;
+                    self.prefix.truncate(len);
+                    self.prefix.push_back(true).unwrap();
+                    rf
+                }
+            };
+            let ptr = match rf {
+                Ref::Value(is_sealed, hash) => {
+                    return Some(Ok(self.entry(is_sealed, Some(hash))))
+                }
+                Ref::Node(None) => return Some(Ok(self.entry(true, None))),
+                Ref::Node(Some(ptr)) => ptr,
+            };
+            match <&RawNode>::from(self.alloc.get(ptr)).decode() {
+                Ok(Node::Branch { children }) => {
+                    self.stack.push((self.prefix.len(), children[1].into()));
+                    self.prefix.push_back(false).unwrap();
+                    self.current = Some(children[0].into());
+                }
+                Ok(Node::Extension { key, child }) => {
+                    self.prefix.extend(key.into_slice()).unwrap();
+                    self.current = Some(child.into());
+                }
+                Err(err) => {
+                    self.stack.clear();
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+    }
+}
+
+/// Result of comparing an Extension key with remaining part of the `after`
+/// sub key when seeking.
+pub(super) enum Seek {
+    /// Extension key is a prefix of `after`; the traversal should continue
+    /// to the child with the key stripped from `after`.
+    Descend,
+    /// All entries under the Extension follow `after`.
+    All,
+    /// None of the entries under the Extension follow `after`.
+    Nothing,
+}
+
+/// Compares Extension key with `after` sub key.
+///
+/// If the key is a prefix of `after`, strips it from `after` and returns
+/// [`Seek::Descend`].  Otherwise `after` is left with unspecified value.
+pub(super) fn seek_extension(
+    mut key: bits::Slice<'_>,
+    after: &mut bits::Slice,
+) -> Seek {
+    while let Some(bit) = key.pop_front() {
+        match after.pop_front() {
+            // `after` is a prefix of the key so all entries follow it.
+            None => return Seek::All,
+            Some(other) if bit == other => (),
+            Some(other) if bit => {
+                debug_assert!(!other);
+                return Seek::All;
+            }
+            Some(_) => return Seek::Nothing,
+        }
+    }
+    Seek::Descend
 }
 
 /// Locates root of the subtrie at the given key.
 ///
 /// `node_ptr` is the root of the trie and `key` is the key of the subtrie
 /// that we’re looking for.
-fn get_subtrie_root<A: memory::Allocator<Value = super::Value>>(
-    alloc: &A,
+///
+/// Returns `None` if there’s no subtrie at given key.  Otherwise, returns
+/// reference to the root of the subtrie and sub key from the requested
+/// subtrie key to the reference.  For example, if trying to get subtrie ‘foo’
+/// and the method found a node at ‘foobar’ the sub key will be ‘bar’.
+fn get_subtrie_root<'a, A: memory::Allocator<Value = super::Value>>(
+    alloc: &'a A,
     mut node_ptr: Option<Ptr>,
     mut key: bits::Slice<'_>,
-) -> GetSubtrieRootResult {
+) -> Result<Option<(bits::Owned, Ref<'a>)>, DecodeError> {
     let mut prefix = bits::Owned::default();
     while !key.is_empty() && node_ptr.is_some() {
         let node = alloc.get(node_ptr.unwrap());
-        let node = match <&RawNode>::from(node).decode() {
-            Ok(node) => node,
-            Err(err) => return GetSubtrieRootResult::Err(err),
-        };
+        let node = <&RawNode>::from(node).decode()?;
 
         let child = match node {
             Node::Branch { children } => {
@@ -114,7 +315,7 @@ fn get_subtrie_root<A: memory::Allocator<Value = super::Value>>(
                     // be ‘123’ while ext_key is ‘156’.  In this case the
                     // key matches no nodes and we need to return an empty
                     // vector.
-                    return GetSubtrieRootResult::Empty;
+                    return Ok(None);
                 }
             }
         };
@@ -123,100 +324,13 @@ fn get_subtrie_root<A: memory::Allocator<Value = super::Value>>(
             Reference::Node(node) => {
                 node_ptr = node.ptr;
             }
-            Reference::Value(value) if key.is_empty() => {
-                return GetSubtrieRootResult::Single(Entry {
-                    is_sealed: value.is_sealed,
-                    sub_key: prefix,
-                    hash: Some(value.hash.clone()),
-                });
+            Reference::Value(_) if key.is_empty() => {
+                return Ok(Some((prefix, child.into())));
             }
             Reference::Value(_) => {
-                return GetSubtrieRootResult::Empty;
+                return Ok(None);
             }
         }
     }
-    GetSubtrieRootResult::Root(prefix, node_ptr)
-}
-
-/// Context for iterating the trie to get subtrie entries.
-struct Context<'a, A> {
-    /// Allocator used to fetch the trie nodes.
-    alloc: &'a A,
-
-    /// Current key prefix to return as `sub_key` of an [`Entry`].
-    prefix: bits::Owned,
-
-    ///
-    entries: Vec<Entry>,
-}
-
-impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
-    fn get(mut self, node_ptr: Option<Ptr>) -> Result<Vec<Entry>, DecodeError> {
-        self.handle_node(node_ptr, self.prefix.len())?;
-        Ok(self.entries)
-    }
-
-    fn handle_node(
-        &mut self,
-        node_ptr: Option<Ptr>,
-        len: u16,
-    ) -> Result<(), DecodeError> {
-        debug_assert!(len <= self.prefix.len());
-
-        let ptr = if let Some(ptr) = node_ptr {
-            ptr
-        } else {
-            self.entries.push(Entry {
-                is_sealed: true,
-                sub_key: self.prefix.clone(),
-                hash: None,
-            });
-            self.prefix.truncate(len);
-            return Ok(());
-        };
-
-        match <&RawNode>::from(self.alloc.get(ptr)).decode()? {
-            Node::Branch { children } => {
-                self.prefix.push_back(false).unwrap();
-                self.handle_ref(children[0], self.prefix.len())?;
-                self.prefix.set_last(true);
-                self.handle_ref(children[1], len)
-            }
-
-            Node::Extension { key, child } => {
-                self.prefix.extend(key.into_slice()).unwrap();
-                self.handle_ref(child, len)
-            }
-        }
-    }
-
-    fn handle_ref(
-        &mut self,
-        nref: Reference<'a>,
-        len: u16,
-    ) -> Result<(), DecodeError> {
-        debug_assert!(len <= self.prefix.len());
-        match nref {
-            Reference::Node(node) => self.handle_node(node.ptr, len),
-            Reference::Value(value) => {
-                self.handle_value(value.is_sealed, value.hash, len);
-                Ok(())
-            }
-        }
-    }
-
-    fn handle_value(
-        &mut self,
-        is_sealed: bool,
-        hash: &'a CryptoHash,
-        len: u16,
-    ) {
-        debug_assert!(len <= self.prefix.len());
-        self.entries.push(Entry {
-            is_sealed,
-            sub_key: self.prefix.clone(),
-            hash: Some(hash.clone()),
-        });
-        self.prefix.truncate(len);
-    }
+    Ok(Some((prefix, Ref::Node(node_ptr))))
 }
@@ -0,0 +1,495 @@
+//! Model-based tests.  They run random sequences of operations on a trie and
+//! on a reference model built on top of a [`BTreeMap`] and check that results
+//! agree.
+//!
+//! When a sequence fails, it’s shrunk to a minimal reproducer which is
+//! included in the panic message.  Number of sequences can be controlled by
+//! STRESS_TEST_ITERATIONS environment variable.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use lib::hash::CryptoHash;
+use lib::test_utils::get_iteration_count;
+use memory::test_utils::TestAllocator;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use super::{BatchOp, Error, Result};
+use crate::bits;
+use crate::nodes::MAX_EXTENSION_KEY_SIZE;
+
+type Trie = super::Trie<TestAllocator<super::Value>>;
+
+/// An operation performed on the trie and on the model.
+#[derive(Clone, Debug, PartialEq)]
+enum Op {
+    Set(Vec<u8>, usize),
+    Del(Vec<u8>),
+    Seal(Vec<u8>),
+    GetSubtrie(Vec<u8>),
+    Prove(Vec<u8>),
+    /// Sequence of `Set`, `Del` and `Seal` operations.
+    Batch(Vec<Op>),
+    /// Building the trie from entries of the model.
+    Build,
+}
+
+/// Reference model of the trie.
+///
+/// Maps keys to value hashes and whether the value has been sealed.  Sealed
+/// values stay in the map since they still occupy their keys.
+#[derive(Default)]
+struct Model(BTreeMap<Vec<u8>, (CryptoHash, bool)>);
+
+/// Returns an error with formatted message if condition doesn’t hold.
+macro_rules! ensure {
+    ($cond:expr, $($fmt:tt)*) => {
+        if !$cond {
+            return Err(format!($($fmt)*));
+        }
+    };
+}
+
+impl Model {
+    /// Returns whether `key` conflicts with another key in the model, i.e.
+    /// whether one of them is a proper prefix of the other.
+    fn has_conflict(&self, key: &[u8]) -> bool {
+        self.0.keys().any(|other| {
+            other.len() != key.len() &&
+                (other.starts_with(key) || key.starts_with(other))
+        })
+    }
+
+    /// Returns whether lookup of `key` may end in a sealed node.
+    ///
+    /// Trie seals a node once all of its descendants are sealed.  For a lookup
+    /// to reach such a node there must be a sealed key sharing a longer
+    /// prefix with `key` than any of the keys which aren’t sealed.  Whether
+    /// the node exists depends on shape of the trie so the condition is only
+    /// necessary.
+    fn may_be_sealed(&self, key: &[u8]) -> bool {
+        let common = |sealed: bool| {
+            self.0
+                .iter()
+                .filter(|(_, (_, is_sealed))| *is_sealed == sealed)
+                .map(|(other, _)| common_prefix_bits(key, other))
+                .max()
+        };
+        match (common(true), common(false)) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(sealed), Some(live)) => sealed > live,
+        }
+    }
+
+    /// Applies the operation on the trie and the model and verifies that
+    /// results agree.
+    ///
+    /// Changes which succeed are also applied to the `batched` trie using
+    /// [`super::Trie::apply_batch`].  Operations of a single [`Op::Batch`] are
+    /// applied to it in a single batch.
+    fn apply(
+        &mut self,
+        trie: &mut Trie,
+        batched: &mut Trie,
+        op: &Op,
+    ) -> Result<(), String> {
+        let ops = match op {
+            Op::Batch(ops) => ops.as_slice(),
+            Op::Build => return self.check_build(),
+            op => core::slice::from_ref(op),
+        };
+        let mut applied = Vec::new();
+        for op in ops {
+            if self.apply_one(trie, op)? {
+                applied.push(op);
+            }
+        }
+
+        let hashes = applied
+            .iter()
+            .map(|op| match op {
+                Op::Set(_, num) => CryptoHash::test(*num),
+                _ => CryptoHash::default(),
+            })
+            .collect::<Vec<_>>();
+        let batch =
+            applied.iter().zip(hashes.iter()).map(|(op, hash)| match op {
+                Op::Set(key, _) => (key.as_slice(), BatchOp::Set(hash)),
+                Op::Del(key) => (key.as_slice(), BatchOp::Del),
+                Op::Seal(key) => (key.as_slice(), BatchOp::Seal),
+                _ => unreachable!(),
+            });
+        batched.apply_batch(batch).map_err(|err| format!("batch failed: {err}"))
+    }
+
+    /// Applies a single operation on the trie and the model and verifies that
+    /// results agree.  Returns whether the operation changed the trie.
+    fn apply_one(&mut self, trie: &mut Trie, op: &Op) -> Result<bool, String> {
+        let old_root = trie.hash().clone();
+        let key = match op {
+            Op::Set(key, _) |
+            Op::Del(key) |
+            Op::Seal(key) |
+            Op::GetSubtrie(key) |
+            Op::Prove(key) => key.as_slice(),
+            Op::Batch(_) | Op::Build => unreachable!(),
+        };
+        let entry = self.0.get(key).cloned();
+        let sealed_err = |err| err == Error::Sealed && self.may_be_sealed(key);
+
+        let changed = match op {
+            Op::Set(_, num) => {
+                let hash = CryptoHash::test(*num);
+                let res = trie.set(key, &hash);
+                let ok = match (entry, &res) {
+                    (Some((_, true)), res) => *res == Err(Error::Sealed),
+                    (Some((_, false)), res) => res.is_ok(),
+                    (None, Err(Error::BadKeyPrefix)) => self.has_conflict(key),
+                    (None, Err(err)) => sealed_err(*err),
+                    (None, Ok(())) => !self.has_conflict(key),
+                };
+                ensure!(ok, "unexpected result: {res:?}");
+                if res.is_ok() {
+                    self.0.insert(key.to_vec(), (hash, false));
+                }
+                res.is_ok()
+            }
+            Op::Del(_) => {
+                let res = trie.del(key);
+                let ok = match (entry, &res) {
+                    (Some((_, true)), res) => *res == Err(Error::Sealed),
+                    (Some((_, false)), res) => *res == Ok(true),
+                    (None, Ok(found)) => !found,
+                    (None, Err(err)) => sealed_err(*err),
+                };
+                ensure!(ok, "unexpected result: {res:?}");
+                if res == Ok(true) {
+                    self.0.remove(key);
+                }
+                res == Ok(true)
+            }
+            Op::Seal(_) => {
+                let res = trie.seal(key);
+                let ok = match (entry, &res) {
+                    (Some((_, true)), res) => {
+                        res.is_ok() || *res == Err(Error::Sealed)
+                    }
+                    (Some((_, false)), res) => res.is_ok(),
+                    (None, Err(Error::NotFound)) => true,
+                    (None, Err(Error::BadKeyPrefix)) => self.has_conflict(key),
+                    (None, Err(err)) => sealed_err(*err),
+                    (None, Ok(())) => false,
+                };
+                ensure!(ok, "unexpected result: {res:?}");
+                if let Some((_, is_sealed)) = self.0.get_mut(key) {
+                    *is_sealed = true;
+                }
+                res.is_ok()
+            }
+            Op::GetSubtrie(_) => {
+                match trie.get_subtrie(key) {
+                    Ok(entries) => self.check_subtrie(key, entries)?,
+                    Err(err) => {
+                        ensure!(sealed_err(err), "unexpected error: {err}")
+                    }
+                }
+                false
+            }
+            Op::Prove(_) => {
+                self.check_proof(trie, key)?;
+                false
+            }
+            Op::Batch(_) | Op::Build => unreachable!(),
+        };
+
+        if !matches!(op, Op::Set(..) | Op::Del(..)) {
+            ensure!(old_root == *trie.hash(), "root hash changed");
+        }
+        Ok(changed)
+    }
+
+    /// Verifies that [`super::Trie::from_sorted_entries`] builds the same trie
+    /// as inserting keys of the model in ascending order.
+    fn check_build(&self) -> Result<(), String> {
+        let entries = self
+            .0
+            .iter()
+            .map(|(key, (hash, sealed))| (key.as_slice(), hash, *sealed));
+        let got = Trie::from_sorted_entries(TestAllocator::new(1000), entries)
+            .map_err(|err| format!("build failed: {err}"))?;
+        let want = self.fresh(false);
+        ensure!(
+            want.hash() == got.hash(),
+            "root hash mismatch; want: {}; got: {}",
+            want.hash(),
+            got.hash(),
+        );
+        Ok(())
+    }
+
+    /// Verifies that result of [`Trie::get_subtrie`] agrees with the model.
+    ///
+    /// Values which aren’t sealed must be returned exactly.  Sealed entries
+    /// may cover whole subtries and must be prefixes of sealed keys or, if
+    /// the lookup of `prefix` may end in a sealed node, cover the prefix.
+    fn check_subtrie(
+        &self,
+        prefix: &[u8],
+        entries: Vec<super::Entry>,
+    ) -> Result<(), String> {
+        let want = self
+            .0
+            .iter()
+            .filter(|(key, (_, is_sealed))| {
+                !is_sealed && key.starts_with(prefix)
+            })
+            .map(|(key, (hash, _))| {
+                (key[prefix.len()..].to_vec(), hash.clone())
+            })
+            .collect::<Vec<_>>();
+        let mut got = Vec::new();
+        for entry in entries {
+            let sub_key = entry.sub_key.as_slice();
+            if !entry.is_sealed {
+                let sub_key = <&[u8]>::try_from(sub_key)
+                    .map_err(|_| format!("misaligned key: {sub_key}"))?;
+                got.push((sub_key.to_vec(), entry.hash.unwrap()));
+                continue;
+            }
+            // Lookup of the prefix itself may end in a sealed node even if
+            // no key starts with the prefix.
+            let covered = sub_key.is_empty() && self.may_be_sealed(prefix);
+            let found = covered ||
+                self.0.iter().any(|(key, (_, is_sealed))| {
+                    *is_sealed &&
+                        key.strip_prefix(prefix)
+                            .and_then(bits::Slice::from_bytes)
+                            .is_some_and(|key| key.starts_with(sub_key))
+                });
+            ensure!(found, "unexpected sealed entry: {sub_key}");
+        }
+        got.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+        ensure!(want == got, "want: {want:?}; got: {got:?}");
+        Ok(())
+    }
+
+    /// Verifies that lookup and proof of given key agree with the model.
+    fn check_proof(&self, trie: &Trie, key: &[u8]) -> Result<(), String> {
+        let res = trie.prove(key);
+        let get = trie.get(key);
+        ensure!(
+            get == res
+                .as_ref()
+                .map(|(hash, _)| hash.clone())
+                .map_err(|err| *err),
+            "get returned {get:?} while prove returned {res:?}",
+        );
+        let (got, proof) = match (self.0.get(key), res) {
+            (Some((_, true)), res) => {
+                ensure!(res.is_err(), "got value of sealed key");
+                return Ok(());
+            }
+            (_, Err(err)) => {
+                ensure!(
+                    err == Error::Sealed && self.may_be_sealed(key),
+                    "unexpected error: {err}"
+                );
+                return Ok(());
+            }
+            (_, Ok(res)) => res,
+        };
+        let want = self.0.get(key).map(|(hash, _)| hash);
+        ensure!(want == got.as_ref(), "want: {want:?}; got: {got:?}");
+        ensure!(
+            proof.verify(trie.hash(), key, want),
+            "proof failed to verify: {proof:?}"
+        );
+        Ok(())
+    }
+
+    /// Builds a new trie by inserting all keys of the model in ascending (or
+    /// descending if `reverse` is true) order and then sealing sealed ones.
+    fn fresh(&self, reverse: bool) -> Trie {
+        let mut fresh = Trie::test(1000);
+        let mut entries = self.0.iter().collect::<Vec<_>>();
+        if reverse {
+            entries.reverse();
+        }
+        for (key, (hash, _)) in entries.iter() {
+            fresh.set(key, hash).unwrap();
+        }
+        for (key, _) in entries.iter().filter(|(_, (_, sealed))| *sealed) {
+            fresh.seal(key).unwrap();
+        }
+        fresh
+    }
+
+    /// Checks state of the trie after an operation.
+    ///
+    /// Verifies that the `batched` trie is the same, that the root hash
+    /// doesn’t depend on the order in which keys were inserted (by building
+    /// fresh tries from the model) and that every key in the model can be
+    /// proven.
+    ///
+    /// How runs of keys longer than a single Extension node are split into
+    /// nodes depends on the order of operations.  If the model has such keys,
+    /// the order isn’t checked.
+    fn check(&self, trie: &Trie, batched: &Trie) -> Result<(), String> {
+        ensure!(
+            batched.hash() == trie.hash(),
+            "batched root hash mismatch; want: {}; got: {}",
+            trie.hash(),
+            batched.hash(),
+        );
+        let long = self.0.keys().any(|key| key.len() > MAX_EXTENSION_KEY_SIZE);
+        for reverse in [false, true].into_iter().filter(|_| !long) {
+            let fresh = self.fresh(reverse);
+            ensure!(
+                fresh.hash() == trie.hash(),
+                "root hash mismatch; want: {}; got: {}",
+                fresh.hash(),
+                trie.hash(),
+            );
+        }
+        for key in self.0.keys() {
+            self.check_proof(trie, key)
+                .map_err(|msg| format!("proving {key:?}: {msg}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns length of the common prefix of two keys in bits.
+fn common_prefix_bits(lhs: &[u8], rhs: &[u8]) -> usize {
+    let bytes = lhs.iter().zip(rhs).take_while(|(a, b)| a == b).count();
+    let bits = match (lhs.get(bytes), rhs.get(bytes)) {
+        (Some(a), Some(b)) => (a ^ b).leading_zeros() as usize,
+        _ => 0,
+    };
+    bytes * 8 + bits
+}
+
+/// Runs sequence of operations returning description of the first failure.
+fn run(ops: &[Op]) -> Result<(), String> {
+    let mut trie = Trie::test(1000);
+    let mut batched = Trie::test(1000);
+    let mut model = Model::default();
+    for (step, op) in ops.iter().enumerate() {
+        model
+            .apply(&mut trie, &mut batched, op)
+            .and_then(|()| model.check(&trie, &batched))
+            .map_err(|msg| format!("step {step}: {op:?}: {msg}"))?;
+    }
+    Ok(())
+}
+
+/// Shrinks a failing sequence of operations.
+///
+/// Repeatedly removes chunks of operations for as long as `fails` keeps
+/// returning `true`.  The result is a sequence from which no single operation
+/// can be removed without making it pass.
+fn shrink(mut ops: Vec<Op>, fails: impl Fn(&[Op]) -> bool) -> Vec<Op> {
+    let mut chunk = ops.len().div_ceil(2);
+    while chunk > 0 {
+        let mut start = 0;
+        while start < ops.len() {
+            let mut candidate = ops.clone();
+            candidate.drain(start..(start + chunk).min(ops.len()));
+            if fails(&candidate) {
+                ops = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    ops
+}
+
+/// Generates a random sequence of operations.
+///
+/// Keys are made of a few distinct bytes so that operations frequently hit
+/// the same keys and keys which are prefixes of one another.  If `long` is
+/// false, keys are one to three bytes long.  Otherwise, they don’t fit in
+/// a single Extension node and are mostly zero so that they share long runs
+/// which span several Extension nodes.
+fn gen_ops(rng: &mut impl Rng, count: usize, long: bool) -> Vec<Op> {
+    fn gen_key(rng: &mut impl Rng, long: bool) -> Vec<u8> {
+        if !long {
+            let len = rng.gen_range(1..=3);
+            return (0..len)
+                .map(|_| *[0x00, 0x0F, 0x80, 0xFF].choose(rng).unwrap())
+                .collect();
+        }
+        let len = *[40, 81].choose(rng).unwrap();
+        let mut key = alloc::vec![0; len];
+        for _ in 0..rng.gen_range(0..=2) {
+            let idx = *[0, 12, 33, 34, 62, len - 1].choose(rng).unwrap();
+            key[idx.min(len - 1)] = *[0x01, 0x80, 0xFF].choose(rng).unwrap();
+        }
+        key
+    }
+
+    fn gen_change(rng: &mut impl Rng, long: bool) -> Op {
+        match rng.gen_range(0..7) {
+            0..=3 => Op::Set(gen_key(rng, long), rng.gen_range(0..4)),
+            4 | 5 => Op::Del(gen_key(rng, long)),
+            _ => Op::Seal(gen_key(rng, long)),
+        }
+    }
+
+    (0..count)
+        .map(|_| match rng.gen_range(0..12) {
+            0..=6 => gen_change(rng, long),
+            7 => {
+                let key = gen_key(rng, long);
+                let key = match long {
+                    false => &key[1..],
+                    true => &key[..rng.gen_range(0..key.len())],
+                };
+                Op::GetSubtrie(key.to_vec())
+            }
+            8 | 9 => Op::Prove(gen_key(rng, long)),
+            10 => Op::Batch(
+                (0..rng.gen_range(1..=4))
+                    .map(|_| gen_change(rng, long))
+                    .collect(),
+            ),
+            _ => Op::Build,
+        })
+        .collect()
+}
+
+#[test]
+fn stress_test_model() {
+    let mut rng = rand::thread_rng();
+    for iter in 0..get_iteration_count(10_000) {
+        let ops = gen_ops(&mut rng, 200, iter % 2 == 1);
+        if run(&ops).is_err() {
+            let ops = shrink(ops, |ops| run(ops).is_err());
+            let err = run(&ops).unwrap_err();
+            panic!("{err}\nMinimal reproducer: {ops:#?}");
+        }
+    }
+}
+
+#[test]
+fn test_shrink() {
+    let mut rng = rand::thread_rng();
+    let set = Op::Set(alloc::vec![1], 0);
+    let del = Op::Del(alloc::vec![1]);
+    // The ‘bug’ is triggered by deleting a key after setting it.
+    let fails = |ops: &[Op]| {
+        ops.iter()
+            .position(|op| *op == set)
+            .is_some_and(|pos| ops[pos..].contains(&del))
+    };
+    let mut ops = gen_ops(&mut rng, 100, false);
+    ops.insert(rng.gen_range(0..=ops.len()), set.clone());
+    ops.push(del.clone());
+    assert_eq!(alloc::vec![set.clone(), del.clone()], shrink(ops, fails));
+}
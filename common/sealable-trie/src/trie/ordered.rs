@@ -0,0 +1,410 @@
+use alloc::vec::Vec;
+
+use lib::hash::{CryptoHash, Hasher};
+
+use super::iter::{seek_extension, Seek};
+use super::{Error, Result, Trie, Value};
+use crate::bits;
+use crate::nodes::{DecodeError, Node, NodeRef, RawNode, Reference};
+
+/// Result of ordered key queries such as [`Trie::next_key`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum FoundKey {
+    /// Value at given key.
+    Value(Vec<u8>, CryptoHash),
+
+    /// Sealed value at given key.
+    SealedValue(Vec<u8>),
+
+    /// Sealed subtrie at given key prefix.
+    ///
+    /// Keys in a sealed subtrie are unknown thus the query cannot be answered.
+    /// The key being looked for may be in the subtrie (if it has any keys
+    /// satisfying the query) or past it.  Note that the prefix doesn’t need
+    /// to be byte-aligned and it may be shorter than the prefix the query was
+    /// restricted to.
+    Sealed(bits::Owned),
+}
+
+impl<A: memory::Allocator<Value = Value>, H: Hasher> Trie<A, H> {
+    /// Returns the smallest key starting with given prefix.
+    ///
+    /// Returns `None` if there are no keys with given prefix.  If the smallest
+    /// key is in a sealed subtrie, returns [`FoundKey::Sealed`] with prefix of
+    /// that subtrie.
+    pub fn first_key(&self, prefix: &[u8]) -> Result<Option<FoundKey>> {
+        self.extreme_key(prefix, false)
+    }
+
+    /// Returns the largest key starting with given prefix.
+    ///
+    /// Returns `None` if there are no keys with given prefix.  If the largest
+    /// key is in a sealed subtrie, returns [`FoundKey::Sealed`] with prefix of
+    /// that subtrie.
+    pub fn last_key(&self, prefix: &[u8]) -> Result<Option<FoundKey>> {
+        self.extreme_key(prefix, true)
+    }
+
+    /// Returns the smallest key starting with given prefix which is greater
+    /// than `after`.
+    ///
+    /// Returns `None` if there’s no such key.  If the key is in a sealed
+    /// subtrie, returns [`FoundKey::Sealed`] with prefix of that subtrie.  In
+    /// particular, this happens if `after` itself is in a sealed subtrie.
+    ///
+    /// Keys are compared lexicographically.  For example, with keys built by
+    /// `trie_ids::TrieKey` which encodes numbers in big-endian, this can be
+    /// used to find the next sequence number of a channel.
+    pub fn next_key(
+        &self,
+        prefix: &[u8],
+        after: &[u8],
+    ) -> Result<Option<FoundKey>> {
+        self.neighbour_key(prefix, after, true)
+    }
+
+    /// Returns the largest key starting with given prefix which is less than
+    /// `before`.
+    ///
+    /// Returns `None` if there’s no such key.  If the key is in a sealed
+    /// subtrie, returns [`FoundKey::Sealed`] with prefix of that subtrie.  In
+    /// particular, this happens if `before` itself is in a sealed subtrie.
+    pub fn prev_key(
+        &self,
+        prefix: &[u8],
+        before: &[u8],
+    ) -> Result<Option<FoundKey>> {
+        self.neighbour_key(prefix, before, false)
+    }
+
+    fn extreme_key(
+        &self,
+        prefix: &[u8],
+        last: bool,
+    ) -> Result<Option<FoundKey>> {
+        let mut rest =
+            bits::Slice::from_bytes(prefix).ok_or(Error::KeyTooLong)?;
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let mut path = bits::Owned::default();
+        let mut rf = Reference::node(self.root_ptr, &self.root_hash);
+        while !rest.is_empty() {
+            let ptr = match rf {
+                // Value at a proper prefix of `prefix`.  Since keys are
+                // prefix-free, there are no keys starting with `prefix`.
+                Reference::Value(_) => return Ok(None),
+                Reference::Node(NodeRef { ptr: None, .. }) => {
+                    return Ok(Some(FoundKey::Sealed(path)))
+                }
+                Reference::Node(NodeRef { ptr: Some(ptr), .. }) => ptr,
+            };
+            match <&RawNode>::from(self.alloc.get(ptr)).decode()? {
+                Node::Branch { children } => {
+                    let bit = rest.pop_front().unwrap();
+                    path.push_back(bit).map_err(|_| Error::KeyTooLong)?;
+                    rf = children[usize::from(bit)];
+                }
+                Node::Extension { key, child } => {
+                    let key = bits::Slice::from(key);
+                    let len = key.len().min(rest.len());
+                    let (head, _) = key.split_at(len).unwrap();
+                    let (prefix, tail) = rest.split_at(len).unwrap();
+                    if head != prefix {
+                        return Ok(None);
+                    }
+                    rest = tail;
+                    path.extend(key).map_err(|_| Error::KeyTooLong)?;
+                    rf = child;
+                }
+            }
+        }
+        self.extreme(path, rf, last).map(Some)
+    }
+
+    fn neighbour_key(
+        &self,
+        prefix: &[u8],
+        key: &[u8],
+        forward: bool,
+    ) -> Result<Option<FoundKey>> {
+        let prefix_bits =
+            bits::Slice::from_bytes(prefix).ok_or(Error::KeyTooLong)?;
+        let key_bits = bits::Slice::from_bytes(key).ok_or(Error::KeyTooLong)?;
+        if self.is_empty() {
+            return Ok(None);
+        } else if !key.starts_with(prefix) {
+            // Keys with given prefix form a contiguous range.  `key` is either
+            // before or after that range.
+            return if (key < prefix) == forward {
+                self.extreme_key(prefix, !forward)
+            } else {
+                Ok(None)
+            };
+        }
+        let found = self.neighbour(key_bits, forward)?;
+        Ok(found.filter(|found| found.has_prefix(prefix_bits)))
+    }
+
+    /// Returns key following (if `forward`) or preceding (otherwise) given
+    /// target key in the entire trie.
+    fn neighbour(
+        &self,
+        mut rest: bits::Slice,
+        forward: bool,
+    ) -> Result<Option<FoundKey>> {
+        let mut path = bits::Owned::default();
+        let mut rf = Reference::node(self.root_ptr, &self.root_hash);
+        // The closest subtrie on the side of the target we’re looking at.
+        let mut candidate = None;
+        loop {
+            let ptr = match rf {
+                // Value at a proper prefix of target precedes it.  Value at
+                // the target is neither before nor after it.
+                Reference::Value(value) => {
+                    if !forward && !rest.is_empty() {
+                        return value_key(path, value.is_sealed, value.hash)
+                            .map(Some);
+                    }
+                    break;
+                }
+                // All keys in a sealed subtrie at target follow it.
+                Reference::Node(NodeRef { ptr: None, .. })
+                    if !forward && rest.is_empty() =>
+                {
+                    break
+                }
+                Reference::Node(NodeRef { ptr: None, .. }) => {
+                    return Ok(Some(FoundKey::Sealed(path)))
+                }
+                Reference::Node(NodeRef { ptr: Some(ptr), .. }) => ptr,
+            };
+            match <&RawNode>::from(self.alloc.get(ptr)).decode()? {
+                Node::Branch { children } => {
+                    let bit = match rest.pop_front() {
+                        // All keys in the subtrie follow the target.
+                        None if forward => {
+                            return self.extreme(path, rf, false).map(Some)
+                        }
+                        None => break,
+                        Some(bit) => bit,
+                    };
+                    if bit != forward {
+                        let mut path = path.clone();
+                        path.push_back(!bit).map_err(|_| Error::KeyTooLong)?;
+                        candidate = Some((path, children[usize::from(!bit)]));
+                    }
+                    path.push_back(bit).map_err(|_| Error::KeyTooLong)?;
+                    rf = children[usize::from(bit)];
+                }
+                Node::Extension { key, child } => {
+                    let follows = match seek_extension(key.into(), &mut rest) {
+                        Seek::Descend => None,
+                        Seek::All => Some(true),
+                        Seek::Nothing => Some(false),
+                    };
+                    path.extend(key.into()).map_err(|_| Error::KeyTooLong)?;
+                    match follows {
+                        None => rf = child,
+                        Some(follows) if follows == forward => {
+                            return self
+                                .extreme(path, child, !forward)
+                                .map(Some)
+                        }
+                        Some(_) => break,
+                    }
+                }
+            }
+        }
+        match candidate {
+            Some((path, rf)) => self.extreme(path, rf, !forward).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the smallest (or the largest if `last` is true) key in
+    /// subtrie at given reference.
+    ///
+    /// `path` is the key of the reference.
+    fn extreme<'b>(
+        &'b self,
+        mut path: bits::Owned,
+        mut rf: Reference<'b>,
+        last: bool,
+    ) -> Result<FoundKey> {
+        loop {
+            let ptr = match rf {
+                Reference::Value(value) => {
+                    return value_key(path, value.is_sealed, value.hash)
+                }
+                Reference::Node(NodeRef { ptr: None, .. }) => {
+                    return Ok(FoundKey::Sealed(path))
+                }
+                Reference::Node(NodeRef { ptr: Some(ptr), .. }) => ptr,
+            };
+            match <&RawNode>::from(self.alloc.get(ptr)).decode()? {
+                Node::Branch { children } => {
+                    path.push_back(last).map_err(|_| Error::KeyTooLong)?;
+                    rf = children[usize::from(last)];
+                }
+                Node::Extension { key, child } => {
+                    path.extend(key.into()).map_err(|_| Error::KeyTooLong)?;
+                    rf = child;
+                }
+            }
+        }
+    }
+}
+
+impl FoundKey {
+    /// Returns whether the key starts with given prefix or, for sealed
+    /// subtries, whether the subtrie may hold keys with given prefix.
+    fn has_prefix(&self, prefix: bits::Slice) -> bool {
+        match self {
+            Self::Value(key, _) | Self::SealedValue(key) => {
+                let key = bits::Slice::from_bytes(key).unwrap();
+                key.starts_with(prefix)
+            }
+            Self::Sealed(path) => {
+                let path = path.as_slice();
+                path.starts_with(prefix) || prefix.starts_with(path)
+            }
+        }
+    }
+}
+
+/// Constructs result for a value at given path.
+fn value_key(
+    path: bits::Owned,
+    is_sealed: bool,
+    hash: &CryptoHash,
+) -> Result<FoundKey> {
+    // Values are always located at byte boundaries.  If it’s not the case,
+    // the reference to the value is invalid.
+    let key = Vec::try_from(path)
+        .map_err(|_| Error::BadRawNode(DecodeError::BadValueRef))?;
+    Ok(if is_sealed {
+        FoundKey::SealedValue(key)
+    } else {
+        FoundKey::Value(key, hash.clone())
+    })
+}
+
+#[test]
+fn test_ordered_keys() {
+    use alloc::collections::BTreeMap;
+
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..lib::test_utils::get_iteration_count(1000) {
+        let mut trie = Trie::test(10_000);
+        let mut model = BTreeMap::new();
+        for _ in 0..rng.gen_range(0..100) {
+            // Fixed-length keys are prefix-free.
+            let key = [rng.gen_range(0..4), rng.gen_range(0..8), rng.gen()];
+            let hash = CryptoHash::test(rng.gen());
+            trie.set(&key, &hash).unwrap();
+            model.insert(key.to_vec(), hash);
+        }
+
+        let found = |(key, hash): (&Vec<u8>, &CryptoHash)| {
+            FoundKey::Value(key.clone(), hash.clone())
+        };
+        for _ in 0..50 {
+            let key = [rng.gen_range(0..4), rng.gen_range(0..8), rng.gen()];
+            let prefix = &key[..rng.gen_range(0..3)];
+            let end = prefix_end(prefix);
+            let range = || {
+                model.range(prefix.to_vec()..).take_while(|(key, _)| {
+                    end.as_ref().is_none_or(|end| *key < end)
+                })
+            };
+
+            assert_eq!(
+                range().next().map(found),
+                trie.first_key(prefix).unwrap()
+            );
+            assert_eq!(
+                range().last().map(found),
+                trie.last_key(prefix).unwrap()
+            );
+
+            // Use keys both inside and outside of the prefix range.
+            let key = if rng.gen() {
+                key.to_vec()
+            } else {
+                alloc::vec![rng.gen_range(0..4), rng.gen_range(0..8)]
+            };
+            let want = range().find(|(k, _)| **k > key).map(found);
+            assert_eq!(want, trie.next_key(prefix, &key).unwrap(), "{key:?}");
+            let want = range().filter(|(k, _)| **k < key).last().map(found);
+            assert_eq!(want, trie.prev_key(prefix, &key).unwrap(), "{key:?}");
+        }
+    }
+}
+
+/// Returns the smallest key greater than all keys starting with given prefix
+/// or `None` if there’s no such key.
+#[cfg(test)]
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(byte) = end.pop() {
+        if byte != 0xff {
+            end.push(byte + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[test]
+fn test_ordered_keys_sealed() {
+    let mut trie = Trie::test(1000);
+    assert_eq!(None, trie.first_key(b"").unwrap());
+    assert_eq!(None, trie.next_key(b"", b"foo").unwrap());
+
+    let hash = CryptoHash::test(1);
+    let value = |key: &[u8]| Some(FoundKey::Value(key.to_vec(), hash.clone()));
+    let sealed = |bytes: &[u8], len: u32| {
+        let slice = bits::Slice::new(bytes, lib::u3::U3::_0, len).unwrap();
+        Some(FoundKey::Sealed(bits::Owned::from(slice)))
+    };
+    for key in [b"a0", b"a1", b"a2", b"b0", b"b1", b"c0"] {
+        trie.set(key, &hash).unwrap();
+    }
+    trie.set_and_seal(b"a3", &hash).unwrap();
+    trie.seal(b"b0").unwrap();
+    trie.seal(b"b1").unwrap();
+
+    // Sealed values are reported with their keys.
+    assert_eq!(
+        Some(FoundKey::SealedValue(b"a3".to_vec())),
+        trie.last_key(b"a").unwrap()
+    );
+    assert_eq!(
+        Some(FoundKey::SealedValue(b"a3".to_vec())),
+        trie.next_key(b"a", b"a2").unwrap()
+    );
+    assert_eq!(value(b"a2"), trie.prev_key(b"a", b"a3").unwrap());
+
+    // Subtrie holding ‘b0’ and ‘b1’ is sealed thus the keys are unknown.
+    let b = sealed(b"b0", 15);
+    assert_eq!(b, trie.first_key(b"b").unwrap());
+    assert_eq!(value(b"c0"), trie.last_key(b"").unwrap());
+    assert_eq!(b, trie.next_key(b"", b"a3").unwrap());
+    assert_eq!(b, trie.prev_key(b"", b"c0").unwrap());
+    assert_eq!(b, trie.next_key(b"b", b"b0").unwrap());
+    assert_eq!(b, trie.first_key(b"b1").unwrap());
+    assert_eq!(value(b"c0"), trie.next_key(b"", b"b2").unwrap());
+    let a3 = Some(FoundKey::SealedValue(b"a3".to_vec()));
+    assert_eq!(a3, trie.prev_key(b"", b"b").unwrap());
+
+    // Results outside of the prefix aren’t returned.
+    assert_eq!(None, trie.next_key(b"a", b"a3").unwrap());
+    assert_eq!(None, trie.prev_key(b"c", b"c0").unwrap());
+    assert_eq!(value(b"a0"), trie.next_key(b"a", b"").unwrap());
+    assert_eq!(value(b"c0"), trie.prev_key(b"c", b"d").unwrap());
+    assert_eq!(None, trie.first_key(b"d").unwrap());
+    assert_eq!(None, trie.first_key(b"a00").unwrap());
+}
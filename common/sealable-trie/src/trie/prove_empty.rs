@@ -0,0 +1,101 @@
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+
+use lib::hash::Hasher;
+
+use super::{Error, Result};
+use crate::bits;
+use crate::nodes::{Node, NodeRef, RawNode, Reference};
+use crate::proof::{self, KeySet, Part};
+
+/// Context for [`super::Trie::prove_empty`] operation.
+pub(super) struct Context<'a, A, H> {
+    /// Allocator used to retrieve nodes.
+    alloc: &'a A,
+
+    /// Set of keys whose emptiness is being proven.
+    set: KeySet<'a>,
+
+    /// Path from the root of the trie to the currently visited reference.
+    path: bits::Owned,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
+}
+
+impl<'a, A: memory::Allocator<Value = super::Value>, H: Hasher>
+    Context<'a, A, H>
+{
+    pub(super) fn new(alloc: &'a A, set: KeySet<'a>) -> Self {
+        Self { alloc, set, path: bits::Owned::default(), hasher: PhantomData }
+    }
+
+    /// Traverses parts of the trie starting at the given root node which
+    /// overlap with the set of keys and generates proof of their emptiness.
+    ///
+    /// Returns `None` if there is a value in the set.
+    pub(super) fn prove(
+        mut self,
+        root: NodeRef,
+    ) -> Result<Option<proof::EmptyProof>> {
+        let root = self.handle_ref(Reference::Node(root))?;
+        Ok(root.map(|root| proof::EmptyProof::new(Some(root))))
+    }
+
+    /// Handles reference located at the current path.
+    ///
+    /// References whose keys are outside of the set are included as opaque
+    /// references.  Otherwise, returns `None` if reference is to a value or
+    /// continues traversal if it is to a node.
+    fn handle_ref(&mut self, rf: Reference) -> Result<Option<Part>> {
+        match rf {
+            Reference::Value(_) if self.set.contains(self.path.as_slice()) => {
+                Ok(None)
+            }
+            Reference::Node(node)
+                if self.set.overlaps(self.path.as_slice()) =>
+            {
+                self.handle_node(node)
+            }
+            _ => Ok(Some(Part::Ref(rf.into()))),
+        }
+    }
+
+    /// Handles a node which overlaps with the set.
+    fn handle_node(&mut self, nref: NodeRef) -> Result<Option<Part>> {
+        let ptr = nref.ptr.ok_or(Error::Sealed)?;
+        let node = <&RawNode>::from(self.alloc.get(ptr)).decode()?;
+        debug_assert_eq!(*nref.hash, node.hash_with::<H>());
+
+        let len = self.path.len();
+        let part = match node {
+            Node::Branch { children } => {
+                let mut parts = [None, None];
+                for (bit, part) in parts.iter_mut().enumerate() {
+                    self.path.truncate(len);
+                    self.path
+                        .push_back(bit != 0)
+                        .map_err(|_| Error::KeyTooLong)?;
+                    *part = self.handle_ref(children[bit])?;
+                    if part.is_none() {
+                        break;
+                    }
+                }
+                match parts {
+                    [Some(left), Some(right)] => {
+                        Some(Part::Branch(Box::new([left, right])))
+                    }
+                    _ => None,
+                }
+            }
+            Node::Extension { key, child } => {
+                self.path.extend(key.into()).map_err(|_| Error::KeyTooLong)?;
+                self.handle_ref(child)?.map(|child| {
+                    Part::Extension(proof::encode_ext_key(key), Box::new(child))
+                })
+            }
+        };
+        self.path.truncate(len);
+        Ok(part)
+    }
+}
@@ -0,0 +1,110 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use lib::hash::{CryptoHash, Hasher};
+
+use super::{Error, Result};
+use crate::bits;
+use crate::nodes::{Node, NodeRef, RawNode, Reference};
+use crate::proof::{self, Part};
+
+/// Keys yet to be looked up together with their index in the original list of
+/// keys.
+type Keys<'a> = Vec<(usize, bits::Slice<'a>)>;
+
+/// Context for [`super::Trie::prove_many`] operation.
+pub(super) struct Context<'a, A, H> {
+    /// Allocator used to retrieve nodes.
+    alloc: &'a A,
+
+    /// Values found at each of the keys.
+    values: Vec<Option<CryptoHash>>,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
+}
+
+impl<'a, A: memory::Allocator<Value = super::Value>, H: Hasher>
+    Context<'a, A, H>
+{
+    pub(super) fn new(alloc: &'a A, count: usize) -> Self {
+        Self { alloc, values: alloc::vec![None; count], hasher: PhantomData }
+    }
+
+    /// Looks up all the keys starting from the given root node and generates
+    /// proof for them.
+    pub(super) fn prove(
+        mut self,
+        root: NodeRef,
+        keys: Keys,
+    ) -> Result<(Vec<Option<CryptoHash>>, proof::MultiProof)> {
+        let root = self.handle_ref(Reference::Node(root), keys)?;
+        Ok((self.values, proof::MultiProof::new(Some(root))))
+    }
+
+    /// Handles reference reached by given keys.
+    ///
+    /// If there are no keys reaching the reference, returns an opaque
+    /// reference.  Otherwise continues traversal.
+    fn handle_ref(&mut self, rf: Reference, keys: Keys) -> Result<Part> {
+        if keys.is_empty() {
+            return Ok(Part::Ref(rf.into()));
+        }
+        match rf {
+            Reference::Node(node) => self.handle_node(node, keys),
+            Reference::Value(value) if value.is_sealed => Err(Error::Sealed),
+            Reference::Value(value) => {
+                // Keys which terminate here are the keys of this value.  Any
+                // other keys are longer and don’t exist in the trie.
+                let mut found = false;
+                for (idx, key) in keys {
+                    if key.is_empty() {
+                        self.values[idx] = Some(value.hash.clone());
+                        found = true;
+                    }
+                }
+                Ok(if found { Part::Value } else { Part::Ref(rf.into()) })
+            }
+        }
+    }
+
+    /// Handles a node reached by given non-empty list of keys.
+    fn handle_node(&mut self, nref: NodeRef, keys: Keys) -> Result<Part> {
+        let ptr = nref.ptr.ok_or(Error::Sealed)?;
+        let node = <&RawNode>::from(self.alloc.get(ptr)).decode()?;
+        debug_assert_eq!(*nref.hash, node.hash_with::<H>());
+
+        // If all keys terminate at this node there’s no value at any of them.
+        // Reference to the node is sufficient to prove that.
+        if keys.iter().all(|(_, key)| key.is_empty()) {
+            return Ok(Part::Ref(Reference::<_, bool>::Node(nref).into()));
+        }
+
+        match node {
+            Node::Branch { children } => {
+                let mut sides = [Vec::new(), Vec::new()];
+                for (idx, mut key) in keys {
+                    if let Some(bit) = key.pop_front() {
+                        sides[usize::from(bit)].push((idx, key));
+                    }
+                }
+                let [left, right] = sides;
+                let left = self.handle_ref(children[0], left)?;
+                let right = self.handle_ref(children[1], right)?;
+                Ok(Part::Branch(Box::new([left, right])))
+            }
+            Node::Extension { key: ext_key, child } => {
+                let keys = keys
+                    .into_iter()
+                    .filter_map(|(idx, mut key)| {
+                        key.strip_prefix(ext_key.into()).then_some((idx, key))
+                    })
+                    .collect();
+                let child = self.handle_ref(child, keys)?;
+                let ext_key = proof::encode_ext_key(ext_key);
+                Ok(Part::Extension(ext_key, Box::new(child)))
+            }
+        }
+    }
+}
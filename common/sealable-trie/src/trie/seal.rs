@@ -1,34 +1,58 @@
+use core::marker::PhantomData;
+
+use lib::hash::Hasher;
+use memory::Ptr;
+
+use super::versions::NodeLog;
 use super::{Error, Result};
 use crate::bits;
 use crate::nodes::{Node, NodeRef, RawNode, Reference, ValueRef};
 
 /// Context for [`super::Trie::seal`] operation.
-pub(super) struct Context<'a, A> {
+pub(super) struct Context<'a, A: memory::Allocator<Value = super::Value>, H> {
     /// Part of the key yet to be traversed.
     ///
     /// It starts as the key user provided and as trie is traversed bits are
     /// removed from its front.
     key: bits::Slice<'a>,
 
-    /// Allocator used to retrieve and free nodes.
-    alloc: &'a mut A,
+    /// Write log used to modify and free nodes.
+    wlog: NodeLog<'a, A>,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
 }
 
-impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
-    pub(super) fn new(alloc: &'a mut A, key: bits::Slice<'a>) -> Self {
-        Self { key, alloc }
+impl<'a, A: memory::Allocator<Value = super::Value>, H: Hasher>
+    Context<'a, A, H>
+{
+    pub(super) fn new(wlog: NodeLog<'a, A>, key: bits::Slice<'a>) -> Self {
+        Self { key, wlog, hasher: PhantomData }
+    }
+
+    /// Traverses the trie starting from given root node to find node at
+    /// context’s key and seals it.
+    ///
+    /// Returns `None` if the root node hasn’t moved.  Otherwise, returns new
+    /// pointer to the root node which is `None` if the root has been sealed.
+    pub(super) fn seal(mut self, nref: NodeRef) -> Result<Option<Option<Ptr>>> {
+        let res = self.seal_node(nref)?;
+        self.wlog.commit();
+        Ok(res)
     }
 
     /// Traverses the trie starting from node `ptr` to find node at context’s
     /// key and seals it.
     ///
-    /// Returns `true` if node at `ptr` has been sealed.  This lets caller know
-    /// that `ptr` has been freed and it has to update references to it.
-    pub(super) fn seal(&mut self, nref: NodeRef) -> Result<bool> {
+    /// Returns `None` if node at `ptr` hasn’t moved.  Otherwise returns new
+    /// pointer to the node which is `None` if the node has been sealed.  This
+    /// lets caller know that `ptr` has been freed (or, in persistent mode,
+    /// copied) and it has to update references to it.
+    fn seal_node(&mut self, nref: NodeRef) -> Result<Option<Option<Ptr>>> {
         let ptr = nref.ptr.ok_or(Error::Sealed)?;
-        let node = RawNode(*self.alloc.get(ptr));
+        let node = RawNode(*self.wlog.allocator().get(ptr));
         let node = node.decode()?;
-        debug_assert_eq!(*nref.hash, node.hash());
+        debug_assert_eq!(*nref.hash, node.hash_with::<H>());
 
         let result = match node {
             Node::Branch { children } => self.seal_branch(children),
@@ -37,14 +61,14 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
 
         match result {
             SealResult::Replace(node) => {
-                self.alloc.set(ptr, *node);
-                Ok(false)
+                let new_ptr = self.wlog.set(ptr, *node)?;
+                Ok((new_ptr != ptr).then_some(Some(new_ptr)))
             }
             SealResult::Free => {
-                self.alloc.free(ptr);
-                Ok(true)
+                self.wlog.free(ptr);
+                Ok(Some(None))
             }
-            SealResult::Done => Ok(false),
+            SealResult::Done => Ok(None),
         }
     }
 
@@ -55,11 +79,16 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         let side = usize::from(self.key.pop_front().ok_or(Error::NotFound)?);
         match self.seal_child(children[side])? {
             None => Ok(SealResult::Done),
-            Some(_) if children[1 - side].is_sealed() => Ok(SealResult::Free),
             Some(child) => {
                 children[side] = child;
-                let node = RawNode::branch(children[0], children[1]);
-                Ok(SealResult::Replace(node))
+                Ok(if is_sealed_branch(&children) {
+                    SealResult::Free
+                } else {
+                    SealResult::Replace(RawNode::branch(
+                        children[0],
+                        children[1],
+                    ))
+                })
             }
         }
     }
@@ -83,11 +112,9 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         child: Reference<'b>,
     ) -> Result<Option<Reference<'b>>> {
         match child {
-            Reference::Node(node) => Ok(if self.seal(node)? {
-                Some(NodeRef::new(None, node.hash).into())
-            } else {
-                None
-            }),
+            Reference::Node(node) => Ok(self
+                .seal_node(node)?
+                .map(|ptr| NodeRef::new(ptr, node.hash).into())),
             Reference::Value(value) => {
                 if value.is_sealed {
                     Err(Error::Sealed)
@@ -101,6 +128,15 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
     }
 }
 
+/// Returns whether Branch node with given children is sealed.
+///
+/// A Branch node is sealed once both of its children are sealed.  Sealed
+/// Branch nodes aren’t stored in the trie.  Note that the same doesn’t apply to
+/// Extension nodes which are kept even if their child is sealed.
+pub(super) fn is_sealed_branch(children: &[Reference; 2]) -> bool {
+    children[0].is_sealed() && children[1].is_sealed()
+}
+
 enum SealResult {
     Free,
     Replace(RawNode),
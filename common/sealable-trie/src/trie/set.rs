@@ -1,12 +1,15 @@
-use lib::hash::CryptoHash;
+use core::marker::PhantomData;
+
+use lib::hash::{CryptoHash, Hasher};
 use memory::Ptr;
 
+use super::versions::NodeLog;
 use super::{Error, Result};
 use crate::bits::{self, ExtKey};
 use crate::nodes::{Node, NodeRef, RawNode, Reference};
 
 /// Context for [`super::Trie::set`] operation.
-pub(super) struct Context<'a, A: memory::Allocator<Value = super::Value>> {
+pub(super) struct Context<'a, A: memory::Allocator<Value = super::Value>, H> {
     /// Part of the key yet to be traversed.
     ///
     /// It starts as the key user provided and as trie is traversed bits are
@@ -16,26 +19,42 @@ pub(super) struct Context<'a, A: memory::Allocator<Value = super::Value>> {
     /// Hash to insert into the trie.
     value_hash: &'a CryptoHash,
 
-    /// Allocator used to allocate new nodes.
-    wlog: memory::WriteLog<'a, A>,
+    /// Whether to seal the value once it’s inserted.
+    ///
+    /// When set, rather than inserting the value and sealing it afterwards,
+    /// the value is inserted as sealed and Branch nodes whose both children
+    /// end up sealed are never stored.  The resulting trie is the same as if
+    /// the value was set and then sealed.
+    seal: bool,
+
+    /// Write log used to allocate and modify nodes.
+    wlog: NodeLog<'a, A>,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
 }
 
-impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
+impl<'a, A: memory::Allocator<Value = super::Value>, H: Hasher>
+    Context<'a, A, H>
+{
     pub(super) fn new(
-        alloc: &'a mut A,
+        wlog: NodeLog<'a, A>,
         key: bits::Slice<'a>,
         value_hash: &'a CryptoHash,
+        seal: bool,
     ) -> Self {
-        let wlog = memory::WriteLog::new(alloc);
-        Self { key, value_hash, wlog }
+        Self { key, value_hash, seal, wlog, hasher: PhantomData }
     }
 
     /// Inserts value hash into the trie.
+    ///
+    /// Returns pointer to the new root node and its hash.  The pointer is
+    /// `None` if the value is sealed and the entire trie ended up sealed.
     pub(super) fn set(
         mut self,
         root_ptr: Option<Ptr>,
         root_hash: &CryptoHash,
-    ) -> Result<(Ptr, CryptoHash)> {
+    ) -> Result<(Option<Ptr>, CryptoHash)> {
         let res = (|| {
             if let Some(ptr) = root_ptr {
                 // Trie is non-empty, handle normally.
@@ -61,11 +80,11 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
     }
 
     /// Inserts value into the trie starting at node pointed by given reference.
-    fn handle(&mut self, nref: NodeRef) -> Result<(Ptr, CryptoHash)> {
+    fn handle(&mut self, nref: NodeRef) -> Result<(Option<Ptr>, CryptoHash)> {
         let nref = (nref.ptr.ok_or(Error::Sealed)?, nref.hash);
         let node = RawNode(*self.wlog.allocator().get(nref.0));
         let node = node.decode()?;
-        debug_assert_eq!(*nref.1, node.hash());
+        debug_assert_eq!(*nref.1, node.hash_with::<H>());
         match node {
             Node::Branch { children } => self.handle_branch(nref, children),
             Node::Extension { key, child } => {
@@ -79,7 +98,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         &mut self,
         nref: (Ptr, &CryptoHash),
         children: [Reference<'_>; 2],
-    ) -> Result<(Ptr, CryptoHash)> {
+    ) -> Result<(Option<Ptr>, CryptoHash)> {
         // If we’ve reached the end of the key, it’s been a prefix of an
         // existing value which is disallowed.
         let bit = self.key.pop_front().ok_or(Error::BadKeyPrefix)?;
@@ -90,7 +109,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         let child = owned_ref.to_ref();
         let children =
             if bit { [children[0], child] } else { [child, children[1]] };
-        self.set_node(nref.0, RawNode::branch(children[0], children[1]))
+        self.set_branch(Some(nref.0), children)
     }
 
     /// Inserts value assuming current node is an Extension.
@@ -99,7 +118,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         nref: (Ptr, &CryptoHash),
         ext_key: bits::ExtKey<'_>,
         child: Reference<'_>,
-    ) -> Result<(Ptr, CryptoHash)> {
+    ) -> Result<(Option<Ptr>, CryptoHash)> {
         // If we’ve reached the end of the key, it’s been a prefix of an
         // existing value which is disallowed.
         if self.key.is_empty() {
@@ -122,7 +141,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
             debug_assert_eq!(Some(ext_key), prefix);
             let owned_ref = self.handle_reference(child)?;
             let node = RawNode::extension(ext_key, owned_ref.to_ref());
-            return self.set_node(nref.0, node);
+            return self.set_node(nref.0, node).map(|(p, h)| (Some(p), h));
         };
 
         // If we’ve reached the end of the key, it’s been a prefix of an
@@ -150,6 +169,8 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         //
         // However, keep in mind that each of prefix or suffix may be empty.  If
         // that’s the case, corresponding Extension node is not created.
+        // Furthermore, if we’re sealing the value and the other child of the
+        // Branch is sealed, the Branch is sealed as well and not stored.
         let our_ref = self.insert_value()?;
         let their_hash: CryptoHash;
         let their_ref = match bits::ExtKey::try_from(suffix) {
@@ -163,13 +184,13 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         };
         let mut children = [their_ref; 2];
         children[our] = our_ref.to_ref();
-        let node = RawNode::branch(children[0], children[1]);
-        let (ptr, hash) = self.set_node(nref.0, node)?;
+        let (ptr, hash) = self.set_branch(Some(nref.0), children)?;
 
         match prefix {
             Some(prefix) => {
-                let child = Reference::node(Some(ptr), &hash);
-                self.alloc_extension_node(prefix, child)
+                let child = Reference::node(ptr, &hash);
+                let (ptr, hash) = self.alloc_extension_node(prefix, child)?;
+                Ok((Some(ptr), hash))
             }
             None => Ok((ptr, hash)),
         }
@@ -199,7 +220,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
                 // It’s a value reference so we just need to update it.  We know
                 // key is empty so there's nothing complex we need to do.  Just
                 // return new value reference.
-                Ok(OwnedRef::Value(self.value_hash.clone()))
+                Ok(OwnedRef::Value(self.seal, self.value_hash.clone()))
             }
         }
     }
@@ -216,7 +237,7 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         let mut hash = self.value_hash.clone();
         for chunk in self.key.chunks().rev() {
             let child = match ptr {
-                None => Reference::value(false, &hash),
+                None => Reference::value(self.seal, &hash),
                 Some(_) => Reference::node(ptr, &hash),
             };
             let (p, h) = self.alloc_node(RawNode::extension(chunk, child))?;
@@ -227,11 +248,11 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         Ok(if let Some(ptr) = ptr {
             // We’ve updated some nodes.  Insert node reference to the first
             // one.
-            OwnedRef::Node(ptr, hash)
+            OwnedRef::Node(Some(ptr), hash)
         } else {
             // ptr being None means that the above loop never run which means
             // self.key is empty.  We just need to return value reference.
-            OwnedRef::Value(hash)
+            OwnedRef::Value(self.seal, hash)
         })
     }
 
@@ -247,35 +268,65 @@ impl<'a, A: memory::Allocator<Value = super::Value>> Context<'a, A> {
         self.alloc_node(RawNode::extension(key, child))
     }
 
-    /// Sets value of a node cell at given address and returns its hash.
+    /// Stores a Branch node with given children and returns pointer to it and
+    /// its hash.
+    ///
+    /// If `ptr` is given, the node cell at that address is reused.  Otherwise
+    /// a new node is allocated.  However, if both children are sealed, the
+    /// node is sealed as well and thus not stored (and `ptr` is freed).  In
+    /// that case returned pointer is `None`.  This can happen only when
+    /// sealing the value.
+    fn set_branch(
+        &mut self,
+        ptr: Option<Ptr>,
+        children: [Reference<'_>; 2],
+    ) -> Result<(Option<Ptr>, CryptoHash)> {
+        let node = RawNode::branch(children[0], children[1]);
+        if !super::seal::is_sealed_branch(&children) {
+            let (ptr, hash) = match ptr {
+                Some(ptr) => self.set_node(ptr, node)?,
+                None => self.alloc_node(node)?,
+            };
+            return Ok((Some(ptr), hash));
+        }
+        if let Some(ptr) = ptr {
+            self.wlog.free(ptr);
+        }
+        Ok((None, node.decode()?.hash_with::<H>()))
+    }
+
+    /// Sets value of a node cell at given address and returns pointer to the
+    /// node and its hash.
+    ///
+    /// In persistent mode, the node may be stored at a new address.
     fn set_node(
         &mut self,
         ptr: Ptr,
         node: RawNode,
     ) -> Result<(Ptr, CryptoHash)> {
-        let hash = node.decode().unwrap().hash();
-        self.wlog.set(ptr, *node);
+        let hash = node.decode().unwrap().hash_with::<H>();
+        let ptr = self.wlog.set(ptr, *node)?;
         Ok((ptr, hash))
     }
 
     /// Allocates a new node and sets it to given value.
     fn alloc_node(&mut self, node: RawNode) -> Result<(Ptr, CryptoHash)> {
-        let hash = node.decode()?.hash();
+        let hash = node.decode()?.hash_with::<H>();
         let ptr = self.wlog.alloc(*node)?;
         Ok((ptr, hash))
     }
 }
 
 enum OwnedRef {
-    Node(Ptr, CryptoHash),
-    Value(CryptoHash),
+    Node(Option<Ptr>, CryptoHash),
+    Value(bool, CryptoHash),
 }
 
 impl OwnedRef {
     fn to_ref(&self) -> Reference {
         match self {
-            Self::Node(ptr, hash) => Reference::node(Some(*ptr), hash),
-            Self::Value(hash) => Reference::value(false, hash),
+            Self::Node(ptr, hash) => Reference::node(*ptr, hash),
+            Self::Value(is_sealed, hash) => Reference::value(*is_sealed, hash),
         }
     }
 }
@@ -0,0 +1,306 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use borsh::maybestd::io;
+use borsh::{BorshDeserialize, BorshSerialize};
+use lib::hash::{CryptoHash, Hasher};
+use memory::Ptr;
+
+use super::{Trie, Value, EMPTY_TRIE_ROOT};
+use crate::bits::ExtKey;
+use crate::nodes::{Node, RawNode, Reference};
+use crate::proof;
+
+/// Magic bytes at the start of a snapshot.
+const MAGIC: [u8; 4] = *b"STri";
+
+/// Current version of the snapshot format.
+///
+/// Version is written after the magic bytes.  Importing a snapshot with
+/// unknown version fails.
+const VERSION: u8 = 1;
+
+// Snapshot encoding (version 1):
+//
+//     snapshot := MAGIC VERSION <root-hash> <record>*
+//
+// If root hash is EMPTY_TRIE_ROOT, there are no records.  Otherwise there’s
+// a single record for the root node which recursively includes its children.
+// Records are written in pre-order:
+//  - 0x00 <record> <record>  — Branch node with left and right child
+//  - 0x01 <key-buf> <record> — Extension node with key and child
+//  - 0x02 <hash>             — Value
+//  - 0x03 <hash>             — Sealed value
+//  - 0x04 <hash>             — Sealed node
+//
+// Hashes of unsealed nodes aren’t included.  They are recalculated when
+// importing and the root hash is compared with one in the header.
+const TAG_BRANCH: u8 = 0x00;
+const TAG_EXTENSION: u8 = 0x01;
+const TAG_VALUE: u8 = 0x02;
+const TAG_SEALED_VALUE: u8 = 0x03;
+const TAG_SEALED_NODE: u8 = 0x04;
+
+impl<A: memory::Allocator<Value = Value>, H: Hasher> Trie<A, H> {
+    /// Writes snapshot of the trie into given writer.
+    ///
+    /// The snapshot includes all nodes of the trie as well as sealed values
+    /// and sealed nodes.  It can be read with [`Self::import`] to reconstruct
+    /// the trie with the same root hash possibly using a different allocator.
+    ///
+    /// The trie is traversed iteratively and nodes are written as they are
+    /// visited so memory usage is proportional to the depth of the trie.
+    pub fn export(&self, wr: &mut impl io::Write) -> io::Result<()> {
+        wr.write_all(&MAGIC)?;
+        (VERSION, self.root_hash.as_array()).serialize(wr)?;
+        if self.root_hash == EMPTY_TRIE_ROOT {
+            return Ok(());
+        }
+
+        let mut stack =
+            alloc::vec![Reference::node(self.root_ptr, &self.root_hash)];
+        while let Some(rf) = stack.pop() {
+            let ptr = match rf {
+                Reference::Value(value) => {
+                    let tag = if value.is_sealed {
+                        TAG_SEALED_VALUE
+                    } else {
+                        TAG_VALUE
+                    };
+                    (tag, value.hash.as_array()).serialize(wr)?;
+                    continue;
+                }
+                Reference::Node(node) => match node.ptr {
+                    Some(ptr) => ptr,
+                    None => {
+                        (TAG_SEALED_NODE, node.hash.as_array())
+                            .serialize(wr)?;
+                        continue;
+                    }
+                },
+            };
+            let node = <&RawNode>::from(self.alloc.get(ptr))
+                .decode()
+                .map_err(|err| invalid_data(format!("{err}")))?;
+            match node {
+                Node::Branch { children } => {
+                    TAG_BRANCH.serialize(wr)?;
+                    stack.push(children[1]);
+                    stack.push(children[0]);
+                }
+                Node::Extension { key, child } => {
+                    TAG_EXTENSION.serialize(wr)?;
+                    wr.write_all(&proof::encode_ext_key(key))?;
+                    stack.push(child);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads trie snapshot written by [`Self::export`] and constructs a trie
+    /// using given allocator.
+    ///
+    /// Verifies that hash of the reconstructed trie matches root hash stored
+    /// in the snapshot.  The snapshot doesn’t record hash function used to
+    /// hash nodes so it must be imported into a trie using the same function
+    /// as the exported one.  On failure, all nodes allocated while importing are
+    /// freed.  Reads only as much data as needed so the snapshot may be
+    /// followed by other data in the reader.
+    pub fn import(mut alloc: A, rd: &mut impl io::Read) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        rd.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("invalid trie snapshot magic".into()));
+        }
+        let (version, root_hash) = <(u8, [u8; 32])>::deserialize_reader(rd)?;
+        if version != VERSION {
+            let msg = format!("unsupported trie snapshot version: {version}");
+            return Err(invalid_data(msg));
+        }
+        let root_hash = CryptoHash(root_hash);
+        if root_hash == EMPTY_TRIE_ROOT {
+            return Ok(Self::with_hasher(alloc));
+        }
+
+        let mut wlog = memory::WriteLog::new(&mut alloc);
+        let root = import_nodes::<A, H>(&mut wlog, rd)?;
+        let root_ptr = match root {
+            OwnedRef::Node(ptr, hash) if hash == root_hash => ptr,
+            OwnedRef::Node(_, hash) => {
+                let msg = format!(
+                    "trie snapshot root hash mismatch: {hash} ≠ {root_hash}"
+                );
+                return Err(invalid_data(msg));
+            }
+            OwnedRef::Value(..) => {
+                return Err(invalid_data(
+                    "trie snapshot root is a value".into(),
+                ))
+            }
+        };
+        wlog.commit();
+        Ok(Self::from_parts_with_hasher(alloc, root_ptr, root_hash))
+    }
+}
+
+/// A reference to a node or value constructed during import.
+enum OwnedRef {
+    Node(Option<Ptr>, CryptoHash),
+    Value(bool, CryptoHash),
+}
+
+impl OwnedRef {
+    fn to_ref(&self) -> Reference {
+        match self {
+            Self::Node(ptr, hash) => Reference::node(*ptr, hash),
+            Self::Value(is_sealed, hash) => Reference::value(*is_sealed, hash),
+        }
+    }
+}
+
+/// A node whose children are yet to be read during import.
+enum Pending {
+    /// A Branch node; holds the left child once it’s been read.
+    Branch(Option<OwnedRef>),
+    /// An Extension node with given encoded key.
+    Extension(Box<[u8]>),
+}
+
+/// Reads records from the snapshot and allocates the nodes.
+///
+/// Nodes are allocated in post-order, i.e. once all of their children have
+/// been read.  Returns reference to the root node.
+fn import_nodes<A: memory::Allocator<Value = Value>, H: Hasher>(
+    wlog: &mut memory::WriteLog<A>,
+    rd: &mut impl io::Read,
+) -> io::Result<OwnedRef> {
+    let mut stack = Vec::new();
+    loop {
+        let mut rf = match u8::deserialize_reader(rd)? {
+            TAG_BRANCH => {
+                stack.push(Pending::Branch(None));
+                continue;
+            }
+            TAG_EXTENSION => {
+                let key = proof::deserialize_ext_key(rd, "Extension")?;
+                stack.push(Pending::Extension(key));
+                continue;
+            }
+            tag @ (TAG_VALUE | TAG_SEALED_VALUE) => {
+                let hash = CryptoHash(<_>::deserialize_reader(rd)?);
+                OwnedRef::Value(tag == TAG_SEALED_VALUE, hash)
+            }
+            TAG_SEALED_NODE => {
+                OwnedRef::Node(None, CryptoHash(<_>::deserialize_reader(rd)?))
+            }
+            tag => {
+                let msg = format!("invalid trie snapshot record: {tag}");
+                return Err(invalid_data(msg));
+            }
+        };
+
+        // Pop all the nodes whose children are now complete.
+        loop {
+            let node = match stack.pop() {
+                None => return Ok(rf),
+                Some(Pending::Branch(None)) => {
+                    stack.push(Pending::Branch(Some(rf)));
+                    break;
+                }
+                Some(Pending::Branch(Some(left))) => {
+                    RawNode::branch(left.to_ref(), rf.to_ref())
+                }
+                Some(Pending::Extension(key)) => {
+                    let key = ExtKey::decode(&key, 0).ok_or_else(|| {
+                        invalid_data("invalid Extension key".into())
+                    })?;
+                    RawNode::extension(key, rf.to_ref())
+                }
+            };
+            let hash = node
+                .decode()
+                .map_err(|err| invalid_data(format!("{err}")))?
+                .hash_with::<H>();
+            let ptr = wlog.alloc(node.0).map_err(|_| {
+                io::Error::new(io::ErrorKind::Other, "out of memory")
+            })?;
+            rf = OwnedRef::Node(Some(ptr), hash);
+        }
+    }
+}
+
+/// Returns an `io::Error` of kind `InvalidData` with specified message.
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[test]
+fn test_snapshot() {
+    use memory::test_utils::TestAllocator;
+
+    let mut trie = Trie::test(1000);
+    let mut keys = Vec::new();
+    for num in 0..100u8 {
+        let key = [b'k', num / 10, num % 10];
+        trie.set(&key, &CryptoHash::test(usize::from(num))).unwrap();
+        keys.push(key);
+    }
+    for num in 0..30 {
+        trie.seal(&keys[num * 2]).unwrap();
+    }
+    trie.set(
+        b"a long key which needs more than one Extension node",
+        &CryptoHash::test(1000),
+    )
+    .unwrap();
+
+    let mut bytes = Vec::new();
+    trie.export(&mut bytes).unwrap();
+    assert_eq!(&MAGIC, &bytes[..4]);
+
+    let got =
+        Trie::<_>::import(TestAllocator::new(1000), &mut &bytes[..]).unwrap();
+    assert_eq!(trie.hash(), got.hash());
+    assert_eq!(trie.alloc.count(), got.alloc.count());
+    for key in keys.iter() {
+        assert_eq!(trie.get(key), got.get(key));
+    }
+    let mut again = Vec::new();
+    got.export(&mut again).unwrap();
+    assert_eq!(bytes, again);
+
+    // Snapshot may be followed by other data.
+    let mut rd = &[&bytes[..], b"foo"].concat()[..];
+    Trie::<_>::import(TestAllocator::new(1000), &mut rd).unwrap();
+    assert_eq!(b"foo", rd);
+
+    // Empty trie.
+    let empty = Trie::test(10);
+    let mut empty_bytes = Vec::new();
+    empty.export(&mut empty_bytes).unwrap();
+    assert_eq!(4 + 1 + 32, empty_bytes.len());
+    let got = Trie::<_>::import(TestAllocator::new(10), &mut &empty_bytes[..]);
+    assert!(got.unwrap().is_empty());
+
+    // Invalid snapshots.
+    let import = |bytes: &[u8], capacity: usize| {
+        let alloc = TestAllocator::<Value>::new(capacity);
+        Trie::<_>::import(alloc, &mut &bytes[..])
+            .map(|_| ())
+            .map_err(|err| err.kind())
+    };
+    let mut bad = bytes.clone();
+    bad[4] = 2;
+    assert_eq!(Err(io::ErrorKind::InvalidData), import(&bad, 1000));
+    let mut bad = bytes.clone();
+    bad[5] ^= 1;
+    assert_eq!(Err(io::ErrorKind::InvalidData), import(&bad, 1000));
+    let mut bad = bytes.clone();
+    *bad.last_mut().unwrap() ^= 1;
+    assert_eq!(Err(io::ErrorKind::InvalidData), import(&bad, 1000));
+    import(&bytes[..bytes.len() - 1], 1000).unwrap_err();
+    assert_eq!(Err(io::ErrorKind::Other), import(&bytes, 10));
+}
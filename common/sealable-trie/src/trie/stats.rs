@@ -0,0 +1,312 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use lib::hash::Hasher;
+
+use super::{Error, Result, Trie, Value};
+use crate::bits;
+use crate::nodes::{Node, RawNode, Reference};
+
+/// Statistics of nodes stored in a trie or a part of it.
+///
+/// Depth of a reference is the number of nodes on the path from the root of
+/// the trie to the reference.  In particular, children of the root node have
+/// depth one.  Values and sealed nodes are considered leaves.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of Branch nodes stored in the trie.
+    pub branches: usize,
+
+    /// Number of Extension nodes stored in the trie.
+    pub extensions: usize,
+
+    /// Number of unsealed values.
+    pub values: usize,
+
+    /// Number of sealed values.
+    ///
+    /// Sealed values aren’t stored in the trie; only a sealed reference to
+    /// them in their parent node remains.
+    pub sealed_values: usize,
+
+    /// Number of references to sealed nodes.
+    ///
+    /// Each such reference replaces at least one Branch node which has been
+    /// freed when sealing.  Contents of sealed subtries are unknown so the
+    /// exact number of freed nodes cannot be determined.
+    pub sealed_nodes: usize,
+
+    /// Maximum depth of a leaf.
+    pub max_depth: usize,
+
+    /// Sum of depths of all leaves.
+    ///
+    /// Together with [`Self::leaves`] used to calculate average depth.
+    pub total_depth: usize,
+}
+
+/// Statistics of a trie broken down by key prefixes.
+///
+/// See [`Trie::stats_by_prefix`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrefixStats {
+    /// Statistics of nodes and values which don’t belong to a single prefix.
+    ///
+    /// Those are nodes closer to the root than the prefix length (e.g. the
+    /// root Branch node which splits keys with different prefixes) and
+    /// values whose keys are shorter than the prefix.
+    pub shared: Stats,
+
+    /// Statistics of each key prefix present in the trie.
+    ///
+    /// A node is accounted towards a prefix if all keys under it start with
+    /// that prefix.
+    pub prefixes: BTreeMap<Vec<u8>, Stats>,
+}
+
+impl Stats {
+    /// Returns number of nodes stored in the trie.
+    pub fn nodes(&self) -> usize { self.branches + self.extensions }
+
+    /// Returns number of bytes used by the nodes stored in the trie.
+    pub fn bytes(&self) -> usize { self.nodes() * RawNode::SIZE }
+
+    /// Returns number of leaves, i.e. values (sealed or not) and sealed
+    /// nodes.
+    pub fn leaves(&self) -> usize {
+        self.values + self.sealed_values + self.sealed_nodes
+    }
+
+    /// Returns average depth of a leaf or zero if there are no leaves.
+    pub fn average_depth(&self) -> f64 {
+        match self.leaves() {
+            0 => 0.0,
+            leaves => self.total_depth as f64 / leaves as f64,
+        }
+    }
+
+    /// Adds statistics from `other` into this object.
+    pub fn merge(&mut self, other: &Stats) {
+        self.branches += other.branches;
+        self.extensions += other.extensions;
+        self.values += other.values;
+        self.sealed_values += other.sealed_values;
+        self.sealed_nodes += other.sealed_nodes;
+        self.max_depth = self.max_depth.max(other.max_depth);
+        self.total_depth += other.total_depth;
+    }
+
+    /// Records a leaf at given depth.
+    fn add_leaf(&mut self, depth: usize) {
+        self.max_depth = self.max_depth.max(depth);
+        self.total_depth += depth;
+    }
+}
+
+impl PrefixStats {
+    /// Returns statistics of the entire trie.
+    pub fn total(&self) -> Stats {
+        let mut total = self.shared.clone();
+        for stats in self.prefixes.values() {
+            total.merge(stats);
+        }
+        total
+    }
+
+    /// Returns statistics object for given location.
+    fn get_mut(&mut self, loc: &Location) -> &mut Stats {
+        match loc {
+            Location::Shared(_) => &mut self.shared,
+            Location::Prefix(prefix) => {
+                self.prefixes.entry(prefix.clone()).or_default()
+            }
+        }
+    }
+}
+
+impl<A: memory::Allocator<Value = Value>, H: Hasher> Trie<A, H> {
+    /// Walks the entire trie and returns its statistics.
+    ///
+    /// Sealed subtries aren’t stored in the trie thus they are accounted for
+    /// as single sealed references.
+    pub fn stats(&self) -> Result<Stats> {
+        Ok(self.stats_by_prefix(0)?.total())
+    }
+
+    /// Walks the entire trie and returns its statistics broken down by key
+    /// prefixes of given length in bytes.
+    ///
+    /// For example, with `prefix_len` equal one, the statistics are grouped
+    /// by the first byte of the keys which can be used to see how much space
+    /// each namespace of keys (such as `trie_ids::Tag`) uses.
+    pub fn stats_by_prefix(&self, prefix_len: usize) -> Result<PrefixStats> {
+        let prefix_bits = prefix_len
+            .checked_mul(8)
+            .and_then(|bits| u32::try_from(bits).ok())
+            .ok_or(Error::KeyTooLong)?;
+        let mut res = PrefixStats::default();
+        if self.is_empty() {
+            return Ok(res);
+        }
+
+        let root = Location::new(bits::Owned::default(), prefix_bits);
+        let mut stack = alloc::vec![(
+            Reference::node(self.root_ptr, &self.root_hash),
+            0,
+            root
+        )];
+        while let Some((rf, depth, loc)) = stack.pop() {
+            let ptr = match rf {
+                Reference::Value(value) => {
+                    let stats = res.get_mut(&loc);
+                    if value.is_sealed {
+                        stats.sealed_values += 1;
+                    } else {
+                        stats.values += 1;
+                    }
+                    stats.add_leaf(depth);
+                    continue;
+                }
+                Reference::Node(node) => match node.ptr {
+                    Some(ptr) => ptr,
+                    None => {
+                        let stats = res.get_mut(&loc);
+                        stats.sealed_nodes += 1;
+                        stats.add_leaf(depth);
+                        continue;
+                    }
+                },
+            };
+            match <&RawNode>::from(self.alloc.get(ptr)).decode()? {
+                Node::Branch { children } => {
+                    res.get_mut(&loc).branches += 1;
+                    for bit in [true, false] {
+                        let child = children[usize::from(bit)];
+                        let loc = loc.child_bit(bit, prefix_bits)?;
+                        stack.push((child, depth + 1, loc));
+                    }
+                }
+                Node::Extension { key, child } => {
+                    // An Extension node leads to a single child so if it
+                    // crosses the prefix boundary it belongs to the prefix.
+                    let loc = loc.child(key.into(), prefix_bits)?;
+                    res.get_mut(&loc).extensions += 1;
+                    stack.push((child, depth + 1, loc));
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Location of a reference in the trie used to attribute it to a prefix.
+enum Location {
+    /// The reference is at given path shorter than the prefix.
+    Shared(bits::Owned),
+    /// All keys under the reference start with given prefix.
+    Prefix(Vec<u8>),
+}
+
+impl Location {
+    /// Returns location of a reference at given path.
+    fn new(path: bits::Owned, prefix_bits: u32) -> Self {
+        match path.as_slice().split_at(prefix_bits) {
+            Some((prefix, _)) => Self::Prefix(Vec::try_from(prefix).unwrap()),
+            None => Self::Shared(path),
+        }
+    }
+
+    /// Returns location of a child of a Branch node.
+    fn child_bit(&self, bit: bool, prefix_bits: u32) -> Result<Self> {
+        Ok(match self {
+            Self::Shared(path) => {
+                let mut path = path.clone();
+                path.push_back(bit).map_err(|_| Error::KeyTooLong)?;
+                Self::new(path, prefix_bits)
+            }
+            Self::Prefix(prefix) => Self::Prefix(prefix.clone()),
+        })
+    }
+
+    /// Returns location of a child of an Extension node with given key.
+    fn child(&self, suffix: bits::Slice, prefix_bits: u32) -> Result<Self> {
+        Ok(match self {
+            Self::Shared(path) => {
+                let mut path = path.clone();
+                path.extend(suffix).map_err(|_| Error::KeyTooLong)?;
+                Self::new(path, prefix_bits)
+            }
+            Self::Prefix(prefix) => Self::Prefix(prefix.clone()),
+        })
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut trie = Trie::test(1000);
+    assert_eq!(Ok(Stats::default()), trie.stats());
+    assert_eq!(Ok(PrefixStats::default()), trie.stats_by_prefix(1));
+
+    let mut keys = Vec::new();
+    for tag in 0..3u8 {
+        for num in 0..(10 << tag) {
+            keys.push([tag, 0, num]);
+        }
+    }
+    for (idx, key) in keys.iter().enumerate() {
+        trie.set(key, &lib::hash::CryptoHash::test(idx)).unwrap();
+    }
+    trie.set(
+        b"\x02a long key which needs more than one Extension node",
+        &lib::hash::CryptoHash::test(1000),
+    )
+    .unwrap();
+    for key in keys.iter().filter(|key| key[0] == 1).take(8) {
+        trie.seal(key).unwrap();
+    }
+    trie.seal(&keys[0]).unwrap();
+
+    let stats = trie.stats().unwrap();
+    assert_eq!(trie.alloc.count(), stats.nodes());
+    assert_eq!(trie.alloc.count() * RawNode::SIZE, stats.bytes());
+    // Eight values are hidden in a sealed node.
+    assert_eq!(keys.len() + 1 - 8, stats.values + stats.sealed_values);
+    assert_eq!(1, stats.sealed_values);
+    assert_eq!(1, stats.sealed_nodes);
+    assert_eq!(
+        (63, 10, 11),
+        (stats.branches, stats.extensions, stats.max_depth)
+    );
+    assert!(stats.average_depth() >= 1.0);
+    assert!(stats.average_depth() <= stats.max_depth as f64);
+
+    let by_tag = trie.stats_by_prefix(1).unwrap();
+    assert_eq!(stats, by_tag.total());
+    assert_eq!(
+        alloc::vec![&[0][..], &[1], &[2]],
+        by_tag.prefixes.keys().collect::<Vec<_>>()
+    );
+    // Root Branch splitting keys with different tags.
+    assert_eq!(0, by_tag.shared.leaves());
+    assert_ne!(0, by_tag.shared.nodes());
+    let tag = |tag: u8| &by_tag.prefixes[&[tag][..]];
+    assert_eq!((9, 1), (tag(0).values, tag(0).sealed_values));
+    assert_eq!((12, 1), (tag(1).values, tag(1).sealed_nodes));
+    assert_eq!(41, tag(2).values);
+    assert!(tag(2).nodes() > tag(0).nodes());
+
+    // Everything is in a single prefix.
+    let by_none = trie.stats_by_prefix(0).unwrap();
+    assert_eq!(Stats::default(), by_none.shared);
+    assert_eq!(Some(&stats), by_none.prefixes.get(&[][..]));
+
+    // Keys shorter than the prefix are shared.
+    let by_long = trie.stats_by_prefix(4).unwrap();
+    assert_eq!(stats, by_long.total());
+    let shared = &by_long.shared;
+    assert_eq!(
+        (61, 1, 1),
+        (shared.values, shared.sealed_values, shared.sealed_nodes)
+    );
+    assert_eq!(1, by_long.prefixes.len());
+}
@@ -1,3 +1,4 @@
+use alloc::string::ToString;
 use alloc::vec::Vec;
 use std::collections::HashMap;
 use std::println;
@@ -168,6 +169,42 @@ fn test_set_and_seal() { make_trie(false, true); }
 #[test]
 fn test_set_and_seal_small() { make_trie(true, true); }
 
+/// Tests that `set_and_seal` results in the same trie as `set` followed by
+/// `seal`.
+#[test]
+fn test_set_and_seal_matches_set_then_seal() {
+    let count = lib::test_utils::get_iteration_count(500);
+    let mut rng = rand::thread_rng();
+    let mut one = super::Trie::test(count * 8);
+    let mut two = super::Trie::test(count * 8);
+    let mut keys = Vec::with_capacity(count);
+
+    for num in 0..count {
+        // Use short keys with small alphabet so that we get plenty of common
+        // prefixes, overwrites and keys colliding with sealed subtries.
+        let len = rng.gen_range(1..=4);
+        let key = (0..len).map(|_| rng.gen_range(0..4)).collect::<Vec<u8>>();
+        let value = CryptoHash::test(num);
+        let (want, got) = if rng.gen() {
+            (two.set(&key, &value), one.set(&key, &value))
+        } else {
+            let want = two.set(&key, &value).and_then(|()| two.seal(&key));
+            (want, one.set_and_seal(&key, &value))
+        };
+        assert_eq!(want, got, "key: {key:?}");
+        assert_eq!(
+            (two.hash(), two.alloc.count()),
+            (one.hash(), one.alloc.count()),
+            "key: {key:?}"
+        );
+        keys.push(key);
+    }
+
+    for key in keys {
+        assert_eq!(two.get(&key), one.get(&key), "key: {key:?}");
+    }
+}
+
 fn do_test_del((mut trie, keys): (TestTrie, &[u8]), want_mid_count: usize) {
     let (left, right) = keys.split_at(keys.len() / 2);
     for b in left {
@@ -383,7 +420,492 @@ fn stress_test_iter() {
         let (ref buf, len) = keys.as_slice().choose(&mut rng).unwrap();
         let len = rng.gen_range(1..=usize::from(*len));
         trie.check_get_subtrie(&buf[..len], false);
+        trie.check_iter_subtrie(&buf[..len]);
+    }
+}
+
+#[test]
+fn test_iter_subtrie_sealed() {
+    let mut trie = TestTrie::new(100);
+    for key in ["a\x00", "a\x01", "bar", "baz", "foo", "qux"] {
+        trie.set(key.as_bytes(), false);
+    }
+    // Sealing both children of a Branch seals the Branch itself.
+    trie.trie.seal(b"a\x00").unwrap();
+    trie.trie.seal(b"a\x01").unwrap();
+    trie.trie.seal(b"baz").unwrap();
+
+    let collect = |key: &[u8], after: Option<&[u8]>| {
+        let after = after.map(crate::bits::Slice::from_bytes);
+        trie.trie
+            .iter_subtrie(key, after.map(Option::unwrap))
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.is_sealed, entry.sub_key.to_string(), entry.hash)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let all = collect(b"", None);
+    let got = all
+        .iter()
+        .map(|(is_sealed, _, hash)| (*is_sealed, hash.is_some()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        [
+            (true, false),
+            (false, true),
+            (true, true),
+            (false, true),
+            (false, true)
+        ],
+        got.as_slice()
+    );
+
+    assert_eq!(&all[..], collect(b"", Some(b"a")).as_slice());
+    assert_eq!(&all[1..], collect(b"", Some(b"a\x01")).as_slice());
+    assert_eq!(&all[2..], collect(b"", Some(b"bar")).as_slice());
+    assert_eq!(&all[3..], collect(b"", Some(b"baz")).as_slice());
+    assert_eq!(&all[3..], collect(b"", Some(b"c")).as_slice());
+    assert_eq!(&all[4..], collect(b"", Some(b"foo")).as_slice());
+    assert_eq!(&all[..0], collect(b"", Some(b"qux")).as_slice());
+    assert_eq!(&all[..], collect(b"", Some(b"")).as_slice());
+    assert_eq!(&all[..0], collect(b"x", None).as_slice());
+    let qux = collect(b"q", None);
+    assert_eq!(1, qux.len());
+    assert_eq!((false, &all[4].2), (qux[0].0, &qux[0].2));
+    assert_eq!(&qux[..0], collect(b"q", Some(b"ux")).as_slice());
+    assert_eq!(&qux[..], collect(b"q", Some(b"u")).as_slice());
+
+    // Resuming after sealed Branch node.
+    let sealed = crate::bits::Slice::new(b"a\x00", lib::u3::U3::_0, 15);
+    let got =
+        trie.trie.iter_subtrie(b"", Some(sealed.unwrap())).unwrap().count();
+    assert_eq!(all.len() - 1, got);
+
+    let empty = super::Trie::test(10);
+    assert_eq!(0, empty.iter_subtrie(b"", None).unwrap().count());
+}
+
+#[test]
+fn test_versions() {
+    let mut trie = super::Trie::test(1000);
+    trie.set_version_horizon(Some(2));
+    let keys: [&[u8]; 4] = [b"bar", b"baz", b"foo", b"qux"];
+    for (idx, key) in keys.iter().enumerate() {
+        trie.set(key, &CryptoHash::test(idx)).unwrap();
     }
+    trie.checkpoint();
+    let root1 = trie.hash().clone();
+
+    trie.set(b"foo", &CryptoHash::test(10)).unwrap();
+    trie.del(b"bar").unwrap();
+    trie.seal(b"baz").unwrap();
+    trie.checkpoint();
+    let root2 = trie.hash().clone();
+
+    trie.set(b"qux", &CryptoHash::test(11)).unwrap();
+    trie.set(b"quux", &CryptoHash::test(12)).unwrap();
+    let root3 = trie.hash().clone();
+    assert_eq!([&root1, &root2], trie.versions().collect::<Vec<_>>()[..]);
+
+    // Old versions are intact.
+    for (idx, key) in keys.iter().enumerate() {
+        let want = Some(CryptoHash::test(idx));
+        assert_eq!(Ok(want.clone()), trie.get_at(&root1, key));
+        let (value, proof) = trie.prove_at(&root1, key).unwrap();
+        assert_eq!(want, value);
+        assert!(proof.verify(&root1, key, value.as_ref()));
+    }
+    assert_eq!(Ok(None), trie.get_at(&root2, b"bar"));
+    assert_eq!(Err(super::Error::Sealed), trie.get_at(&root2, b"baz"));
+    assert_eq!(Ok(Some(CryptoHash::test(10))), trie.get_at(&root2, b"foo"));
+    assert_eq!(Ok(Some(CryptoHash::test(3))), trie.get_at(&root2, b"qux"));
+    assert_eq!(Ok(None), trie.get_at(&root2, b"quux"));
+    assert_eq!(Ok(Some(CryptoHash::test(12))), trie.get_at(&root3, b"quux"));
+
+    // Checkpoint drops the oldest version.
+    trie.checkpoint();
+    assert_eq!([&root2, &root3], trie.versions().collect::<Vec<_>>()[..]);
+    let bad_root = CryptoHash::test(100);
+    assert_eq!(Err(super::Error::UnknownRoot), trie.get_at(&root1, b"foo"));
+    assert_eq!(Err(super::Error::UnknownRoot), trie.get_at(&bad_root, b"foo"));
+    assert_eq!(Ok(None), trie.get_at(&root2, b"quux"));
+
+    // Disabling persistent mode frees all retired nodes.
+    trie.set_version_horizon(None);
+    assert_eq!(0, trie.versions().count());
+    let mut fresh = super::Trie::test(1000);
+    for key in [&b"foo"[..], b"qux", b"quux"] {
+        fresh.set(key, &trie.get(key).unwrap().unwrap()).unwrap();
+    }
+    fresh.set_and_seal(b"baz", &CryptoHash::test(1)).unwrap();
+    assert_eq!(fresh.hash(), trie.hash());
+    assert_eq!(fresh.alloc.count(), trie.alloc.count());
+}
+
+/// Tests running trie operations speculatively on top of a write log.
+#[test]
+fn test_write_log() {
+    #[track_caller]
+    fn check<A: memory::Allocator<Value = super::Value>>(
+        trie: &super::Trie<A>,
+        want: &[(&[u8], Option<usize>)],
+    ) {
+        for (key, want) in want {
+            let want = want.map(CryptoHash::test);
+            assert_eq!(Ok(want), trie.get(key), "{key:?}");
+        }
+        let report = trie.verify_integrity();
+        assert!(report.is_healthy(), "Trie is corrupted: {report:?}");
+    }
+
+    let mut alloc = TestAllocator::new(1000);
+    let mut trie = super::Trie::new(&mut alloc);
+    for (idx, key) in [&b"bar"[..], b"baz", b"foo"].into_iter().enumerate() {
+        trie.set(key, &CryptoHash::test(idx)).unwrap();
+    }
+    let (_, root_ptr, root_hash) = trie.into_parts();
+    let count = alloc.count();
+    let original: &[(&[u8], _)] =
+        &[(b"bar", Some(0)), (b"baz", Some(1)), (b"foo", Some(2))];
+
+    let mut wlog = memory::WriteLog::new(&mut alloc);
+    let savepoint = wlog.savepoint();
+    let mut trie =
+        super::Trie::from_parts(&mut wlog, root_ptr, root_hash.clone());
+    trie.set(b"qux", &CryptoHash::test(3)).unwrap();
+    trie.del(b"bar").unwrap();
+    trie.set(b"foo", &CryptoHash::test(4)).unwrap();
+    check(&trie, &[(b"bar", None), (b"foo", Some(4)), (b"qux", Some(3))]);
+    wlog.rollback_to(savepoint);
+
+    // Rolled back changes are no longer visible.
+    let mut trie =
+        super::Trie::from_parts(&mut wlog, root_ptr, root_hash.clone());
+    check(&trie, original);
+    trie.set(b"qux", &CryptoHash::test(3)).unwrap();
+    let (_, _, new_hash) = trie.into_parts();
+    core::mem::drop(wlog);
+
+    // Dropping the write log discards all changes.
+    assert_eq!(count, alloc.count());
+    let trie = super::Trie::from_parts(&mut alloc, root_ptr, root_hash.clone());
+    check(&trie, original);
+
+    let mut wlog = memory::WriteLog::new(&mut alloc);
+    let mut trie = super::Trie::from_parts(&mut wlog, root_ptr, root_hash);
+    trie.set(b"qux", &CryptoHash::test(3)).unwrap();
+    let (_, root_ptr, root_hash) = trie.into_parts();
+    assert_eq!(new_hash, root_hash);
+    wlog.commit();
+
+    let trie = super::Trie::from_parts(&mut alloc, root_ptr, root_hash);
+    check(&trie, &[(b"bar", Some(0)), (b"foo", Some(2)), (b"qux", Some(3))]);
+}
+
+#[test]
+fn stress_test_versions() {
+    let mut rng = rand::thread_rng();
+    for _ in 0..lib::test_utils::get_iteration_count(10_000) {
+        let mut trie = super::Trie::test(10_000);
+        trie.set_version_horizon(Some(3));
+        let mut map = HashMap::new();
+        let mut versions = Vec::new();
+        for _ in 0..10 {
+            for _ in 0..20 {
+                let key = [rng.gen_range(0..4u8), rng.gen()];
+                if rng.gen_ratio(1, 4) {
+                    trie.del(&key).unwrap();
+                    map.remove(&key);
+                } else {
+                    let value = CryptoHash::test(rng.gen());
+                    trie.set(&key, &value).unwrap();
+                    map.insert(key, value);
+                }
+            }
+            trie.checkpoint();
+            let report = trie.verify_integrity();
+            assert!(report.is_healthy(), "Trie is corrupted: {report:?}");
+            versions.push((trie.hash().clone(), map.clone()));
+            if versions.len() > 3 {
+                versions.remove(0);
+            }
+            for (root, map) in versions.iter() {
+                for (key, value) in map.iter() {
+                    assert_eq!(Ok(Some(value.clone())), trie.get_at(root, key));
+                }
+            }
+        }
+
+        trie.set_version_horizon(None);
+        for key in map.keys() {
+            trie.del(key).unwrap();
+        }
+        assert_eq!(0, trie.alloc.count());
+    }
+}
+
+/// Builds a trie using hash function `H` and checks that proofs it generates
+/// verify with the same hash function, including after serialisation.
+///
+/// Returns root hash of the trie and proof for `foo` key.
+fn check_hasher<H: lib::hash::Hasher>() -> (CryptoHash, crate::proof::Proof) {
+    use crate::proof::KeySet;
+
+    let mut trie = super::Trie::<_, H>::with_hasher(TestAllocator::new(100));
+    let long_key = "a long key which needs more than one Extension node";
+    let keys = ["foo", "bar", "baz", "qux", "quux", long_key];
+    for (idx, key) in keys.iter().enumerate() {
+        trie.set(key.as_bytes(), &CryptoHash::test(idx)).unwrap();
+    }
+    trie.seal(b"qux").unwrap();
+    let root = trie.hash().clone();
+
+    let lookup = ["foo", "bar", "ba", "bay", "quuxx", long_key];
+    for key in lookup {
+        let (value, proof) = trie.prove(key.as_bytes()).unwrap();
+        assert!(proof.verify_with::<H>(&root, key.as_bytes(), value.as_ref()));
+        #[cfg(feature = "borsh")]
+        {
+            let bytes = borsh::to_vec(&proof).unwrap();
+            let got: crate::proof::Proof =
+                borsh::BorshDeserialize::try_from_slice(&bytes).unwrap();
+            assert_eq!(proof, got);
+            assert!(got.verify_with::<H>(
+                &root,
+                key.as_bytes(),
+                value.as_ref()
+            ));
+        }
+    }
+
+    let keys = lookup.map(str::as_bytes);
+    let (values, proof) = trie.prove_many(keys).unwrap();
+    let entries = keys
+        .iter()
+        .zip(values.iter())
+        .map(|(key, value)| (*key, value.as_ref()))
+        .collect::<Vec<_>>();
+    assert!(proof.verify_with::<H>(&root, &entries));
+
+    let set = KeySet::Range(b"bara", b"baz");
+    let proof = trie.prove_empty(set).unwrap().unwrap();
+    assert!(proof.verify_with::<H>(&root, set));
+
+    let value = CryptoHash::test(100);
+    let witness = trie.set_with_proof(b"bay", &value).unwrap();
+    let got = witness.verify_set_with::<H>(&root, b"bay", &value);
+    assert_eq!(Some(trie.hash()), got.as_ref());
+    let old_root = trie.hash().clone();
+    let witness = trie.del_with_proof(b"bay").unwrap().1;
+    assert_eq!(
+        Some(root.clone()),
+        witness.verify_del_with::<H>(&old_root, b"bay")
+    );
+
+    let (_, proof) = trie.prove(b"foo").unwrap();
+    (root, proof)
+}
+
+#[test]
+fn test_hasher_sha256() {
+    let (root, proof) = check_hasher::<lib::hash::Sha256>();
+    assert!(proof.verify(&root, b"foo", Some(&CryptoHash::test(0))));
+
+    // SHA-256 is the default.
+    let mut trie = super::Trie::test(100);
+    let mut sha = super::Trie::<_, lib::hash::Sha256>::with_hasher(
+        TestAllocator::new(100),
+    );
+    trie.set(b"foo", &CryptoHash::test(0)).unwrap();
+    sha.set(b"foo", &CryptoHash::test(0)).unwrap();
+    assert_eq!(trie.hash(), sha.hash());
+}
+
+#[test]
+fn test_hasher_keccak256() {
+    let (root, proof) = check_hasher::<lib::hash::Keccak256>();
+    let (sha_root, _) = check_hasher::<lib::hash::Sha256>();
+    assert_ne!(sha_root, root);
+    let value = CryptoHash::test(0);
+    assert!(proof.verify_with::<lib::hash::Keccak256>(
+        &root,
+        b"foo",
+        Some(&value)
+    ));
+    assert!(!proof.verify(&root, b"foo", Some(&value)));
+}
+
+/// Tests keys longer than 8191 bytes, i.e. whose length in bits doesn’t fit
+/// in u16.
+#[test]
+fn test_long_keys() {
+    let long = |len: usize, tail: u8| {
+        let mut key = alloc::vec![0xAB; len];
+        key.push(tail);
+        key
+    };
+    let keys = [long(12_000, 1), long(12_000, 2), long(9000, 3), long(10, 4)];
+
+    let mut trie = super::Trie::test(2000);
+    for (idx, key) in keys.iter().enumerate() {
+        trie.set(key, &CryptoHash::test(idx)).unwrap();
+    }
+    for (idx, key) in keys.iter().enumerate() {
+        assert_eq!(Some(CryptoHash::test(idx)), trie.get(key).unwrap());
+    }
+    assert!(trie.verify_integrity().is_healthy());
+    let root = trie.hash().clone();
+
+    // Both membership and non-membership proofs, including ones where
+    // remaining length of the lookup key doesn’t fit in u16.
+    let lookup = [
+        keys[0].clone(),
+        keys[2].clone(),
+        long(12_000, 5),
+        [&keys[3][..], &[0xCD; 10_000][..]].concat(),
+        [&[0xAB; 100][..], &[0xCD; 10_000][..]].concat(),
+    ];
+    for key in lookup.iter() {
+        let (value, proof) = trie.prove(key).unwrap();
+        assert!(proof.verify(&root, key, value.as_ref()));
+        #[cfg(feature = "borsh")]
+        {
+            let bytes = borsh::to_vec(&proof).unwrap();
+            let got: crate::proof::Proof =
+                borsh::BorshDeserialize::try_from_slice(&bytes).unwrap();
+            assert!(got.verify(&root, key, value.as_ref()));
+        }
+    }
+
+    let entries = trie.get_subtrie(&[0xAB; 10_000]).unwrap();
+    assert_eq!(2, entries.len());
+
+    // Building the trie in one go gives the same result.
+    let mut sorted = keys.iter().collect::<Vec<_>>();
+    sorted.sort();
+    let hashes = (0..sorted.len()).map(CryptoHash::test).collect::<Vec<_>>();
+    let mut want = super::Trie::test(2000);
+    for (key, hash) in sorted.iter().zip(hashes.iter()) {
+        want.set(key, hash).unwrap();
+    }
+    let entries = sorted
+        .iter()
+        .zip(hashes.iter())
+        .map(|(key, hash)| (key.as_slice(), hash, false));
+    let got = super::Trie::<_>::from_sorted_entries(
+        TestAllocator::new(2000),
+        entries,
+    )
+    .unwrap();
+    assert_eq!(want.hash(), got.hash());
+
+    trie.seal(&keys[0]).unwrap();
+    assert_eq!(Err(super::Error::Sealed), trie.get(&keys[0]));
+    assert!(trie.del(&keys[1]).unwrap());
+    assert_eq!(None, trie.get(&keys[1]).unwrap());
+    assert!(trie.del(&keys[2]).unwrap());
+    assert!(trie.verify_integrity().is_healthy());
+}
+
+#[test]
+fn test_max_key_size() {
+    use super::{BatchOp, Error, MAX_KEY_SIZE};
+
+    let key = |len: usize, idx: usize, byte: u8| {
+        let mut key = alloc::vec![0; len];
+        key[idx] = byte;
+        key
+    };
+    let keys = [
+        key(MAX_KEY_SIZE, MAX_KEY_SIZE - 1, 1),
+        key(MAX_KEY_SIZE, MAX_KEY_SIZE - 1, 2),
+        key(MAX_KEY_SIZE, 100, 1),
+        key(MAX_KEY_SIZE, 100, 2),
+    ];
+    let hashes = (0..keys.len()).map(CryptoHash::test).collect::<Vec<_>>();
+
+    let mut trie = super::Trie::test(5000);
+    for (key, hash) in keys.iter().zip(hashes.iter()) {
+        trie.set(key, hash).unwrap();
+    }
+    assert!(trie.verify_integrity().is_healthy());
+    let root = trie.hash().clone();
+
+    // Proofs for the longest keys (and some even longer) can be serialised.
+    let too_long = key(MAX_KEY_SIZE + 1, MAX_KEY_SIZE, 1);
+    let lookup = [&keys[0], &keys[3], &too_long];
+    for key in lookup {
+        let (value, proof) = trie.prove(key).unwrap();
+        assert!(proof.verify(&root, key, value.as_ref()));
+        #[cfg(feature = "borsh")]
+        {
+            use borsh::BorshDeserialize;
+
+            let bytes = borsh::to_vec(&proof).unwrap();
+            let got = crate::proof::Proof::try_from_slice(&bytes).unwrap();
+            assert!(got.verify(&root, key, value.as_ref()));
+
+            let mut bytes = Vec::new();
+            proof.serialize_compact(&mut bytes).unwrap();
+            let got = crate::proof::Proof::try_from_slice(&bytes).unwrap();
+            assert!(got.verify(&root, key, value.as_ref()));
+        }
+    }
+    let (values, proof) =
+        trie.prove_many(lookup.iter().map(|key| key.as_slice())).unwrap();
+    let entries = lookup
+        .iter()
+        .zip(values.iter())
+        .map(|(key, value)| (key.as_slice(), value.as_ref()))
+        .collect::<Vec<_>>();
+    assert!(proof.verify(&root, &entries));
+    #[cfg(feature = "borsh")]
+    {
+        let bytes = borsh::to_vec(&proof).unwrap();
+        let got: crate::proof::MultiProof =
+            borsh::BorshDeserialize::try_from_slice(&bytes).unwrap();
+        assert!(got.verify(&root, &entries));
+    }
+
+    // Keys longer than the limit can’t be stored.
+    assert_eq!(Ok(None), trie.get(&too_long));
+    assert_eq!(Err(Error::KeyTooLong), trie.set(&too_long, &hashes[0]));
+    assert_eq!(
+        Err(Error::KeyTooLong),
+        trie.set_and_seal(&too_long, &hashes[0])
+    );
+    assert_eq!(
+        Err(Error::KeyTooLong),
+        trie.apply_batch([(too_long.as_slice(), BatchOp::Set(&hashes[0]))])
+    );
+    let entries = [(too_long.as_slice(), &hashes[0], false)];
+    assert_eq!(
+        Err(Error::KeyTooLong),
+        super::Trie::<_>::from_sorted_entries(TestAllocator::new(10), entries)
+            .map(|_| ())
+    );
+    assert_eq!(&root, trie.hash());
+
+    // Building the trie in one go gives the same result.
+    let mut sorted = keys.iter().zip(hashes.iter()).collect::<Vec<_>>();
+    sorted.sort_by_key(|(key, _)| *key);
+    let entries =
+        sorted.iter().map(|(key, hash)| (key.as_slice(), *hash, false));
+    let got = super::Trie::<_>::from_sorted_entries(
+        TestAllocator::new(5000),
+        entries,
+    )
+    .unwrap();
+    assert_eq!(&root, got.hash());
+
+    trie.seal(&keys[0]).unwrap();
+    assert!(trie.del(&keys[1]).unwrap());
+    assert!(trie.del(&keys[2]).unwrap());
+    assert_eq!(Some(hashes[3].clone()), trie.get(&keys[3]).unwrap());
+    assert!(trie.verify_integrity().is_healthy());
 }
 
 #[derive(Clone, Eq, Ord)]
@@ -617,6 +1139,42 @@ impl TestTrie {
         assert_eq!(want, self.get_subtrie(prefix, verbose));
     }
 
+    /// Checks that iterating over subtrie returns the same entries as
+    /// [`Self::get_subtrie`] and that iteration can be resumed after any of
+    /// the entries.
+    pub fn check_iter_subtrie(&self, prefix: &[u8]) {
+        let want = self.get_subtrie(prefix, false);
+        let entries = self
+            .trie
+            .iter_subtrie(prefix, None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let got = entries
+            .iter()
+            .map(|entry| {
+                let key: &[u8] = entry.sub_key.as_slice().try_into().unwrap();
+                Self::make_entry(key, entry.hash.as_ref().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(want, got);
+
+        for (idx, entry) in entries.iter().enumerate() {
+            let got = self
+                .trie
+                .iter_subtrie(prefix, Some(entry.sub_key.as_slice()))
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    let key: &[u8] =
+                        entry.sub_key.as_slice().try_into().unwrap();
+                    Self::make_entry(key, entry.hash.as_ref().unwrap())
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(&want[idx + 1..], got.as_slice());
+        }
+    }
+
     fn check_all_reads(&self) {
         for (key, value) in self.mapping.iter() {
             let got = self.trie.get(&key).unwrap_or_else(|err| {
@@ -624,6 +1182,8 @@ impl TestTrie {
             });
             assert_eq!(Some(value), got.as_ref(), "Invalid value at ‘{key:?}’");
         }
+        let report = self.trie.verify_integrity();
+        assert!(report.is_healthy(), "Trie is corrupted: {report:?}");
     }
 
     fn next_value(&mut self) -> CryptoHash {
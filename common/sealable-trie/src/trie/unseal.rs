@@ -0,0 +1,305 @@
+use core::marker::PhantomData;
+
+use lib::hash::{CryptoHash, Hasher};
+use memory::Ptr;
+
+use super::seal::is_sealed_branch;
+use super::versions::NodeLog;
+use super::{Error, Result};
+use crate::bits::{self, ExtKey};
+use crate::nodes::{Node, NodeRef, RawNode, Reference};
+use crate::proof::{self, Part};
+
+/// Context for [`super::Trie::unseal`] operation.
+pub(super) struct Context<'a, A: memory::Allocator<Value = super::Value>, H> {
+    /// Write log used to allocate and modify nodes.
+    wlog: NodeLog<'a, A>,
+
+    /// `(key, value_hash)` pairs providing hashes of [`Part::Value`] parts of
+    /// the proof.
+    entries: &'a [(&'a [u8], Option<&'a CryptoHash>)],
+
+    /// Path to the part of the trie currently being processed.
+    path: bits::Owned,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
+}
+
+/// A reference to a node or value which has been restored.
+enum Restored {
+    Node(Option<Ptr>, CryptoHash),
+    Value(bool, CryptoHash),
+}
+
+impl Restored {
+    fn to_ref(&self) -> Reference {
+        match self {
+            Self::Node(ptr, hash) => Reference::node(*ptr, hash),
+            Self::Value(is_sealed, hash) => Reference::value(*is_sealed, hash),
+        }
+    }
+}
+
+impl<'a, A: memory::Allocator<Value = super::Value>, H: Hasher>
+    Context<'a, A, H>
+{
+    pub(super) fn new(
+        wlog: NodeLog<'a, A>,
+        entries: &'a [(&'a [u8], Option<&'a CryptoHash>)],
+    ) -> Self {
+        Self {
+            wlog,
+            entries,
+            path: bits::Owned::default(),
+            hasher: PhantomData,
+        }
+    }
+
+    /// Traverses the trie starting from given root node together with the
+    /// proof and restores all sealed nodes and values the proof includes.
+    ///
+    /// Returns `None` if the root node hasn’t moved.  Otherwise, returns new
+    /// pointer to the root node.
+    pub(super) fn unseal(
+        mut self,
+        root: NodeRef,
+        part: &Part,
+    ) -> Result<Option<Option<Ptr>>> {
+        let res = match self.handle_ref(Reference::Node(root), part)? {
+            None => None,
+            Some(Restored::Node(ptr, _)) => Some(ptr),
+            Some(Restored::Value(..)) => return Err(Error::BadProof),
+        };
+        self.wlog.commit();
+        Ok(res)
+    }
+
+    /// Matches reference in the trie with part of the proof.
+    ///
+    /// Returns `None` if the reference hasn’t changed.  Otherwise returns the
+    /// new reference which caller must store in place of `rf`.
+    fn handle_ref(
+        &mut self,
+        rf: Reference,
+        part: &Part,
+    ) -> Result<Option<Restored>> {
+        match (rf, part) {
+            (_, Part::Ref(want)) => {
+                if proof::OwnedRef::from(&rf) == *want {
+                    Ok(None)
+                } else {
+                    Err(Error::BadProof)
+                }
+            }
+            (Reference::Value(value), Part::Value) => {
+                if self.value_hash()? != value.hash {
+                    Err(Error::BadProof)
+                } else if value.is_sealed {
+                    Ok(Some(Restored::Value(false, value.hash.clone())))
+                } else {
+                    Ok(None)
+                }
+            }
+            (Reference::Node(node), _) => match node.ptr {
+                Some(ptr) => self.handle_node(ptr, node.hash, part),
+                None => match self.build(part)? {
+                    Restored::Node(ptr, hash) if hash == *node.hash => {
+                        Ok(ptr.map(|ptr| Restored::Node(Some(ptr), hash)))
+                    }
+                    _ => Err(Error::BadProof),
+                },
+            },
+            (Reference::Value(_), _) => Err(Error::BadProof),
+        }
+    }
+
+    /// Matches an unsealed node with part of the proof.
+    ///
+    /// Structure of the node must match the part.  If any of the node’s
+    /// children has been restored, the node is updated.
+    fn handle_node(
+        &mut self,
+        ptr: Ptr,
+        hash: &CryptoHash,
+        part: &Part,
+    ) -> Result<Option<Restored>> {
+        let node = RawNode(*self.wlog.allocator().get(ptr));
+        let node = node.decode()?;
+        debug_assert_eq!(*hash, node.hash_with::<H>());
+
+        let node = match (node, part) {
+            (Node::Branch { children }, Part::Branch(parts)) => {
+                let left = self.handle_child(children[0], &parts[0], false)?;
+                let right = self.handle_child(children[1], &parts[1], true)?;
+                if left.is_none() && right.is_none() {
+                    return Ok(None);
+                }
+                RawNode::branch(
+                    left.as_ref().map_or(children[0], Restored::to_ref),
+                    right.as_ref().map_or(children[1], Restored::to_ref),
+                )
+            }
+            (Node::Extension { key, child }, Part::Extension(want, part)) => {
+                if *proof::encode_ext_key(key) != **want {
+                    return Err(Error::BadProof);
+                }
+                let len = self.path.len();
+                self.path
+                    .extend(key.into_slice())
+                    .map_err(|_| Error::KeyTooLong)?;
+                let child = self.handle_ref(child, part)?;
+                self.path.truncate(len);
+                match child {
+                    None => return Ok(None),
+                    Some(child) => RawNode::extension(key, child.to_ref()),
+                }
+            }
+            _ => return Err(Error::BadProof),
+        };
+
+        let new_ptr = self.wlog.set(ptr, node.0)?;
+        Ok((new_ptr != ptr)
+            .then(|| Restored::Node(Some(new_ptr), hash.clone())))
+    }
+
+    /// Matches a child of a Branch node with part of the proof.
+    fn handle_child(
+        &mut self,
+        child: Reference,
+        part: &Part,
+        bit: bool,
+    ) -> Result<Option<Restored>> {
+        let len = self.path.len();
+        self.path.push_back(bit).map_err(|_| Error::KeyTooLong)?;
+        let res = self.handle_ref(child, part);
+        self.path.truncate(len);
+        res
+    }
+
+    /// Allocates nodes described by part of the proof.
+    ///
+    /// Opaque references in the proof become sealed nodes and values.  Branch
+    /// nodes whose both children are sealed aren’t allocated, matching the
+    /// behaviour of [`super::Trie::seal`].
+    fn build(&mut self, part: &Part) -> Result<Restored> {
+        let node = match part {
+            Part::Ref(rf) => {
+                return Ok(match Reference::from(rf) {
+                    Reference::Node(node) => {
+                        Restored::Node(None, node.hash.clone())
+                    }
+                    Reference::Value(value) => {
+                        Restored::Value(true, value.hash.clone())
+                    }
+                })
+            }
+            Part::Value => {
+                return Ok(Restored::Value(false, self.value_hash()?.clone()))
+            }
+            Part::Branch(parts) => {
+                let left = self.build_child(&parts[0], false)?;
+                let right = self.build_child(&parts[1], true)?;
+                let children = [left.to_ref(), right.to_ref()];
+                if is_sealed_branch(&children) {
+                    let hash = Node::Branch { children }.hash_with::<H>();
+                    return Ok(Restored::Node(None, hash));
+                }
+                RawNode::branch(children[0], children[1])
+            }
+            Part::Extension(key, child) => {
+                let key = ExtKey::decode(key, 0).ok_or(Error::BadProof)?;
+                let len = self.path.len();
+                self.path
+                    .extend(key.into_slice())
+                    .map_err(|_| Error::KeyTooLong)?;
+                let child = self.build(child)?;
+                self.path.truncate(len);
+                RawNode::extension(key, child.to_ref())
+            }
+        };
+        let hash = node.decode().map_err(|_| Error::BadProof)?.hash_with::<H>();
+        let ptr = self.wlog.alloc(node.0)?;
+        Ok(Restored::Node(Some(ptr), hash))
+    }
+
+    /// Allocates nodes described by a child of a Branch node in the proof.
+    fn build_child(&mut self, part: &Part, bit: bool) -> Result<Restored> {
+        let len = self.path.len();
+        self.path.push_back(bit).map_err(|_| Error::KeyTooLong)?;
+        let res = self.build(part);
+        self.path.truncate(len);
+        res
+    }
+
+    /// Returns hash of the value at current path as given in the entries.
+    fn value_hash(&self) -> Result<&'a CryptoHash> {
+        let key = <&[u8]>::try_from(self.path.as_slice())
+            .map_err(|_| Error::BadProof)?;
+        proof::find_value(self.entries, key).ok_or(Error::BadProof)
+    }
+}
+
+#[test]
+fn test_unseal() {
+    use alloc::vec::Vec;
+
+    let mut trie = super::Trie::test(1000);
+    let keys = (0..100u8).map(|num| [b'k', num / 10, num % 10]);
+    let keys = keys.collect::<Vec<_>>();
+    for (idx, key) in keys.iter().enumerate() {
+        trie.set(key, &CryptoHash::test(idx)).unwrap();
+    }
+    let count = trie.alloc.count();
+    let root_hash = trie.hash().clone();
+
+    // Proofs taken while the values are still available.
+    let (values, proof) =
+        trie.prove_many(keys.iter().map(|key| &key[..])).unwrap();
+    let entries = keys
+        .iter()
+        .zip(values.iter())
+        .map(|(key, value)| (&key[..], value.as_ref()))
+        .collect::<Vec<_>>();
+    let (_, partial) = trie.prove_many([&keys[10][..], &keys[11][..]]).unwrap();
+
+    for key in keys.iter() {
+        trie.seal(key).unwrap();
+    }
+    assert_eq!(&root_hash, trie.hash());
+    assert_eq!(Err(Error::Sealed), trie.get(&keys[10]));
+
+    // Invalid proofs and entries are rejected and the trie is left untouched.
+    let some_hash = CryptoHash::test(1000);
+    let mut bad = entries.clone();
+    bad[10].1 = Some(&some_hash);
+    assert_eq!(Err(Error::BadProof), trie.unseal(&proof, &bad));
+    assert_eq!(Err(Error::BadProof), trie.unseal(&proof, &entries[..50]));
+    let mut other = super::Trie::test(1000);
+    other.set(&keys[10], &some_hash).unwrap();
+    let (_, other) = other.prove_many([&keys[10][..]]).unwrap();
+    let bad = [(&keys[10][..], Some(&some_hash))];
+    assert_eq!(Err(Error::BadProof), trie.unseal(&other, &bad));
+    assert_eq!(Err(Error::Sealed), trie.get(&keys[10]));
+
+    // Unseal only some of the values.
+    trie.unseal(&partial, &entries[10..12]).unwrap();
+    assert_eq!(&root_hash, trie.hash());
+    assert_eq!(Ok(Some(CryptoHash::test(10))), trie.get(&keys[10]));
+    assert_eq!(Ok(Some(CryptoHash::test(11))), trie.get(&keys[11]));
+    assert_eq!(Err(Error::Sealed), trie.get(&keys[12]));
+
+    // Unseal everything.  Unsealing already unsealed values is a no-op.
+    trie.unseal(&proof, &entries).unwrap();
+    assert_eq!(&root_hash, trie.hash());
+    assert_eq!(count, trie.alloc.count());
+    for (key, value) in entries.iter() {
+        assert_eq!(Ok(value.cloned()), trie.get(key));
+    }
+    trie.unseal(&proof, &entries).unwrap();
+    assert_eq!(count, trie.alloc.count());
+
+    // Values can be sealed again.
+    trie.seal(&keys[10]).unwrap();
+    assert_eq!(Err(Error::Sealed), trie.get(&keys[10]));
+}
@@ -0,0 +1,452 @@
+use alloc::vec::Vec;
+
+use lib::hash::{CryptoHash, Hasher, Sha256};
+use memory::Ptr;
+
+use super::{integrity, iter, Error, IntegrityReport, Result, Trie, Value};
+use crate::nodes::RawNode;
+use crate::proof;
+
+/// Number of value bytes stored in a single block.
+///
+/// The first four bytes of a block hold pointer to the next block of the
+/// value.
+const CHUNK_SIZE: usize = RawNode::SIZE - 4;
+
+/// A trie which in addition to value hashes stores the values themselves.
+///
+/// The trie works like [`Trie`] except that rather than value hashes it
+/// accepts and returns the values.  Hash of a value (calculated with the hash
+/// function `H`) is what’s stored in the trie thus proofs and root hash are the
+/// same as of a `Trie` holding hashes of the values.  In other words, it saves
+/// the users from keeping the values in a separate storage.
+///
+/// Values are stored in the same allocator as the trie nodes as linked lists
+/// of blocks each holding up to 68 bytes of the value.  It’s thus best suited
+/// for small values.  To be able to locate the values, the object maintains
+/// an index—a second trie stored in the same allocator—which maps keys to
+/// locations of their values.
+///
+/// The index’s root hash is never used thus its nodes aren’t hashed.  Updating
+/// the index costs memory accesses but no hash calculations beyond those done
+/// for the main trie.
+///
+/// Sealing a value removes it from the storage.
+#[derive(Debug)]
+pub struct ValueTrie<A, H = Sha256> {
+    /// The trie holding value hashes.
+    trie: Trie<A, H>,
+
+    /// Root of the index trie.
+    ///
+    /// In the index, value hash stored at a key describes location of the
+    /// value rather than being an actual hash; see [`Location`].
+    index_ptr: Option<Ptr>,
+}
+
+/// ‘Hash function’ used for nodes of the index trie.
+///
+/// Root hash of the index is never used so rather than calculating hashes of
+/// the nodes, all of them get the same [`IndexHasher::NODE_HASH`].  It’s
+/// non-zero since [`super::EMPTY_TRIE_ROOT`] root hash means empty trie.
+#[derive(Debug)]
+struct IndexHasher;
+
+impl IndexHasher {
+    const NODE_HASH: CryptoHash = CryptoHash([0xff; 32]);
+}
+
+impl Hasher for IndexHasher {
+    fn digestv(_: &[&[u8]]) -> CryptoHash { Self::NODE_HASH }
+}
+
+/// Location of a value in the allocator.
+///
+/// It’s encoded in place of value hash in the index trie.  The first four bytes
+/// are pointer to the first block (or zero if the value is empty) and the next
+/// four bytes are length of the value.  Remaining bytes are zero.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Location {
+    ptr: Option<Ptr>,
+    len: u32,
+}
+
+impl<A: memory::Allocator<Value = Value>> ValueTrie<A> {
+    /// Creates a new empty trie using given allocator.
+    ///
+    /// The trie uses SHA-256 to hash nodes and values.  See
+    /// [`Self::with_hasher`] for using a different hash function.
+    pub fn new(alloc: A) -> Self { Self::with_hasher(alloc) }
+}
+
+impl<A: memory::Allocator<Value = Value>, H: Hasher> ValueTrie<A, H> {
+    /// Creates a new empty trie using given allocator and hash function `H`.
+    pub fn with_hasher(alloc: A) -> Self {
+        Self { trie: Trie::with_hasher(alloc), index_ptr: None }
+    }
+
+    /// Creates a new trie from individual parts.
+    ///
+    /// `index_ptr` is the root of the index trie as returned by
+    /// [`Self::into_parts`].  It’s up to the caller to guarantee that all the
+    /// values are correct and correspond to nodes stored within the pool
+    /// allocator `alloc`.
+    pub fn from_parts(
+        alloc: A,
+        root_ptr: Option<Ptr>,
+        root_hash: CryptoHash,
+        index_ptr: Option<Ptr>,
+    ) -> Self {
+        let trie = Trie::from_parts_with_hasher(alloc, root_ptr, root_hash);
+        Self { trie, index_ptr }
+    }
+
+    /// Deconstructs the object into the individual parts — allocator, root
+    /// pointer, root hash and pointer to the root of the index.
+    pub fn into_parts(self) -> (A, Option<Ptr>, CryptoHash, Option<Ptr>) {
+        let (alloc, root_ptr, root_hash) = self.trie.into_parts();
+        (alloc, root_ptr, root_hash, self.index_ptr)
+    }
+
+    /// Returns the underlying trie holding value hashes.
+    ///
+    /// The trie can be used to generate proofs or inspect it in other ways.
+    /// Modifying it directly isn’t possible since that would make the stored
+    /// values out of sync.
+    ///
+    /// Note that the returned trie isn’t aware of the index nor of the stored
+    /// values.  In particular, [`Trie::verify_integrity`] reports blocks they
+    /// occupy as leaked (use [`Self::verify_integrity`] instead) and
+    /// [`Trie::stats`] doesn’t account for them.
+    pub fn trie(&self) -> &Trie<A, H> { &self.trie }
+
+    /// Verifies integrity of the trie, of the index and of stored values.
+    ///
+    /// Works like [`Trie::verify_integrity`] but additionally walks the index
+    /// and blocks holding the values so that they aren’t reported as leaked.
+    /// `nodes` in the report counts index nodes and value blocks as well.
+    /// Value blocks are checked only if no issues were found in the index.
+    pub fn verify_integrity(&self) -> IntegrityReport {
+        let mut ctx = integrity::Context::new(&self.trie);
+        ctx.walk_with::<IndexHasher>(self.index_ptr, &IndexHasher::NODE_HASH);
+        if !ctx.is_healthy() || self.index_ptr.is_none() {
+            return ctx.finish();
+        }
+        let entries = iter::get_entries(&self.trie.alloc, self.index_ptr, &[]);
+        let locations = entries
+            .iter()
+            .flatten()
+            .filter_map(|entry| entry.hash.as_ref())
+            .filter_map(|hash| Location::decode(hash).ok());
+        for loc in locations {
+            let mut ptr = loc.ptr;
+            while let Some(block) = ptr.filter(|ptr| ctx.mark(*ptr)) {
+                ptr = self.next_block(block);
+            }
+        }
+        ctx.finish()
+    }
+
+    /// Returns hash of the root node.
+    pub fn hash(&self) -> &CryptoHash { self.trie.hash() }
+
+    /// Returns whether the trie is empty.
+    pub fn is_empty(&self) -> bool { self.trie.is_empty() }
+
+    /// Retrieves value at given key.
+    ///
+    /// Returns `None` if there’s no value at given key.  Returns an error if
+    /// the value (or its ancestor) has been sealed.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.trie.get(key)?.is_none() {
+            return Ok(None);
+        }
+        let loc = self.locate(key)?.ok_or(Error::NotFound)?;
+        Ok(Some(self.read(loc)))
+    }
+
+    /// Retrieves value at given key and provides proof of the result.
+    ///
+    /// The proof is for the hash of the value.  See [`Trie::prove`].
+    pub fn prove(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, proof::Proof)> {
+        let (_, proof) = self.trie.prove(key)?;
+        Ok((self.get(key)?, proof))
+    }
+
+    /// Inserts a new value or updates an existing one.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let old = self.locate(key)?;
+        let loc = self.write(value)?;
+        if let Err(err) = self.trie.set(key, &H::digest(value)) {
+            self.free(loc);
+            return Err(err);
+        }
+        if let Err(err) =
+            self.update_index(|index| index.set(key, &loc.encode()))
+        {
+            // The only possible error is running out of memory.  Restore
+            // previous state of the trie so the value isn’t missing.
+            let _ = match old {
+                Some(old) => {
+                    let hash = H::digest(&self.read(old));
+                    self.trie.set(key, &hash)
+                }
+                None => self.trie.del(key).map(|_| ()),
+            };
+            self.free(loc);
+            return Err(err);
+        }
+        if let Some(old) = old {
+            self.free(old);
+        }
+        Ok(())
+    }
+
+    /// Seals value at given key and removes it from the storage.
+    ///
+    /// See [`Trie::seal`].
+    pub fn seal(&mut self, key: &[u8]) -> Result<()> {
+        self.trie.seal(key)?;
+        self.remove(key)
+    }
+
+    /// Deletes value at given key.  Returns `false` if key was not found.
+    pub fn del(&mut self, key: &[u8]) -> Result<bool> {
+        if !self.trie.del(key)? {
+            return Ok(false);
+        }
+        self.remove(key)?;
+        Ok(true)
+    }
+
+    /// Returns location of value at given key or `None` if it isn’t stored.
+    fn locate(&self, key: &[u8]) -> Result<Option<Location>> {
+        // Index uses the same allocator as the trie so the trie can be used to
+        // look it up.
+        let (loc, _) = self.trie.get_impl_with::<IndexHasher>(
+            self.index_ptr,
+            &self.index_hash(),
+            key,
+            false,
+        )?;
+        loc.as_ref().map(Location::decode).transpose()
+    }
+
+    /// Removes value at given key from the index and frees it.
+    fn remove(&mut self, key: &[u8]) -> Result<()> {
+        if let Some(loc) = self.locate(key)? {
+            self.update_index(|index| index.del(key).map(|_| ()))?;
+            self.free(loc);
+        }
+        Ok(())
+    }
+
+    /// Returns root hash of the index trie.
+    ///
+    /// The index is never sealed so it’s empty if and only if there’s no root
+    /// node.
+    fn index_hash(&self) -> CryptoHash {
+        match self.index_ptr {
+            None => super::EMPTY_TRIE_ROOT,
+            Some(_) => IndexHasher::NODE_HASH,
+        }
+    }
+
+    /// Performs an operation on the index trie.
+    fn update_index(
+        &mut self,
+        func: impl FnOnce(&mut Trie<&mut A, IndexHasher>) -> Result<()>,
+    ) -> Result<()> {
+        let hash = self.index_hash();
+        let mut index = Trie::from_parts_with_hasher(
+            &mut self.trie.alloc,
+            self.index_ptr,
+            hash,
+        );
+        let res = func(&mut index);
+        let (_, ptr, _) = index.into_parts();
+        self.index_ptr = ptr;
+        res
+    }
+
+    /// Stores value in the allocator and returns its location.
+    fn write(&mut self, value: &[u8]) -> Result<Location> {
+        let len = u32::try_from(value.len()).map_err(|_| Error::OutOfMemory)?;
+        let mut next = None;
+        for chunk in value.chunks(CHUNK_SIZE).rev() {
+            let mut block = [0; RawNode::SIZE];
+            let (head, tail) = block.split_at_mut(4);
+            head.copy_from_slice(
+                &next.map_or(0, |ptr: Ptr| ptr.get()).to_be_bytes(),
+            );
+            tail[..chunk.len()].copy_from_slice(chunk);
+            match self.trie.alloc.alloc(block) {
+                Ok(ptr) => next = Some(ptr),
+                Err(err) => {
+                    self.free(Location { ptr: next, len });
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(Location { ptr: next, len })
+    }
+
+    /// Reads value stored at given location.
+    fn read(&self, loc: Location) -> Vec<u8> {
+        let mut value = Vec::with_capacity(loc.len as usize);
+        let mut ptr = loc.ptr;
+        while let Some(block) = ptr.map(|ptr| self.trie.alloc.get(ptr)) {
+            let (head, tail) =
+                stdx::split_array_ref::<4, CHUNK_SIZE, { RawNode::SIZE }>(
+                    block,
+                );
+            let len = CHUNK_SIZE.min(loc.len as usize - value.len());
+            value.extend_from_slice(&tail[..len]);
+            ptr = Ptr::new_truncated(u32::from_be_bytes(*head));
+        }
+        value
+    }
+
+    /// Returns pointer to the block following given block of a value.
+    fn next_block(&self, ptr: Ptr) -> Option<Ptr> {
+        let (head, _) = stdx::split_array_ref::<4, CHUNK_SIZE, { RawNode::SIZE }>(
+            self.trie.alloc.get(ptr),
+        );
+        Ptr::new_truncated(u32::from_be_bytes(*head))
+    }
+
+    /// Frees blocks of value stored at given location.
+    fn free(&mut self, loc: Location) {
+        let mut ptr = loc.ptr;
+        while let Some(block) = ptr {
+            ptr = self.next_block(block);
+            self.trie.alloc.free(block);
+        }
+    }
+}
+
+impl Location {
+    /// Encodes the location as stored in the index trie.
+    fn encode(&self) -> CryptoHash {
+        let mut hash = CryptoHash::default();
+        let ptr = self.ptr.map_or(0, |ptr| ptr.get());
+        hash.0[..4].copy_from_slice(&ptr.to_be_bytes());
+        hash.0[4..8].copy_from_slice(&self.len.to_be_bytes());
+        hash
+    }
+
+    /// Decodes the location as stored in the index trie.
+    fn decode(hash: &CryptoHash) -> Result<Self> {
+        let bad = || Error::BadRawNode(crate::nodes::DecodeError::BadValueRef);
+        let (ptr, rest) = stdx::split_array_ref::<4, 28, 32>(hash.as_array());
+        let (len, _) = stdx::split_array_ref::<4, 24, 28>(rest);
+        let ptr = Ptr::new(u32::from_be_bytes(*ptr)).map_err(|_| bad())?;
+        let len = u32::from_be_bytes(*len);
+        if ptr.is_none() != (len == 0) {
+            return Err(bad());
+        }
+        Ok(Self { ptr, len })
+    }
+}
+
+#[test]
+fn test_value_trie() {
+    use alloc::collections::BTreeMap;
+
+    use rand::seq::SliceRandom;
+    use rand::Rng;
+
+    type TestTrie = ValueTrie<memory::test_utils::TestAllocator<Value>>;
+
+    let mut trie =
+        TestTrie::new(memory::test_utils::TestAllocator::new(10_000));
+    let mut hashes = Trie::test(10_000);
+    let mut model = BTreeMap::new();
+    let mut rng = rand::thread_rng();
+    for _ in 0..lib::test_utils::get_iteration_count(100) {
+        let key = [rng.gen_range(0..32), rng.gen()];
+        let len = *[0, 1, 67, 68, 69, 200].choose(&mut rng).unwrap();
+        let value = (0..len).map(|_| rng.gen()).collect::<Vec<u8>>();
+        if rng.gen_ratio(1, 4) {
+            assert_eq!(hashes.del(&key), trie.del(&key));
+            model.remove(&key);
+        } else {
+            trie.set(&key, &value).unwrap();
+            hashes.set(&key, &CryptoHash::digest(&value)).unwrap();
+            model.insert(key, value);
+        }
+        assert_eq!(hashes.hash(), trie.hash());
+    }
+    let report = trie.verify_integrity();
+    assert!(report.is_healthy(), "{report:?}");
+    assert_eq!(trie.trie.alloc.count(), report.nodes);
+    assert!(!trie.trie().verify_integrity().is_healthy());
+    for (key, value) in model.iter() {
+        assert_eq!(Some(value), trie.get(key).unwrap().as_ref());
+        let (got, proof) = trie.prove(key).unwrap();
+        assert_eq!(Some(value), got.as_ref());
+        assert!(proof.verify(
+            trie.hash(),
+            key,
+            Some(&CryptoHash::digest(value))
+        ));
+    }
+    assert_eq!(None, trie.get(&[255, 0]).unwrap());
+
+    // Sealing frees the value.
+    let (key, _) = model.pop_first().unwrap();
+    let count = trie.trie.alloc.count();
+    trie.seal(&key).unwrap();
+    assert!(trie.trie.alloc.count() < count);
+    assert_eq!(Err(Error::Sealed), trie.get(&key));
+
+    // Round-trip through parts.
+    let (alloc, root_ptr, root_hash, index_ptr) = trie.into_parts();
+    let mut trie = TestTrie::from_parts(alloc, root_ptr, root_hash, index_ptr);
+    for (key, value) in model.iter() {
+        assert_eq!(Some(value), trie.get(key).unwrap().as_ref());
+    }
+
+    // Deleting everything frees all the blocks other than the sealed node.
+    for key in model.keys() {
+        assert!(trie.del(key).unwrap());
+    }
+    assert_eq!(None, trie.index_ptr);
+    assert!(trie.trie.alloc.count() <= 1);
+    assert!(trie.verify_integrity().is_healthy());
+}
+
+#[test]
+fn test_index_not_hashed() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /// SHA-256 which counts how many times it’s been called.
+    struct Counting;
+
+    impl Hasher for Counting {
+        fn digestv(slices: &[&[u8]]) -> CryptoHash {
+            COUNT.fetch_add(1, Ordering::Relaxed);
+            Sha256::digestv(slices)
+        }
+    }
+
+    let new_alloc = || memory::test_utils::TestAllocator::new(1000);
+    let mut trie = ValueTrie::<_, Counting>::with_hasher(new_alloc());
+    let mut hashes = Trie::<_, Counting>::with_hasher(new_alloc());
+    for key in 0..50u8 {
+        let key = [key.wrapping_mul(37), key];
+        let value = [key[0]; 100];
+        let hash = CryptoHash::digest(&value);
+
+        COUNT.store(0, Ordering::Relaxed);
+        hashes.set(&key, &hash).unwrap();
+        let want = COUNT.swap(0, Ordering::Relaxed);
+        trie.set(&key, &value).unwrap();
+        // The only extra hash is of the value itself.
+        assert_eq!(want + 1, COUNT.load(Ordering::Relaxed));
+        assert_eq!(hashes.hash(), trie.hash());
+    }
+    assert!(trie.verify_integrity().is_healthy());
+}
@@ -0,0 +1,229 @@
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
+
+use lib::hash::CryptoHash;
+use memory::Ptr;
+
+/// State of a persistent trie, i.e. one which keeps old versions of the trie
+/// alive.
+///
+/// In persistent mode, nodes reachable from retained versions are never
+/// modified.  Instead, modifications allocate new nodes (copy-on-write) and
+/// the replaced nodes are retired.  Retired nodes are freed once all versions
+/// they belong to fall beyond the garbage-collection horizon.
+#[derive(Debug)]
+pub(super) struct Versions {
+    /// Maximum number of retained versions.
+    horizon: usize,
+
+    /// Retained versions, oldest first.
+    versions: VecDeque<Version>,
+
+    /// Nodes allocated since the last checkpoint.
+    ///
+    /// Those nodes aren’t reachable from any retained version and thus can be
+    /// modified in place.
+    fresh: BTreeSet<Ptr>,
+}
+
+/// A retained version of the trie.
+#[derive(Debug)]
+struct Version {
+    /// Pointer to the root node of the version.
+    root_ptr: Option<Ptr>,
+
+    /// Hash of the root node of the version.
+    root_hash: CryptoHash,
+
+    /// Nodes which belong to this version but not to the next one.
+    ///
+    /// For the newest version those are nodes retired since the version was
+    /// created.  They are freed when this version is dropped.
+    retired: Vec<Ptr>,
+}
+
+impl Versions {
+    pub(super) fn new(horizon: usize) -> Self {
+        Self { horizon, versions: VecDeque::new(), fresh: BTreeSet::new() }
+    }
+
+    /// Returns the garbage-collection horizon.
+    pub(super) fn horizon(&self) -> usize { self.horizon }
+
+    /// Changes the garbage-collection horizon freeing nodes of versions which
+    /// fall beyond it.
+    pub(super) fn set_horizon<A: memory::Allocator>(
+        &mut self,
+        alloc: &mut A,
+        horizon: usize,
+    ) {
+        self.horizon = horizon;
+        while self.versions.len() > self.horizon {
+            let version = self.versions.pop_front().unwrap();
+            for ptr in version.retired {
+                alloc.free(ptr);
+            }
+        }
+        if self.versions.is_empty() {
+            self.fresh.clear();
+        }
+    }
+
+    /// Records a new version of the trie with given root.
+    pub(super) fn checkpoint<A: memory::Allocator>(
+        &mut self,
+        alloc: &mut A,
+        root_ptr: Option<Ptr>,
+        root_hash: &CryptoHash,
+    ) {
+        if self.horizon == 0 {
+            return;
+        }
+        self.fresh.clear();
+        self.versions.push_back(Version {
+            root_ptr,
+            root_hash: root_hash.clone(),
+            retired: Vec::new(),
+        });
+        self.set_horizon(alloc, self.horizon);
+    }
+
+    /// Returns pointer to the root node of the newest retained version with
+    /// given root hash.
+    pub(super) fn find(&self, root_hash: &CryptoHash) -> Option<Option<Ptr>> {
+        self.versions
+            .iter()
+            .rev()
+            .find(|version| version.root_hash == *root_hash)
+            .map(|version| version.root_ptr)
+    }
+
+    /// Returns iterator over root hashes of retained versions, oldest first.
+    pub(super) fn roots(&self) -> impl Iterator<Item = &CryptoHash> {
+        self.versions.iter().map(|version| &version.root_hash)
+    }
+
+    /// Returns iterator over root nodes of retained versions, oldest first.
+    pub(super) fn root_refs(
+        &self,
+    ) -> impl Iterator<Item = (Option<Ptr>, &CryptoHash)> {
+        self.versions
+            .iter()
+            .map(|version| (version.root_ptr, &version.root_hash))
+    }
+
+    /// Returns whether node at given pointer may be reachable from a retained
+    /// version and thus must not be modified.
+    fn is_shared(&self, ptr: Ptr) -> bool {
+        !self.versions.is_empty() && !self.fresh.contains(&ptr)
+    }
+}
+
+/// A write log used by trie operations which handles copy-on-write of nodes
+/// when trie is in persistent mode.
+///
+/// Outside of persistent mode, this is a thin wrapper around
+/// [`memory::WriteLog`].
+pub(super) struct NodeLog<'a, A: memory::Allocator> {
+    /// Underlying write log.
+    wlog: memory::WriteLog<'a, A>,
+
+    /// State of the persistent trie or `None` if not in persistent mode.
+    versions: Option<&'a mut Versions>,
+
+    /// Nodes allocated by the operation.
+    allocated: Vec<Ptr>,
+
+    /// Fresh nodes freed by the operation.
+    freed: Vec<Ptr>,
+
+    /// Shared nodes replaced or removed by the operation.
+    retired: Vec<Ptr>,
+}
+
+impl<'a, A: memory::Allocator> NodeLog<'a, A> {
+    pub(super) fn new(
+        alloc: &'a mut A,
+        versions: Option<&'a mut Versions>,
+    ) -> Self {
+        Self {
+            wlog: memory::WriteLog::new(alloc),
+            versions,
+            allocated: Vec::new(),
+            freed: Vec::new(),
+            retired: Vec::new(),
+        }
+    }
+
+    /// Commits all changes to the allocator and updates state of the
+    /// persistent trie.
+    ///
+    /// To roll changes back, drop the object.
+    pub(super) fn commit(self) {
+        self.wlog.commit();
+        if let Some(versions) = self.versions {
+            for ptr in self.freed {
+                versions.fresh.remove(&ptr);
+            }
+            versions.fresh.extend(self.allocated);
+            if let Some(version) = versions.versions.back_mut() {
+                version.retired.extend(self.retired);
+            }
+        }
+    }
+
+    /// Returns underlying allocator.
+    pub(super) fn allocator(&self) -> &A { self.wlog.allocator() }
+
+    /// Allocates a new node.
+    pub(super) fn alloc(
+        &mut self,
+        value: A::Value,
+    ) -> Result<Ptr, memory::OutOfMemory> {
+        let ptr = self.wlog.alloc(value)?;
+        if self.versions.is_some() {
+            self.allocated.push(ptr);
+        }
+        Ok(ptr)
+    }
+
+    /// Sets value of the node at given pointer.
+    ///
+    /// If the node is shared with a retained version, allocates a new node
+    /// instead and retires the old one.  Returns pointer to the node with the
+    /// new value.
+    pub(super) fn set(
+        &mut self,
+        ptr: Ptr,
+        value: A::Value,
+    ) -> Result<Ptr, memory::OutOfMemory> {
+        if self.is_shared(ptr) {
+            let new_ptr = self.alloc(value)?;
+            self.retired.push(ptr);
+            Ok(new_ptr)
+        } else {
+            self.wlog.set(ptr, value);
+            Ok(ptr)
+        }
+    }
+
+    /// Frees a node or, if it’s shared with a retained version, retires it.
+    pub(super) fn free(&mut self, ptr: Ptr) {
+        if self.is_shared(ptr) {
+            self.retired.push(ptr);
+        } else {
+            if self.versions.is_some() {
+                self.freed.push(ptr);
+            }
+            self.wlog.free(ptr);
+        }
+    }
+
+    /// Returns whether node at given pointer is shared with a retained
+    /// version.
+    fn is_shared(&self, ptr: Ptr) -> bool {
+        self.versions.as_ref().is_some_and(|versions| {
+            versions.is_shared(ptr) && !self.allocated.contains(&ptr)
+        })
+    }
+}
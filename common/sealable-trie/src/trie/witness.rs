@@ -0,0 +1,119 @@
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+
+use lib::hash::Hasher;
+
+use super::{Error, Result};
+use crate::bits;
+use crate::nodes::{Node, NodeRef, RawNode, Reference};
+use crate::proof::{self, Part};
+
+/// Context for generating witnesses of trie modifications.
+pub(super) struct Context<'a, A, H> {
+    /// Allocator used to retrieve nodes.
+    alloc: &'a A,
+
+    /// Part of the key yet to be traversed.
+    ///
+    /// It starts as the key user provided and as trie is traversed bits are
+    /// removed from its front.
+    key: bits::Slice<'a>,
+
+    /// Whether to include contents of siblings of nodes on the path.
+    ///
+    /// When deleting a value, its parent Branch node is replaced by an
+    /// Extension which may be merged with the sibling.  To replicate that, the
+    /// verifier needs to know whether the sibling is an Extension node.
+    expand_siblings: bool,
+
+    /// Hash function used to hash nodes.
+    hasher: PhantomData<H>,
+}
+
+impl<'a, A: memory::Allocator<Value = super::Value>, H: Hasher>
+    Context<'a, A, H>
+{
+    pub(super) fn new(
+        alloc: &'a A,
+        key: bits::Slice<'a>,
+        expand_siblings: bool,
+    ) -> Self {
+        Self { alloc, key, expand_siblings, hasher: PhantomData }
+    }
+
+    /// Generates witness for modification of value at the context’s key in
+    /// a non-empty trie with given root node.
+    pub(super) fn witness(mut self, root: NodeRef) -> Result<proof::Witness> {
+        let root = self.handle_ref(Reference::Node(root))?;
+        Ok(proof::Witness::new(Some(root)))
+    }
+
+    /// Handles reference on the path to the key.
+    fn handle_ref(&mut self, rf: Reference) -> Result<Part> {
+        match rf {
+            Reference::Node(nref) if !self.key.is_empty() => {
+                self.handle_node(nref)
+            }
+            // Either a value or the key has been exhausted.  In either case
+            // nothing below the reference is affected by the modification.
+            _ => Ok(Part::Ref(rf.into())),
+        }
+    }
+
+    /// Handles node on the path to the key.
+    fn handle_node(&mut self, nref: NodeRef) -> Result<Part> {
+        let node = self.get(nref)?;
+        match node {
+            Node::Branch { children } => {
+                let bit = usize::from(self.key.pop_front().unwrap());
+                let mut parts = [Part::Value, Part::Value];
+                parts[bit] = self.handle_ref(children[bit])?;
+                parts[1 - bit] = self.handle_sibling(children[1 - bit])?;
+                Ok(Part::Branch(Box::new(parts)))
+            }
+            Node::Extension { key, child } => {
+                let child = if self.key.strip_prefix(key.into()) {
+                    self.handle_ref(child)?
+                } else {
+                    Part::Ref(child.into())
+                };
+                let key = proof::encode_ext_key(key);
+                Ok(Part::Extension(key, Box::new(child)))
+            }
+        }
+    }
+
+    /// Handles sibling of a node on the path to the key.
+    ///
+    /// If [`Self::expand_siblings`] is set and the sibling is an unsealed
+    /// node, includes the node with its children as opaque references.
+    /// Otherwise returns an opaque reference.
+    fn handle_sibling(&self, rf: Reference) -> Result<Part> {
+        let nref = match rf {
+            Reference::Node(nref)
+                if self.expand_siblings && nref.ptr.is_some() =>
+            {
+                nref
+            }
+            _ => return Ok(Part::Ref(rf.into())),
+        };
+        Ok(match self.get(nref)? {
+            Node::Branch { children } => Part::Branch(Box::new([
+                Part::Ref(children[0].into()),
+                Part::Ref(children[1].into()),
+            ])),
+            Node::Extension { key, child } => Part::Extension(
+                proof::encode_ext_key(key),
+                Box::new(Part::Ref(child.into())),
+            ),
+        })
+    }
+
+    /// Reads and decodes node pointed by given reference.
+    fn get(&self, nref: NodeRef) -> Result<Node<'a>> {
+        let ptr = nref.ptr.ok_or(Error::Sealed)?;
+        let node = <&RawNode>::from(self.alloc.get(ptr)).decode()?;
+        debug_assert_eq!(*nref.hash, node.hash_with::<H>());
+        Ok(node)
+    }
+}
@@ -35,6 +35,7 @@ pub struct TrieKey {
 
 /// A discriminant used as the first byte of each trie key to create namespaces
 /// for different objects stored in the trie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u8)]
 pub enum Tag {
     ClientState = 0,
@@ -51,6 +52,29 @@ impl From<Tag> for u8 {
     fn from(tag: Tag) -> u8 { tag as u8 }
 }
 
+impl TryFrom<u8> for Tag {
+    type Error = u8;
+
+    /// Converts the first byte of a trie key into a tag.
+    ///
+    /// This can be used to map per-prefix statistics of the trie (see
+    /// `sealable_trie::Trie::stats_by_prefix`) to namespaces.  Returns the
+    /// byte as error if it’s not a valid tag.
+    fn try_from(tag: u8) -> Result<Self, u8> {
+        Ok(match tag {
+            0 => Self::ClientState,
+            1 => Self::ConsensusState,
+            2 => Self::Connection,
+            3 => Self::ChannelEnd,
+            4 => Self::NextSequence,
+            5 => Self::Commitment,
+            6 => Self::Receipt,
+            7 => Self::Ack,
+            _ => return Err(tag),
+        })
+    }
+}
+
 impl TrieKey {
     /// Constructs a new key for a client state path for client with given
     /// counter.
@@ -182,6 +206,19 @@ impl core::fmt::Debug for TrieKey {
     }
 }
 
+#[test]
+fn test_tag_from_u8() {
+    for byte in 0..=u8::MAX {
+        match Tag::try_from(byte) {
+            Ok(tag) => assert_eq!(byte, u8::from(tag)),
+            Err(err) => {
+                assert_eq!(byte, err);
+                assert!(byte > 7);
+            }
+        }
+    }
+}
+
 #[test]
 fn test_display() {
     let want = "0123456789abcdef";
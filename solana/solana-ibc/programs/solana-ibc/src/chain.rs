@@ -3,7 +3,7 @@ use core::num::NonZeroU64;
 use anchor_lang::prelude::*;
 use guestchain::manager::PendingBlock;
 pub use guestchain::Config;
-use lib::hash::CryptoHash;
+use lib::hash::{CryptoHash, HashVersion};
 pub use solana_ed25519::{PubKey, Signature, Verifier};
 
 use crate::error::Error;
@@ -44,9 +44,11 @@ impl ChainData {
         &self,
         height: guestchain::BlockHeight,
     ) -> Result<Option<(CryptoHash, NonZeroU64)>, ChainNotInitialised> {
-        let block = self.get()?.manager.head().1;
-        Ok((block.block_height == height)
-            .then(|| (block.calc_hash(), block.timestamp_ns)))
+        let manager = &self.get()?.manager;
+        let block = manager.head().1;
+        Ok((block.block_height == height).then(|| {
+            (block.calc_hash(manager.hash_version()), block.timestamp_ns)
+        }))
     }
 
     /// Initialises a new guest blockchain with given configuration and genesis
@@ -62,6 +64,7 @@ impl ChainData {
     ) -> Result {
         let (host_height, host_timestamp) = get_host_head()?;
         let genesis = Block::generate_genesis(
+            config.hash_version,
             1.into(),
             host_height,
             host_timestamp,
@@ -132,9 +135,11 @@ impl ChainData {
             .add_signature(pubkey.clone(), signature, verifier)
             .map_err(into_error)?;
 
+        let version = manager.hash_version();
         let mut hash = None;
         if res.got_new_signature() {
-            let hash = hash.get_or_insert_with(|| manager.head().1.calc_hash());
+            let hash =
+                hash.get_or_insert_with(|| manager.head().1.calc_hash(version));
             events::emit(events::BlockSigned {
                 block_hash: hash.clone(),
                 pubkey,
@@ -142,7 +147,8 @@ impl ChainData {
             .map_err(ProgramError::BorshIoError)?;
         }
         if res.got_quorum() {
-            let hash = hash.unwrap_or_else(|| manager.head().1.calc_hash());
+            let hash =
+                hash.unwrap_or_else(|| manager.head().1.calc_hash(version));
             events::emit(events::BlockFinalised { block_hash: hash })
                 .map_err(ProgramError::BorshIoError)?;
         }
@@ -212,6 +218,11 @@ impl ChainData {
         Ok(inner.manager.genesis().clone())
     }
 
+    /// Returns hashing scheme used by the chain.
+    pub fn hash_version(&self) -> Result<HashVersion, ChainNotInitialised> {
+        Ok(self.get()?.manager.hash_version())
+    }
+
     /// Checks whether given `program_id` matches expected staking program id.
     ///
     /// The staking program id is stored within the chain account.  Various
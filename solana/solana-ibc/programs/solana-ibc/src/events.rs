@@ -243,6 +243,7 @@ mod snapshot_tests {
 
     fn make_header() -> CowHeader<'static> {
         let block = crate::chain::Block::generate_genesis(
+            lib::hash::HashVersion::V0,
             guestchain::BlockHeight::from(0),
             guestchain::HostHeight::from(42),
             core::num::NonZeroU64::new(24).unwrap(),
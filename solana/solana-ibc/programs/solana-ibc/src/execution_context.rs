@@ -20,8 +20,12 @@ impl ibc::ClientExecutionContext for IbcStorage<'_, '_> {
     ) -> Result {
         msg!("store_client_state({}, {:?})", path, state);
         let mut store = self.borrow_mut();
+        let version = store.chain.hash_version()?;
         let mut client = store.private.client_mut(&path.0, true)?;
-        let hash = client.client_state.set(&state)?.digest_with_client(&path.0);
+        let hash = client
+            .client_state
+            .set(&state)?
+            .digest_with_client(version, &path.0);
         let key = trie_ids::TrieKey::for_client_state(client.index);
         store.provable.set(&key, &hash).map_err(error)
     }
@@ -111,6 +115,7 @@ impl IbcStorage<'_, '_> {
             let head = store.chain.head()?;
             (head.timestamp_ns, head.block_height)
         };
+        let version = store.chain.hash_version()?;
 
         let mut client = store.private.client_mut(client_id, false)?;
         let state = storage::ClientConsensusState::new(
@@ -118,7 +123,7 @@ impl IbcStorage<'_, '_> {
             processed_height,
             &state,
         )?;
-        let hash = state.digest(client_id)?;
+        let hash = state.digest(version, client_id)?;
         client.consensus_states.insert(height, state);
 
         let trie_key =
@@ -147,15 +147,17 @@ impl ClientConsensusState {
     /// reflect it somehow in the value stored in the trie.  We therefore hash
     /// the id together with the serialised state to get the final hash.
     ///
-    /// Specifically, calculates `digest(client_id || b'0' || serialised)`.
+    /// Specifically, calculates `digest(client_id || b'0' || serialised)`
+    /// using `version` hashing scheme; see [`cf_guest::digest_with_client_id`].
     pub fn digest(
         &self,
+        version: lib::hash::HashVersion,
         client_id: &ibc::ClientId,
     ) -> Result<CryptoHash, ibc::ClientError> {
         match self.0.as_bytes().get(16..) {
-            Some(serialised) => {
-                Ok(cf_guest::digest_with_client_id(client_id, serialised))
-            }
+            Some(serialised) => Ok(cf_guest::digest_with_client_id(
+                version, client_id, serialised,
+            )),
             None => Err(ibc::ClientError::ClientSpecific {
                 description: "Internal: Bad AnyConsensusState".into(),
             }),
@@ -524,10 +526,15 @@ impl<T> Serialised<T> {
     #[inline]
     pub fn digest(&self) -> CryptoHash { CryptoHash::digest(self.0.as_slice()) }
 
-    /// Returns digest of the serialised value with client id mixed in.
+    /// Returns digest of the serialised value with client id mixed in using
+    /// `version` hashing scheme.
     #[inline]
-    pub fn digest_with_client(&self, client_id: &ibc::ClientId) -> CryptoHash {
-        cf_guest::digest_with_client_id(client_id, self.as_bytes())
+    pub fn digest_with_client(
+        &self,
+        version: lib::hash::HashVersion,
+        client_id: &ibc::ClientId,
+    ) -> CryptoHash {
+        cf_guest::digest_with_client_id(version, client_id, self.as_bytes())
     }
 }
 
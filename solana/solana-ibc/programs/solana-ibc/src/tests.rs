@@ -126,6 +126,7 @@ fn anchor_test_deliver() -> Result<()> {
                 min_quorum_stake: NonZeroU128::new(1000).unwrap(),
                 min_block_length: 5.into(),
                 min_epoch_length: 200_000.into(),
+                hash_version: lib::hash::HashVersion::V0,
             },
             staking_program_id: Pubkey::from_str(STAKING_PROGRAM_ID).unwrap(),
             genesis_epoch: chain::Epoch::new(
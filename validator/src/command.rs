@@ -9,6 +9,7 @@ use anchor_lang::solana_program::pubkey::Pubkey;
 use clap::{arg, command, Args, Parser, Subcommand};
 use dialoguer::theme::ColorfulTheme;
 use dialoguer::Input;
+use lib::hash::CryptoHash;
 use log::LevelFilter;
 use serde::de::Error as SerdeError;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -22,7 +23,7 @@ pub struct Config {
     pub rpc_url: String,
     pub ws_url: String,
     pub program_id: String,
-    pub genesis_hash: String,
+    pub genesis_hash: CryptoHash,
     pub keypair: InnerKeypair,
     pub log_level: String,
 }
@@ -97,9 +98,9 @@ struct RunArgs {
     #[arg(long)]
     program_id: Option<String>,
 
-    /// genesis hash
+    /// genesis hash (in hex, base58 or base64)
     #[arg(short, long)]
-    genesis_hash: Option<String>,
+    genesis_hash: Option<CryptoHash>,
 
     /// Private key
     #[arg(long)]
@@ -124,9 +125,9 @@ struct InitArgs {
     #[arg(long)]
     program_id: String,
 
-    /// genesis hash
+    /// genesis hash (in hex, base58 or base64)
     #[arg(short, long)]
-    genesis_hash: String,
+    genesis_hash: CryptoHash,
 
     /// Private key
     #[arg(long)]
@@ -159,9 +160,9 @@ struct StakeArgs {
     #[arg(long)]
     program_id: Option<String>,
 
-    /// genesis hash
+    /// genesis hash (in hex, base58 or base64)
     #[arg(short, long)]
-    genesis_hash: Option<String>,
+    genesis_hash: Option<CryptoHash>,
 
     /// Private key
     #[arg(long)]
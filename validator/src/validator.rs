@@ -10,7 +10,6 @@ use anchor_client::solana_sdk::signature::Keypair;
 use anchor_client::solana_sdk::signer::Signer;
 use anchor_client::{Client, Cluster};
 use anchor_lang::solana_program::pubkey::Pubkey;
-use lib::hash::CryptoHash;
 use solana_ibc::chain::ChainData;
 
 use crate::command::Config;
@@ -47,11 +46,11 @@ pub fn run_validator(config: Config) {
 
     log::info!("Validator running");
 
-    let genesis_hash = &CryptoHash::from_base64(&config.genesis_hash)
-        .expect("Invalid Genesis Hash");
+    let genesis_hash = &config.genesis_hash;
 
     // Check if there is a pending block to sign
     let chain_account: ChainData = program.account(chain).unwrap();
+    let hash_version = chain_account.hash_version().unwrap();
     if chain_account.pending_block().unwrap().is_some() {
         if chain_account
             .pending_block()
@@ -104,6 +103,7 @@ pub fn run_validator(config: Config) {
         log::info!("Found New Block Event {:?}", event);
         // Fetching the pending block fingerprint
         let fingerprint = guestchain::block::Fingerprint::new(
+            hash_version,
             genesis_hash,
             &event.block_header.0,
         );